@@ -123,6 +123,59 @@ impl CliPrinter {
         println!("{}", "👋 Goodbye!".bright_blue().bold());
     }
 
+    pub fn print_commands() {
+        println!("{}", "Commands:".bright_blue().bold());
+        for (command, description) in [
+            ("/reset", "Clear the agent memory and start a new conversation"),
+            ("/history", "Show the tasks and answers of this conversation"),
+            ("/tools", "List the tools and managed agents of the agent"),
+            ("/help", "Show this list"),
+            ("/exit", "Quit"),
+        ] {
+            println!("  {} {}", command.bright_cyan(), description);
+        }
+    }
+
+    pub fn print_unknown_command(command: &str) {
+        println!(
+            "{} /{}, type /help for the list of commands",
+            "⚠️  Unknown command:".yellow().italic(),
+            command
+        );
+    }
+
+    pub fn print_reset() {
+        println!("{}", "🧹 Memory cleared".bright_blue().bold());
+    }
+
+    pub fn print_history(history: &[(String, String)]) -> Result<()> {
+        if history.is_empty() {
+            println!("{}", "No tasks yet".yellow().italic());
+            return Ok(());
+        }
+        for (index, (task, answer)) in history.iter().enumerate() {
+            println!(
+                "\n{} {}",
+                format!("📍 Task {}:", index + 1).bright_cyan().bold(),
+                task
+            );
+            PrettyPrinter::new()
+                .input(bat::Input::from_bytes(answer.as_bytes()))
+                .language("Markdown")
+                .wrapping_mode(bat::WrappingMode::Character)
+                .print()?;
+            println!();
+        }
+        Ok(())
+    }
+
+    pub fn print_tools(tools: &[(String, String)]) {
+        println!("{}", "🔧 Tools:".bright_magenta().bold());
+        for (name, description) in tools {
+            println!("  {} {}", name.bright_white().bold(), description);
+        }
+    }
+
     pub fn print_step(step: &Step) -> Result<String> {
         match step {
             Step::ActionStep(action_step) => {
@@ -143,7 +196,7 @@ impl CliPrinter {
 
                 if let Some(answer) = &action_step.final_answer {
                     Self::print_final_answer(answer)?;
-                    return Ok(answer.clone());
+                    return Ok(answer.text.clone());
                 }
             }
             Step::PlanningStep(plan, facts) => {
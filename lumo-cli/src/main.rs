@@ -3,18 +3,20 @@ use async_trait::async_trait;
 use clap::{Parser, ValueEnum};
 use futures::StreamExt;
 use lumo::agent::{
-    AgentStream, CodeAgent, CodeAgentBuilder, FunctionCallingAgent, FunctionCallingAgentBuilder,
-    McpAgentBuilder, StreamResult,
+    Agent, AgentStream, CodeAgent, CodeAgentBuilder, FunctionCallingAgent,
+    FunctionCallingAgentBuilder, McpAgentBuilder, StreamResult,
 };
 use lumo::agent::{McpAgent, Step};
+use lumo::config::{AgentConfig, AgentKind, ConfiguredModel};
 use lumo::errors::AgentError;
 use lumo::models::model_traits::{Model, ModelResponse};
 use lumo::models::ollama::{OllamaModel, OllamaModelBuilder};
 use lumo::models::openai::{OpenAIServerModel, OpenAIServerModelBuilder};
-use lumo::models::types::Message;
+use lumo::models::types::{GenerationConfig, Message};
+use lumo::models::wire_log::{enable_wire_log, FileSink, WireLog};
 use lumo::tools::exa_search::ExaSearchTool;
 use lumo::tools::{
-    AsyncTool, DuckDuckGoSearchTool, GoogleSearchTool, PythonInterpreterTool, ToolInfo,
+    AnyTool, AsyncTool, DuckDuckGoSearchTool, GoogleSearchTool, PythonInterpreterTool, ToolInfo,
     VisitWebsiteTool,
 };
 use mcp_client::{
@@ -22,7 +24,7 @@ use mcp_client::{
 };
 use opentelemetry::trace::{FutureExt, SpanKind, TraceContextExt, Tracer};
 use opentelemetry::{global, Context, KeyValue};
-use std::{collections::HashMap, fs::File, io, time::Duration};
+use std::{fs::File, io, path::PathBuf, time::Duration};
 use tracing::Level;
 use tracing_subscriber::{fmt, EnvFilter};
 mod config;
@@ -61,6 +63,8 @@ enum ModelType {
 enum ModelWrapper {
     OpenAI(OpenAIServerModel),
     Ollama(OllamaModel),
+    /// The model of an agent file.
+    Configured(ConfiguredModel),
 }
 
 enum AgentWrapper<
@@ -94,16 +98,12 @@ impl Model for ModelWrapper {
         messages: Vec<Message>,
         history: Option<Vec<Message>>,
         tools: Vec<ToolInfo>,
-        max_tokens: Option<usize>,
-        args: Option<HashMap<String, Vec<String>>>,
+        config: GenerationConfig,
     ) -> Result<Box<dyn ModelResponse>, AgentError> {
         match self {
-            ModelWrapper::OpenAI(m) => {
-                Ok(m.run(messages, history, tools, max_tokens, args).await?)
-            }
-            ModelWrapper::Ollama(m) => {
-                Ok(m.run(messages, history, tools, max_tokens, args).await?)
-            }
+            ModelWrapper::OpenAI(m) => Ok(m.run(messages, history, tools, config).await?),
+            ModelWrapper::Ollama(m) => Ok(m.run(messages, history, tools, config).await?),
+            ModelWrapper::Configured(m) => Ok(m.run(messages, history, tools, config).await?),
         }
     }
 }
@@ -150,6 +150,52 @@ struct Args {
     /// Context length of the model
     #[arg(short = 'c', long)]
     ctx_length: Option<usize>,
+
+    /// Sampling temperature
+    #[arg(short = 't', long)]
+    temperature: Option<f32>,
+
+    /// Maximum number of tokens to generate per model call
+    #[arg(long)]
+    max_tokens: Option<usize>,
+
+    /// Nucleus sampling probability
+    #[arg(long)]
+    top_p: Option<f32>,
+
+    /// Seed for sampling, for models that support it
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// A TOML, YAML or JSON agent config (`lumo::config::AgentConfig`) describing the agent: model, tools, system
+    /// prompt and managed agents. Its settings override the matching options.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Appends every request to the model provider and its response to this file as JSON lines, with the API keys
+    /// redacted
+    #[arg(long)]
+    wire_log: Option<PathBuf>,
+}
+
+impl Args {
+    /// Overrides the options with the settings of an agent config. An MCP agent stays an MCP agent, since agent
+    /// configs only describe function calling and code agents.
+    fn apply(&mut self, config: &AgentConfig) {
+        if !matches!(self.agent_type, AgentType::Mcp) {
+            self.agent_type = match config.agent_type {
+                AgentKind::FunctionCalling => AgentType::FunctionCalling,
+                AgentKind::Code => AgentType::Code,
+            };
+        }
+        if let Some(model) = &config.model {
+            self.model_id = model.model_id.clone();
+            self.temperature = model.temperature.or(self.temperature);
+            self.ctx_length = model.ctx_length.or(self.ctx_length);
+        }
+        self.max_steps = config.max_steps.or(self.max_steps);
+        self.planning_interval = config.planning_interval.or(self.planning_interval);
+    }
 }
 
 fn create_tool(tool_type: &ToolType) -> Box<dyn AsyncTool> {
@@ -162,10 +208,61 @@ fn create_tool(tool_type: &ToolType) -> Box<dyn AsyncTool> {
     }
 }
 
+fn create_model(
+    model_type: &ModelType,
+    model_id: &str,
+    base_url: Option<&str>,
+    api_key: Option<&str>,
+    ctx_length: Option<usize>,
+) -> Result<ModelWrapper> {
+    let model = match model_type {
+        ModelType::OpenAI => ModelWrapper::OpenAI(
+            OpenAIServerModelBuilder::new(model_id)
+                .with_base_url(base_url)
+                .with_api_key(api_key)
+                .build()?,
+        ),
+        ModelType::Gemini => ModelWrapper::OpenAI(
+            OpenAIServerModelBuilder::new(model_id)
+                .with_base_url(Some(base_url.unwrap_or(
+                    "https://generativelanguage.googleapis.com/v1beta/openai/chat/completions",
+                )))
+                .with_api_key(Some(
+                    api_key.unwrap_or(
+                        &std::env::var("GOOGLE_API_KEY")
+                            .unwrap_or_else(|_| "Gemini API key not found".to_string()),
+                    ),
+                ))
+                .build()?,
+        ),
+        ModelType::Ollama => ModelWrapper::Ollama(
+            OllamaModelBuilder::new()
+                .model_id(model_id)
+                .ctx_length(ctx_length.unwrap_or(20000))
+                .temperature(Some(0.1))
+                .url(base_url.unwrap_or("http://localhost:11434"))
+                .with_native_tools(true)
+                .build(),
+        ),
+    };
+    Ok(model)
+}
+
 #[tracing::instrument]
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    let agent_config = args
+        .config
+        .as_ref()
+        .map(AgentConfig::from_file)
+        .transpose()?;
+    if let Some(config) = &agent_config {
+        args.apply(config);
+    }
+    if let Some(path) = &args.wire_log {
+        enable_wire_log(WireLog::new(FileSink::new(path)?));
+    }
 
     // Initialize tracing subscriber with custom formatting
     let tracer_provider = init_tracer();
@@ -221,41 +318,62 @@ async fn main() -> Result<()> {
         endpoint,
     );
 
-    let tools: Vec<Box<dyn AsyncTool>> = args.tools.iter().map(create_tool).collect();
+    let (tools, managed_agents): (Vec<Box<dyn AsyncTool>>, Vec<Box<dyn Agent>>) =
+        match &agent_config {
+            Some(config) => {
+                let tools = if config.tools.is_empty() && config.tool_tags.is_empty() {
+                    args.tools.iter().map(create_tool).collect()
+                } else {
+                    config.build_tools()?
+                };
+                (tools, config.build_managed_agents()?)
+            }
+            None => (args.tools.iter().map(create_tool).collect(), vec![]),
+        };
 
-    // Create model based on type
-    let model = match args.model_type {
-        ModelType::OpenAI => ModelWrapper::OpenAI(
-            OpenAIServerModelBuilder::new(&args.model_id)
-                .with_base_url(args.base_url.as_deref())
-                .with_api_key(args.api_key.as_deref())
-                .build()?,
-        ),
-        ModelType::Gemini => ModelWrapper::OpenAI(
-            OpenAIServerModelBuilder::new(&args.model_id)
-                .with_base_url(Some(args.base_url.as_deref().unwrap_or(
-                    "https://generativelanguage.googleapis.com/v1beta/openai/chat/completions",
-                )))
-                .with_api_key(Some(
-                    args.api_key.as_deref().unwrap_or(
-                        &std::env::var("GOOGLE_API_KEY")
-                            .unwrap_or_else(|_| "Gemini API key not found".to_string()),
-                    ),
-                ))
-                .build()?,
-        ),
-        ModelType::Ollama => ModelWrapper::Ollama(
-            OllamaModelBuilder::new()
-                .model_id(&args.model_id)
-                .ctx_length(args.ctx_length.unwrap_or(20000))
-                .temperature(Some(0.1))
-                .url(args.base_url.as_deref().unwrap_or("http://localhost:11434"))
-                .with_native_tools(true)
-                .build(),
-        ),
+    // Listed by the /tools command
+    let mut tool_descriptions = tools
+        .iter()
+        .map(|tool| (tool.name().to_string(), tool.description().to_string()))
+        .collect::<Vec<_>>();
+    tool_descriptions.extend(managed_agents.iter().map(|agent| {
+        (
+            agent.name().to_string(),
+            format!("Managed agent: {}", agent.description()),
+        )
+    }));
+    if let AgentType::Mcp = args.agent_type {
+        tool_descriptions.extend(
+            servers
+                .servers
+                .keys()
+                .map(|name| (name.clone(), "MCP server".to_string())),
+        );
+    }
+
+    // Create model based on type, unless the agent config has one
+    let model_config = agent_config
+        .as_ref()
+        .and_then(|config| config.model.as_ref());
+    let model = match model_config {
+        Some(model) => ModelWrapper::Configured(model.build()?),
+        None => create_model(
+            &args.model_type,
+            &args.model_id,
+            args.base_url.as_deref(),
+            args.api_key.as_deref(),
+            args.ctx_length,
+        )?,
     };
+    let file_system_prompt = agent_config
+        .as_ref()
+        .and_then(|config| config.system_prompt.as_deref());
+    let stop_sequences = agent_config
+        .as_ref()
+        .and_then(|config| config.stop_sequences.clone());
 
     let system_prompt = match args.model_type {
+        _ if file_system_prompt.is_some() => file_system_prompt,
         ModelType::Ollama => Some(
             r#"You are a helpful assistant that can answer questions and help with tasks. You are given access tools which you can use to answer the user's question. 
         
@@ -270,24 +388,44 @@ The current time is {{current_time}}"#,
         _ => servers.system_prompt.as_deref(),
     };
 
+    let mut generation_config = agent_config
+        .as_ref()
+        .and_then(|config| config.generation_config.clone())
+        .unwrap_or_else(GenerationConfig::new);
+    generation_config.temperature = generation_config.temperature.or(args.temperature);
+    generation_config.max_tokens = generation_config.max_tokens.or(args.max_tokens);
+    generation_config.top_p = generation_config.top_p.or(args.top_p);
+    generation_config.seed = generation_config.seed.or(args.seed);
+
     let mut agent = match args.agent_type {
-        AgentType::FunctionCalling => AgentWrapper::FunctionCalling(
-            FunctionCallingAgentBuilder::new(model)
+        AgentType::FunctionCalling => {
+            let mut builder = FunctionCallingAgentBuilder::new(model)
                 .with_tools(tools)
+                .with_managed_agents(managed_agents)
                 .with_system_prompt(system_prompt)
                 .with_max_steps(args.max_steps)
                 .with_planning_interval(args.planning_interval)
                 .with_logging_level(args.logging_level)
-                .build()?,
-        ),
-        AgentType::Code => AgentWrapper::Code(
-            CodeAgentBuilder::new(model)
+                .with_generation_config(Some(generation_config.clone()));
+            if let Some(stop_sequences) = stop_sequences {
+                builder = builder.with_stop_sequences(stop_sequences);
+            }
+            AgentWrapper::FunctionCalling(builder.build()?)
+        }
+        AgentType::Code => {
+            let mut builder = CodeAgentBuilder::new(model)
                 .with_tools(tools)
+                .with_managed_agents(managed_agents)
+                .with_system_prompt(file_system_prompt)
                 .with_max_steps(args.max_steps)
                 .with_planning_interval(args.planning_interval)
                 .with_logging_level(args.logging_level)
-                .build()?,
-        ),
+                .with_generation_config(Some(generation_config.clone()));
+            if let Some(stop_sequences) = stop_sequences {
+                builder = builder.with_stop_sequences(stop_sequences);
+            }
+            AgentWrapper::Code(builder.build()?)
+        }
         AgentType::Mcp => {
             // Initialize all configured servers
             let mut clients = Vec::new();
@@ -320,24 +458,29 @@ The current time is {{current_time}}"#,
             }
 
             // Create MCP agent with all initialized clients
-            AgentWrapper::Mcp(
-                McpAgentBuilder::new(model)
-                    .with_system_prompt(system_prompt)
-                    .with_max_steps(args.max_steps)
-                    .with_planning_interval(args.planning_interval)
-                    .with_mcp_clients(clients)
-                    .build()
-                    .await?,
-            )
+            let mut builder = McpAgentBuilder::new(model)
+                .with_system_prompt(system_prompt)
+                .with_max_steps(args.max_steps)
+                .with_planning_interval(args.planning_interval)
+                .with_managed_agents(managed_agents)
+                .with_mcp_clients(clients)
+                .with_generation_config(Some(generation_config));
+            if let Some(stop_sequences) = stop_sequences {
+                builder = builder.with_stop_sequences(stop_sequences);
+            }
+            AgentWrapper::Mcp(builder.build().await?)
         }
     };
 
     let mut file: File = File::create("logs.txt")?;
 
     let mut task_count = 1;
+    // Set by /reset, so that the next task starts with an empty memory
+    let mut reset = false;
+    let mut history: Vec<(String, String)> = Vec::new();
     loop {
         let mut cli_printer = CliPrinter::new()?;
-        let task = cli_printer.prompt_user()?;
+        let mut task = cli_printer.prompt_user()?;
 
         let task_name = format!("Task {}", task_count);
 
@@ -345,6 +488,33 @@ The current time is {{current_time}}"#,
             CliPrinter::handle_empty_input();
             continue;
         }
+        if let Some(command) = task.trim().strip_prefix('/') {
+            match command {
+                "exit" | "quit" => task = "exit".to_string(),
+                "reset" => {
+                    reset = true;
+                    history.clear();
+                    CliPrinter::print_reset();
+                    continue;
+                }
+                "history" => {
+                    CliPrinter::print_history(&history)?;
+                    continue;
+                }
+                "tools" => {
+                    CliPrinter::print_tools(&tool_descriptions);
+                    continue;
+                }
+                "help" => {
+                    CliPrinter::print_commands();
+                    continue;
+                }
+                command => {
+                    CliPrinter::print_unknown_command(command);
+                    continue;
+                }
+            }
+        }
         if task == "exit" {
             if let (Some((provider, _)), Some(context)) = (&tracer_provider, &cx) {
                 context.span().end();
@@ -370,7 +540,8 @@ The current time is {{current_time}}"#,
             None
         };
 
-        let mut result = agent.stream_run(&task, false)?;
+        let mut result = agent.stream_run(&task, reset)?;
+        reset = false;
         let mut final_answer = String::new();
         while let Some(step) = if let Some(context) = &cx2 {
             result.next().with_context(context.clone()).await
@@ -385,6 +556,7 @@ The current time is {{current_time}}"#,
                 println!("Error: {:?}", step);
             }
         }
+        history.push((task.clone(), final_answer.clone()));
         if let Some(context) = &cx2 {
             context.span().set_attribute(KeyValue::new("output.value", final_answer));
             context.span().end();
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use anyhow::Result;
 use async_trait::async_trait;
 use clap::{Parser, ValueEnum};
@@ -7,11 +5,11 @@ use lumo::models::gemini::{GeminiServerModel, GeminiServerModelBuilder};
 use serde_json;
 use lumo::agent::{CodeAgentBuilder, FunctionCallingAgentBuilder, Step};
 use lumo::agent::{Agent, CodeAgent, FunctionCallingAgent};
-use lumo::errors::AgentError;
+use lumo::errors::{AgentError, RunError};
 use lumo::models::model_traits::{Model, ModelResponse};
 use lumo::models::ollama::{OllamaModel, OllamaModelBuilder};
 use lumo::models::openai::{OpenAIServerModel, OpenAIServerModelBuilder};
-use lumo::models::types::Message;
+use lumo::models::types::{GenerationConfig, Message};
 use lumo::tools::{
     AsyncTool, DuckDuckGoSearchTool, GoogleSearchTool, PythonInterpreterTool, ToolInfo,
     VisitWebsiteTool,
@@ -52,7 +50,7 @@ enum AgentWrapper {
 }
 
 impl AgentWrapper {
-    async fn run(&mut self, task: &str, reset: bool) -> Result<String, AgentError> {
+    async fn run(&mut self, task: &str, reset: bool) -> Result<String, RunError> {
         match self {
             AgentWrapper::FunctionCalling(agent) => agent.run(task, reset).await,
             AgentWrapper::Code(agent) => agent.run(task, reset).await,
@@ -73,13 +71,12 @@ impl Model for ModelWrapper {
         messages: Vec<Message>,
         history: Option<Vec<Message>>,
         tools: Vec<ToolInfo>,
-        max_tokens: Option<usize>,
-        args: Option<HashMap<String, Vec<String>>>,
+        config: GenerationConfig,
     ) -> Result<Box<dyn ModelResponse>, AgentError> {
         match self {
-            ModelWrapper::OpenAI(m) => Ok(m.run(messages, history, tools, max_tokens, args).await?),
-            ModelWrapper::Ollama(m) => Ok(m.run(messages, history, tools, max_tokens, args).await?),
-            ModelWrapper::Gemini(m) => Ok(m.run(messages, history, tools, max_tokens, args).await?),
+            ModelWrapper::OpenAI(m) => Ok(m.run(messages, history, tools, config).await?),
+            ModelWrapper::Ollama(m) => Ok(m.run(messages, history, tools, config).await?),
+            ModelWrapper::Gemini(m) => Ok(m.run(messages, history, tools, config).await?),
         }
     }
 }
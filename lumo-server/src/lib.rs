@@ -1,25 +1,26 @@
 pub mod auth;
 pub mod config;
+pub mod openai;
 use actix_web::{dev::Server, get, post, web::Json, App, HttpResponse, HttpServer, Responder};
 use anyhow::Result;
-use base64::{self, Engine};
 use config::Servers;
 use lumo::{
     agent::{Agent, CodeAgentBuilder, FunctionCallingAgentBuilder},
-    models::{openai::OpenAIServerModelBuilder, types::Message},
+    models::{
+        openai::OpenAIServerModelBuilder,
+        types::{GenerationConfig, Message},
+    },
+    telemetry::{init_tracing, TelemetryConfig},
     tools::{exa_search::ExaSearchTool, AsyncTool, DuckDuckGoSearchTool, GoogleSearchTool, VisitWebsiteTool},
 };
 use opentelemetry::trace::FutureExt;
 use opentelemetry::trace::Tracer;
-use opentelemetry::trace::TracerProvider;
 use opentelemetry::Context;
 use opentelemetry::KeyValue;
 use opentelemetry::{
     global,
     trace::{SpanKind, TraceContextExt},
 };
-use opentelemetry_otlp::{WithExportConfig, WithHttpConfig};
-use opentelemetry_sdk::trace::{self as sdktrace, BatchConfigBuilder, BatchSpanProcessor};
 use opentelemetry_sdk::trace::SdkTracerProvider;
 use tracing::instrument;
 
@@ -56,6 +57,8 @@ struct RunTaskRequest {
     agent_type: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     max_results: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GenerationConfig>,
 }
 
 #[derive(Serialize)]
@@ -103,94 +106,47 @@ fn create_tool(tool_type: &ToolType, max_results: Option<usize>) -> Box<dyn Asyn
     }
 }
 
+/// Picks the API key for a provider from the environment based on its base url.
+fn api_key_for(base_url: &str) -> Option<String> {
+    if base_url == "https://api.openai.com/v1/chat/completions" {
+        std::env::var("OPENAI_API_KEY").ok()
+    } else if base_url == "https://generativelanguage.googleapis.com/v1beta/openai/chat/completions" {
+        std::env::var("GOOGLE_API_KEY").ok()
+    } else if base_url.to_lowercase().contains("groq") {
+        std::env::var("GROQ_API_KEY").ok()
+    } else if base_url.to_lowercase().contains("anthropic") {
+        std::env::var("ANTHROPIC_API_KEY").ok()
+    } else {
+        None
+    }
+}
+
 pub fn init_tracer() -> Option<SdkTracerProvider> {
     dotenv().ok();
 
-    let (langfuse_public_key, langfuse_secret_key, endpoint) = if cfg!(debug_assertions) {
-        match (
-            std::env::var("LANGFUSE_PUBLIC_KEY_DEV"),
-            std::env::var("LANGFUSE_SECRET_KEY_DEV"),
-            std::env::var("LANGFUSE_HOST_DEV"),
-        ) {
-            (Ok(public_key), Ok(secret_key), Ok(host)) => (
-                public_key,
-                secret_key,
-                format!("{}/api/public/otel/v1/traces", host),
-            ),
-            _ => return None, // If any key is missing, return None to disable tracing
-        }
-    } else {
-        match (
-            std::env::var("LANGFUSE_PUBLIC_KEY"),
-            std::env::var("LANGFUSE_SECRET_KEY"),
-            std::env::var("LANGFUSE_HOST"),
-        ) {
-            (Ok(public_key), Ok(secret_key), Ok(host)) => (
-                public_key,
-                secret_key,
-                format!("{}/api/public/otel/v1/traces", host),
-            ),
-            _ => return None, // If any key is missing, return None to disable tracing
-        }
+    let suffix = if cfg!(debug_assertions) { "_DEV" } else { "" };
+    let (langfuse_public_key, langfuse_secret_key, host) = match (
+        std::env::var(format!("LANGFUSE_PUBLIC_KEY{}", suffix)),
+        std::env::var(format!("LANGFUSE_SECRET_KEY{}", suffix)),
+        std::env::var(format!("LANGFUSE_HOST{}", suffix)),
+    ) {
+        (Ok(public_key), Ok(secret_key), Ok(host)) => (public_key, secret_key, host),
+        _ => return None, // If any key is missing, return None to disable tracing
     };
 
-    // Basic Auth: base64(public_key:secret_key)
-    let auth_header = format!(
-        "Basic {}",
-        base64::engine::general_purpose::STANDARD
-            .encode(format!("{}:{}", langfuse_public_key, langfuse_secret_key))
-    );
-
-    let mut headers = std::collections::HashMap::new();
-    headers.insert("Authorization".to_string(), auth_header);
-
-    let otlp_exporter = match opentelemetry_otlp::SpanExporter::builder()
-        .with_http()
-        .with_endpoint(endpoint)
-        .with_protocol(opentelemetry_otlp::Protocol::HttpBinary)
-        .with_headers(headers)
-        .build()
-    {
-        Ok(exporter) => exporter,
-        Err(_) => return None,
-    };
-
-    let batch = BatchSpanProcessor::builder(otlp_exporter)
-        .with_batch_config(
-            BatchConfigBuilder::default()
-                .with_max_queue_size(512)
-                .build(),
-        )
-        .build();
-
-    let tracer_provider = sdktrace::SdkTracerProvider::builder()
-        .with_span_processor(batch)
-        .with_resource(
-            opentelemetry_sdk::resource::Resource::builder()
-                .with_service_name("lumo")
-                .with_attributes(vec![
-                    KeyValue::new(
-                        "deployment.environment",
-                        if cfg!(debug_assertions) {
-                            "development".to_string()
-                        } else {
-                            std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string())
-                        },
-                    ),
-                    KeyValue::new("deployment.name", "lumo"),
-                    KeyValue::new("deployment.version", env!("CARGO_PKG_VERSION")),
-                ])
-                .build(),
+    let config = TelemetryConfig::langfuse(&host, &langfuse_public_key, &langfuse_secret_key)
+        .with_resource_attribute(
+            "deployment.environment",
+            if cfg!(debug_assertions) {
+                "development".to_string()
+            } else {
+                std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".to_string())
+            },
         )
-        .build();
-
-    // Initialize the tracer
-    let _ = tracer_provider.tracer("lumo");
-
-    // Set the global tracer provider
-    opentelemetry::global::set_tracer_provider(tracer_provider.clone());
+        .with_resource_attribute("deployment.name", "lumo".to_string())
+        .with_resource_attribute("deployment.version", env!("CARGO_PKG_VERSION").to_string());
 
-    Some(tracer_provider)
+    init_tracing(config).ok()
 }
 
 #[get("/health_check")]
@@ -228,19 +184,7 @@ async fn run_task(req: Json<RunTaskRequest>) -> Result<impl Responder, actix_web
         .start(&tracer);
     let cx = Context::current_with_span(span);
     // use base url to get the right key from environment variables
-    let api_key = if req.base_url == "https://api.openai.com/v1/chat/completions" {
-        std::env::var("OPENAI_API_KEY").ok()
-    } else if req.base_url
-        == "https://generativelanguage.googleapis.com/v1beta/openai/chat/completions"
-    {
-        std::env::var("GOOGLE_API_KEY").ok()
-    } else if req.base_url.to_lowercase().contains("groq") {
-        std::env::var("GROQ_API_KEY").ok()
-    } else if req.base_url.to_lowercase().contains("anthropic") {
-        std::env::var("ANTHROPIC_API_KEY").ok()
-    } else {
-        None
-    };
+    let api_key = api_key_for(&req.base_url);
 
     cx.span()
         .set_attribute(KeyValue::new("gen_ai.system", req.base_url.clone()));
@@ -301,6 +245,7 @@ async fn run_task(req: Json<RunTaskRequest>) -> Result<impl Responder, actix_web
                 .with_history(req.history.clone())
                 .with_mcp_clients(clients)
                 .with_logging_level(Some(log::LevelFilter::Info))
+                .with_generation_config(req.generation_config.clone())
                 .build()
                 .await
                 .map_err(actix_web::error::ErrorInternalServerError)?;
@@ -327,6 +272,7 @@ async fn run_task(req: Json<RunTaskRequest>) -> Result<impl Responder, actix_web
                 .with_max_steps(req.max_steps)
                 .with_history(req.history.clone())
                 .with_logging_level(Some(log::LevelFilter::Info))
+                .with_generation_config(req.generation_config.clone())
                 .build()
                 .map_err(actix_web::error::ErrorInternalServerError)?;
 
@@ -355,6 +301,7 @@ async fn run_task(req: Json<RunTaskRequest>) -> Result<impl Responder, actix_web
                 .with_history(req.history.clone())
                 .with_system_prompt(servers.system_prompt.as_deref())
                 .with_logging_level(Some(log::LevelFilter::Info))
+                .with_generation_config(req.generation_config.clone())
                 .build()
                 .map_err(actix_web::error::ErrorInternalServerError)?;
 
@@ -391,6 +338,7 @@ pub fn run(listener: TcpListener) -> std::io::Result<Server> {
             .wrap(auth::ApiKeyAuth)
             .service(health_check)
             .service(run_task)
+            .service(openai::chat_completions)
     })
     .listen(listener)?
     .run())
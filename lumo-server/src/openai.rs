@@ -0,0 +1,186 @@
+//! An OpenAI-compatible chat completions endpoint, so that chat frontends that speak the OpenAI API can talk to a
+//! lumo agent without custom integration code.
+//!
+//! The agent behind the endpoint is configured with environment variables:
+//! - `LUMO_BASE_URL`: chat completions url of the model provider. Defaults to OpenAI.
+//! - `LUMO_AGENT_TYPE`: `function-calling` (default) or `code-agent`.
+//! - `LUMO_TOOLS`: comma separated tool names, e.g. `DuckDuckGo,VisitWebsite`.
+//! - `LUMO_MAX_STEPS`: maximum number of steps per request.
+//!
+//! The `model` of the request is passed on to the provider. The last user message is the task and the messages
+//! before it are the history. The request and response types are the ones of `lumo::server`, the axum version of
+//! this endpoint for applications that build their own agent.
+
+use std::str::FromStr;
+
+use actix_web::{http::header, post, web::Bytes, web::Json, HttpResponse};
+use futures::{Stream, StreamExt};
+use lumo::{
+    agent::{AgentStream, FunctionCallingAgentBuilder, Step},
+    models::openai::OpenAIServerModelBuilder,
+    server::{error_body, into_task_and_history, ChatCompletionRequest, Completion, Delta},
+};
+use tracing::instrument;
+
+#[cfg(feature = "code")]
+use lumo::agent::CodeAgentBuilder;
+
+use crate::{api_key_for, config::Servers, create_tool, ToolType};
+
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1/chat/completions";
+
+fn event(data: &str) -> Bytes {
+    Bytes::from(format!("data: {}\n\n", data))
+}
+
+fn chunk(completion: &Completion, delta: Delta, finish_reason: Option<&'static str>) -> Bytes {
+    event(&serde_json::to_string(&completion.chunk(delta, finish_reason)).unwrap_or_default())
+}
+
+/// How the agent behind the endpoint is set up, read from the environment on every request.
+struct AgentSettings {
+    base_url: String,
+    agent_type: String,
+    tools: Vec<String>,
+    max_steps: Option<usize>,
+}
+
+impl AgentSettings {
+    fn from_env() -> Self {
+        Self {
+            base_url: std::env::var("LUMO_BASE_URL")
+                .unwrap_or_else(|_| DEFAULT_BASE_URL.to_string()),
+            agent_type: std::env::var("LUMO_AGENT_TYPE")
+                .unwrap_or_else(|_| "function-calling".to_string()),
+            tools: std::env::var("LUMO_TOOLS")
+                .map(|tools| {
+                    tools
+                        .split(',')
+                        .map(|tool| tool.trim().to_string())
+                        .filter(|tool| !tool.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            max_steps: std::env::var("LUMO_MAX_STEPS")
+                .ok()
+                .and_then(|steps| steps.parse().ok()),
+        }
+    }
+}
+
+#[post("/v1/chat/completions")]
+#[instrument(skip(req), fields(model = %req.model, stream = req.stream))]
+pub async fn chat_completions(
+    req: Json<ChatCompletionRequest>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let req = req.into_inner();
+    let settings = AgentSettings::from_env();
+    let (task, history) =
+        into_task_and_history(&req.messages).map_err(actix_web::error::ErrorBadRequest)?;
+    let history = if history.is_empty() {
+        None
+    } else {
+        Some(history)
+    };
+
+    let model = OpenAIServerModelBuilder::new(&req.model)
+        .with_base_url(Some(&settings.base_url))
+        .with_api_key(api_key_for(&settings.base_url).as_deref())
+        .build()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let tools = settings
+        .tools
+        .iter()
+        .map(|tool| ToolType::from_str(tool).map(|t| create_tool(&t, None)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let completion = Completion::new(&req.model);
+
+    match settings.agent_type.as_str() {
+        #[cfg(feature = "code")]
+        "code-agent" => {
+            let agent = CodeAgentBuilder::new(model)
+                .with_tools(tools)
+                .with_max_steps(settings.max_steps)
+                .with_history(history)
+                .with_generation_config(Some(req.generation_config))
+                .build()
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            respond(agent, task, completion, req.stream).await
+        }
+        _ => {
+            let servers = Servers::load().map_err(actix_web::error::ErrorInternalServerError)?;
+            let agent = FunctionCallingAgentBuilder::new(model)
+                .with_tools(tools)
+                .with_max_steps(settings.max_steps)
+                .with_history(history)
+                .with_system_prompt(servers.system_prompt.as_deref())
+                .with_generation_config(Some(req.generation_config))
+                .build()
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            respond(agent, task, completion, req.stream).await
+        }
+    }
+}
+
+async fn respond<A: AgentStream + 'static>(
+    mut agent: A,
+    task: String,
+    completion: Completion,
+    stream: bool,
+) -> Result<HttpResponse, actix_web::Error> {
+    if stream {
+        return Ok(HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .streaming(sse_stream(agent, task, completion)));
+    }
+    let answer = agent
+        .run(&task, true)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(completion.response(answer, agent.get_usage())))
+}
+
+/// Runs the agent and sends the final answer as `chat.completion.chunk` events. Intermediate steps are sent as SSE
+/// comments, which clients ignore but which keep the connection alive during long runs.
+fn sse_stream<A: AgentStream + 'static>(
+    mut agent: A,
+    task: String,
+    completion: Completion,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    async_stream::stream! {
+        yield Ok(chunk(&completion, Delta { role: Some("assistant"), content: None }, None));
+
+        let mut answer = None;
+        let mut error = None;
+        match agent.stream_run(&task, true) {
+            Ok(mut steps) => {
+                while let Some(step) = steps.next().await {
+                    match step {
+                        Ok(Step::ActionStep(step)) => match step.final_answer {
+                            Some(final_answer) => answer = Some(final_answer.text),
+                            None => yield Ok(Bytes::from(format!(": step {}\n\n", step.step))),
+                        },
+                        Ok(_) => {}
+                        Err(e) => {
+                            error = Some(e.to_string());
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => error = Some(e.to_string()),
+        }
+
+        match error {
+            Some(message) => {
+                yield Ok(event(&error_body(&message, "agent_error").to_string()));
+            }
+            None => {
+                yield Ok(chunk(&completion, Delta { role: None, content: answer }, None));
+                yield Ok(chunk(&completion, Delta::default(), Some("stop")));
+            }
+        }
+        yield Ok(event("[DONE]"));
+    }
+}
@@ -0,0 +1,27 @@
+use std::net::TcpListener;
+
+use lumo_server::run;
+
+fn spawn_app() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind address");
+    let port = listener.local_addr().unwrap().port();
+    let server = run(listener).expect("Failed to bind address");
+    let _ = tokio::spawn(server);
+    format!("http://localhost:{}", port)
+}
+
+#[actix_web::test]
+async fn chat_completions_requires_a_user_message() {
+    let url = spawn_app();
+    let client = reqwest::Client::new();
+    let response = client
+        .post(url + "/v1/chat/completions")
+        .json(&serde_json::json!({
+            "model": "gpt-4o-mini",
+            "messages": [{"role": "system", "content": "You are a helpful assistant."}]
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -1,8 +1,14 @@
+use std::time::Duration;
+
 use serde::Serialize;
 
+use super::answer::Answer;
 use crate::{
     errors::AgentError,
-    models::{openai::ToolCall, types::Message},
+    models::{
+        openai::ToolCall,
+        types::{Message, Usage},
+    },
 };
 
 #[derive(Debug, Serialize, Clone)]
@@ -35,9 +41,18 @@ pub struct AgentStep {
     pub tool_call: Option<Vec<ToolCall>>,
     pub error: Option<AgentError>,
     pub observations: Option<Vec<String>>,
-    pub final_answer: Option<String>,
+    /// The answer of the final step, with the observations it cites.
+    pub final_answer: Option<Answer>,
     pub step: usize,
     pub task: Option<String>,
+    /// The id of the run the step belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// The tokens of the model calls made during the step.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<Duration>,
 }
 
 impl AgentStep {
@@ -51,6 +66,9 @@ impl AgentStep {
             final_answer: None,
             step,
             task,
+            run_id: None,
+            usage: None,
+            duration: None,
         }
     }
 }
@@ -1,23 +1,137 @@
-use super::agent_step::Step;
+use super::{
+    agent_step::Step,
+    answer::{observation_id, Answer, CITATION_PROMPT},
+    budget::Budget,
+    delegation::Delegation,
+    dry_run::DryRun,
+    hooks::{AgentHook, AgentHooks},
+    reflection::{critique_observation, parse_critique, ReflectionConfig},
+    run_report::RunReport,
+    runtime_facts::{FactsPlacement, RuntimeFacts},
+};
 use crate::{
     agent::agent_step::AgentStep,
-    errors::AgentError,
+    context::RunContext,
+    errors::{AgentError, BudgetExceededError, MaxStepsExceededError, RunError},
     models::{
         model_traits::Model,
-        types::{Message, MessageRole},
+        tokenizer::fit_to_context,
+        types::{split_image_data_urls, GenerationConfig, Message, MessageRole, ToolChoice, Usage},
     },
+    prompts::FINAL_STEP_PROMPT,
+    runtime::Instant,
+    tools::ToolInfo,
 };
 use anyhow::Result;
 use async_trait::async_trait;
 use log::info;
+use opentelemetry::{
+    global,
+    trace::{FutureExt, SpanKind, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use tracing::Instrument;
 
 #[cfg(feature = "stream")]
-use {futures::Stream, std::pin::Pin};
+use {
+    crate::models::stream::{ChatChunk, ChunkSink},
+    futures::{Stream, StreamExt},
+    std::{future::Future, pin::Pin, task::Poll},
+};
 
-#[cfg(feature = "stream")]
+/// The most tokens kept free for the answer when the memory is fit to the context length of the model, if the
+/// generation config has no `max_tokens`. Models with a short context keep a quarter of it.
+const ANSWER_TOKENS: usize = 4096;
+
+/// The step logged for the answer the model gave on the final step.
+fn final_answer_step(step: usize, run_id: Option<String>, answer: Answer) -> Step {
+    Step::ActionStep(AgentStep {
+        final_answer: Some(answer),
+        run_id,
+        ..AgentStep::new(step, None)
+    })
+}
+
+/// The telemetry span and the log span of a run. Every span of the run is a child of the run span, so the runs of
+/// managed agents, which are started inside a step of their manager, end up in the trace of the manager.
+fn run_span(
+    agent: &'static str,
+    run_id: &str,
+    parent_run_id: Option<String>,
+) -> (Context, tracing::Span) {
+    let tracer = global::tracer("lumo");
+    let mut attributes = vec![
+        KeyValue::new("lumo.agent", agent),
+        KeyValue::new("lumo.run_id", run_id.to_string()),
+    ];
+    if let Some(parent_run_id) = &parent_run_id {
+        attributes.push(KeyValue::new("lumo.parent_run_id", parent_run_id.clone()));
+    }
+    let span = tracer
+        .span_builder(format!("{} run", agent))
+        .with_kind(SpanKind::Internal)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &Context::current());
+    let log_span = tracing::info_span!(
+        "run",
+        agent = agent,
+        run_id = %run_id,
+        parent_run_id = parent_run_id.as_deref().unwrap_or_default()
+    );
+    (Context::current_with_span(span), log_span)
+}
+
+/// The messages a run adds to a conversation: the tool calls of its steps, each followed by its result, and the
+/// answer. The `final_answer` call is left out, since the answer is the last message.
+fn run_messages(logs: &[Step], answer: &str) -> Vec<Message> {
+    let mut messages = Vec::new();
+    for step in logs {
+        let Step::ActionStep(step) = step else {
+            continue;
+        };
+        let calls = step
+            .tool_call
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, tool_call)| tool_call.function.name != "final_answer")
+            .collect::<Vec<_>>();
+        if calls.is_empty() {
+            continue;
+        }
+        messages.push(Message::tool_calls(
+            step.llm_output.as_deref().unwrap_or_default(),
+            calls
+                .iter()
+                .map(|(_, tool_call)| (*tool_call).clone())
+                .collect(),
+        ));
+        for (index, tool_call) in calls {
+            let result = step
+                .observations
+                .as_ref()
+                .and_then(|observations| observations.get(index).cloned())
+                .or_else(|| step.error.as_ref().map(|error| error.message().to_string()))
+                .unwrap_or_default();
+            messages.push(Message {
+                tool_call_id: tool_call.id.clone(),
+                ..Message::new(MessageRole::ToolResponse, &result)
+            });
+        }
+    }
+    messages.push(Message::assistant(answer));
+    messages
+}
+
+/// The stream of a run. It is `Send`, so that it can be served from any task of a multi-threaded runtime.
+#[cfg(all(feature = "stream", not(target_arch = "wasm32")))]
+pub type StreamResult<'a, T> = Result<Pin<Box<dyn Stream<Item = Result<T>> + Send + 'a>>>;
+/// The models of the browser do not stream `Send` responses, so neither do the runs.
+#[cfg(all(feature = "stream", target_arch = "wasm32"))]
 pub type StreamResult<'a, T> = Result<Pin<Box<dyn Stream<Item = Result<T>> + 'a>>>;
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 pub trait Agent: Send + Sync {
     fn name(&self) -> &'static str;
     fn get_max_steps(&self) -> usize;
@@ -25,6 +139,11 @@ pub trait Agent: Send + Sync {
     fn reset_step_number(&mut self);
     fn set_step_number(&mut self, step_number: usize);
     fn increment_step_number(&mut self);
+    /// The steps of the current run, which the run report, the answer and the dry run are read from. The default
+    /// has no steps, for agents written before it was added; agents that keep their steps should return them.
+    fn get_logs(&self) -> &[Step] {
+        &[]
+    }
     fn get_logs_mut(&mut self) -> &mut Vec<Step>;
     fn set_task(&mut self, task: &str);
     fn get_task(&self) -> &str;
@@ -39,30 +158,234 @@ pub trait Agent: Send + Sync {
     ) -> Result<Option<Step>>;
     fn description(&self) -> &'static str;
     fn model(&self) -> &dyn Model;
+    /// The sampling parameters used for every model call made by the agent.
+    fn get_generation_config(&self) -> GenerationConfig {
+        GenerationConfig::default()
+    }
+    /// The messages of earlier conversations that are sent to the model before the agent memory.
+    fn get_history(&self) -> Option<Vec<Message>> {
+        None
+    }
+    fn set_history(&mut self, _history: Option<Vec<Message>>) {}
+    /// The tokens used by every model call the agent has made so far.
+    fn get_usage(&self) -> Usage {
+        Usage::default()
+    }
+    fn add_usage(&mut self, _usage: Usage) {}
+    fn get_budget(&self) -> Option<Budget> {
+        None
+    }
+    /// The hooks that are called during a run.
+    fn get_hooks(&self) -> AgentHooks {
+        AgentHooks::default()
+    }
+    /// The message sent to the model on the final step, once the agent has used all of its steps.
+    fn get_final_step_prompt(&self) -> &str {
+        FINAL_STEP_PROMPT
+    }
+    /// The values shared by the tools of the agent, which the system prompt and the task can use as
+    /// `{{context.<key>}}`.
+    fn get_context(&self) -> RunContext {
+        RunContext::default()
+    }
+    /// Replaces the context of the agent, so that it shares the values and the cancellation of another run. Agents
+    /// that do not override it keep their own context.
+    fn set_context(&mut self, _context: RunContext) {}
+    /// Whether the observations have ids in the memory of the agent, which its final answers cite.
+    fn cites_sources(&self) -> bool {
+        false
+    }
+    /// The final answer of the last run, with the observations it cites.
+    fn answer(&self) -> Option<Answer> {
+        self.get_logs().iter().rev().find_map(|step| match step {
+            Step::ActionStep(step) => step.final_answer.clone(),
+            _ => None,
+        })
+    }
+    /// The id of the current run, or of the last run once it is over. It is in every step of the run, in the
+    /// attributes of its spans and in the fields of its log lines.
+    fn get_run_id(&self) -> Option<String> {
+        None
+    }
+    /// Called at the start of every run with a new id.
+    fn set_run_id(&mut self, _run_id: String) {}
+    /// The id of the run of the manager agent, when the agent runs as a managed agent.
+    fn get_parent_run_id(&self) -> Option<String> {
+        None
+    }
+    fn set_parent_run_id(&mut self, _parent_run_id: Option<String>) {}
+    /// Where the agent is in a hierarchy of managed agents, and the limits of the hierarchy.
+    fn get_delegation(&self) -> Delegation {
+        Delegation::default()
+    }
+    fn set_delegation(&mut self, _delegation: Delegation) {}
+    /// The self-critique of the agent, if it has one.
+    fn get_reflection(&self) -> Option<ReflectionConfig> {
+        None
+    }
+    /// The facts about the environment added to the messages of every step, if the agent has them.
+    fn get_runtime_facts(&self) -> Option<RuntimeFacts> {
+        None
+    }
+    /// Whether tool calls are only recorded, not made. Agents that cannot hold back their tool calls always return
+    /// `false`.
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+    fn set_dry_run(&mut self, _dry_run: bool) {}
+    /// Sets where the chunks of the model calls are sent, see [`AgentStream::stream_events`]. Agents that do not
+    /// override it send no chunks.
+    #[cfg(feature = "stream")]
+    fn set_chunk_sink(&mut self, _sink: Option<ChunkSink>) {}
+    /// A report of the current run, or of the last run once it is over.
+    fn run_report(&self) -> RunReport {
+        RunReport::from_logs(self.name(), self.get_task(), self.get_logs(), self.get_usage())
+    }
+    /// The tool the model is made to call on the final step. Agents that do not answer through a tool return
+    /// `None`, and the model is asked for a plain text answer.
+    fn get_final_answer_tool(&self) -> Option<ToolInfo> {
+        None
+    }
+    /// Returns [`AgentError::BudgetExceeded`] if the run that started at `started` with the usage counter at
+    /// `start_usage` went over the budget of the agent.
+    fn check_budget(&mut self, start_usage: &Usage, started: Instant) -> Result<(), AgentError> {
+        let Some(budget) = self.get_budget() else {
+            return Ok(());
+        };
+        let usage = self.get_usage().since(start_usage);
+        let elapsed = started.elapsed();
+        match budget.exceeded(&usage, elapsed) {
+            Some(message) => {
+                let logs = self.get_logs_mut().clone();
+                let partial_answer = logs.iter().rev().find_map(|step| match step {
+                    Step::ActionStep(step) => step.llm_output.clone().filter(|o| !o.is_empty()),
+                    _ => None,
+                });
+                Err(AgentError::BudgetExceeded(Box::new(BudgetExceededError {
+                    message,
+                    usage,
+                    cost: budget.cost(&usage),
+                    elapsed,
+                    partial_answer,
+                    logs,
+                })))
+            }
+            None => Ok(()),
+        }
+    }
+    /// Returns [`AgentError::Cancelled`] if the context of the agent was cancelled.
+    fn check_cancelled(&self) -> Result<(), AgentError> {
+        if self.get_context().is_cancelled() {
+            Err(AgentError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+    /// The error of a run that used all of its steps without an answer.
+    fn max_steps_error(&mut self) -> AgentError {
+        AgentError::MaxStepsExceeded(Box::new(MaxStepsExceededError {
+            message: "Max steps reached without final answer".to_string(),
+            max_steps: self.get_max_steps(),
+            logs: self.get_logs_mut().clone(),
+        }))
+    }
     async fn step(&mut self, log_entry: &mut Step) -> Result<Option<AgentStep>, AgentError>;
 
+    /// Asks the model to critique the trajectory and the proposed final `answer`, if the reflection of the agent
+    /// has a review due after the current step. Returns the critique, which the caller adds to the memory as an
+    /// observation, or `None` if the work was accepted or no review was due. `revisions` counts the rejected
+    /// answers of the run.
+    async fn reflect(
+        &mut self,
+        task: &str,
+        answer: Option<&str>,
+        revisions: &mut usize,
+    ) -> Result<Option<Step>, AgentError> {
+        let Some(reflection) = self.get_reflection() else {
+            return Ok(None);
+        };
+        if !reflection.is_due(self.get_step_number(), answer, *revisions) {
+            return Ok(None);
+        }
+        let mut messages = self.write_inner_memory_from_logs(None)?;
+        messages.push(Message::user(&reflection.render_prompt(task, answer)));
+        let response = self
+            .model()
+            .run(messages, self.get_history(), vec![], self.get_generation_config())
+            .await?;
+        self.add_usage(response.get_usage().unwrap_or_default());
+        let Some(critique) = parse_critique(&response.get_response()?) else {
+            return Ok(None);
+        };
+        info!("Reflection: {}", critique);
+        if answer.is_some() {
+            *revisions += 1;
+        }
+        Ok(Some(Step::ActionStep(AgentStep {
+            observations: Some(vec![critique_observation(&critique, answer.is_some())]),
+            run_id: self.get_run_id(),
+            ..AgentStep::new(self.get_step_number(), Some(task.to_string()))
+        })))
+    }
+
     async fn direct_run(&mut self, task: &str) -> Result<String, AgentError> {
         let mut final_answer: Option<String> = None;
+        let mut revisions = 0;
+        let start_usage = self.get_usage();
+        let started = Instant::now();
         while final_answer.is_none() && self.get_step_number() < self.get_max_steps() {
-            let mut step_log = Step::ActionStep(AgentStep::new(self.get_step_number(), Some(task.to_string())));
+            self.check_cancelled()?;
+            self.check_budget(&start_usage, started)?;
+            self.get_delegation().record_step()?;
+            let mut step_log = Step::ActionStep(AgentStep {
+                run_id: self.get_run_id(),
+                ..AgentStep::new(self.get_step_number(), Some(task.to_string()))
+            });
+            let step_usage = self.get_usage();
+            let step_started = Instant::now();
 
             if let Some(planning_interval) = self.get_planning_interval() {
                 if self.get_step_number() % planning_interval == 1 {
                     self.planning_step(task, self.get_step_number() == 1, self.get_step_number())
                         .await
-                        .unwrap();
+                        .map_err(|e| AgentError::Execution(e.to_string()))?;
                 }
             }
 
+            let mut step_answer = None;
             if let Some(step) = self.step(&mut step_log).await? {
-                final_answer = step.final_answer;
+                step_answer = step.final_answer;
+            }
+            if let Step::ActionStep(step) = &mut step_log {
+                let usage = self.get_usage().since(&step_usage);
+                step.usage = Some(usage);
+                step.duration = Some(step_started.elapsed());
+                self.get_hooks()
+                    .on_step_end(step, &usage, step_started.elapsed())
+                    .await?;
             }
             self.get_logs_mut().push(step_log);
+            match self.reflect(task, step_answer.as_deref(), &mut revisions).await? {
+                Some(critique) => self.get_logs_mut().push(critique),
+                None => final_answer = step_answer.map(String::from),
+            }
             self.increment_step_number();
         }
 
         if final_answer.is_none() && self.get_step_number() >= self.get_max_steps() {
+            self.check_cancelled()?;
+            self.check_budget(&start_usage, started)?;
             final_answer = self.provide_final_answer(task).await?;
+            if let Some(answer) = &final_answer {
+                let answer = if self.cites_sources() {
+                    Answer::with_citations_from(answer.as_str(), self.get_logs())
+                } else {
+                    Answer::new(answer.as_str())
+                };
+                let step_log =
+                    final_answer_step(self.get_step_number(), self.get_run_id(), answer);
+                self.get_logs_mut().push(step_log);
+            }
         }
         info!(
             "Final answer: {}",
@@ -70,10 +393,57 @@ pub trait Agent: Send + Sync {
                 .clone()
                 .unwrap_or("Could not find answer".to_string())
         );
-        Ok(final_answer.unwrap_or_else(|| "Max steps reached without final answer".to_string()))
+        match final_answer {
+            Some(answer) => Ok(answer),
+            None => Err(self.max_steps_error()),
+        }
+    }
+
+    /// Runs the agent on `task` without calling its tools, and returns the tool calls it would make. The model is
+    /// told that each call was not made and goes on as if it had worked. Managed agents are not run either.
+    async fn dry_run(&mut self, task: &str) -> Result<DryRun, RunError> {
+        let dry_run = self.is_dry_run();
+        self.set_dry_run(true);
+        let result = self.run(task, true).await;
+        self.set_dry_run(dry_run);
+        Ok(DryRun::from_logs(self.get_logs(), result?))
+    }
+
+    /// Runs the agent on a conversation whose state is kept by the caller, such as a chat stored in a database, and
+    /// returns the messages the run adds to it: the tool calls, their results and the reply of the agent.
+    ///
+    /// The last message is the task and must come from the user. The messages before it, with their tool calls and
+    /// results, are sent as the history of this run only; the history of the agent is left as it was. System
+    /// messages are left out, since the agent has its own system prompt. As with [`run`](Agent::run), the steps of
+    /// the run are in the logs of the agent afterwards.
+    async fn run_with_messages(&mut self, messages: &[Message]) -> Result<Vec<Message>, RunError> {
+        let (task, earlier) = match messages.split_last() {
+            Some((task, earlier)) if task.role == MessageRole::User => (task, earlier),
+            _ => {
+                return Err(RunError {
+                    error: AgentError::Execution(
+                        "The last message must be a user message with the task".to_string(),
+                    ),
+                    logs: vec![],
+                    usage: Usage::default(),
+                })
+            }
+        };
+        let history = earlier
+            .iter()
+            .filter(|message| message.role != MessageRole::System)
+            .cloned()
+            .collect::<Vec<_>>();
+        let agent_history = self.get_history();
+        self.set_history((!history.is_empty()).then_some(history));
+        let result = self.run(&task.content, true).await;
+        self.set_history(agent_history);
+        let answer = result?;
+        Ok(run_messages(self.get_logs(), &answer))
     }
 
-    async fn run(&mut self, task: &str, reset: bool) -> Result<String, AgentError> {
+    /// Runs the agent on `task`. When the run fails, the error comes with the steps the agent took until then.
+    async fn run(&mut self, task: &str, reset: bool) -> Result<String, RunError> {
         self.set_task(task);
         self.set_step_number(1);
         let system_prompt_step = Step::SystemPromptStep(self.get_system_prompt().to_string());
@@ -92,30 +462,87 @@ pub trait Agent: Send + Sync {
         self.set_task(task);
         self.set_step_number(1);
 
-        self.direct_run(task).await
+        self.get_delegation().start_run();
+        let run_id = nanoid::nanoid!(12);
+        self.set_run_id(run_id.clone());
+        let (cx, log_span) = run_span(self.name(), &run_id, self.get_parent_run_id());
+        let _slot = self.get_delegation().acquire_slot().await;
+
+        let hooks = self.get_hooks();
+        let start_usage = self.get_usage();
+        let started = Instant::now();
+        let result = async {
+            match hooks.on_run_start(self.name(), task).await {
+                Ok(()) => {
+                    let result = self.direct_run(task).await;
+                    let usage = self.get_usage().since(&start_usage);
+                    hooks
+                        .on_run_end(&result, &usage, started.elapsed())
+                        .await
+                        .and(result)
+                }
+                Err(e) => Err(e),
+            }
+        }
+        .with_context(cx.clone())
+        .instrument(log_span)
+        .await;
+        cx.span().end();
+        result.map_err(|error| RunError {
+            error,
+            logs: self.get_logs_mut().clone(),
+            usage: self.get_usage().since(&start_usage),
+        })
     }
 
+    /// Runs the final step once the agent has used all of its steps: the model is told to answer now and, if the
+    /// agent has a final answer tool, made to call it. Returns `None` if the model still gives no answer.
     async fn provide_final_answer(&mut self, task: &str) -> Result<Option<String>, AgentError> {
-        let mut input_messages = vec![Message {
-            role: MessageRole::User,
-            content: "An agent tried to answer a user query but it got stuck and failed to do so. You are tasked with providing an answer instead. Here is the agent's memory:".to_string(),
-            tool_call_id: None,
-            tool_calls: None,
-        }];
-
-        input_messages.extend(self.write_inner_memory_from_logs(Some(false))?[1..].to_vec());
+        let mut input_messages = self.write_inner_memory_from_logs(None)?;
         input_messages.push(Message {
             role: MessageRole::User,
-            content: format!("Based on the above, please provide an answer to the following user request: \n```\n{}", task),
+            content: format!(
+                "{}\n\nThe task was:\n```\n{}\n```",
+                self.get_final_step_prompt(),
+                task
+            ),
             tool_call_id: None,
             tool_calls: None,
+            parts: vec![],
+            name: None,
         });
+        let mut config = self.get_generation_config();
+        let tools = match self.get_final_answer_tool() {
+            Some(tool) => {
+                config.tool_choice = Some(ToolChoice::Tool(tool.function.name.clone()));
+                vec![tool]
+            }
+            None => vec![],
+        };
+        let final_answer_tool = tools.first().map(|tool| tool.function.name.clone());
         let response = self
             .model()
-            .run(input_messages, None, vec![], None, None)
-            .await?
-            .get_response()?;
-        Ok(Some(response))
+            .run(input_messages, self.get_history(), tools, config)
+            .await?;
+        self.add_usage(response.get_usage().unwrap_or_default());
+
+        let answer = response
+            .get_tools_used()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|tool_call| Some(&tool_call.function.name) == final_answer_tool.as_ref())
+            .and_then(|tool_call| match &tool_call.function.arguments["answer"] {
+                serde_json::Value::String(answer) => Some(answer.clone()),
+                serde_json::Value::Null => None,
+                answer => Some(answer.to_string()),
+            });
+        match answer {
+            Some(answer) => Ok(Some(answer)),
+            None => {
+                let answer = response.get_response()?;
+                Ok((!answer.trim().is_empty()).then_some(answer))
+            }
+        }
     }
 
     fn write_inner_memory_from_logs(
@@ -124,6 +551,12 @@ pub trait Agent: Send + Sync {
     ) -> Result<Vec<Message>, AgentError> {
         let mut memory = Vec::new();
         let summary_mode = summary_mode.unwrap_or(false);
+        let context = self.get_context();
+        let cites_sources = self.cites_sources();
+        let facts = self
+            .get_runtime_facts()
+            .map(|facts| (facts.placement, facts.render()))
+            .filter(|(_, facts)| !facts.is_empty());
         for log in self.get_logs_mut() {
             match log {
                 Step::ToolCall(_) => {}
@@ -134,6 +567,8 @@ pub trait Agent: Send + Sync {
                             content: "[FACTS]:\n".to_owned() + facts.as_str(),
                             tool_call_id: None,
                             tool_calls: None,
+                            parts: vec![],
+                            name: None,
                         });
                     }
                     memory.push(Message {
@@ -141,23 +576,36 @@ pub trait Agent: Send + Sync {
                         content: "[PLAN]:\n".to_owned() + plan.as_str(),
                         tool_call_id: None,
                         tool_calls: None,
+                        parts: vec![],
+                        name: None,
                     });
                 }
                 Step::TaskStep(task) => {
                     memory.push(Message {
                         role: MessageRole::User,
-                        content: "New Task: ".to_owned() + task.as_str(),
+                        content: "New Task: ".to_owned() + &context.render(task),
                         tool_call_id: None,
                         tool_calls: None,
+                        parts: vec![],
+                        name: None,
                     });
                 }
                 Step::SystemPromptStep(prompt) => {
-                    memory.push(Message {
-                        role: MessageRole::System,
-                        content: prompt.to_string(),
-                        tool_call_id: None,
-                        tool_calls: None,
-                    });
+                    let mut content = context.render(prompt);
+                    if cites_sources {
+                        content = format!("{}\n\n{}", content, CITATION_PROMPT);
+                    }
+                    match &facts {
+                        Some((FactsPlacement::SystemPrompt, facts)) => {
+                            content = format!("{}\n\n{}", content, facts);
+                            memory.push(Message::system(&content));
+                        }
+                        Some((FactsPlacement::Message, facts)) => {
+                            memory.push(Message::system(&content));
+                            memory.push(Message::user(facts));
+                        }
+                        None => memory.push(Message::system(&content)),
+                    }
                 }
                 Step::ActionStep(step_log) => {
                     if step_log.llm_output.is_some() && !summary_mode {
@@ -182,14 +630,30 @@ pub trait Agent: Send + Sync {
                             content: llm_output,
                             tool_call_id: None,
                             tool_calls: step_log.tool_call.clone(),
+                            parts: vec![],
+                            name: None,
                         });
                     }
 
                     if let (Some(tool_calls), Some(observations)) =
                         (&step_log.tool_call, &step_log.observations)
                     {
+                        // Tool messages can only hold text, so images in the observations are sent in a user
+                        // message after the tool messages.
+                        let mut images = Vec::new();
                         for (i, tool_call) in tool_calls.iter().enumerate() {
-                            let message_content = format!("Observation: {}", observations[i]);
+                            let (observation, observation_images) =
+                                split_image_data_urls(&observations[i]);
+                            images.extend(observation_images);
+                            let message_content = if cites_sources {
+                                format!(
+                                    "Observation [{}]: {}",
+                                    observation_id(step_log.step, i + 1),
+                                    observation
+                                )
+                            } else {
+                                format!("Observation: {}", observation)
+                            };
 
                             let id = if tool_call.id.is_some() {
                                 if tool_call.id.as_ref().unwrap().is_empty() {
@@ -206,6 +670,8 @@ pub trait Agent: Send + Sync {
                                 content: message_content,
                                 tool_call_id: id,
                                 tool_calls: None,
+                                parts: vec![],
+                                name: None,
                             });
 
                             // if let Some(task) = &step_log.task {
@@ -217,12 +683,27 @@ pub trait Agent: Send + Sync {
                             //     });
                             // }
                         }
+                        if !images.is_empty() {
+                            memory.push(Message {
+                                role: MessageRole::User,
+                                content: "Here are the images from the observations above."
+                                    .to_string(),
+                                tool_call_id: None,
+                                tool_calls: None,
+                                parts: images,
+                                name: None,
+                            });
+                        }
                     } else if let Some(observations) = &step_log.observations {
+                        let (observations, images) =
+                            split_image_data_urls(&observations.join("\n"));
                         memory.push(Message {
                             role: MessageRole::User,
-                            content: format!("Observations: {}", observations.join("\n")),
+                            content: format!("Observations: {}", observations),
                             tool_call_id: None,
                             tool_calls: None,
+                            parts: images,
+                            name: None,
                         });
                     }
                     if step_log.error.is_some() {
@@ -235,18 +716,73 @@ pub trait Agent: Send + Sync {
                             content: error_string,
                             tool_call_id: None,
                             tool_calls: None,
+                            parts: vec![],
+                            name: None,
                         });
                     }
                 }
             }
         }
+
+        let model = self.model();
+        if let Some(context_length) = model.context_length() {
+            let tokenizer = model.tokenizer();
+            let reserved = self
+                .get_generation_config()
+                .max_tokens
+                .unwrap_or(ANSWER_TOKENS.min(context_length / 4))
+                + tokenizer.count_messages_tokens(&self.get_history().unwrap_or_default());
+            let compacted = fit_to_context(
+                &mut memory,
+                tokenizer.as_ref(),
+                context_length.saturating_sub(reserved),
+            );
+            if compacted > 0 {
+                tracing::warn!(
+                    compacted,
+                    context_length,
+                    "Compacted the agent memory to fit the context window"
+                );
+            }
+        }
         Ok(memory)
     }
 }
 
+/// What a streamed run sends: the chunks of the model as they arrive, and each step once it is done.
+#[cfg(feature = "stream")]
+#[derive(Debug, Clone)]
+pub enum AgentEvent {
+    /// A chunk of the answer of the model in the step that is running: its text, or a tool call as it is written.
+    Chunk(ChatChunk),
+    /// A step that is done, as [`AgentStream::stream_run`] sends it.
+    Step(Step),
+}
+
+/// The next of the chunks of a step and the end of the step.
+#[cfg(feature = "stream")]
+enum Polled<T> {
+    Chunk(ChatChunk),
+    Done(T),
+}
+
 #[cfg(feature = "stream")]
 pub trait AgentStream: Agent {
+    /// Runs the agent on `task` and streams its steps once they are done.
     fn stream_run<'a>(&'a mut self, task: &'a str, reset: bool) -> StreamResult<'a, Step> {
+        let events = self.stream_events(task, reset)?;
+        Ok(Box::pin(events.filter_map(|event| async move {
+            match event {
+                Ok(AgentEvent::Step(step)) => Some(Ok(step)),
+                Ok(AgentEvent::Chunk(_)) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })))
+    }
+
+    /// Runs the agent on `task` and streams the model calls of its steps as [`AgentEvent::Chunk`]s, while the step
+    /// runs, followed by the step itself once it is done.
+    fn stream_events<'a>(&'a mut self, task: &'a str, reset: bool) -> StreamResult<'a, AgentEvent> {
         let system_prompt_step = Step::SystemPromptStep(self.get_system_prompt().to_string());
         if reset {
             self.get_logs_mut().clear();
@@ -262,19 +798,57 @@ pub trait AgentStream: Agent {
         self.get_logs_mut().push(Step::TaskStep(task.to_string()));
         self.set_task(task);
         self.set_step_number(1);
+        let run_id = nanoid::nanoid!(12);
+        self.set_run_id(run_id.clone());
+        self.get_delegation().start_run();
+        let (cx, log_span) = run_span(self.name(), &run_id, self.get_parent_run_id());
 
         let mut final_answer: Option<String> = None;
+        let mut revisions = 0;
+        let mut run_error: Option<AgentError> = None;
+        let start_usage = self.get_usage();
+        let started = Instant::now();
+        let hooks = self.get_hooks();
+
+        let (sink, mut chunks) = futures::channel::mpsc::unbounded();
+        self.set_chunk_sink(Some(sink));
 
         let stream = async_stream::stream! {
+            if let Err(e) = hooks.on_run_start(self.name(), task).await {
+                self.set_chunk_sink(None);
+                yield Err(e.into());
+                return;
+            }
             while final_answer.is_none() && self.get_step_number() < self.get_max_steps() {
-                let mut step_log = Step::ActionStep(AgentStep::new(self.get_step_number(), Some(task.to_string())));
+                if let Err(e) = self.check_cancelled() {
+                    run_error = Some(e.clone());
+                    yield Err(e.into());
+                    break;
+                }
+                if let Err(e) = self.check_budget(&start_usage, started) {
+                    run_error = Some(e.clone());
+                    yield Err(e.into());
+                    break;
+                }
+                if let Err(e) = self.get_delegation().record_step() {
+                    run_error = Some(e.clone());
+                    yield Err(e.into());
+                    break;
+                }
+                let mut step_log = Step::ActionStep(AgentStep {
+                    run_id: self.get_run_id(),
+                    ..AgentStep::new(self.get_step_number(), Some(task.to_string()))
+                });
+                let step_usage = self.get_usage();
+                let step_started = Instant::now();
 
                 if let Some(planning_interval) = self.get_planning_interval() {
                     if self.get_step_number() % planning_interval == 1 {
                         match self.planning_step(task, self.get_step_number() == 1, self.get_step_number()).await {
-                            Ok(Some(step)) => yield Ok(step),
+                            Ok(Some(step)) => yield Ok(AgentEvent::Step(step)),
                             Ok(None) => {},
                             Err(e) => {
+                                run_error = Some(AgentError::Execution(e.to_string()));
                                 yield Err(e);
                                 break;
                             }
@@ -282,17 +856,57 @@ pub trait AgentStream: Agent {
                     }
                 }
 
-                match self.step(&mut step_log).await {
+                // The chunks are sent while the step runs, so they are read as they arrive rather than after the step
+                let result = {
+                    let mut step = self.step(&mut step_log);
+                    loop {
+                        let polled = futures::future::poll_fn(|cx| {
+                            if let Poll::Ready(Some(chunk)) = chunks.poll_next_unpin(cx) {
+                                return Poll::Ready(Polled::Chunk(chunk));
+                            }
+                            step.as_mut().poll(cx).map(Polled::Done)
+                        })
+                        .await;
+                        match polled {
+                            Polled::Chunk(chunk) => yield Ok(AgentEvent::Chunk(chunk)),
+                            Polled::Done(result) => break result,
+                        }
+                    }
+                };
+                while let Some(Some(chunk)) = futures::FutureExt::now_or_never(chunks.next()) {
+                    yield Ok(AgentEvent::Chunk(chunk));
+                }
+                match result {
                     Ok(Some(step)) => {
+                        if let Step::ActionStep(step) = &mut step_log {
+                            let usage = self.get_usage().since(&step_usage);
+                            step.usage = Some(usage);
+                            step.duration = Some(step_started.elapsed());
+                            if let Err(e) = hooks.on_step_end(step, &usage, step_started.elapsed()).await {
+                                run_error = Some(e.clone());
+                                yield Err(e.into());
+                                break;
+                            }
+                        }
                         self.get_logs_mut().push(step_log.clone());
-                        self.increment_step_number();
-                        if let Some(answer) = step.final_answer.clone() {
-                            final_answer = Some(answer);
+                        yield Ok(AgentEvent::Step(step_log));
+                        match self.reflect(task, step.final_answer.as_deref(), &mut revisions).await {
+                            Ok(Some(critique)) => {
+                                self.get_logs_mut().push(critique.clone());
+                                yield Ok(AgentEvent::Step(critique));
+                            }
+                            Ok(None) => final_answer = step.final_answer.clone().map(String::from),
+                            Err(e) => {
+                                run_error = Some(e.clone());
+                                yield Err(e.into());
+                                break;
+                            }
                         }
-                        yield Ok(step_log);
+                        self.increment_step_number();
                     }
                     Ok(None) => {},
                     Err(e) => {
+                        run_error = Some(e.clone());
                         yield Err(e.into());
                         break;
                     }
@@ -300,20 +914,59 @@ pub trait AgentStream: Agent {
             }
 
             if final_answer.is_none() && self.get_step_number() >= self.get_max_steps() {
-                match self.provide_final_answer(task).await {
+                let answer = match self
+                    .check_cancelled()
+                    .and_then(|()| self.check_budget(&start_usage, started))
+                {
+                    Ok(()) => self.provide_final_answer(task).await,
+                    Err(e) => Err(e),
+                };
+                match answer {
                     Ok(Some(answer)) => {
-                        yield Ok(Step::ActionStep(AgentStep {
-                            final_answer: Some(answer),
-                            step: self.get_step_number(),
-                            ..Default::default()
-                        }));
+                        let cited = if self.cites_sources() {
+                            Answer::with_citations_from(answer.as_str(), self.get_logs())
+                        } else {
+                            Answer::new(answer.as_str())
+                        };
+                        let step_log = final_answer_step(self.get_step_number(), self.get_run_id(), cited);
+                        final_answer = Some(answer);
+                        self.get_logs_mut().push(step_log.clone());
+                        yield Ok(AgentEvent::Step(step_log));
                     }
                     Ok(None) => {},
-                    Err(e) => yield Err(e.into()),
+                    Err(e) => {
+                        run_error = Some(e.clone());
+                        yield Err(e.into());
+                    }
                 }
             }
+
+            let result = match (run_error, final_answer) {
+                (Some(e), _) => Err(e),
+                (None, Some(answer)) => Ok(answer),
+                (None, None) => {
+                    let e = self.max_steps_error();
+                    yield Err(e.clone().into());
+                    Err(e)
+                }
+            };
+            self.set_chunk_sink(None);
+            let usage = self.get_usage().since(&start_usage);
+            if let Err(e) = hooks.on_run_end(&result, &usage, started.elapsed()).await {
+                yield Err(e.into());
+            }
         };
 
-        Ok(Box::pin(stream))
+        // The run is in the spans of the run while the stream is polled, like a run that is not streamed
+        let mut stream = Box::pin(stream);
+        Ok(Box::pin(futures::stream::poll_fn(move |task_cx| {
+            let _attached = cx.clone().attach();
+            let _entered = log_span.enter();
+            let polled = stream.as_mut().poll_next(task_cx);
+            if let Poll::Ready(None) = polled {
+                cx.span().end();
+            }
+            polled
+        })))
     }
 }
@@ -0,0 +1,251 @@
+//! Final answers with the observations they are based on.
+//!
+//! Agents built `with_citations` see every observation with an id, `[<step>.<call>]` such as `[2.1]` for the
+//! result of the first tool call of step 2, and are told to cite the ids of the observations that their answer
+//! relies on. The ids cited in the final answer become the [`Citation`]s of the [`Answer`] in
+//! [`AgentStep::final_answer`](super::AgentStep::final_answer), with the tool, the tool call id and the text of the
+//! observation, so an answer can be traced back to its sources.
+//!
+//! ```rust,ignore
+//! let mut agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_tools(vec![Box::new(DuckDuckGoSearchTool::new())])
+//!     .with_citations(true)
+//!     .build()?;
+//! agent.run("When was the Evoluon in Eindhoven opened?", true).await?;
+//! for citation in agent.answer().map(|answer| answer.citations).unwrap_or_default() {
+//!     println!("[{}] {}: {}", citation.id, citation.tool, citation.observation);
+//! }
+//! ```
+
+use std::fmt;
+use std::ops::Deref;
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::agent_step::Step;
+
+/// Added to the system prompt of agents that cite their sources.
+pub const CITATION_PROMPT: &str = "Every observation starts with its id in square brackets, like [2.1]. When your \
+final answer uses information from observations, cite their ids after the sentences that use them, for example \
+\"It opened in 1966 [2.1].\" Only cite ids of observations you were given.";
+
+/// An observation that a final answer cites.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+    /// The id the answer cites, `<step>.<call>`.
+    pub id: String,
+    pub step: usize,
+    /// The id the model gave the tool call, if it gave one.
+    pub tool_call_id: Option<String>,
+    pub tool: String,
+    pub observation: String,
+}
+
+/// The final answer of an agent, with the observations it cites.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Answer {
+    pub text: String,
+    pub citations: Vec<Citation>,
+}
+
+impl Answer {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            citations: vec![],
+        }
+    }
+
+    /// The answer `text` with the observations of `logs` that it cites, in the order they are first cited. Ids
+    /// that are not observations of the current task are left out.
+    pub fn with_citations_from(text: impl Into<String>, logs: &[Step]) -> Self {
+        let text = text.into();
+        let mut citations: Vec<Citation> = vec![];
+        for id in cited_ids(&text) {
+            if citations.iter().any(|citation| citation.id == id) {
+                continue;
+            }
+            if let Some(citation) = find_observation(logs, &id) {
+                citations.push(citation);
+            }
+        }
+        Self { text, citations }
+    }
+}
+
+/// The id of the observation of the `call`-th tool call of `step`, both starting at 1.
+pub fn observation_id(step: usize, call: usize) -> String {
+    format!("{}.{}", step, call)
+}
+
+/// The ids in square brackets in `text`, such as `[2.1]` or `[2.1, 3.2]`.
+fn cited_ids(text: &str) -> Vec<String> {
+    let pattern = Regex::new(r"\[(\d+\.\d+(?:\s*[,;]\s*\d+\.\d+)*)\]").unwrap();
+    pattern
+        .captures_iter(text)
+        .flat_map(|captures| {
+            captures[1]
+                .split([',', ';'])
+                .map(|id| id.trim().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn find_observation(logs: &[Step], id: &str) -> Option<Citation> {
+    let (step, call) = id.split_once('.')?;
+    let (step, call) = (step.parse::<usize>().ok()?, call.parse::<usize>().ok()?);
+    for log in logs.iter().rev() {
+        match log {
+            Step::ActionStep(action) if action.step == step => {
+                let tool_call = action.tool_call.as_ref()?.get(call.checked_sub(1)?)?;
+                let observation = action.observations.as_ref()?.get(call - 1)?;
+                return Some(Citation {
+                    id: id.to_string(),
+                    step,
+                    tool_call_id: tool_call.id.clone().filter(|id| !id.is_empty()),
+                    tool: tool_call.function.name.clone(),
+                    observation: observation.clone(),
+                });
+            }
+            Step::TaskStep(_) => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+impl Deref for Answer {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl fmt::Display for Answer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl From<String> for Answer {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<&str> for Answer {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<Answer> for String {
+    fn from(answer: Answer) -> Self {
+        answer.text
+    }
+}
+
+/// An answer without citations is written as its text, as final answers were before they had citations.
+impl Serialize for Answer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Cited<'a> {
+            text: &'a str,
+            citations: &'a [Citation],
+        }
+        if self.citations.is_empty() {
+            serializer.serialize_str(&self.text)
+        } else {
+            Cited {
+                text: &self.text,
+                citations: &self.citations,
+            }
+            .serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Answer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Written {
+            Text(String),
+            Cited {
+                text: String,
+                #[serde(default)]
+                citations: Vec<Citation>,
+            },
+        }
+        Ok(match Written::deserialize(deserializer)? {
+            Written::Text(text) => Self::new(text),
+            Written::Cited { text, citations } => Self { text, citations },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::AgentStep,
+        models::openai::{FunctionCall, ToolCall},
+    };
+    use serde_json::json;
+
+    fn call(id: &str, name: &str) -> ToolCall {
+        ToolCall {
+            id: Some(id.to_string()),
+            call_type: Some("function".to_string()),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: json!({}),
+            },
+        }
+    }
+
+    #[test]
+    fn test_answer_citations() {
+        let logs = vec![
+            Step::TaskStep("When was the Evoluon opened?".to_string()),
+            Step::ActionStep(AgentStep {
+                tool_call: Some(vec![call("call_1", "search"), call("call_2", "visit")]),
+                observations: Some(vec![
+                    "The Evoluon is in Eindhoven".to_string(),
+                    "It opened in 1966".to_string(),
+                ]),
+                ..AgentStep::new(1, None)
+            }),
+        ];
+        let answer = Answer::with_citations_from(
+            "The Evoluon in Eindhoven [1.1] opened in 1966 [1.2; 1.1]. See also [3.1] and [1.9].",
+            &logs,
+        );
+        assert_eq!(&*answer, answer.text);
+        assert_eq!(answer.citations.len(), 2);
+        assert_eq!(answer.citations[0].id, "1.1");
+        assert_eq!(answer.citations[0].tool, "search");
+        assert_eq!(answer.citations[1].tool_call_id.as_deref(), Some("call_2"));
+        assert_eq!(answer.citations[1].observation, "It opened in 1966");
+        let written = serde_json::to_value(&answer).unwrap();
+        assert_eq!(written["citations"][1]["id"], "1.2");
+        assert_eq!(serde_json::from_value::<Answer>(written).unwrap(), answer);
+        assert_eq!(
+            serde_json::to_value(Answer::from("1966")).unwrap(),
+            json!("1966")
+        );
+        assert_eq!(
+            serde_json::from_value::<Answer>(json!("1966")).unwrap(),
+            Answer::new("1966")
+        );
+        assert_eq!(
+            Answer::with_citations_from("[1.1]", &logs[1..])
+                .citations
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Limits on the tokens, dollar cost and wall-clock time of a single run.
+//!
+//! The budget is checked before every step, so a run stops at the first step boundary after a limit is reached.
+//! A step that is already running is not interrupted.
+
+use std::time::Duration;
+
+use crate::models::{pricing::ModelPricing, types::Usage};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Budget {
+    pub max_tokens: Option<usize>,
+    /// Maximum cost in US dollars. Only enforced when `pricing` is set.
+    pub max_cost: Option<f64>,
+    pub pricing: Option<ModelPricing>,
+    pub max_duration: Option<Duration>,
+}
+
+impl Budget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Limits the dollar cost of a run. The price of a known model is in
+    /// [`Model::info`](crate::models::model_traits::Model::info), or can be looked up with
+    /// [`pricing_for_model`](crate::models::pricing::pricing_for_model).
+    pub fn with_max_cost(mut self, max_cost: f64, pricing: ModelPricing) -> Self {
+        self.max_cost = Some(max_cost);
+        self.pricing = Some(pricing);
+        self
+    }
+
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    pub fn cost(&self, usage: &Usage) -> Option<f64> {
+        self.pricing.map(|pricing| pricing.cost(usage))
+    }
+
+    /// Returns a description of the first limit that `usage` and `elapsed` go over, if any.
+    pub fn exceeded(&self, usage: &Usage, elapsed: Duration) -> Option<String> {
+        if let Some(max_tokens) = self.max_tokens {
+            if usage.total_tokens() >= max_tokens {
+                return Some(format!(
+                    "Token budget exceeded: used {} of {} tokens",
+                    usage.total_tokens(),
+                    max_tokens
+                ));
+            }
+        }
+        if let (Some(max_cost), Some(cost)) = (self.max_cost, self.cost(usage)) {
+            if cost >= max_cost {
+                return Some(format!(
+                    "Cost budget exceeded: spent ${:.4} of ${:.4}",
+                    cost, max_cost
+                ));
+            }
+        }
+        if let Some(max_duration) = self.max_duration {
+            if elapsed >= max_duration {
+                return Some(format!(
+                    "Time budget exceeded: ran for {:.1}s of {:.1}s",
+                    elapsed.as_secs_f64(),
+                    max_duration.as_secs_f64()
+                ));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_exceeded() {
+        let budget = Budget::new()
+            .with_max_tokens(1000)
+            .with_max_cost(0.01, ModelPricing::new(1.0, 10.0))
+            .with_max_duration(Duration::from_secs(60));
+
+        assert!(budget
+            .exceeded(&Usage::new(100, 100), Duration::from_secs(1))
+            .is_none());
+        assert!(budget
+            .exceeded(&Usage::new(900, 100), Duration::from_secs(1))
+            .unwrap()
+            .starts_with("Token budget"));
+        let budget = budget.with_max_cost(0.001, ModelPricing::new(1.0, 10.0));
+        assert!(budget
+            .exceeded(&Usage::new(100, 100), Duration::from_secs(1))
+            .unwrap()
+            .starts_with("Cost budget"));
+        assert!(Budget::new()
+            .with_max_duration(Duration::from_secs(60))
+            .exceeded(&Usage::default(), Duration::from_secs(61))
+            .unwrap()
+            .starts_with("Time budget"));
+    }
+}
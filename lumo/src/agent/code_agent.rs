@@ -1,31 +1,50 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use opentelemetry::trace::{FutureExt, TraceContextExt};
-use std::{collections::HashMap, mem::ManuallyDrop};
+use std::{mem::ManuallyDrop, sync::Arc};
 use tracing::{instrument, Span};
 
 use crate::{
+    artifacts::ArtifactStore,
+    config::{AgentConfig, ConfiguredModel},
+    context::RunContext,
     errors::{AgentError, InterpreterError},
     local_python_interpreter::LocalPythonInterpreter,
     models::{
+        conversation::{validate_messages, Conversation},
         model_traits::Model,
         openai::{FunctionCall, ToolCall},
-        types::Message,
+        prefill::Prefill,
+        types::{GenerationConfig, Message, MessageRole, Usage},
     },
-    prompts::CODE_SYSTEM_PROMPT,
+    prompts::{parse_retry_prompt, PromptSection, PromptTemplate, CODE_SYSTEM_PROMPT},
+    sandbox::{interpreter_result, with_final_answer, SandboxBackend},
     telemetry::AgentTelemetry,
-    tools::{AsyncTool, FinalAnswerTool},
+    tools::{AgentIo, AskUserTool, AsyncTool, FinalAnswerTool, ReadArtifactTool, ToolRetryPolicy},
 };
 
-use super::{agent_step::Step, agent_trait::Agent, multistep_agent::MultiStepAgent, AgentStep};
+use super::{
+    agent_step::Step,
+    agent_trait::Agent,
+    budget::Budget,
+    delegation::{Delegation, DelegationLimits},
+    dry_run::{calls_tool, dry_run_code_observation},
+    hooks::{AgentHook, AgentHooks},
+    loop_detector::LoopDetector,
+    multistep_agent::{MultiStepAgent, DEFAULT_MAX_OBSERVATION_SIZE},
+    reflection::ReflectionConfig,
+    runtime_facts::RuntimeFacts,
+    AgentStep,
+};
 
 #[cfg(feature = "stream")]
-use super::agent_trait::AgentStream;
+use {super::agent_trait::AgentStream, crate::models::stream::ChunkSink};
 
 #[cfg(feature = "code-agent")]
 pub struct CodeAgent<M: Model> {
     base_agent: MultiStepAgent<M>,
     local_python_interpreter: ManuallyDrop<LocalPythonInterpreter>,
+    sandbox: Option<Arc<dyn SandboxBackend>>,
     telemetry: AgentTelemetry,
 }
 
@@ -67,11 +86,19 @@ impl<M: Model + Send + Sync + 'static> CodeAgent<M> {
         Ok(Self {
             base_agent,
             local_python_interpreter: ManuallyDrop::new(local_python_interpreter),
+            sandbox: None,
             telemetry: AgentTelemetry::new("lumo"),
         })
     }
 }
 
+impl CodeAgent<ConfiguredModel> {
+    /// Builds the agent and its managed agents from an [`AgentConfig`].
+    pub fn from_config(config: &AgentConfig) -> Result<Self> {
+        config.code_builder()?.build()
+    }
+}
+
 pub struct CodeAgentBuilder<'a, M: Model> {
     name: Option<&'a str>,
     model: M,
@@ -83,6 +110,25 @@ pub struct CodeAgentBuilder<'a, M: Model> {
     planning_interval: Option<usize>,
     history: Option<Vec<Message>>,
     logging_level: Option<log::LevelFilter>,
+    hooks: Vec<Arc<dyn AgentHook>>,
+    generation_config: Option<GenerationConfig>,
+    parse_retry: usize,
+    budget: Option<Budget>,
+    max_observation_size: Option<usize>,
+    artifact_store: Option<Arc<dyn ArtifactStore>>,
+    tool_retry: Option<ToolRetryPolicy>,
+    io: Option<Arc<dyn AgentIo>>,
+    prompt_template: Option<PromptTemplate>,
+    final_step_prompt: Option<&'a str>,
+    stop_sequences: Option<Vec<String>>,
+    context: Option<RunContext>,
+    reflection: Option<ReflectionConfig>,
+    runtime_facts: Option<RuntimeFacts>,
+    prefill: Option<Prefill>,
+    loop_detector: Option<LoopDetector>,
+    citations: bool,
+    delegation_limits: Option<DelegationLimits>,
+    sandbox: Option<Arc<dyn SandboxBackend>>,
 }
 
 impl<'a, M: Model + Send + Sync + 'static> CodeAgentBuilder<'a, M> {
@@ -98,6 +144,25 @@ impl<'a, M: Model + Send + Sync + 'static> CodeAgentBuilder<'a, M> {
             planning_interval: None,
             history: None,
             logging_level: None,
+            hooks: vec![],
+            generation_config: None,
+            parse_retry: 0,
+            budget: None,
+            max_observation_size: None,
+            artifact_store: None,
+            tool_retry: None,
+            io: None,
+            prompt_template: None,
+            final_step_prompt: None,
+            stop_sequences: None,
+            context: None,
+            reflection: None,
+            runtime_facts: None,
+            prefill: None,
+            loop_detector: None,
+            citations: false,
+            delegation_limits: None,
+            sandbox: None,
         }
     }
     pub fn with_name(mut self, name: Option<&'a str>) -> Self {
@@ -112,6 +177,82 @@ impl<'a, M: Model + Send + Sync + 'static> CodeAgentBuilder<'a, M> {
         self.system_prompt = system_prompt;
         self
     }
+    /// Builds the system prompt from `prompt_template`. Ignored when a system prompt is set with
+    /// `with_system_prompt`.
+    pub fn with_prompt_template(mut self, prompt_template: Option<PromptTemplate>) -> Self {
+        self.prompt_template = prompt_template;
+        self
+    }
+    /// Replaces one section of the default system prompt, or of the template set with `with_prompt_template`.
+    pub fn with_prompt_section(mut self, section: PromptSection, text: &str) -> Self {
+        let template = self
+            .prompt_template
+            .take()
+            .unwrap_or_else(PromptTemplate::code);
+        self.prompt_template = Some(template.with_section(section, text));
+        self
+    }
+    /// The message that tells the model to give its answer once the agent has used all of its steps.
+    pub fn with_final_step_prompt(mut self, final_step_prompt: Option<&'a str>) -> Self {
+        self.final_step_prompt = final_step_prompt;
+        self
+    }
+    /// Replaces the stop sequences of the action steps, which default to `Observation:`. An empty list sends no stop
+    /// sequences, for chat templates that break on them.
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.stop_sequences = Some(stop_sequences);
+        self
+    }
+    /// The [`RunContext`] shared by the tools of the agent, whose values the prompts can use as
+    /// `{{context.<key>}}`. Keep a clone to seed it before a run and read it afterwards.
+    pub fn with_context(mut self, context: RunContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+    /// Tells the model the current date and time, the timezone and other facts about the environment, which it
+    /// cannot know on its own. The facts are gathered again at every step; see [`RuntimeFacts`] for which ones and
+    /// where they go in the messages.
+    pub fn with_runtime_facts(mut self, runtime_facts: RuntimeFacts) -> Self {
+        self.runtime_facts = Some(runtime_facts);
+        self
+    }
+    /// Starts the answer of the model with a prefill at every step, or at the steps the prefill is given for.
+    pub fn with_prefill(mut self, prefill: Prefill) -> Self {
+        self.prefill = Some(prefill);
+        self
+    }
+    /// Answers a tool call with the earlier result instead of making it, once the task has enough calls like it.
+    pub fn with_loop_detector(mut self, loop_detector: LoopDetector) -> Self {
+        self.loop_detector = Some(loop_detector);
+        self
+    }
+    /// Gives the observations ids and asks the model to cite the ones its final answer uses. The cited
+    /// observations are the citations of the [`Answer`](crate::agent::Answer) of the final step.
+    pub fn with_citations(mut self, citations: bool) -> Self {
+        self.citations = citations;
+        self
+    }
+    /// Makes the agent critique its own work every few steps or before it returns its final answer. A rejected
+    /// final answer is dropped and the critique is added as an observation, so the agent keeps going. Every review
+    /// is an extra model call, counted in the usage of the run.
+    pub fn with_reflection(mut self, reflection: ReflectionConfig) -> Self {
+        self.reflection = Some(reflection);
+        self
+    }
+    /// Limits how deep managed agents can be nested and how many steps they can take together. The limits of the
+    /// top-level agent apply to the whole hierarchy.
+    pub fn with_delegation_limits(mut self, delegation_limits: DelegationLimits) -> Self {
+        self.delegation_limits = Some(delegation_limits);
+        self
+    }
+    /// Runs the code of the agent in a sandbox, such as a [`DockerSandbox`](crate::sandbox::DockerSandbox), instead
+    /// of the local interpreter. Every run gets a new sandbox. The code can only call `final_answer` there, so the
+    /// agent should have no other tools, and variables do not outlive a step, but files do. Keep a clone of the
+    /// sandbox to download the files the code wrote after a run.
+    pub fn with_sandbox(mut self, sandbox: Arc<dyn SandboxBackend>) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
     pub fn with_managed_agents(mut self, managed_agents: Vec<Box<dyn Agent>>) -> Self {
         self.managed_agents = managed_agents;
         self
@@ -132,28 +273,132 @@ impl<'a, M: Model + Send + Sync + 'static> CodeAgentBuilder<'a, M> {
         self.history = history;
         self
     }
+    /// The messages before the task, checked against the rules of the chat APIs when the agent is built.
+    pub fn with_conversation(mut self, conversation: Conversation) -> Self {
+        self.history = Some(conversation.into_messages());
+        self
+    }
     pub fn with_logging_level(mut self, logging_level: Option<log::LevelFilter>) -> Self {
         self.logging_level = logging_level;
         self
     }
+    /// Sampling parameters used for every model call made by the agent.
+    pub fn with_generation_config(mut self, generation_config: Option<GenerationConfig>) -> Self {
+        self.generation_config = generation_config;
+        self
+    }
+    /// When the code in the model response cannot be parsed, send the error back to the model and let it retry up to
+    /// `parse_retry` times before the step fails.
+    pub fn with_parse_retry(mut self, parse_retry: usize) -> Self {
+        self.parse_retry = parse_retry;
+        self
+    }
+    /// Limits the tokens, cost and time of every run. The run fails with [`AgentError::BudgetExceeded`] once a
+    /// limit is reached.
+    pub fn with_budget(mut self, budget: Option<Budget>) -> Self {
+        self.budget = budget;
+        self
+    }
+    /// Observations longer than this many characters are truncated before they are added to the memory. Defaults
+    /// to 30000.
+    pub fn with_max_observation_size(mut self, max_observation_size: Option<usize>) -> Self {
+        self.max_observation_size = max_observation_size;
+        self
+    }
+    /// Saves the full output of truncated observations in `artifact_store` and gives the agent a `read_artifact`
+    /// tool to read them.
+    pub fn with_artifact_store(mut self, artifact_store: Option<Arc<dyn ArtifactStore>>) -> Self {
+        self.artifact_store = artifact_store;
+        self
+    }
+    /// How the tools that the code calls are retried when they time out or are rate limited. Defaults to two
+    /// retries with exponential backoff.
+    pub fn with_tool_retry(mut self, tool_retry: Option<ToolRetryPolicy>) -> Self {
+        self.tool_retry = tool_retry;
+        self
+    }
+    /// Gives the agent an `ask_user` tool, which asks the user a question through `io` and waits for the reply.
+    pub fn with_io(mut self, io: Arc<dyn AgentIo>) -> Self {
+        self.io = Some(io);
+        self
+    }
+    /// Adds a hook that is run on every step. Hooks run in the order they are added.
+    pub fn with_hook(mut self, hook: impl AgentHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+    pub fn with_hooks(mut self, hooks: Vec<Arc<dyn AgentHook>>) -> Self {
+        self.hooks.extend(hooks);
+        self
+    }
     pub fn build(self) -> Result<CodeAgent<M>> {
-        CodeAgent::new(
+        if let Some(history) = &self.history {
+            validate_messages(history)?;
+        }
+        let mut tools = self.tools;
+        if let Some(store) = &self.artifact_store {
+            tools.push(Box::new(ReadArtifactTool::new(store.clone(), None)));
+        }
+        if let Some(io) = &self.io {
+            tools.push(Box::new(AskUserTool::new(io.clone())));
+        }
+        let template_prompt = self
+            .prompt_template
+            .as_ref()
+            .map(|template| template.to_prompt())
+            .transpose()?;
+        let system_prompt = self.system_prompt.or(template_prompt.as_deref());
+        let mut agent = CodeAgent::new(
             self.name,
             self.model,
-            self.tools,
-            self.system_prompt,
+            tools,
+            system_prompt,
             self.managed_agents,
             self.description,
             self.max_steps,
             self.planning_interval,
             self.history,
             self.logging_level,
-        )
+        )?;
+        agent.base_agent.hooks = AgentHooks::new(self.hooks);
+        agent.base_agent.generation_config = self.generation_config.unwrap_or_default();
+        agent.base_agent.parse_retry = self.parse_retry;
+        agent.base_agent.budget = self.budget;
+        agent.base_agent.max_observation_size = self
+            .max_observation_size
+            .unwrap_or(DEFAULT_MAX_OBSERVATION_SIZE);
+        agent.base_agent.artifact_store = self.artifact_store;
+        agent.base_agent.tool_retry = self.tool_retry.unwrap_or_default();
+        if let Some(final_step_prompt) = self.final_step_prompt {
+            agent.base_agent.final_step_prompt = final_step_prompt.to_string();
+        }
+        if let Some(stop_sequences) = self.stop_sequences {
+            agent.base_agent.stop_sequences = stop_sequences;
+        }
+        if let Some(context) = self.context {
+            agent.base_agent.context = context;
+        }
+        agent.base_agent.reflection = self.reflection;
+        agent.base_agent.runtime_facts = self.runtime_facts;
+        agent.base_agent.prefill = self.prefill;
+        agent.base_agent.loop_detector = self.loop_detector;
+        agent.base_agent.citations = self.citations;
+        if let Some(delegation_limits) = self.delegation_limits {
+            agent.base_agent.delegation = Delegation::new(delegation_limits);
+        }
+        if self.sandbox.is_some() && agent.base_agent.tools.len() > 1 {
+            tracing::warn!(
+                "The code of a sandboxed agent cannot call its tools, only final_answer"
+            );
+        }
+        agent.sandbox = self.sandbox;
+        Ok(agent)
     }
 }
 
 #[cfg(feature = "code-agent")]
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl<M: Model + Send + Sync + 'static> Agent for CodeAgent<M> {
     fn name(&self) -> &'static str {
         self.base_agent.name()
@@ -173,6 +418,9 @@ impl<M: Model + Send + Sync + 'static> Agent for CodeAgent<M> {
     fn increment_step_number(&mut self) {
         self.base_agent.increment_step_number()
     }
+    fn get_logs(&self) -> &[Step] {
+        self.base_agent.get_logs()
+    }
     fn get_logs_mut(&mut self) -> &mut Vec<Step> {
         self.base_agent.get_logs_mut()
     }
@@ -207,100 +455,253 @@ impl<M: Model + Send + Sync + 'static> Agent for CodeAgent<M> {
     fn model(&self) -> &dyn Model {
         self.base_agent.model()
     }
+    fn get_generation_config(&self) -> GenerationConfig {
+        self.base_agent.get_generation_config()
+    }
+    fn get_history(&self) -> Option<Vec<Message>> {
+        self.base_agent.get_history()
+    }
+    fn set_history(&mut self, history: Option<Vec<Message>>) {
+        self.base_agent.set_history(history);
+    }
+    fn get_usage(&self) -> Usage {
+        self.base_agent.get_usage()
+    }
+    fn add_usage(&mut self, usage: Usage) {
+        self.base_agent.add_usage(usage);
+    }
+    fn get_budget(&self) -> Option<Budget> {
+        self.base_agent.get_budget()
+    }
+    fn get_hooks(&self) -> AgentHooks {
+        self.base_agent.get_hooks()
+    }
+    fn get_final_step_prompt(&self) -> &str {
+        self.base_agent.get_final_step_prompt()
+    }
+    fn get_context(&self) -> RunContext {
+        self.base_agent.get_context()
+    }
+    fn set_context(&mut self, context: RunContext) {
+        self.base_agent.set_context(context);
+    }
+    fn get_reflection(&self) -> Option<ReflectionConfig> {
+        self.base_agent.get_reflection()
+    }
+    fn get_runtime_facts(&self) -> Option<RuntimeFacts> {
+        self.base_agent.get_runtime_facts()
+    }
+    fn is_dry_run(&self) -> bool {
+        self.base_agent.is_dry_run()
+    }
+    fn cites_sources(&self) -> bool {
+        self.base_agent.cites_sources()
+    }
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.base_agent.set_dry_run(dry_run);
+    }
+    #[cfg(feature = "stream")]
+    fn set_chunk_sink(&mut self, sink: Option<ChunkSink>) {
+        self.base_agent.set_chunk_sink(sink);
+    }
+    fn get_delegation(&self) -> Delegation {
+        self.base_agent.get_delegation()
+    }
+    fn set_delegation(&mut self, delegation: Delegation) {
+        self.base_agent.set_delegation(delegation);
+    }
+    fn get_run_id(&self) -> Option<String> {
+        self.base_agent.get_run_id()
+    }
+    fn set_run_id(&mut self, run_id: String) {
+        self.base_agent.set_run_id(run_id);
+    }
+    fn get_parent_run_id(&self) -> Option<String> {
+        self.base_agent.get_parent_run_id()
+    }
+    fn set_parent_run_id(&mut self, parent_run_id: Option<String>) {
+        self.base_agent.set_parent_run_id(parent_run_id);
+    }
     #[instrument(skip(self, log_entry), fields(step = ?self.get_step_number()))]
     async fn step(&mut self, log_entry: &mut Step) -> Result<Option<AgentStep>, AgentError> {
         let step_result = match log_entry {
             Step::ActionStep(step_log) => {
+                if let (Some(sandbox), 1) = (&self.sandbox, self.get_step_number()) {
+                    // A new run starts in a new sandbox
+                    sandbox.stop().await?;
+                }
+                self.telemetry.set_run_id(self.base_agent.run_id.clone());
                 let cx = self.telemetry.start_step(self.get_step_number() as i64);
                 let span = Span::current();
                 span.record("step_type", "action");
                 let agent_memory = self.base_agent.write_inner_memory_from_logs(None)?;
+                step_log.agent_memory = Some(agent_memory);
+                self.base_agent.hooks.on_step_start(step_log).await?;
+                let agent_memory = step_log.agent_memory.clone().unwrap_or_default();
                 self.base_agent.input_messages = Some(agent_memory.clone());
-                step_log.agent_memory = Some(agent_memory.clone());
                 self.telemetry
                     .log_agent_memory(&serde_json::to_value(&agent_memory).unwrap_or_default());
 
-                let llm_output = self
-                    .base_agent
-                    .model
-                    .run(
-                        self.base_agent.input_messages.as_ref().unwrap().clone(),
-                        self.base_agent.history.clone(),
-                        vec![],
-                        None,
-                        Some(HashMap::from([(
-                            "stop".to_string(),
-                            vec!["Observation:".to_string(), "<end_code>".to_string()],
-                        )])),
+                let mut config = GenerationConfig::new()
+                    .with_stop(
+                        [
+                            self.base_agent.stop_sequences.clone(),
+                            vec!["<end_code>".to_string()],
+                        ]
+                        .concat(),
                     )
-                    .with_context(cx.clone())
-                    .await?;
+                    .merge(&self.base_agent.generation_config);
+                if let Some(prefill) = self.base_agent.step_prefill() {
+                    config.prefill = Some(prefill);
+                }
+                let mut input_messages = agent_memory.clone();
+                let mut retries = 0;
+                let (response, code) = loop {
+                    let llm_output = self
+                        .base_agent
+                        .call_model(input_messages.clone(), vec![], config.clone())
+                        .with_context(cx.clone())
+                        .await?;
+                    self.base_agent.usage += llm_output.get_usage().unwrap_or_default();
 
-                let response = llm_output.get_response()?;
-                step_log.llm_output = Some(response.clone());
+                    let mut response = llm_output.get_response()?;
+                    self.base_agent.hooks.on_llm_response(&mut response).await?;
 
-                let code = match parse_code_blobs(&response) {
-                    Ok(code) => code,
-                    Err(e) => {
-                        step_log.error = Some(e.clone());
-                        tracing::info!("Error: {}", response + "\n" + &e.to_string());
-                        self.telemetry.log_tool_result(&e.to_string(), false, &cx);
-                        return Ok(Some(step_log.clone()));
+                    match parse_code_blobs(&response) {
+                        Ok(code) => break (response, code),
+                        Err(e) if retries < self.base_agent.parse_retry => {
+                            retries += 1;
+                            tracing::warn!(
+                                error = %e,
+                                retry = retries,
+                                "Could not parse code, asking the model to repair it"
+                            );
+                            input_messages.push(Message::new(MessageRole::Assistant, &response));
+                            input_messages.push(Message::new(
+                                MessageRole::User,
+                                &parse_retry_prompt(e.message()),
+                            ));
+                        }
+                        Err(e) => {
+                            step_log.llm_output = Some(response.clone());
+                            step_log.error = Some(e.clone());
+                            tracing::info!("Error: {}", response + "\n" + &e.to_string());
+                            self.telemetry.log_tool_result(&e.to_string(), false, &cx);
+                            return Ok(Some(step_log.clone()));
+                        }
                     }
                 };
+                step_log.llm_output = Some(response.clone());
 
-                tracing::info!("Code: {}", code);
-                let tool_call = vec![ToolCall {
+                let mut tool_call = ToolCall {
                     id: Some(format!("call_{}", nanoid::nanoid!())),
                     call_type: Some("function".to_string()),
                     function: FunctionCall {
                         name: "python_interpreter".to_string(),
                         arguments: serde_json::json!({ "code": code }),
                     },
-                }];
-                step_log.tool_call = Some(tool_call.clone());
-                self.telemetry.log_tool_calls(&tool_call, &cx);
+                };
+                self.base_agent.hooks.on_tool_call(&mut tool_call).await?;
+                let code = tool_call.function.arguments["code"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string();
+                tracing::info!("Code: {}", code);
+                step_log.tool_call = Some(vec![tool_call.clone()]);
+                self.telemetry.log_tool_calls(&[tool_call.clone()], &cx);
 
-                let result = self.local_python_interpreter.forward(&code);
-                match result {
-                    Ok(result) => {
-                        let (result, execution_logs) = result;
-                        let mut observation = match (execution_logs.is_empty(), result.is_empty()) {
-                            (false, false) => {
-                                format!("Execution logs: {}\nResult: {}", execution_logs, result)
-                            }
-                            (false, true) => format!("Execution logs: {}", execution_logs),
-                            (true, false) => format!("Result: {}", result),
-                            (true, true) => String::from("No output or logs generated"),
-                        };
-                        if observation.len() > 30000 {
-                            observation = observation.chars().take(30000).collect::<String>();
-                            observation = format!("{} \n....This content has been truncated due to the 30000 character limit.....", observation);
-                        } else {
-                            observation = observation.to_string();
-                        }
-                        tracing::info!("Observation: {}", observation);
-                        self.telemetry.log_tool_result(&observation, true, &cx);
-                        step_log.observations = Some(vec![observation]);
-                    }
-                    Err(e) => match e {
-                        InterpreterError::FinalAnswer(answer) => {
-                            step_log.final_answer = Some(answer.clone());
-                            step_log.observations = Some(vec![format!("Final answer: {}", answer)]);
-                            self.telemetry.log_final_answer(&answer);
-                            cx.span().set_attribute(opentelemetry::KeyValue::new(
-                                "end_time",
-                                chrono::Utc::now().to_rfc3339(),
-                            ));
-                            cx.span().end_with_timestamp(std::time::SystemTime::now());
-                            return Ok(Some(step_log.clone()));
+                self.local_python_interpreter
+                    .set_context(self.base_agent.tool_context());
+                self.local_python_interpreter
+                    .set_tool_retry(self.base_agent.tool_retry.clone());
+                let tool_names = self
+                    .base_agent
+                    .tools
+                    .iter()
+                    .map(|tool| tool.name())
+                    .chain(
+                        self.base_agent
+                            .managed_agents
+                            .iter()
+                            .map(|agent| agent.name()),
+                    )
+                    .collect::<Vec<_>>();
+                if self.base_agent.dry_run && calls_tool(&code, &tool_names) {
+                    tracing::info!("Dry run, not executing code");
+                    let observation = dry_run_code_observation();
+                    self.telemetry.log_tool_result(&observation, true, &cx);
+                    step_log.observations = Some(vec![observation]);
+                } else if let Some(observation) = self.base_agent.repeated_call(&tool_call.function)
+                {
+                    tracing::warn!("Repeated code, not executing it again");
+                    self.telemetry.log_tool_result(&observation, false, &cx);
+                    step_log.observations = Some(vec![observation]);
+                } else {
+                    let started = crate::runtime::Instant::now();
+                    let result = match &self.sandbox {
+                        Some(sandbox) => {
+                            interpreter_result(sandbox.execute(&with_final_answer(&code)).await?)
                         }
-                        _ => {
-                            step_log.error = Some(AgentError::Execution(e.to_string()));
-                            tracing::info!("Error: {}", e);
-                            self.telemetry.log_tool_result(&e.to_string(), false, &cx);
+                        None => self.local_python_interpreter.forward(&code),
+                    };
+                    let duration = started.elapsed();
+                    match result {
+                        Ok(result) => {
+                            self.telemetry
+                                .record_tool_call("python_interpreter", duration, None);
+                            let (result, execution_logs) = result;
+                            let observation = match (execution_logs.is_empty(), result.is_empty()) {
+                                (false, false) => {
+                                    format!(
+                                        "Execution logs: {}\nResult: {}",
+                                        execution_logs, result
+                                    )
+                                }
+                                (false, true) => format!("Execution logs: {}", execution_logs),
+                                (true, false) => format!("Result: {}", result),
+                                (true, true) => String::from("No output or logs generated"),
+                            };
+                            let mut observation =
+                                self.base_agent.limit_observation(observation).await;
+                            self.base_agent
+                                .hooks
+                                .on_observation(&tool_call, &mut observation)
+                                .await?;
+                            tracing::info!("Observation: {}", observation);
+                            self.telemetry.log_tool_result(&observation, true, &cx);
+                            step_log.observations = Some(vec![observation]);
                         }
-                    },
+                        Err(e) => match e {
+                            InterpreterError::FinalAnswer(mut answer) => {
+                                self.telemetry.record_tool_call(
+                                    "python_interpreter",
+                                    duration,
+                                    None,
+                                );
+                                self.base_agent.hooks.on_final_answer(&mut answer).await?;
+                                step_log.final_answer = Some(self.base_agent.new_answer(&answer));
+                                step_log.observations =
+                                    Some(vec![format!("Final answer: {}", answer)]);
+                                self.telemetry.log_final_answer(&answer);
+                                cx.span().set_attribute(opentelemetry::KeyValue::new(
+                                    "end_time",
+                                    chrono::Utc::now().to_rfc3339(),
+                                ));
+                                cx.span().end_with_timestamp(crate::runtime::now());
+                                return Ok(Some(step_log.clone()));
+                            }
+                            _ => {
+                                step_log.error = Some(AgentError::Execution(e.to_string()));
+                                tracing::info!("Error: {}", e);
+                                self.telemetry.record_tool_call(
+                                    "python_interpreter",
+                                    duration,
+                                    step_log.error.as_ref(),
+                                );
+                                self.telemetry.log_tool_result(&e.to_string(), false, &cx);
+                            }
+                        },
+                    }
                 }
                 self.telemetry
                     .log_observations(&step_log.observations.clone().unwrap_or_default());
@@ -308,7 +709,7 @@ impl<M: Model + Send + Sync + 'static> Agent for CodeAgent<M> {
                     "end_time",
                     chrono::Local::now().to_rfc3339(),
                 ));
-                cx.span().end_with_timestamp(std::time::SystemTime::now());
+                cx.span().end_with_timestamp(crate::runtime::now());
                 step_log
             }
             _ => {
@@ -338,22 +739,24 @@ pub fn parse_code_blobs(code_blob: &str) -> Result<String, AgentError> {
     if matches.is_empty() {
         // Check if it's a direct code blob or final answer
         if code_blob.contains("final") && code_blob.contains("answer") {
-            return Err(AgentError::Parsing(
+            return Err(AgentError::parsing(
                 "The code blob is invalid. It seems like you're trying to return the final answer. Use:\n\
                 Code:\n\
                 ```py\n\
                 final_answer(\"YOUR FINAL ANSWER HERE\")\n\
-                ```".to_string(),
+                ```",
+                code_blob,
             ));
         }
 
-        return Err(AgentError::Parsing(
+        return Err(AgentError::parsing(
             "The code blob is invalid. Make sure to include code with the correct pattern, for instance:\n\
             Thoughts: Your thoughts\n\
             Code:\n\
             ```py\n\
             # Your python code here\n\
-            ```".to_string(),
+            ```",
+            code_blob,
         ));
     }
 
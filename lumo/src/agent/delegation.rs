@@ -0,0 +1,220 @@
+//! Limits on delegation to managed agents.
+//!
+//! Managed agents can have managed agents of their own, and every level multiplies the steps, and the tokens, a
+//! single task can take. The limits of the agent at the top of the hierarchy apply to the whole hierarchy: each
+//! managed agent gets a [`Delegation`] one level deeper than its manager, and all of them count their steps on the
+//! same counter. A run that goes over a limit fails with [`AgentError::DelegationLimit`]. An agent started through
+//! an [`AgentTool`](crate::tools::AgentTool), by a tool call or from the code of a code agent, counts as a managed
+//! agent of the agent that called the tool.
+//!
+//! The managed agents of one agent can also be limited in how many run at the same time, e.g. when a step calls
+//! several agent tools at once. The runs over the limit wait for one of the others to finish. The limit is per
+//! manager, so a managed agent waiting for its own managed agents never holds up the agents it waits for.
+//!
+//! ```rust,ignore
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_managed_agents(vec![Box::new(researcher)])
+//!     .with_delegation_limits(
+//!         DelegationLimits::new()
+//!             .with_max_depth(2)
+//!             .with_max_sub_agent_steps(30)
+//!             .with_max_parallel_agents(4),
+//!     )
+//!     .build()?;
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::errors::{AgentError, DelegationLimitError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DelegationLimits {
+    /// How deep managed agents can be nested. The managed agents of the top-level agent are at depth 1.
+    pub max_depth: usize,
+    /// The steps that all the managed agents of the hierarchy can take together in one run of the top-level agent.
+    pub max_sub_agent_steps: Option<usize>,
+    /// How many managed agents of one agent can run at the same time.
+    pub max_parallel_agents: Option<usize>,
+}
+
+impl Default for DelegationLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 5,
+            max_sub_agent_steps: None,
+            max_parallel_agents: None,
+        }
+    }
+}
+
+impl DelegationLimits {
+    /// A depth of at most five levels and no limit on the steps or on the agents running at once.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_max_sub_agent_steps(mut self, max_sub_agent_steps: usize) -> Self {
+        self.max_sub_agent_steps = Some(max_sub_agent_steps);
+        self
+    }
+
+    pub fn with_max_parallel_agents(mut self, max_parallel_agents: usize) -> Self {
+        self.max_parallel_agents = Some(max_parallel_agents.max(1));
+        self
+    }
+}
+
+/// Where an agent is in a delegation hierarchy. Clones share the step counter and the slots of the managed agents.
+#[derive(Debug, Clone, Default)]
+pub struct Delegation {
+    pub limits: DelegationLimits,
+    /// Zero for the top-level agent.
+    pub depth: usize,
+    sub_agent_steps: Arc<AtomicUsize>,
+    /// The slots of the managed agents of this agent, if their parallel runs are limited.
+    slots: Option<Arc<Semaphore>>,
+    /// The slots of the manager of this agent, one of which this agent takes while it runs.
+    manager_slots: Option<Arc<Semaphore>>,
+}
+
+impl Delegation {
+    /// The delegation of a top-level agent.
+    pub fn new(limits: DelegationLimits) -> Self {
+        Self {
+            limits,
+            slots: Self::new_slots(&limits),
+            ..Self::default()
+        }
+    }
+
+    fn new_slots(limits: &DelegationLimits) -> Option<Arc<Semaphore>> {
+        limits
+            .max_parallel_agents
+            .map(|max_parallel_agents| Arc::new(Semaphore::new(max_parallel_agents)))
+    }
+
+    /// The delegation of the managed agent `agent_name`, started by the agent of this delegation. Fails if the
+    /// managed agent would be nested deeper than the limit.
+    pub fn child(&self, agent_name: &str) -> Result<Delegation, AgentError> {
+        let depth = self.depth + 1;
+        if depth > self.limits.max_depth {
+            return Err(self.limit_error(format!(
+                "Delegation depth limit reached: {} would run at depth {}, but at most {} levels of managed \
+                 agents are allowed",
+                agent_name, depth, self.limits.max_depth
+            )));
+        }
+        Ok(Delegation {
+            limits: self.limits,
+            depth,
+            sub_agent_steps: self.sub_agent_steps.clone(),
+            slots: Self::new_slots(&self.limits),
+            manager_slots: self.slots.clone(),
+        })
+    }
+
+    /// Waits until the agent of this delegation may run next to the other managed agents of its manager. The slot
+    /// is given back when the returned permit is dropped. `None` if the parallel runs are not limited.
+    pub async fn acquire_slot(&self) -> Option<OwnedSemaphorePermit> {
+        let slots = self.manager_slots.clone()?;
+        // The semaphore is never closed
+        slots.acquire_owned().await.ok()
+    }
+
+    pub fn sub_agent_steps(&self) -> usize {
+        self.sub_agent_steps.load(Ordering::SeqCst)
+    }
+
+    /// Resets the step counter at the start of a run of the top-level agent. Does nothing for a managed agent,
+    /// whose steps count towards the run of the top-level agent.
+    pub fn start_run(&self) {
+        if self.depth == 0 {
+            self.sub_agent_steps.store(0, Ordering::SeqCst);
+        }
+    }
+
+    /// Counts a step of a managed agent. Fails if the managed agents of the hierarchy went over their steps.
+    pub fn record_step(&self) -> Result<(), AgentError> {
+        if self.depth == 0 {
+            return Ok(());
+        }
+        let steps = self.sub_agent_steps.fetch_add(1, Ordering::SeqCst) + 1;
+        match self.limits.max_sub_agent_steps {
+            Some(max_steps) if steps > max_steps => Err(self.limit_error(format!(
+                "Sub-agent step budget exceeded: the managed agents took more than {} steps",
+                max_steps
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn limit_error(&self, message: String) -> AgentError {
+        AgentError::DelegationLimit(DelegationLimitError {
+            message,
+            depth: self.depth,
+            sub_agent_steps: self.sub_agent_steps(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delegation_limits() {
+        let root = Delegation::new(
+            DelegationLimits::new()
+                .with_max_depth(2)
+                .with_max_sub_agent_steps(3),
+        );
+        root.record_step().unwrap();
+        let child = root.child("researcher").unwrap();
+        let grandchild = child.child("searcher").unwrap();
+        assert_eq!(grandchild.depth, 2);
+        assert!(matches!(
+            grandchild.child("reader"),
+            Err(AgentError::DelegationLimit(_))
+        ));
+
+        child.record_step().unwrap();
+        grandchild.record_step().unwrap();
+        grandchild.record_step().unwrap();
+        assert_eq!(root.sub_agent_steps(), 3);
+        assert!(child.record_step().is_err());
+
+        child.start_run();
+        assert_eq!(root.sub_agent_steps(), 4);
+        root.start_run();
+        assert_eq!(grandchild.sub_agent_steps(), 0);
+    }
+
+    #[test]
+    fn test_max_parallel_agents() {
+        use futures::FutureExt;
+
+        let root = Delegation::new(DelegationLimits::new().with_max_parallel_agents(1));
+        assert!(root.acquire_slot().now_or_never().unwrap().is_none());
+
+        let researcher = root.child("researcher").unwrap();
+        let writer = root.child("writer").unwrap();
+        let slot = researcher.acquire_slot().now_or_never().unwrap();
+        assert!(slot.is_some());
+        assert!(writer.acquire_slot().now_or_never().is_none());
+
+        // The managed agents of the researcher have slots of their own
+        let searcher = researcher.child("searcher").unwrap();
+        assert!(searcher.acquire_slot().now_or_never().unwrap().is_some());
+
+        drop(slot);
+        assert!(writer.acquire_slot().now_or_never().unwrap().is_some());
+    }
+}
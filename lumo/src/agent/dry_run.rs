@@ -0,0 +1,144 @@
+//! Dry runs, which show what an agent would do with a task without doing it.
+//!
+//! In a dry run the model plans and takes its steps as usual, but the tools are not called: every call gets an
+//! observation that says so, and the model carries on as if the call had worked. The calls, with their arguments,
+//! are returned in the order the model made them, which makes a dry run a way to preview a workflow with side
+//! effects or to test a prompt.
+//!
+//! ```rust,ignore
+//! let dry_run = agent.dry_run("Delete the staging databases older than a month").await?;
+//! for call in &dry_run.tool_calls {
+//!     println!("{} {}", call.name, call.arguments);
+//! }
+//! ```
+
+use serde::Serialize;
+
+use super::{agent_step::Step, run_report::ToolCallReport};
+use crate::tools::SEARCH_TOOLS_NAME;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRun {
+    /// The tool calls the agent would make, in order. The calls of the code agent are its code blocks, as calls of
+    /// `python_interpreter`.
+    pub tool_calls: Vec<ToolCallReport>,
+    /// The answer the agent would give, as if every tool call had worked.
+    pub final_answer: String,
+    pub steps: usize,
+}
+
+impl DryRun {
+    pub fn from_logs(logs: &[Step], final_answer: String) -> Self {
+        let mut tool_calls = Vec::new();
+        let mut steps = 0;
+        for log in logs {
+            if let Step::ActionStep(step) = log {
+                steps += 1;
+                tool_calls.extend(
+                    step.tool_call
+                        .iter()
+                        .flatten()
+                        .filter(|call| {
+                            !matches!(
+                                call.function.name.as_str(),
+                                "final_answer" | SEARCH_TOOLS_NAME
+                            )
+                        })
+                        .map(|call| ToolCallReport {
+                            name: call.function.name.clone(),
+                            arguments: call.function.arguments.clone(),
+                        }),
+                );
+            }
+        }
+        Self {
+            tool_calls,
+            final_answer,
+            steps,
+        }
+    }
+}
+
+/// The observation of a tool call that was not made because of a dry run.
+pub(crate) fn dry_run_observation(tool: &str) -> String {
+    format!(
+        "Dry run: `{}` was not called. Assume that it worked and returned what you expected, and go on with the \
+         next step.",
+        tool
+    )
+}
+
+/// The observation of code that was not run because of a dry run.
+#[cfg(feature = "code-agent")]
+pub(crate) fn dry_run_code_observation() -> String {
+    "Dry run: the code calls tools, so it was not run. Assume that it worked and printed what you expected, and go \
+     on with the next step."
+        .to_string()
+}
+
+/// Whether `code` calls one of the `tools`. Code that calls none of them has no side effects through the agent,
+/// so it runs in a dry run.
+#[cfg(feature = "code-agent")]
+pub(crate) fn calls_tool(code: &str, tools: &[&str]) -> bool {
+    tools
+        .iter()
+        .filter(|tool| **tool != "final_answer")
+        .any(|tool| {
+            regex::Regex::new(&format!(r"\b{}\s*\(", regex::escape(tool)))
+                .map(|pattern| pattern.is_match(code))
+                .unwrap_or(true)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::AgentStep,
+        models::openai::{FunctionCall, ToolCall},
+    };
+    use serde_json::json;
+
+    fn call(name: &str) -> ToolCall {
+        ToolCall {
+            id: None,
+            call_type: Some("function".to_string()),
+            function: FunctionCall {
+                name: name.to_string(),
+                arguments: json!({"path": "/tmp/a"}),
+            },
+        }
+    }
+
+    #[test]
+    fn test_dry_run_from_logs() {
+        let logs = vec![
+            Step::SystemPromptStep("You are an agent".to_string()),
+            Step::ActionStep(AgentStep {
+                tool_call: Some(vec![call("delete_file"), call(SEARCH_TOOLS_NAME)]),
+                observations: Some(vec![dry_run_observation("delete_file")]),
+                ..AgentStep::new(1, None)
+            }),
+            Step::ActionStep(AgentStep {
+                tool_call: Some(vec![call("final_answer")]),
+                final_answer: Some("Deleted".to_string()),
+                ..AgentStep::new(2, None)
+            }),
+        ];
+        let dry_run = DryRun::from_logs(&logs, "Deleted".to_string());
+        assert_eq!(dry_run.steps, 2);
+        assert_eq!(dry_run.tool_calls.len(), 1);
+        assert_eq!(dry_run.tool_calls[0].name, "delete_file");
+    }
+
+    #[cfg(feature = "code-agent")]
+    #[test]
+    fn test_calls_tool() {
+        assert!(calls_tool("rm = delete_file ('/tmp/a')", &["delete_file"]));
+        assert!(!calls_tool(
+            "x = 1 + 2\nfinal_answer(x)",
+            &["delete_file", "final_answer"]
+        ));
+        assert!(!calls_tool("undelete_file_count = 2", &["delete_file"]));
+    }
+}
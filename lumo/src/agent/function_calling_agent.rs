@@ -1,28 +1,48 @@
 use anyhow::Result;
 use async_trait::async_trait;
-use futures::future::join_all;
+use futures::stream::{self, StreamExt};
 use opentelemetry::trace::{FutureExt, TraceContextExt};
 use serde_json::json;
-use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::{
     agent::Agent,
+    artifacts::ArtifactStore,
+    config::{AgentConfig, ConfiguredModel},
+    context::RunContext,
     errors::AgentError,
     models::{
+        conversation::{validate_messages, Conversation},
         model_traits::Model,
         openai::{FunctionCall, ToolCall},
-        types::Message,
+        prefill::Prefill,
+        types::{GenerationConfig, Message, MessageRole, ToolChoice, Usage},
     },
-    prompts::TOOL_CALLING_SYSTEM_PROMPT,
+    prompts::{parse_retry_prompt, PromptSection, PromptTemplate, TOOL_CALLING_SYSTEM_PROMPT},
     telemetry::AgentTelemetry,
-    tools::{AsyncTool, ToolFunctionInfo, ToolGroup, ToolInfo, ToolType},
+    tools::{
+        AgentIo, AskUserTool, AsyncTool, ReadArtifactTool, ToolFunctionInfo, ToolGroup, ToolInfo,
+        ToolRetryPolicy, ToolSelector, ToolType, SEARCH_TOOLS_NAME,
+    },
 };
 use tracing::instrument;
 
-use super::{agent_step::Step, multistep_agent::MultiStepAgent, AgentStep};
+use super::{
+    agent_step::Step,
+    budget::Budget,
+    delegation::{Delegation, DelegationLimits},
+    dry_run::dry_run_observation,
+    hooks::{AgentHook, AgentHooks},
+    loop_detector::LoopDetector,
+    multistep_agent::{MultiStepAgent, DEFAULT_MAX_OBSERVATION_SIZE},
+    reflection::ReflectionConfig,
+    runtime_facts::RuntimeFacts,
+    step_strategy::{ReAct, StepContext, StepDecision, StepStrategy},
+    AgentStep,
+};
 
 #[cfg(feature = "stream")]
-use super::agent_trait::AgentStream;
+use {super::agent_trait::AgentStream, crate::models::stream::ChunkSink};
 
 pub struct FunctionCallingAgent<M>
 where
@@ -30,6 +50,7 @@ where
 {
     base_agent: MultiStepAgent<M>,
     telemetry: AgentTelemetry,
+    strategy: Box<dyn StepStrategy>,
 }
 
 impl<M: Model + Send + Sync + 'static> FunctionCallingAgent<M> {
@@ -62,10 +83,18 @@ impl<M: Model + Send + Sync + 'static> FunctionCallingAgent<M> {
         Ok(Self {
             base_agent,
             telemetry: AgentTelemetry::new("lumo"),
+            strategy: Box::new(ReAct),
         })
     }
 }
 
+impl FunctionCallingAgent<ConfiguredModel> {
+    /// Builds the agent and its managed agents from an [`AgentConfig`].
+    pub fn from_config(config: &AgentConfig) -> Result<Self> {
+        config.function_calling_builder()?.build()
+    }
+}
+
 pub struct FunctionCallingAgentBuilder<'a, M>
 where
     M: Model + std::fmt::Debug + Send + Sync + 'static,
@@ -80,6 +109,27 @@ where
     planning_interval: Option<usize>,
     history: Option<Vec<Message>>,
     logging_level: Option<log::LevelFilter>,
+    hooks: Vec<Arc<dyn AgentHook>>,
+    generation_config: Option<GenerationConfig>,
+    parse_retry: usize,
+    budget: Option<Budget>,
+    max_observation_size: Option<usize>,
+    tool_retry: Option<ToolRetryPolicy>,
+    artifact_store: Option<Arc<dyn ArtifactStore>>,
+    io: Option<Arc<dyn AgentIo>>,
+    prompt_template: Option<PromptTemplate>,
+    final_step_prompt: Option<&'a str>,
+    max_parallel_tools: Option<usize>,
+    stop_sequences: Option<Vec<String>>,
+    context: Option<RunContext>,
+    reflection: Option<ReflectionConfig>,
+    runtime_facts: Option<RuntimeFacts>,
+    prefill: Option<Prefill>,
+    loop_detector: Option<LoopDetector>,
+    citations: bool,
+    strategy: Option<Box<dyn StepStrategy>>,
+    delegation_limits: Option<DelegationLimits>,
+    max_tools_per_request: Option<usize>,
 }
 
 impl<'a, M: Model + std::fmt::Debug + Send + Sync + 'static> FunctionCallingAgentBuilder<'a, M> {
@@ -95,6 +145,27 @@ impl<'a, M: Model + std::fmt::Debug + Send + Sync + 'static> FunctionCallingAgen
             planning_interval: None,
             history: None,
             logging_level: None,
+            hooks: vec![],
+            generation_config: None,
+            parse_retry: 0,
+            budget: None,
+            max_observation_size: None,
+            tool_retry: None,
+            artifact_store: None,
+            io: None,
+            prompt_template: None,
+            final_step_prompt: None,
+            stop_sequences: None,
+            context: None,
+            reflection: None,
+            runtime_facts: None,
+            prefill: None,
+            loop_detector: None,
+            citations: false,
+            strategy: None,
+            delegation_limits: None,
+            max_tools_per_request: None,
+            max_parallel_tools: None,
         }
     }
     pub fn with_name(mut self, name: Option<&'a str>) -> Self {
@@ -109,6 +180,85 @@ impl<'a, M: Model + std::fmt::Debug + Send + Sync + 'static> FunctionCallingAgen
         self.system_prompt = system_prompt;
         self
     }
+    /// Builds the system prompt from `prompt_template`. Ignored when a system prompt is set with
+    /// `with_system_prompt`.
+    pub fn with_prompt_template(mut self, prompt_template: Option<PromptTemplate>) -> Self {
+        self.prompt_template = prompt_template;
+        self
+    }
+    /// Replaces one section of the default system prompt, or of the template set with `with_prompt_template`.
+    pub fn with_prompt_section(mut self, section: PromptSection, text: &str) -> Self {
+        let template = self
+            .prompt_template
+            .take()
+            .unwrap_or_else(PromptTemplate::tool_calling);
+        self.prompt_template = Some(template.with_section(section, text));
+        self
+    }
+    /// The message that tells the model to give its answer once the agent has used all of its steps.
+    pub fn with_final_step_prompt(mut self, final_step_prompt: Option<&'a str>) -> Self {
+        self.final_step_prompt = final_step_prompt;
+        self
+    }
+    /// Replaces the stop sequences of the action steps, which default to `Observation:`. An empty list sends no stop
+    /// sequences, for chat templates that break on them.
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.stop_sequences = Some(stop_sequences);
+        self
+    }
+    /// The [`RunContext`] shared by the tools of the agent, whose values the prompts can use as
+    /// `{{context.<key>}}`. Keep a clone to seed it before a run and read it afterwards.
+    pub fn with_context(mut self, context: RunContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+    /// Tells the model the current date and time, the timezone and other facts about the environment, which it
+    /// cannot know on its own. The facts are gathered again at every step; see [`RuntimeFacts`] for which ones and
+    /// where they go in the messages.
+    pub fn with_runtime_facts(mut self, runtime_facts: RuntimeFacts) -> Self {
+        self.runtime_facts = Some(runtime_facts);
+        self
+    }
+    /// Starts the answer of the model with a prefill at every step, or at the steps the prefill is given for.
+    pub fn with_prefill(mut self, prefill: Prefill) -> Self {
+        self.prefill = Some(prefill);
+        self
+    }
+    /// Answers a tool call with the earlier result instead of making it, once the task has enough calls like it.
+    pub fn with_loop_detector(mut self, loop_detector: LoopDetector) -> Self {
+        self.loop_detector = Some(loop_detector);
+        self
+    }
+    /// Gives the observations ids and asks the model to cite the ones its final answer uses. The cited
+    /// observations are the citations of the [`Answer`](crate::agent::Answer) of the final step.
+    pub fn with_citations(mut self, citations: bool) -> Self {
+        self.citations = citations;
+        self
+    }
+    /// How the agent chooses the action of each step, [`ReAct`] by default.
+    pub fn with_strategy(mut self, strategy: impl StepStrategy + 'static) -> Self {
+        self.strategy = Some(Box::new(strategy));
+        self
+    }
+    /// Makes the agent critique its own work every few steps or before it returns its final answer. A rejected
+    /// final answer is dropped and the critique is added as an observation, so the agent keeps going. Every review
+    /// is an extra model call, counted in the usage of the run.
+    pub fn with_reflection(mut self, reflection: ReflectionConfig) -> Self {
+        self.reflection = Some(reflection);
+        self
+    }
+    /// Limits how deep managed agents can be nested and how many steps they can take together. The limits of the
+    /// top-level agent apply to the whole hierarchy.
+    pub fn with_delegation_limits(mut self, delegation_limits: DelegationLimits) -> Self {
+        self.delegation_limits = Some(delegation_limits);
+        self
+    }
+    /// Sends at most `max_tools_per_request` tools with each request, for providers that cap the number of tools.
+    /// The tools that best match the task are sent, and the model can find the others with the `search_tools` tool.
+    pub fn with_max_tools_per_request(mut self, max_tools_per_request: usize) -> Self {
+        self.max_tools_per_request = Some(max_tools_per_request);
+        self
+    }
     pub fn with_managed_agents(mut self, managed_agents: Vec<Box<dyn Agent>>) -> Self {
         self.managed_agents = managed_agents;
         self
@@ -129,27 +279,155 @@ impl<'a, M: Model + std::fmt::Debug + Send + Sync + 'static> FunctionCallingAgen
         self.history = history;
         self
     }
+    /// The messages before the task, checked against the rules of the chat APIs when the agent is built.
+    pub fn with_conversation(mut self, conversation: Conversation) -> Self {
+        self.history = Some(conversation.into_messages());
+        self
+    }
     pub fn with_logging_level(mut self, logging_level: Option<log::LevelFilter>) -> Self {
         self.logging_level = logging_level;
         self
     }
+    /// Sampling parameters used for every model call made by the agent.
+    pub fn with_generation_config(mut self, generation_config: Option<GenerationConfig>) -> Self {
+        self.generation_config = generation_config;
+        self
+    }
+    /// How the model may use the tools, for example [`ToolChoice::Auto`] to let it answer without the
+    /// `final_answer` tool. Sets the tool choice of the generation config.
+    pub fn with_tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.generation_config = Some(
+            self.generation_config
+                .unwrap_or_default()
+                .with_tool_choice(tool_choice),
+        );
+        self
+    }
+    /// When the model emits a tool call that cannot be parsed, send the error back to the model and let it retry up
+    /// to `parse_retry` times before the step fails.
+    pub fn with_parse_retry(mut self, parse_retry: usize) -> Self {
+        self.parse_retry = parse_retry;
+        self
+    }
+    /// Limits the tokens, cost and time of every run. The run fails with [`AgentError::BudgetExceeded`] once a
+    /// limit is reached.
+    pub fn with_budget(mut self, budget: Option<Budget>) -> Self {
+        self.budget = budget;
+        self
+    }
+    /// Observations longer than this many characters are truncated before they are added to the memory. Defaults
+    /// to 30000.
+    pub fn with_max_observation_size(mut self, max_observation_size: Option<usize>) -> Self {
+        self.max_observation_size = max_observation_size;
+        self
+    }
+    /// Saves the full output of truncated observations in `artifact_store` and gives the agent a `read_artifact`
+    /// tool to read them.
+    pub fn with_artifact_store(mut self, artifact_store: Option<Arc<dyn ArtifactStore>>) -> Self {
+        self.artifact_store = artifact_store;
+        self
+    }
+    /// Gives the agent an `ask_user` tool, which asks the user a question through `io` and waits for the reply.
+    pub fn with_io(mut self, io: Arc<dyn AgentIo>) -> Self {
+        self.io = Some(io);
+        self
+    }
+    /// How tool calls that time out or are rate limited are retried. Defaults to two retries with exponential
+    /// backoff.
+    pub fn with_tool_retry(mut self, tool_retry: Option<ToolRetryPolicy>) -> Self {
+        self.tool_retry = tool_retry;
+        self
+    }
+    /// Runs at most `max_parallel_tools` tool calls of a step at the same time. The observations are always in the
+    /// order of the calls.
+    pub fn with_max_parallel_tools(mut self, max_parallel_tools: usize) -> Self {
+        self.max_parallel_tools = Some(max_parallel_tools);
+        self
+    }
+    /// Adds a hook that is run on every step. Hooks run in the order they are added.
+    pub fn with_hook(mut self, hook: impl AgentHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+    pub fn with_hooks(mut self, hooks: Vec<Arc<dyn AgentHook>>) -> Self {
+        self.hooks.extend(hooks);
+        self
+    }
     pub fn build(self) -> Result<FunctionCallingAgent<M>> {
-        FunctionCallingAgent::new(
+        if let Some(history) = &self.history {
+            validate_messages(history)?;
+        }
+        let mut tools = self.tools;
+        if let Some(store) = &self.artifact_store {
+            tools.push(Box::new(ReadArtifactTool::new(store.clone(), None)));
+        }
+        if let Some(io) = &self.io {
+            tools.push(Box::new(AskUserTool::new(io.clone())));
+        }
+        let tool_selector = self.max_tools_per_request.map(ToolSelector::new);
+        // The final answer tool is added by the agent
+        let tool_count = tools.len() + self.managed_agents.len() + 1;
+        if let Some(selector) = tool_selector
+            .as_ref()
+            .filter(|selector| selector.defers(tool_count))
+        {
+            tools.push(Box::new(selector.search_tool()));
+        }
+        let template_prompt = self
+            .prompt_template
+            .as_ref()
+            .map(|template| template.to_prompt())
+            .transpose()?;
+        let system_prompt = self.system_prompt.or(template_prompt.as_deref());
+        let mut agent = FunctionCallingAgent::new(
             self.name,
             self.model,
-            self.tools,
-            self.system_prompt,
+            tools,
+            system_prompt,
             self.managed_agents,
             self.description,
             self.max_steps,
             self.planning_interval,
             self.history,
             self.logging_level,
-        )
+        )?;
+        agent.base_agent.hooks = AgentHooks::new(self.hooks);
+        agent.base_agent.generation_config = self.generation_config.unwrap_or_default();
+        agent.base_agent.parse_retry = self.parse_retry;
+        agent.base_agent.budget = self.budget;
+        agent.base_agent.max_observation_size = self
+            .max_observation_size
+            .unwrap_or(DEFAULT_MAX_OBSERVATION_SIZE);
+        agent.base_agent.artifact_store = self.artifact_store;
+        if let Some(final_step_prompt) = self.final_step_prompt {
+            agent.base_agent.final_step_prompt = final_step_prompt.to_string();
+        }
+        agent.base_agent.tool_retry = self.tool_retry.unwrap_or_default();
+        agent.base_agent.max_parallel_tools = self.max_parallel_tools;
+        if let Some(stop_sequences) = self.stop_sequences {
+            agent.base_agent.stop_sequences = stop_sequences;
+        }
+        if let Some(context) = self.context {
+            agent.base_agent.context = context;
+        }
+        agent.base_agent.reflection = self.reflection;
+        agent.base_agent.runtime_facts = self.runtime_facts;
+        agent.base_agent.prefill = self.prefill;
+        agent.base_agent.loop_detector = self.loop_detector;
+        agent.base_agent.citations = self.citations;
+        if let Some(strategy) = self.strategy {
+            agent.strategy = strategy;
+        }
+        if let Some(delegation_limits) = self.delegation_limits {
+            agent.base_agent.delegation = Delegation::new(delegation_limits);
+        }
+        agent.base_agent.tool_selector = tool_selector;
+        Ok(agent)
     }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCallingAgent<M> {
     fn name(&self) -> &'static str {
         self.base_agent.name()
@@ -184,12 +462,85 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCalli
     fn increment_step_number(&mut self) {
         self.base_agent.increment_step_number();
     }
+    fn get_logs(&self) -> &[Step] {
+        self.base_agent.get_logs()
+    }
     fn get_logs_mut(&mut self) -> &mut Vec<Step> {
         self.base_agent.get_logs_mut()
     }
     fn model(&self) -> &dyn Model {
         self.base_agent.model()
     }
+    fn get_generation_config(&self) -> GenerationConfig {
+        self.base_agent.get_generation_config()
+    }
+    fn get_history(&self) -> Option<Vec<Message>> {
+        self.base_agent.get_history()
+    }
+    fn set_history(&mut self, history: Option<Vec<Message>>) {
+        self.base_agent.set_history(history);
+    }
+    fn get_usage(&self) -> Usage {
+        self.base_agent.get_usage()
+    }
+    fn add_usage(&mut self, usage: Usage) {
+        self.base_agent.add_usage(usage);
+    }
+    fn get_budget(&self) -> Option<Budget> {
+        self.base_agent.get_budget()
+    }
+    fn get_hooks(&self) -> AgentHooks {
+        self.base_agent.get_hooks()
+    }
+    fn get_final_step_prompt(&self) -> &str {
+        self.base_agent.get_final_step_prompt()
+    }
+    fn get_context(&self) -> RunContext {
+        self.base_agent.get_context()
+    }
+    fn set_context(&mut self, context: RunContext) {
+        self.base_agent.set_context(context);
+    }
+    fn get_reflection(&self) -> Option<ReflectionConfig> {
+        self.base_agent.get_reflection()
+    }
+    fn get_runtime_facts(&self) -> Option<RuntimeFacts> {
+        self.base_agent.get_runtime_facts()
+    }
+    fn is_dry_run(&self) -> bool {
+        self.base_agent.is_dry_run()
+    }
+    fn cites_sources(&self) -> bool {
+        self.base_agent.cites_sources()
+    }
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.base_agent.set_dry_run(dry_run);
+    }
+    #[cfg(feature = "stream")]
+    fn set_chunk_sink(&mut self, sink: Option<ChunkSink>) {
+        self.base_agent.set_chunk_sink(sink);
+    }
+    fn get_delegation(&self) -> Delegation {
+        self.base_agent.get_delegation()
+    }
+    fn set_delegation(&mut self, delegation: Delegation) {
+        self.base_agent.set_delegation(delegation);
+    }
+    fn get_run_id(&self) -> Option<String> {
+        self.base_agent.get_run_id()
+    }
+    fn set_run_id(&mut self, run_id: String) {
+        self.base_agent.set_run_id(run_id);
+    }
+    fn get_parent_run_id(&self) -> Option<String> {
+        self.base_agent.get_parent_run_id()
+    }
+    fn set_parent_run_id(&mut self, parent_run_id: Option<String>) {
+        self.base_agent.set_parent_run_id(parent_run_id);
+    }
+    fn get_final_answer_tool(&self) -> Option<ToolInfo> {
+        self.base_agent.get_final_answer_tool()
+    }
     fn set_planning_interval(&mut self, planning_interval: Option<usize>) {
         self.base_agent.set_planning_interval(planning_interval);
     }
@@ -211,11 +562,14 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCalli
     async fn step(&mut self, log_entry: &mut Step) -> Result<Option<AgentStep>, AgentError> {
         match log_entry {
             Step::ActionStep(step_log) => {
+                self.telemetry.set_run_id(self.base_agent.run_id.clone());
                 let cx = self.telemetry.start_step(self.get_step_number() as i64);
 
                 let agent_memory = self.base_agent.write_inner_memory_from_logs(None)?;
+                step_log.agent_memory = Some(agent_memory);
+                self.base_agent.hooks.on_step_start(step_log).await?;
+                let agent_memory = step_log.agent_memory.clone().unwrap_or_default();
                 self.base_agent.input_messages = Some(agent_memory.clone());
-                step_log.agent_memory = Some(agent_memory.clone());
                 self.telemetry
                     .log_agent_memory(&serde_json::to_value(&agent_memory).unwrap_or_default());
 
@@ -248,25 +602,53 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCalli
                     .collect::<Vec<_>>();
 
                 tools.extend(managed_agents);
+                if let Some(selector) = &self.base_agent.tool_selector {
+                    tools = selector.select(&self.base_agent.task, tools);
+                }
 
-                let model_message = self
-                    .base_agent
-                    .model
-                    .run(
-                        self.base_agent.input_messages.as_ref().unwrap().clone(),
-                        self.base_agent.history.clone(),
-                        tools,
-                        None,
-                        Some(HashMap::from([(
-                            "stop".to_string(),
-                            vec!["Observation:".to_string()],
-                        )])),
-                    )
-                    .with_context(cx.clone())
-                    .await?;
-
-                step_log.llm_output = Some(model_message.get_response().unwrap_or_default());
+                let mut config = GenerationConfig::new()
+                    .with_stop(self.base_agent.stop_sequences.clone())
+                    .merge(&self.base_agent.generation_config);
+                if let Some(prefill) = self.base_agent.step_prefill() {
+                    config.prefill = Some(prefill);
+                }
+                if self.get_step_number() == 1 {
+                    self.strategy.reset();
+                }
+                let mut context = StepContext::new(
+                    &self.base_agent.model,
+                    &self.base_agent.task,
+                    self.base_agent.step_number,
+                    agent_memory.clone(),
+                    self.base_agent.history.clone(),
+                    tools,
+                    config,
+                    self.base_agent.parse_retry,
+                )
+                .with_telemetry_context(cx.clone());
+                #[cfg(feature = "stream")]
+                {
+                    context = context.with_chunk_sink(self.base_agent.chunk_sink.clone());
+                }
+                let decision = self.strategy.decide(&mut context).await;
+                self.base_agent.usage += context.usage();
+                let model_message = match decision? {
+                    StepDecision::Respond(model_message) => model_message,
+                    StepDecision::Malformed { response, error } => {
+                        step_log.llm_output = Some(response);
+                        step_log.error = Some(error);
+                        cx.span().end_with_timestamp(crate::runtime::now());
+                        return Ok(Some(step_log.clone()));
+                    }
+                };
+
+                let mut response = model_message.get_response();
+                if let Ok(text) = response.as_mut() {
+                    self.base_agent.hooks.on_llm_response(text).await?;
+                }
+                step_log.llm_output = Some(response.clone().unwrap_or_default());
                 let mut observations = Vec::new();
+                let mut failed = false;
                 let mut tools = model_message.get_tools_used()?;
                 step_log.tool_call = if tools.is_empty() {
                     None
@@ -276,7 +658,7 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCalli
 
                 self.telemetry.log_tool_calls(&tools, &cx);
 
-                if let Ok(response) = model_message.get_response() {
+                if let Ok(mut response) = response {
                     if !response.trim().is_empty() {
                         if let Ok(action) = parse_response(&response) {
                             tools = vec![ToolCall {
@@ -293,23 +675,32 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCalli
                     }
                     if tools.is_empty() {
                         self.base_agent.write_inner_memory_from_logs(None)?;
-                        step_log.final_answer = Some(response.clone());
+                        self.base_agent.hooks.on_final_answer(&mut response).await?;
+                        step_log.final_answer = Some(self.base_agent.new_answer(&response));
                         step_log.observations = Some(vec![response.clone()]);
                         self.telemetry.log_final_answer(&response);
                         cx.span().set_attribute(opentelemetry::KeyValue::new(
                             "end_time",
                             chrono::Utc::now().to_rfc3339(),
                         ));
-                        cx.span().end_with_timestamp(std::time::SystemTime::now());
+                        cx.span().end_with_timestamp(crate::runtime::now());
                         return Ok(Some(step_log.clone()));
                     }
                 }
 
+                for tool in tools.iter_mut() {
+                    self.base_agent.hooks.on_tool_call(tool).await?;
+                }
+                if !tools.is_empty() {
+                    step_log.tool_call = Some(tools.clone());
+                }
+
                 if tools.is_empty() {
                     step_log.tool_call = None;
                     observations = vec!["No tool call was made. If this is the final answer, use the final_answer tool to return your answer.".to_string()];
                 } else {
                     let tools_ref = &self.base_agent.tools;
+                    let tool_context = self.base_agent.tool_context();
                     let mut futures = vec![];
                     let managed_agent_names = self
                         .base_agent
@@ -318,53 +709,122 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCalli
                         .map(|agent| agent.name())
                         .collect::<Vec<_>>();
 
-                    let mut called_tools = Vec::new();
-                    for tool in &tools {
+                    // Observations are put back in the order of the calls, since the memory pairs the n-th
+                    // observation with the n-th tool call and models may rely on that order.
+                    let mut ordered_observations = vec![String::new(); tools.len()];
+                    let mut called_tools: Vec<&ToolCall> = Vec::new();
+                    let mut call_indices = Vec::new();
+                    for (index, tool) in tools.iter().enumerate() {
                         let function_name = tool.function.name.clone();
+                        let repeated = self.base_agent.repeated_call(&tool.function);
                         match function_name.as_str() {
                             "final_answer" => {
-                                let answer = tools_ref.call(&tool.function).await?;
-                                step_log.final_answer = Some(answer.clone());
+                                let mut answer = tools_ref.call(&tool.function).await?;
+                                self.base_agent.hooks.on_final_answer(&mut answer).await?;
+                                step_log.final_answer = Some(self.base_agent.new_answer(&answer));
                                 step_log.observations = Some(vec![answer.clone()]);
                                 self.telemetry.log_final_answer(&answer);
                                 cx.span().set_attribute(opentelemetry::KeyValue::new(
                                     "end_time",
                                     chrono::Utc::now().to_rfc3339(),
                                 ));
-                                cx.span().end_with_timestamp(std::time::SystemTime::now());
+                                cx.span().end_with_timestamp(crate::runtime::now());
                                 return Ok(Some(step_log.clone()));
                             }
+                            _ if self.base_agent.dry_run && function_name != SEARCH_TOOLS_NAME => {
+                                tracing::info!(
+                                    tool = %function_name,
+                                    args = ?tool.function.arguments,
+                                    "Dry run, not executing tool call:"
+                                );
+                                ordered_observations[index] = dry_run_observation(&function_name);
+                            }
+                            _ if repeated.is_some() => {
+                                tracing::warn!(
+                                    tool = %function_name,
+                                    args = ?tool.function.arguments,
+                                    "Repeated tool call, not executing it again"
+                                );
+                                ordered_observations[index] = repeated.unwrap_or_default();
+                            }
                             _ => {
                                 if !managed_agent_names.contains(&function_name.as_str()) {
-                                    let tool_call = tools_ref.call(&tool.function);
+                                    let tool_call = tools_ref.call_with_retry(
+                                        &tool.function,
+                                        &self.base_agent.tool_retry,
+                                        &tool_context,
+                                    );
                                     tracing::info!(
                                         tool = %function_name,
                                         args = ?tool.function.arguments,
                                         "Executing tool call:"
                                     );
-                                    called_tools.push(tool.function.clone());
-                                    futures.push(tool_call);
+                                    called_tools.push(tool);
+                                    call_indices.push(index);
+                                    futures.push(async move {
+                                        let started = crate::runtime::Instant::now();
+                                        let result = tool_call.await;
+                                        (result, started.elapsed())
+                                    });
                                 } else {
-                                    let task = tool.function.arguments.get("task");
-                                    if let Some(task) = task {
-                                        if let Some(task_str) = task.as_str() {
+                                    match tool.function.arguments["task"].as_str() {
+                                        Some(task_str) => {
                                             tracing::info!(
                                                 tool = %function_name,
                                                 args = ?tool.function.arguments,
                                                 "Executing tool call: Agent Selected {}",
                                                 function_name
                                             );
-                                            let result = self
+                                            let agent = self
                                                 .base_agent
                                                 .managed_agents
                                                 .iter_mut()
                                                 .find(|agent| {
                                                     agent.name() == function_name.as_str()
                                                 })
-                                                .unwrap()
+                                                .unwrap();
+                                            let usage_before = agent.get_usage();
+                                            agent.set_parent_run_id(
+                                                self.base_agent.run_id.clone(),
+                                            );
+                                            agent.set_delegation(
+                                                self.base_agent.delegation.child(agent.name())?,
+                                            );
+                                            let outcome = agent
                                                 .run(task_str, true)
+                                                .with_context(cx.clone())
+                                                .await;
+                                            self.base_agent.usage +=
+                                                agent.get_usage().since(&usage_before);
+                                            let result = match outcome {
+                                                Ok(answer) => answer,
+                                                Err(run_error)
+                                                    if run_error.error.stops_manager() =>
+                                                {
+                                                    return Err(run_error.error);
+                                                }
+                                                Err(run_error) => {
+                                                    failed = true;
+                                                    format!(
+                                                        "Managed agent {} failed: {}",
+                                                        function_name, run_error.error
+                                                    )
+                                                }
+                                            };
+                                            let mut result =
+                                                self.base_agent.limit_observation(result).await;
+                                            self.base_agent
+                                                .hooks
+                                                .on_observation(tool, &mut result)
                                                 .await?;
-                                            observations.push(result);
+                                            ordered_observations[index] = result;
+                                        }
+                                        None => {
+                                            failed = true;
+                                            ordered_observations[index] = format!(
+                                                "Error: the call to {} needs a `task` argument",
+                                                function_name
+                                            );
                                         }
                                     }
                                 }
@@ -373,32 +833,56 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCalli
                     }
                     // }
 
-                    let results = join_all(futures).await;
-                    for (i, result) in results.into_iter().enumerate() {
+                    let max_parallel_tools = self
+                        .base_agent
+                        .max_parallel_tools
+                        .unwrap_or(futures.len())
+                        .max(1);
+                    // `buffered` runs at most `max_parallel_tools` calls at a time and yields the results in the
+                    // order of the calls, whatever order they finish in.
+                    let results = stream::iter(futures)
+                        .buffered(max_parallel_tools)
+                        .collect::<Vec<_>>()
+                        .await;
+                    for (i, (result, duration)) in results.into_iter().enumerate() {
                         let cx = self.telemetry.log_tool_execution(
-                            &called_tools[i].name,
-                            &called_tools[i].arguments,
+                            &called_tools[i].function.name,
+                            &called_tools[i].function.arguments,
                             &cx,
                         );
-                        match result {
-                            Ok(result) => {
-                                observations.push(result.clone());
-                                self.telemetry.log_tool_result(&result, true, &cx);
-                            }
-                            Err(e) => {
-                                observations.push(e.to_string());
-                                self.telemetry.log_tool_result(&e.to_string(), false, &cx);
+                        self.telemetry.record_tool_call(
+                            &called_tools[i].function.name,
+                            duration,
+                            result.as_ref().err(),
+                        );
+                        let (observation, success) = match result {
+                            Ok(result) => (result, true),
+                            Err(AgentError::Tool(error)) if error.is_fatal() => {
+                                self.telemetry
+                                    .log_tool_result(&error.to_string(), false, &cx);
+                                cx.span().end_with_timestamp(crate::runtime::now());
+                                return Err(AgentError::Tool(error));
                             }
-                        }
+                            Err(e) => (e.to_string(), false),
+                        };
+                        failed |= !success;
+                        let mut observation = self.base_agent.limit_observation(observation).await;
+                        self.telemetry.log_tool_result(&observation, success, &cx);
+                        self.base_agent
+                            .hooks
+                            .on_observation(called_tools[i], &mut observation)
+                            .await?;
+                        ordered_observations[call_indices[i]] = observation;
                         cx.span().set_attribute(opentelemetry::KeyValue::new(
                             "end_time",
                             chrono::Local::now().to_rfc3339(),
                         ));
-                        cx.span().end_with_timestamp(std::time::SystemTime::now());
+                        cx.span().end_with_timestamp(crate::runtime::now());
                     }
-                  
+                    observations = ordered_observations;
                 }
 
+                self.strategy.observe(&tools, &observations, failed);
                 step_log.observations = Some(observations);
                 self.telemetry
                     .log_observations(&step_log.observations.clone().unwrap_or_default());
@@ -406,7 +890,7 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> Agent for FunctionCalli
                     "end_time",
                     chrono::Local::now().to_rfc3339(),
                 ));
-                cx.span().end_with_timestamp(std::time::SystemTime::now());
+                cx.span().end_with_timestamp(crate::runtime::now());
                 Ok(Some(step_log.clone()))
             }
             _ => {
@@ -441,14 +925,62 @@ fn extract_action_json(text: &str) -> Option<String> {
 
     None
 }
+
+/// Returns a parsing error if the model tried to call a tool but the call cannot be used: either a native tool call
+/// whose arguments are not a JSON object, or an `Action:` / `<tool_call>` block in the text that is not valid JSON.
+pub fn malformed_tool_call_error(response: &str, tool_calls: &[ToolCall]) -> Option<AgentError> {
+    for tool_call in tool_calls {
+        let arguments = &tool_call.function.arguments;
+        if !arguments.is_object() && !arguments.is_null() {
+            return Some(AgentError::parsing(
+                format!(
+                    "The arguments of the call to `{}` are not a valid JSON object: {}",
+                    tool_call.function.name, arguments
+                ),
+                response,
+            ));
+        }
+    }
+    if tool_calls.is_empty() && (response.contains("Action:") || response.contains("<tool_call>")) {
+        return match parse_response(response) {
+            Ok(action) if action["name"].as_str().is_some_and(|name| !name.is_empty()) => None,
+            Ok(_) => Some(AgentError::parsing(
+                "The tool call is missing the name of the tool",
+                response,
+            )),
+            Err(e) => Some(e),
+        };
+    }
+    None
+}
+
+/// The messages that show the model its malformed response and ask it to try again.
+pub fn parse_retry_messages(
+    response: &str,
+    tool_calls: &[ToolCall],
+    error: &AgentError,
+) -> Vec<Message> {
+    let content = if tool_calls.is_empty() {
+        response.to_string()
+    } else {
+        format!(
+            "{}\n{}",
+            response,
+            serde_json::to_string(tool_calls).unwrap_or_default()
+        )
+    };
+    vec![
+        Message::new(MessageRole::Assistant, &content),
+        Message::new(MessageRole::User, &parse_retry_prompt(error.message())),
+    ]
+}
+
 // Example usage in your parse_response function:
 pub fn parse_response(response: &str) -> Result<serde_json::Value, AgentError> {
     if let Some(json_str) = extract_action_json(response) {
-        serde_json::from_str(&json_str).map_err(|e| AgentError::Parsing(e.to_string()))
+        serde_json::from_str(&json_str).map_err(|e| AgentError::parsing(e.to_string(), response))
     } else {
-        Err(AgentError::Parsing(
-            "No valid action JSON found".to_string(),
-        ))
+        Err(AgentError::parsing("No valid action JSON found", response))
     }
 }
 
@@ -458,6 +990,10 @@ impl<M: Model + std::fmt::Debug + Send + Sync + 'static> AgentStream for Functio
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{model_traits::ModelResponse, replay::RecordedResponse};
+
+    #[cfg(feature = "stream")]
+    use crate::{agent::AgentEvent, models::stream::ChatChunk};
 
     #[test]
     fn test_extract_action_json() {
@@ -482,4 +1018,513 @@ mod tests {
         );
         // assert_eq!(json_str, serde_json::json!({"name": "final_answer", "arguments": {"answer": "This is the final answer"}}));
     }
+
+    #[test]
+    fn test_malformed_tool_call_error() {
+        let valid = r#"Action: {"name": "search", "arguments": {"query": "rust"}}"#;
+        assert!(malformed_tool_call_error(valid, &[]).is_none());
+
+        let invalid = r#"Action: {"name": "search", "arguments": {"query": "rust"}"#;
+        assert!(malformed_tool_call_error(invalid, &[]).is_some());
+
+        let tool_call = ToolCall {
+            id: None,
+            call_type: None,
+            function: FunctionCall {
+                name: "search".to_string(),
+                arguments: serde_json::json!("{\"query\": "),
+            },
+        };
+        assert!(malformed_tool_call_error("", &[tool_call]).is_some());
+        assert!(malformed_tool_call_error("The answer is 42", &[]).is_none());
+    }
+
+    /// Only answers through the final answer tool when it is forced to.
+    #[derive(Debug)]
+    struct StubbornModel;
+
+    #[async_trait]
+    impl Model for StubbornModel {
+        async fn run(
+            &self,
+            input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            let forced = config.tool_choice == Some(ToolChoice::tool("final_answer"))
+                && input_messages
+                    .last()
+                    .is_some_and(|message| message.content.starts_with("Answer now."));
+            Ok(Box::new(RecordedResponse {
+                content: Some("Still thinking".to_string()),
+                tool_calls: if forced {
+                    vec![ToolCall {
+                        id: None,
+                        call_type: Some("function".to_string()),
+                        function: FunctionCall {
+                            name: "final_answer".to_string(),
+                            arguments: json!({"answer": "42"}),
+                        },
+                    }]
+                } else {
+                    vec![]
+                },
+                usage: None,
+                error: None,
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_final_step_forces_final_answer() {
+        let mut agent = FunctionCallingAgentBuilder::new(StubbornModel)
+            .with_max_steps(Some(1))
+            .with_final_step_prompt(Some("Answer now."))
+            .build()
+            .unwrap();
+        assert_eq!(agent.run("What is 6 times 7?", true).await.unwrap(), "42");
+    }
+
+    /// Deletes a file on the first step and answers once it has an observation.
+    #[derive(Debug)]
+    struct DeletingModel;
+
+    #[async_trait]
+    impl Model for DeletingModel {
+        async fn run(
+            &self,
+            input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            _config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            let observed = input_messages
+                .iter()
+                .any(|message| message.content.contains("Dry run"));
+            let (name, arguments) = if observed {
+                ("final_answer", json!({"answer": "Deleted"}))
+            } else {
+                ("delete_file", json!({"path": "/tmp/report.txt"}))
+            };
+            Ok(Box::new(RecordedResponse {
+                content: None,
+                tool_calls: vec![ToolCall {
+                    id: Some(format!("call_{}", name)),
+                    call_type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: name.to_string(),
+                        arguments,
+                    },
+                }],
+                usage: None,
+                error: None,
+            }))
+        }
+    }
+
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    struct DeleteFileToolParams {
+        #[allow(dead_code)]
+        path: String,
+    }
+
+    #[derive(Clone)]
+    struct DeleteFileTool {
+        calls: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl crate::tools::Tool for DeleteFileTool {
+        type Params = DeleteFileToolParams;
+        fn name(&self) -> &'static str {
+            "delete_file"
+        }
+        fn description(&self) -> &'static str {
+            "Deletes a file"
+        }
+        async fn forward(&self, _arguments: DeleteFileToolParams) -> Result<String> {
+            self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok("Deleted".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut agent = FunctionCallingAgentBuilder::new(DeletingModel)
+            .with_tools(vec![Box::new(DeleteFileTool {
+                calls: calls.clone(),
+            })])
+            .build()
+            .unwrap();
+        let dry_run = agent.dry_run("Delete the report").await.unwrap();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert_eq!(dry_run.final_answer, "Deleted");
+        assert_eq!(dry_run.tool_calls.len(), 1);
+        assert_eq!(dry_run.tool_calls[0].name, "delete_file");
+        assert_eq!(dry_run.tool_calls[0].arguments["path"], "/tmp/report.txt");
+        assert!(!agent.is_dry_run());
+    }
+
+    #[tokio::test]
+    async fn test_search_tools_only_when_tools_are_deferred() {
+        let delete_file = || -> Box<dyn AsyncTool> {
+            Box::new(DeleteFileTool {
+                calls: std::sync::Arc::default(),
+            })
+        };
+        let model = crate::models::testing::ScriptedModel::new().with_final_answer("done");
+        let mut agent = FunctionCallingAgentBuilder::new(model.clone())
+            .with_tools(vec![delete_file()])
+            .with_max_tools_per_request(2)
+            .build()
+            .unwrap();
+        agent.run("Delete the report", false).await.unwrap();
+        assert_eq!(
+            model.calls()[0].tool_names(),
+            vec!["delete_file", "final_answer"]
+        );
+
+        let agent = FunctionCallingAgentBuilder::new(crate::models::testing::ScriptedModel::new())
+            .with_tools(vec![delete_file()])
+            .with_max_tools_per_request(1)
+            .build()
+            .unwrap();
+        assert!(agent
+            .base_agent
+            .tools
+            .iter()
+            .any(|tool| tool.name() == SEARCH_TOOLS_NAME));
+    }
+
+    #[tokio::test]
+    async fn test_run_with_messages() {
+        let model = crate::models::testing::ScriptedModel::new()
+            .with_tool_call("delete_file", json!({"path": "/tmp/report.txt"}))
+            .with_final_answer("The report is deleted.");
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut agent = FunctionCallingAgentBuilder::new(model.clone())
+            .with_tools(vec![Box::new(DeleteFileTool { calls })])
+            .build()
+            .unwrap();
+        let messages = vec![
+            Message::system("You are a file manager."),
+            Message::user("Delete the old report."),
+            Message::assistant("Which report do you mean?"),
+            Message::user("The one in /tmp."),
+        ];
+        let new_messages = agent.run_with_messages(&messages).await.unwrap();
+
+        assert_eq!(model.calls()[0].history.as_ref().unwrap().len(), 2);
+        assert!(agent.get_history().is_none());
+        let expected = vec![
+            Message::tool_calls(
+                "",
+                vec![ToolCall {
+                    id: Some("call_1".to_string()),
+                    call_type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: "delete_file".to_string(),
+                        arguments: json!({"path": "/tmp/report.txt"}),
+                    },
+                }],
+            ),
+            Message::tool_result("call_1", "Deleted"),
+            Message::assistant("The report is deleted."),
+        ];
+        assert_eq!(
+            serde_json::to_value(&new_messages).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+
+        let error = agent.run_with_messages(&messages[..3]).await.unwrap_err();
+        assert!(matches!(error.error, AgentError::Execution(_)));
+    }
+
+    #[derive(serde::Deserialize, schemars::JsonSchema)]
+    struct CancelToolParams {}
+
+    /// Cancels the run it is called in, like a caller that gives up while a tool runs.
+    #[derive(Clone)]
+    struct CancelTool;
+
+    #[async_trait]
+    impl crate::tools::Tool for CancelTool {
+        type Params = CancelToolParams;
+        fn name(&self) -> &'static str {
+            "cancel"
+        }
+        fn description(&self) -> &'static str {
+            "Cancels the run"
+        }
+        async fn forward(&self, _arguments: CancelToolParams) -> Result<String> {
+            Ok("Cancelled".to_string())
+        }
+        async fn forward_with_context(
+            &self,
+            _arguments: CancelToolParams,
+            context: &RunContext,
+        ) -> Result<String> {
+            context.cancel();
+            Ok("Cancelled".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_run() {
+        let model = crate::models::testing::ScriptedModel::new()
+            .with_tool_call("cancel", json!({}))
+            .with_final_answer("Done");
+        let context = RunContext::new();
+        let mut agent = FunctionCallingAgentBuilder::new(model.clone())
+            .with_tools(vec![Box::new(CancelTool)])
+            .with_context(context.clone())
+            .build()
+            .unwrap();
+        let error = agent.run("Do the task", true).await.unwrap_err();
+        assert!(matches!(error.error, AgentError::Cancelled));
+        assert_eq!(model.calls().len(), 1);
+
+        context.resume();
+        assert_eq!(agent.run("Do the task", true).await.unwrap(), "Done");
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_cancelled_stream_run() {
+        let context = RunContext::new();
+        let mut agent = FunctionCallingAgentBuilder::new(
+            crate::models::testing::ScriptedModel::new().with_final_answer("Done"),
+        )
+        .with_context(context.clone())
+        .build()
+        .unwrap();
+        context.cancel();
+        let steps = agent
+            .stream_run("Do the task", true)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(steps.len(), 1);
+        let error = steps[0].as_ref().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<AgentError>(),
+            Some(AgentError::Cancelled)
+        ));
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_stream_run_span() {
+        let exporter = opentelemetry_sdk::trace::InMemorySpanExporter::default();
+        opentelemetry::global::set_tracer_provider(
+            opentelemetry_sdk::trace::SdkTracerProvider::builder()
+                .with_simple_exporter(exporter.clone())
+                .build(),
+        );
+        let mut agent = FunctionCallingAgentBuilder::new(
+            crate::models::testing::ScriptedModel::new().with_final_answer("Done"),
+        )
+        .build()
+        .unwrap();
+        let steps = agent
+            .stream_run("Do the task", true)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert!(steps.iter().all(|step| step.is_ok()));
+
+        let run_id = agent.get_run_id().unwrap();
+        let spans = exporter.get_finished_spans().unwrap();
+        let run_span = spans
+            .iter()
+            .find(|span| {
+                span.attributes.iter().any(|attribute| {
+                    attribute.key.as_str() == "lumo.run_id"
+                        && attribute.value.as_str() == run_id.as_str()
+                }) && span.name.ends_with(" run")
+            })
+            .expect("The streamed run has a run span");
+        assert!(spans
+            .iter()
+            .any(|span| span.name == "Step 1"
+                && span.parent_span_id == run_span.span_context.span_id()));
+    }
+
+    /// Streams the start of its text, then holds the stream until it is released before it calls `final_answer`.
+    #[cfg(feature = "stream")]
+    #[derive(Debug)]
+    struct HeldStreamModel {
+        release: std::sync::Mutex<Option<futures::channel::oneshot::Receiver<()>>>,
+    }
+
+    #[cfg(feature = "stream")]
+    #[async_trait]
+    impl Model for HeldStreamModel {
+        async fn run(
+            &self,
+            _input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            _config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            Err(AgentError::Execution("The model only streams".to_string()))
+        }
+
+        async fn run_stream(
+            &self,
+            _input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            _config: GenerationConfig,
+        ) -> Result<crate::models::stream::ModelStream, AgentError> {
+            let release = self.release.lock().unwrap().take();
+            Ok(Box::pin(async_stream::stream! {
+                yield Ok(ChatChunk::TextDelta("Let me answer.".to_string()));
+                if let Some(release) = release {
+                    let _ = release.await;
+                }
+                yield Ok(ChatChunk::ToolCall(ToolCall {
+                    id: Some("call_1".to_string()),
+                    call_type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: "final_answer".to_string(),
+                        arguments: json!({"answer": "Done"}),
+                    },
+                }));
+            }))
+        }
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_stream_events() {
+        let (release, held) = futures::channel::oneshot::channel();
+        let mut agent = FunctionCallingAgentBuilder::new(HeldStreamModel {
+            release: std::sync::Mutex::new(Some(held)),
+        })
+        .build()
+        .unwrap();
+        let mut events = agent.stream_events("Do the task", true).unwrap();
+
+        // The model has not finished its answer, so the text can only come from the step that is still running
+        let first = events.next().await.unwrap().unwrap();
+        assert!(
+            matches!(&first, AgentEvent::Chunk(ChatChunk::TextDelta(text)) if text == "Let me answer.")
+        );
+
+        release.send(()).unwrap();
+        let events = events.collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[0],
+            Ok(AgentEvent::Chunk(ChatChunk::ToolCall(call))) if call.function.name == "final_answer"
+        ));
+        match &events[1] {
+            Ok(AgentEvent::Step(Step::ActionStep(step))) => {
+                assert_eq!(step.final_answer.as_deref(), Some("Done"))
+            }
+            event => panic!("Expected the step, got {:?}", event),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_steps_without_answer() {
+        let mut agent = FunctionCallingAgentBuilder::new(
+            crate::models::testing::ScriptedModel::new().with_response(""),
+        )
+        .with_max_steps(Some(1))
+        .build()
+        .unwrap();
+        let error = agent.run("What is 6 times 7?", true).await.unwrap_err();
+        assert!(matches!(error.error, AgentError::MaxStepsExceeded(_)));
+    }
+
+    /// An agent whose first step spends more tokens than its budget, so that the budget is exceeded when it runs out
+    /// of steps.
+    fn over_budget_agent(
+        model: crate::models::testing::ScriptedModel,
+    ) -> FunctionCallingAgent<crate::models::testing::ScriptedModel> {
+        FunctionCallingAgentBuilder::new(model)
+            .with_tools(vec![Box::new(DeleteFileTool {
+                calls: std::sync::Arc::default(),
+            })])
+            .with_budget(Some(Budget::new().with_max_tokens(100)))
+            .with_max_steps(Some(2))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_budget_exceeded_before_final_answer() {
+        let model = crate::models::testing::ScriptedModel::new()
+            .with_tool_call("delete_file", json!({"path": "/tmp/report.txt"}))
+            .with_usage(Usage::new(1000, 10));
+        let mut agent = over_budget_agent(model.clone());
+        let error = agent.run("Delete the report", true).await.unwrap_err();
+        assert!(matches!(error.error, AgentError::BudgetExceeded(_)));
+        assert_eq!(model.call_count(), 1);
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_streamed_budget_exceeded_before_final_answer() {
+        let model = crate::models::testing::ScriptedModel::new()
+            .with_tool_call("delete_file", json!({"path": "/tmp/report.txt"}))
+            .with_usage(Usage::new(1000, 10));
+        let mut agent = over_budget_agent(model.clone());
+        let events = agent
+            .stream_events("Delete the report", true)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        let error = events
+            .iter()
+            .find_map(|event| event.as_ref().err())
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<AgentError>(),
+            Some(AgentError::BudgetExceeded(_))
+        ));
+        assert_eq!(model.call_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_planning_step() {
+        let mut agent = FunctionCallingAgentBuilder::new(
+            crate::models::testing::ScriptedModel::new().with_error("The model is down"),
+        )
+        .with_planning_interval(Some(1))
+        .build()
+        .unwrap();
+        let error = agent.run("What is 6 times 7?", true).await.unwrap_err();
+        assert!(error.error.to_string().contains("The model is down"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_managed_agent_is_an_observation() {
+        let researcher = FunctionCallingAgentBuilder::new(
+            crate::models::testing::ScriptedModel::new()
+                .with_response("")
+                .with_usage(Usage::new(10, 5)),
+        )
+        .with_name(Some("researcher"))
+        .with_description(Some("Looks things up"))
+        .with_max_steps(Some(1))
+        .build()
+        .unwrap();
+        let model = crate::models::testing::ScriptedModel::new()
+            .with_tool_call("researcher", json!({"task": "Find the answer"}))
+            .with_final_answer("42");
+        let mut agent = FunctionCallingAgentBuilder::new(model.clone())
+            .with_managed_agents(vec![Box::new(researcher)])
+            .build()
+            .unwrap();
+        let answer = agent.run("What is 6 times 7?", false).await.unwrap();
+        assert_eq!(answer, "42");
+        let observation = model.calls()[1].last_message().unwrap().to_string();
+        assert!(observation.contains("Managed agent researcher failed"));
+        assert!(agent.get_usage().input_tokens >= 10);
+    }
 }
@@ -0,0 +1,228 @@
+//! Hooks let users observe and rewrite what happens inside an agent step without reimplementing `step()`.
+//!
+//! Every callback has a no-op default, so a hook only implements the events it cares about. Values are passed by
+//! mutable reference: a hook can inject messages into the agent memory, rewrite a tool call before it is executed,
+//! or redact an observation before the model sees it. Returning an error aborts the step with that error.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::{
+    errors::AgentError,
+    models::{openai::ToolCall, types::Usage},
+};
+
+use super::agent_step::AgentStep;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait AgentHook: Send + Sync {
+    /// Called when the agent starts a run.
+    async fn on_run_start(&self, _agent: &str, _task: &str) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Called at the start of every step, after the agent memory for the step has been written to
+    /// `step.agent_memory`. Changes to the memory are used as the model input.
+    async fn on_step_start(&self, _step: &mut AgentStep) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Called with the text of the model response before it is parsed.
+    async fn on_llm_response(&self, _response: &mut String) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Called for every tool call before it is executed.
+    async fn on_tool_call(&self, _tool_call: &mut ToolCall) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Called with the observation produced by a tool call before it is added to the agent memory.
+    async fn on_observation(
+        &self,
+        _tool_call: &ToolCall,
+        _observation: &mut String,
+    ) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Called with the final answer before it is returned.
+    async fn on_final_answer(&self, _answer: &mut String) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Called after every step with the tokens the step used and how long it took.
+    async fn on_step_end(
+        &self,
+        _step: &AgentStep,
+        _usage: &Usage,
+        _duration: Duration,
+    ) -> Result<(), AgentError> {
+        Ok(())
+    }
+
+    /// Called when the run is over, with its answer or error and the tokens and time of the whole run.
+    async fn on_run_end(
+        &self,
+        _result: &Result<String, AgentError>,
+        _usage: &Usage,
+        _duration: Duration,
+    ) -> Result<(), AgentError> {
+        Ok(())
+    }
+}
+
+/// An ordered list of hooks. Each callback is run on every hook in the order they were added, so a hook sees the
+/// values as rewritten by the hooks before it.
+#[derive(Clone, Default)]
+pub struct AgentHooks {
+    hooks: Vec<Arc<dyn AgentHook>>,
+}
+
+impl AgentHooks {
+    pub fn new(hooks: Vec<Arc<dyn AgentHook>>) -> Self {
+        Self { hooks }
+    }
+
+    pub fn push(&mut self, hook: Arc<dyn AgentHook>) {
+        self.hooks.push(hook);
+    }
+
+    pub fn extend(&mut self, hooks: Vec<Arc<dyn AgentHook>>) {
+        self.hooks.extend(hooks);
+    }
+
+    pub fn len(&self) -> usize {
+        self.hooks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AgentHook for AgentHooks {
+    async fn on_run_start(&self, agent: &str, task: &str) -> Result<(), AgentError> {
+        for hook in &self.hooks {
+            hook.on_run_start(agent, task).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_step_start(&self, step: &mut AgentStep) -> Result<(), AgentError> {
+        for hook in &self.hooks {
+            hook.on_step_start(step).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_llm_response(&self, response: &mut String) -> Result<(), AgentError> {
+        for hook in &self.hooks {
+            hook.on_llm_response(response).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_tool_call(&self, tool_call: &mut ToolCall) -> Result<(), AgentError> {
+        for hook in &self.hooks {
+            hook.on_tool_call(tool_call).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_observation(
+        &self,
+        tool_call: &ToolCall,
+        observation: &mut String,
+    ) -> Result<(), AgentError> {
+        for hook in &self.hooks {
+            hook.on_observation(tool_call, observation).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_final_answer(&self, answer: &mut String) -> Result<(), AgentError> {
+        for hook in &self.hooks {
+            hook.on_final_answer(answer).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_step_end(
+        &self,
+        step: &AgentStep,
+        usage: &Usage,
+        duration: Duration,
+    ) -> Result<(), AgentError> {
+        for hook in &self.hooks {
+            hook.on_step_end(step, usage, duration).await?;
+        }
+        Ok(())
+    }
+
+    async fn on_run_end(
+        &self,
+        result: &Result<String, AgentError>,
+        usage: &Usage,
+        duration: Duration,
+    ) -> Result<(), AgentError> {
+        for hook in &self.hooks {
+            hook.on_run_end(result, usage, duration).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Suffix(&'static str);
+
+    #[async_trait]
+    impl AgentHook for Suffix {
+        async fn on_final_answer(&self, answer: &mut String) -> Result<(), AgentError> {
+            answer.push_str(self.0);
+            Ok(())
+        }
+    }
+
+    struct Reject;
+
+    #[async_trait]
+    impl AgentHook for Reject {
+        async fn on_tool_call(&self, tool_call: &mut ToolCall) -> Result<(), AgentError> {
+            Err(AgentError::Execution(format!(
+                "{} is not allowed",
+                tool_call.function.name
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hooks_run_in_order() {
+        let hooks = AgentHooks::new(vec![
+            Arc::new(Suffix(" a")),
+            Arc::new(Suffix(" b")),
+            Arc::new(Reject),
+        ]);
+        let mut answer = "answer".to_string();
+        hooks.on_final_answer(&mut answer).await.unwrap();
+        assert_eq!(answer, "answer a b");
+
+        let mut tool_call = ToolCall {
+            id: None,
+            call_type: None,
+            function: crate::models::openai::FunctionCall {
+                name: "python_interpreter".to_string(),
+                arguments: serde_json::json!({}),
+            },
+        };
+        assert!(hooks.on_tool_call(&mut tool_call).await.is_err());
+    }
+}
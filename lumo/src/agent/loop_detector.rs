@@ -0,0 +1,217 @@
+//! Loop detection, which stops an agent from calling a tool again and again with the same arguments.
+//!
+//! A model that does not know what to do next often repeats its last call, sometimes with trivially different
+//! arguments such as another case or word order. Once a tool has been called `max_repeats` times in a task with
+//! arguments that the [`LoopDetector`] finds alike, the next such call is not made: its observation says that the
+//! call was already made and repeats the last result, so the model can move on.
+//!
+//! Arguments are alike when their words, keys and values together, are the same regardless of case, punctuation
+//! and order. [`LoopDetector::with_similarity`] lowers the share of words they must have in common.
+//!
+//! ```rust,ignore
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_tools(tools)
+//!     .with_loop_detector(LoopDetector::new().with_max_repeats(2).with_similarity(0.8))
+//!     .build()?;
+//! ```
+
+use std::collections::BTreeSet;
+
+use serde_json::Value;
+
+use super::agent_step::Step;
+use crate::models::openai::FunctionCall;
+
+const REPEATED_CALL_PREFIX: &str = "You already called";
+
+/// The number of characters of the earlier result that the observation repeats.
+const MAX_RESULT_CHARS: usize = 2000;
+
+#[derive(Debug, Clone)]
+pub struct LoopDetector {
+    /// The number of alike calls of a tool that are made in a task. Later ones get the earlier result.
+    pub max_repeats: usize,
+    /// The share of words, from 0 to 1, that the arguments of two calls must have in common to be alike.
+    pub similarity: f64,
+}
+
+impl Default for LoopDetector {
+    fn default() -> Self {
+        Self {
+            max_repeats: 2,
+            similarity: 1.0,
+        }
+    }
+}
+
+impl LoopDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_repeats(mut self, max_repeats: usize) -> Self {
+        self.max_repeats = max_repeats.max(1);
+        self
+    }
+
+    pub fn with_similarity(mut self, similarity: f64) -> Self {
+        self.similarity = similarity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// The observation to give `call` in place of making it, if the current task of `logs` already has
+    /// `max_repeats` alike calls.
+    pub fn check(&self, logs: &[Step], call: &FunctionCall) -> Option<String> {
+        let words = argument_words(&call.arguments);
+        let mut repeats = 0;
+        let mut last_result = None;
+        for log in logs.iter().rev() {
+            let step = match log {
+                Step::ActionStep(step) => step,
+                Step::TaskStep(_) => break,
+                _ => continue,
+            };
+            let calls = step.tool_call.as_deref().unwrap_or_default();
+            for (index, earlier) in calls.iter().enumerate().rev() {
+                if earlier.function.name != call.name
+                    || similarity(&words, &argument_words(&earlier.function.arguments))
+                        < self.similarity
+                {
+                    continue;
+                }
+                repeats += 1;
+                let result = step
+                    .observations
+                    .as_ref()
+                    .and_then(|observations| observations.get(index))
+                    .filter(|result| !result.starts_with(REPEATED_CALL_PREFIX));
+                if last_result.is_none() {
+                    last_result = result.cloned();
+                }
+            }
+        }
+        if repeats < self.max_repeats {
+            return None;
+        }
+        let mut observation = format!(
+            "{} `{}` with these arguments {} times in this task, so it was not called again.",
+            REPEATED_CALL_PREFIX, call.name, repeats
+        );
+        if let Some(last_result) = last_result {
+            let mut result = last_result
+                .chars()
+                .take(MAX_RESULT_CHARS)
+                .collect::<String>();
+            if result.chars().count() < last_result.chars().count() {
+                result.push_str("...");
+            }
+            observation.push_str(&format!(" The last result was:\n{}\n", result));
+        }
+        observation
+            .push_str(" Use this result, or try another tool or other arguments to make progress.");
+        Some(observation)
+    }
+}
+
+/// The lowercase words of the keys and values of `arguments`.
+fn argument_words(arguments: &Value) -> BTreeSet<String> {
+    let mut words = BTreeSet::new();
+    collect_words(arguments, &mut words);
+    words
+}
+
+fn collect_words(value: &Value, words: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object {
+                add_words(key, words);
+                collect_words(value, words);
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect_words(value, words)),
+        Value::String(text) => add_words(text, words),
+        Value::Null => {}
+        value => add_words(&value.to_string(), words),
+    }
+}
+
+fn add_words(text: &str, words: &mut BTreeSet<String>) {
+    words.extend(
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase),
+    );
+}
+
+/// The Jaccard similarity of two sets of words. Two empty sets are the same.
+fn similarity(first: &BTreeSet<String>, second: &BTreeSet<String>) -> f64 {
+    let union = first.union(second).count();
+    if union == 0 {
+        return 1.0;
+    }
+    first.intersection(second).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::AgentStep, models::openai::ToolCall};
+    use serde_json::json;
+
+    fn step(step: usize, arguments: Value, observation: &str) -> Step {
+        Step::ActionStep(AgentStep {
+            tool_call: Some(vec![ToolCall {
+                id: None,
+                call_type: Some("function".to_string()),
+                function: FunctionCall {
+                    name: "search".to_string(),
+                    arguments,
+                },
+            }]),
+            observations: Some(vec![observation.to_string()]),
+            ..AgentStep::new(step, None)
+        })
+    }
+
+    #[test]
+    fn test_loop_detector() {
+        let call = FunctionCall {
+            name: "search".to_string(),
+            arguments: json!({"query": "Eindhoven population"}),
+        };
+        let mut logs = vec![
+            Step::TaskStep("How many people live in Eindhoven?".to_string()),
+            step(1, json!({"query": "population of Eindhoven"}), "No results"),
+            step(
+                2,
+                json!({"query": "Population, Eindhoven"}),
+                "About 240,000",
+            ),
+        ];
+        let detector = LoopDetector::new();
+        assert_eq!(detector.check(&logs, &call), None);
+
+        logs.push(step(
+            3,
+            json!({"query": "eindhoven  POPULATION"}),
+            "About 240,000",
+        ));
+        let observation = detector.check(&logs, &call).unwrap();
+        assert!(observation.starts_with("You already called `search` with these arguments 2 times"));
+        assert!(observation.contains("The last result was:\nAbout 240,000"));
+
+        logs.push(step(
+            4,
+            json!({"query": "Eindhoven population"}),
+            &observation,
+        ));
+        let observation = detector.check(&logs, &call).unwrap();
+        assert!(observation.contains("3 times"));
+        assert!(observation.contains("About 240,000"));
+
+        let similar = LoopDetector::new().with_max_repeats(3).with_similarity(0.5);
+        assert!(similar.check(&logs, &call).is_some());
+        logs.push(Step::TaskStep("And Tilburg?".to_string()));
+        assert_eq!(detector.check(&logs, &call), None);
+    }
+}
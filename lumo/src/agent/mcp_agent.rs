@@ -1,16 +1,23 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 
 use crate::{
-    agent::parse_response,
+    agent::{malformed_tool_call_error, parse_response, parse_retry_messages},
+    artifacts::ArtifactStore,
+    context::RunContext,
     errors::AgentError,
     models::{
+        conversation::{validate_messages, Conversation},
         model_traits::Model,
         openai::{FunctionCall, ToolCall},
-        types::Message,
+        prefill::Prefill,
+        types::{GenerationConfig, Message, Usage},
     },
-    prompts::TOOL_CALLING_SYSTEM_PROMPT,
+    prompts::{render_template, TOOL_CALLING_SYSTEM_PROMPT},
     telemetry::AgentTelemetry,
-    tools::{ToolFunctionInfo, ToolGroup, ToolInfo, ToolType},
+    tools::{
+        AgentIo, AnyTool, AskUserTool, ReadArtifactTool, ToolFunctionInfo, ToolGroup, ToolInfo,
+        ToolRetryPolicy, ToolSelector, ToolType, SEARCH_TOOLS_NAME,
+    },
 };
 use anyhow::Result;
 use async_trait::async_trait;
@@ -21,10 +28,14 @@ use opentelemetry::trace::{FutureExt, TraceContextExt};
 use serde_json::json;
 use tracing::instrument;
 
-use super::{Agent, AgentStep, MultiStepAgent, Step};
+use super::{
+    dry_run::dry_run_observation, Agent, AgentHook, AgentHooks, AgentStep, Budget, Delegation,
+    DelegationLimits, LoopDetector, MultiStepAgent, ReflectionConfig, RuntimeFacts, Step,
+    DEFAULT_MAX_OBSERVATION_SIZE,
+};
 
 #[cfg(feature = "stream")]
-use super::agent_trait::AgentStream;
+use {super::agent_trait::AgentStream, crate::models::stream::ChunkSink};
 
 fn initialize_system_prompt(system_prompt: String, tools: Vec<Tool>) -> Result<String> {
     let tool_names = tools
@@ -32,10 +43,23 @@ fn initialize_system_prompt(system_prompt: String, tools: Vec<Tool>) -> Result<S
         .map(|tool| tool.name.clone())
         .collect::<Vec<_>>();
     let tool_description = serde_json::to_string(&tools)?;
-    let mut system_prompt = system_prompt.replace("{{tool_names}}", &tool_names.join(", "));
-    system_prompt = system_prompt.replace("{{tool_descriptions}}", &tool_description);
-    system_prompt = system_prompt.replace("{{current_time}}", &chrono::Local::now().to_string());
-    Ok(system_prompt)
+    let now = chrono::Local::now();
+    Ok(render_template(
+        &system_prompt,
+        [
+            ("tool_names", tool_names.join(", ").as_str()),
+            ("tool_descriptions", tool_description.as_str()),
+            ("tools", tool_description.as_str()),
+            ("current_time", now.to_string().as_str()),
+            ("current_date", now.format("%Y-%m-%d").to_string().as_str()),
+        ],
+    ))
+}
+
+/// Whether the tool `name` of the base agent, such as `read_artifact`, is offered to the model and called like the
+/// tools of the MCP servers. The final answer and the tool search have their own handling.
+fn is_local_tool(name: &str) -> bool {
+    name != "final_answer" && name != SEARCH_TOOLS_NAME
 }
 
 pub struct McpAgent<M, S>
@@ -141,6 +165,23 @@ where
     history: Option<Vec<Message>>,
     mcp_clients: Vec<McpClient<S>>,
     logging_level: Option<log::LevelFilter>,
+    hooks: Vec<Arc<dyn AgentHook>>,
+    generation_config: Option<GenerationConfig>,
+    parse_retry: usize,
+    budget: Option<Budget>,
+    max_observation_size: Option<usize>,
+    artifact_store: Option<Arc<dyn ArtifactStore>>,
+    io: Option<Arc<dyn AgentIo>>,
+    tool_retry: Option<ToolRetryPolicy>,
+    stop_sequences: Option<Vec<String>>,
+    context: Option<RunContext>,
+    reflection: Option<ReflectionConfig>,
+    runtime_facts: Option<RuntimeFacts>,
+    prefill: Option<Prefill>,
+    loop_detector: Option<LoopDetector>,
+    citations: bool,
+    delegation_limits: Option<DelegationLimits>,
+    max_tools_per_request: Option<usize>,
 }
 
 impl<'a, M, S> McpAgentBuilder<'a, M, S>
@@ -160,6 +201,23 @@ where
             history: None,
             mcp_clients: vec![],
             logging_level: None,
+            hooks: vec![],
+            generation_config: None,
+            parse_retry: 0,
+            budget: None,
+            max_observation_size: None,
+            artifact_store: None,
+            io: None,
+            tool_retry: None,
+            stop_sequences: None,
+            context: None,
+            reflection: None,
+            runtime_facts: None,
+            prefill: None,
+            loop_detector: None,
+            citations: false,
+            delegation_limits: None,
+            max_tools_per_request: None,
         }
     }
     pub fn with_name(mut self, name: Option<&'a str>) -> Self {
@@ -170,6 +228,60 @@ where
         self.system_prompt = system_prompt;
         self
     }
+    /// Replaces the stop sequences of the action steps, which default to `Observation:`. An empty list sends no stop
+    /// sequences, for chat templates that break on them.
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.stop_sequences = Some(stop_sequences);
+        self
+    }
+    /// The [`RunContext`] shared by the tools of the agent, whose values the prompts can use as
+    /// `{{context.<key>}}`. Keep a clone to seed it before a run and read it afterwards.
+    pub fn with_context(mut self, context: RunContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+    /// Tells the model the current date and time, the timezone and other facts about the environment, which it
+    /// cannot know on its own. The facts are gathered again at every step; see [`RuntimeFacts`] for which ones and
+    /// where they go in the messages.
+    pub fn with_runtime_facts(mut self, runtime_facts: RuntimeFacts) -> Self {
+        self.runtime_facts = Some(runtime_facts);
+        self
+    }
+    /// Starts the answer of the model with a prefill at every step, or at the steps the prefill is given for.
+    pub fn with_prefill(mut self, prefill: Prefill) -> Self {
+        self.prefill = Some(prefill);
+        self
+    }
+    /// Answers a tool call with the earlier result instead of making it, once the task has enough calls like it.
+    pub fn with_loop_detector(mut self, loop_detector: LoopDetector) -> Self {
+        self.loop_detector = Some(loop_detector);
+        self
+    }
+    /// Gives the observations ids and asks the model to cite the ones its final answer uses. The cited
+    /// observations are the citations of the [`Answer`](crate::agent::Answer) of the final step.
+    pub fn with_citations(mut self, citations: bool) -> Self {
+        self.citations = citations;
+        self
+    }
+    /// Makes the agent critique its own work every few steps or before it returns its final answer. A rejected
+    /// final answer is dropped and the critique is added as an observation, so the agent keeps going. Every review
+    /// is an extra model call, counted in the usage of the run.
+    pub fn with_reflection(mut self, reflection: ReflectionConfig) -> Self {
+        self.reflection = Some(reflection);
+        self
+    }
+    /// Limits how deep managed agents can be nested and how many steps they can take together. The limits of the
+    /// top-level agent apply to the whole hierarchy.
+    pub fn with_delegation_limits(mut self, delegation_limits: DelegationLimits) -> Self {
+        self.delegation_limits = Some(delegation_limits);
+        self
+    }
+    /// Sends at most `max_tools_per_request` tools with each request, for providers that cap the number of tools.
+    /// The tools that best match the task are sent, and the model can find the others with the `search_tools` tool.
+    pub fn with_max_tools_per_request(mut self, max_tools_per_request: usize) -> Self {
+        self.max_tools_per_request = Some(max_tools_per_request);
+        self
+    }
     pub fn with_managed_agents(mut self, managed_agents: Vec<Box<dyn Agent>>) -> Self {
         self.managed_agents = managed_agents;
         self
@@ -190,6 +302,11 @@ where
         self.history = history;
         self
     }
+    /// The messages before the task, checked against the rules of the chat APIs when the agent is built.
+    pub fn with_conversation(mut self, conversation: Conversation) -> Self {
+        self.history = Some(conversation.into_messages());
+        self
+    }
     pub fn with_mcp_clients(mut self, mcp_clients: Vec<McpClient<S>>) -> Self {
         self.mcp_clients = mcp_clients;
         self
@@ -198,8 +315,60 @@ where
         self.logging_level = logging_level;
         self
     }
+    /// Sampling parameters used for every model call made by the agent.
+    pub fn with_generation_config(mut self, generation_config: Option<GenerationConfig>) -> Self {
+        self.generation_config = generation_config;
+        self
+    }
+    /// When the model emits a tool call that cannot be parsed, send the error back to the model and let it retry up
+    /// to `parse_retry` times before the step fails.
+    pub fn with_parse_retry(mut self, parse_retry: usize) -> Self {
+        self.parse_retry = parse_retry;
+        self
+    }
+    /// Limits the tokens, cost and time of every run. The run fails with [`AgentError::BudgetExceeded`] once a
+    /// limit is reached.
+    pub fn with_budget(mut self, budget: Option<Budget>) -> Self {
+        self.budget = budget;
+        self
+    }
+    /// Observations longer than this many characters are truncated before they are added to the memory. Defaults
+    /// to 30000.
+    pub fn with_max_observation_size(mut self, max_observation_size: Option<usize>) -> Self {
+        self.max_observation_size = max_observation_size;
+        self
+    }
+    /// Saves the full output of truncated observations in `artifact_store` and gives the agent a `read_artifact`
+    /// tool to read them.
+    pub fn with_artifact_store(mut self, artifact_store: Option<Arc<dyn ArtifactStore>>) -> Self {
+        self.artifact_store = artifact_store;
+        self
+    }
+    /// Gives the agent an `ask_user` tool, which asks the user a question through `io` and waits for the reply.
+    pub fn with_io(mut self, io: Arc<dyn AgentIo>) -> Self {
+        self.io = Some(io);
+        self
+    }
+    /// How the tools of the agent itself, such as `final_answer` and `read_artifact`, are retried when they time
+    /// out or are rate limited. Defaults to two retries with exponential backoff.
+    pub fn with_tool_retry(mut self, tool_retry: Option<ToolRetryPolicy>) -> Self {
+        self.tool_retry = tool_retry;
+        self
+    }
+    /// Adds a hook that is run on every step. Hooks run in the order they are added.
+    pub fn with_hook(mut self, hook: impl AgentHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+    pub fn with_hooks(mut self, hooks: Vec<Arc<dyn AgentHook>>) -> Self {
+        self.hooks.extend(hooks);
+        self
+    }
     pub async fn build(self) -> Result<McpAgent<M, S>> {
-        McpAgent::new(
+        if let Some(history) = &self.history {
+            validate_messages(history)?;
+        }
+        let mut agent = McpAgent::new(
             self.name,
             self.model,
             self.system_prompt,
@@ -211,11 +380,50 @@ where
             self.history,
             self.logging_level,
         )
-        .await
+        .await?;
+        agent.base_agent.hooks = AgentHooks::new(self.hooks);
+        agent.base_agent.generation_config = self.generation_config.unwrap_or_default();
+        agent.base_agent.parse_retry = self.parse_retry;
+        agent.base_agent.budget = self.budget;
+        agent.base_agent.max_observation_size = self
+            .max_observation_size
+            .unwrap_or(DEFAULT_MAX_OBSERVATION_SIZE);
+        if let Some(store) = &self.artifact_store {
+            agent
+                .base_agent
+                .tools
+                .push(Box::new(ReadArtifactTool::new(store.clone(), None)));
+        }
+        agent.base_agent.artifact_store = self.artifact_store;
+        agent.base_agent.tool_retry = self.tool_retry.unwrap_or_default();
+        if let Some(io) = self.io {
+            agent.base_agent.tools.push(Box::new(AskUserTool::new(io)));
+        }
+        if let Some(stop_sequences) = self.stop_sequences {
+            agent.base_agent.stop_sequences = stop_sequences;
+        }
+        if let Some(context) = self.context {
+            agent.base_agent.context = context;
+        }
+        agent.base_agent.reflection = self.reflection;
+        agent.base_agent.runtime_facts = self.runtime_facts;
+        agent.base_agent.prefill = self.prefill;
+        agent.base_agent.loop_detector = self.loop_detector;
+        agent.base_agent.citations = self.citations;
+        if let Some(delegation_limits) = self.delegation_limits {
+            agent.base_agent.delegation = Delegation::new(delegation_limits);
+        }
+        if let Some(max_tools_per_request) = self.max_tools_per_request {
+            let selector = ToolSelector::new(max_tools_per_request);
+            agent.base_agent.tools.push(Box::new(selector.search_tool()));
+            agent.base_agent.tool_selector = Some(selector);
+        }
+        Ok(agent)
     }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl<M, S> Agent for McpAgent<M, S>
 where
     M: Model + std::fmt::Debug + Send + Sync,
@@ -251,12 +459,82 @@ where
     fn increment_step_number(&mut self) {
         self.base_agent.increment_step_number();
     }
+    fn get_logs(&self) -> &[Step] {
+        self.base_agent.get_logs()
+    }
     fn get_logs_mut(&mut self) -> &mut Vec<Step> {
         self.base_agent.get_logs_mut()
     }
     fn model(&self) -> &dyn Model {
         self.base_agent.model()
     }
+    fn get_generation_config(&self) -> GenerationConfig {
+        self.base_agent.get_generation_config()
+    }
+    fn get_history(&self) -> Option<Vec<Message>> {
+        self.base_agent.get_history()
+    }
+    fn set_history(&mut self, history: Option<Vec<Message>>) {
+        self.base_agent.set_history(history);
+    }
+    fn get_usage(&self) -> Usage {
+        self.base_agent.get_usage()
+    }
+    fn add_usage(&mut self, usage: Usage) {
+        self.base_agent.add_usage(usage);
+    }
+    fn get_budget(&self) -> Option<Budget> {
+        self.base_agent.get_budget()
+    }
+    fn get_hooks(&self) -> AgentHooks {
+        self.base_agent.get_hooks()
+    }
+    fn get_final_step_prompt(&self) -> &str {
+        self.base_agent.get_final_step_prompt()
+    }
+    fn get_context(&self) -> RunContext {
+        self.base_agent.get_context()
+    }
+    fn set_context(&mut self, context: RunContext) {
+        self.base_agent.set_context(context);
+    }
+    fn get_reflection(&self) -> Option<ReflectionConfig> {
+        self.base_agent.get_reflection()
+    }
+    fn get_runtime_facts(&self) -> Option<RuntimeFacts> {
+        self.base_agent.get_runtime_facts()
+    }
+    fn is_dry_run(&self) -> bool {
+        self.base_agent.is_dry_run()
+    }
+    fn cites_sources(&self) -> bool {
+        self.base_agent.cites_sources()
+    }
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.base_agent.set_dry_run(dry_run);
+    }
+    #[cfg(feature = "stream")]
+    fn set_chunk_sink(&mut self, sink: Option<ChunkSink>) {
+        self.base_agent.set_chunk_sink(sink);
+    }
+    fn get_delegation(&self) -> Delegation {
+        self.base_agent.get_delegation()
+    }
+    fn set_delegation(&mut self, delegation: Delegation) {
+        self.base_agent.set_delegation(delegation);
+    }
+    fn get_run_id(&self) -> Option<String> {
+        self.base_agent.get_run_id()
+    }
+    fn set_run_id(&mut self, run_id: String) {
+        self.base_agent.set_run_id(run_id);
+    }
+    fn get_parent_run_id(&self) -> Option<String> {
+        self.base_agent.get_parent_run_id()
+    }
+    fn set_parent_run_id(&mut self, parent_run_id: Option<String>) {
+        self.base_agent.set_parent_run_id(parent_run_id);
+    }
     fn get_planning_interval(&self) -> Option<usize> {
         self.base_agent.get_planning_interval()
     }
@@ -280,11 +558,14 @@ where
     async fn step(&mut self, log_entry: &mut Step) -> Result<Option<AgentStep>, AgentError> {
         match log_entry {
             Step::ActionStep(step_log) => {
+                self.telemetry.set_run_id(self.base_agent.run_id.clone());
                 let cx = self.telemetry.start_step(self.get_step_number() as i64);
 
                 let agent_memory = self.base_agent.write_inner_memory_from_logs(None)?;
+                step_log.agent_memory = Some(agent_memory);
+                self.base_agent.hooks.on_step_start(step_log).await?;
+                let agent_memory = step_log.agent_memory.clone().unwrap_or_default();
                 self.base_agent.input_messages = Some(agent_memory.clone());
-                step_log.agent_memory = Some(agent_memory.clone());
                 self.telemetry
                     .log_agent_memory(&serde_json::to_value(&agent_memory).unwrap_or_default());
                 let mut tools = self
@@ -317,6 +598,17 @@ where
                     .collect::<Vec<_>>();
 
                 tools.extend(managed_agents);
+                tools.extend(
+                    self.base_agent
+                        .tools
+                        .iter()
+                        .filter(|tool| is_local_tool(tool.name()))
+                        .map(|tool| tool.tool_info()),
+                );
+                if let Some(selector) = &self.base_agent.tool_selector {
+                    tools.push(selector.search_tool().tool_info());
+                    tools = selector.select(&self.base_agent.task, tools);
+                }
 
                 // Add final answer tool
                 // let final_answer_tool = ToolInfo::from(Tool::new(
@@ -336,23 +628,52 @@ where
                 // tools.push(final_answer_tool);
 
                 tracing::debug!("Starting model inference with {} tools", tools.len());
-                let model_message = self
-                    .base_agent
-                    .model
-                    .run(
-                        self.base_agent.input_messages.as_ref().unwrap().clone(),
-                        self.base_agent.history.clone(),
-                        tools,
-                        None,
-                        Some(HashMap::from([(
-                            "stop".to_string(),
-                            vec!["Observation:".to_string()],
-                        )])),
-                    )
-                    .with_context(cx.clone())
-                    .await?;
+                let mut config = GenerationConfig::new()
+                    .with_stop(self.base_agent.stop_sequences.clone())
+                    .merge(&self.base_agent.generation_config);
+                if let Some(prefill) = self.base_agent.step_prefill() {
+                    config.prefill = Some(prefill);
+                }
+                let mut input_messages = agent_memory.clone();
+                let mut retries = 0;
+                let model_message = loop {
+                    let model_message = self
+                        .base_agent
+                        .call_model(input_messages.clone(), tools.clone(), config.clone())
+                        .with_context(cx.clone())
+                        .await?;
+                    self.base_agent.usage += model_message.get_usage().unwrap_or_default();
+                    let response = model_message.get_response().unwrap_or_default();
+                    let tool_calls = model_message.get_tools_used().unwrap_or_default();
+                    match malformed_tool_call_error(&response, &tool_calls) {
+                        Some(error) if retries < self.base_agent.parse_retry => {
+                            retries += 1;
+                            tracing::warn!(
+                                error = %error,
+                                retry = retries,
+                                "Could not parse tool call, asking the model to repair it"
+                            );
+                            input_messages.extend(parse_retry_messages(
+                                &response,
+                                &tool_calls,
+                                &error,
+                            ));
+                        }
+                        Some(error) if self.base_agent.parse_retry > 0 => {
+                            step_log.llm_output = Some(response);
+                            step_log.error = Some(error);
+                            cx.span().end_with_timestamp(crate::runtime::now());
+                            return Ok(Some(step_log.clone()));
+                        }
+                        _ => break model_message,
+                    }
+                };
 
-                step_log.llm_output = Some(model_message.get_response().unwrap_or_default());
+                let mut response = model_message.get_response();
+                if let Ok(text) = response.as_mut() {
+                    self.base_agent.hooks.on_llm_response(text).await?;
+                }
+                step_log.llm_output = Some(response.clone().unwrap_or_default());
                 let mut observations = Vec::new();
                 let mut tools = model_message.get_tools_used()?;
 
@@ -364,7 +685,7 @@ where
 
                 self.telemetry.log_tool_calls(&tools, &cx);
 
-                if let Ok(response) = model_message.get_response() {
+                if let Ok(mut response) = response {
                     if !response.trim().is_empty() {
                         if let Ok(action) = parse_response(&response) {
                             tools = vec![ToolCall {
@@ -382,14 +703,22 @@ where
                     }
                     if tools.is_empty() {
                         self.base_agent.write_inner_memory_from_logs(None)?;
-                        step_log.final_answer = Some(response.clone());
+                        self.base_agent.hooks.on_final_answer(&mut response).await?;
+                        step_log.final_answer = Some(self.base_agent.new_answer(&response));
                         step_log.observations = Some(vec![response.clone()]);
                         self.telemetry.log_final_answer(&response);
-                        cx.span().end_with_timestamp(std::time::SystemTime::now());
+                        cx.span().end_with_timestamp(crate::runtime::now());
                         return Ok(Some(step_log.clone()));
                     }
                 }
 
+                for tool in tools.iter_mut() {
+                    self.base_agent.hooks.on_tool_call(tool).await?;
+                }
+                if !tools.is_empty() {
+                    step_log.tool_call = Some(tools.clone());
+                }
+
                 let managed_agent_names = self
                     .base_agent
                     .managed_agents
@@ -398,17 +727,87 @@ where
                     .collect::<Vec<_>>();
 
                 let mut called_tools = Vec::new();
+                let tool_context = self.base_agent.tool_context();
                 for tool in &tools {
                     let function_name = tool.clone().function.name;
+                    let repeated = self.base_agent.repeated_call(&tool.function);
 
                     match function_name.as_str() {
                         "final_answer" => {
                             tracing::info!(answer = ?tool.function.arguments, "Final answer received");
-                            let answer = self.base_agent.tools.call(&tool.function).await?;
+                            let mut answer = self
+                                .base_agent
+                                .tools
+                                .call_with_retry(
+                                    &tool.function,
+                                    &self.base_agent.tool_retry,
+                                    &tool_context,
+                                )
+                                .await?;
+                            self.base_agent.hooks.on_final_answer(&mut answer).await?;
                             step_log.observations = Some(vec![answer.clone()]);
-                            step_log.final_answer = Some(answer.clone());
+                            step_log.final_answer = Some(self.base_agent.new_answer(&answer));
                             return Ok(Some(step_log.clone()));
                         }
+                        SEARCH_TOOLS_NAME if self.base_agent.tool_selector.is_some() => {
+                            let result = self
+                                .base_agent
+                                .tools
+                                .call_with_retry(
+                                    &tool.function,
+                                    &self.base_agent.tool_retry,
+                                    &tool_context,
+                                )
+                                .await?;
+                            let mut observation =
+                                format!("Observation from {}: {}", function_name, result);
+                            self.base_agent
+                                .hooks
+                                .on_observation(tool, &mut observation)
+                                .await?;
+                            observations.push(observation);
+                        }
+                        _ if self.base_agent.dry_run => {
+                            tracing::info!(
+                                tool = %function_name,
+                                args = ?tool.function.arguments,
+                                "Dry run, not executing tool call:"
+                            );
+                            observations.push(dry_run_observation(&function_name));
+                        }
+                        _ if repeated.is_some() => {
+                            tracing::warn!(
+                                tool = %function_name,
+                                args = ?tool.function.arguments,
+                                "Repeated tool call, not executing it again"
+                            );
+                            observations.extend(repeated);
+                        }
+                        name if is_local_tool(name)
+                            && self.base_agent.tools.iter().any(|tool| tool.name() == name) =>
+                        {
+                            let mut observation = match self
+                                .base_agent
+                                .tools
+                                .call_with_retry(
+                                    &tool.function,
+                                    &self.base_agent.tool_retry,
+                                    &tool_context,
+                                )
+                                .await
+                            {
+                                Ok(result) => {
+                                    let result = self.base_agent.limit_observation(result).await;
+                                    format!("Observation from {}: {}", function_name, result)
+                                }
+                                Err(e) => format!("Error from {}: {}", function_name, e),
+                            };
+                            self.base_agent
+                                .hooks
+                                .on_observation(tool, &mut observation)
+                                .await?;
+                            observations.push(observation);
+                        }
                         _ => {
                             tracing::info!(
                                 tool = %function_name,
@@ -431,10 +830,15 @@ where
                                             .iter()
                                             .any(|t| t.name == tool.function.name)
                                         {
-                                            futures.push(client.call_tool(
+                                            let call = client.call_tool(
                                                 &tool.function.name,
                                                 tool.function.arguments.clone(),
-                                            ));
+                                            );
+                                            futures.push(async move {
+                                                let started = crate::runtime::Instant::now();
+                                                let result = call.await;
+                                                (result, started.elapsed())
+                                            });
                                         }
                                     }
                                 }
@@ -449,26 +853,60 @@ where
                                             "Executing tool call: Agent Selected {}",
                                             function_name
                                         );
-                                        let result = self
+                                        let agent = self
                                             .base_agent
                                             .managed_agents
                                             .iter_mut()
                                             .find(|agent| agent.name() == function_name.as_str())
-                                            .unwrap()
+                                            .unwrap();
+                                        let usage_before = agent.get_usage();
+                                        agent.set_parent_run_id(self.base_agent.run_id.clone());
+                                        agent.set_delegation(
+                                            self.base_agent.delegation.child(agent.name())?,
+                                        );
+                                        let outcome = agent
                                             .run(task_str, true)
+                                            .with_context(cx.clone())
+                                            .await;
+                                        self.base_agent.usage +=
+                                            agent.get_usage().since(&usage_before);
+                                        let result = match outcome {
+                                            Ok(answer) => answer,
+                                            Err(run_error) if run_error.error.stops_manager() => {
+                                                return Err(run_error.error);
+                                            }
+                                            Err(run_error) => format!(
+                                                "Managed agent {} failed: {}",
+                                                function_name, run_error.error
+                                            ),
+                                        };
+                                        let mut result =
+                                            self.base_agent.limit_observation(result).await;
+                                        self.base_agent
+                                            .hooks
+                                            .on_observation(tool, &mut result)
                                             .await?;
                                         observations.push(result);
                                     }
                                 }
                             }
                             let results = join_all(futures).await;
-                            for (i, result) in results.into_iter().enumerate() {
+                            for (i, (result, duration)) in results.into_iter().enumerate() {
                                 let cx = self.telemetry.log_tool_execution(
                                     &called_tools[i].name,
                                     &called_tools[i].arguments,
                                     &cx,
                                 );
-                                match result {
+                                let error = result
+                                    .as_ref()
+                                    .err()
+                                    .map(|e| AgentError::Execution(e.to_string()));
+                                self.telemetry.record_tool_call(
+                                    &function_name,
+                                    duration,
+                                    error.as_ref(),
+                                );
+                                let mut observation = match result {
                                     Ok(observation) => {
                                         let text = observation
                                             .content
@@ -479,10 +917,11 @@ where
                                             })
                                             .collect::<Vec<_>>()
                                             .join("\n");
+                                        let limited =
+                                            self.base_agent.limit_observation(text.clone()).await;
                                         let formatted = format!(
                                             "Observation from {}: {}",
-                                            function_name,
-                                            text.chars().take(30000).collect::<String>()
+                                            function_name, limited
                                         );
                                         tracing::debug!(
                                             tool = %function_name,
@@ -490,8 +929,7 @@ where
                                             "Tool call succeeded"
                                         );
                                         self.telemetry.log_tool_result(&text, true, &cx);
-
-                                        observations.push(formatted);
+                                        formatted
                                     }
                                     Err(e) => {
                                         let error_msg =
@@ -502,11 +940,15 @@ where
                                             "Tool call failed"
                                         );
                                         self.telemetry.log_tool_result(&error_msg, false, &cx);
-
-                                        observations.push(error_msg);
+                                        error_msg
                                     }
-                                }
-                                cx.span().end_with_timestamp(std::time::SystemTime::now());
+                                };
+                                self.base_agent
+                                    .hooks
+                                    .on_observation(tool, &mut observation)
+                                    .await?;
+                                observations.push(observation);
+                                cx.span().end_with_timestamp(crate::runtime::now());
                             }
                         }
                     }
@@ -532,7 +974,7 @@ where
                         step_log.observations.clone().unwrap_or_default().join("\n")
                     );
                 }
-                cx.span().end_with_timestamp(std::time::SystemTime::now());
+                cx.span().end_with_timestamp(crate::runtime::now());
                 Ok(Some(step_log.clone()))
             }
             _ => {
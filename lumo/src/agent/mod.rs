@@ -4,6 +4,18 @@ pub mod multistep_agent;
 pub mod code_agent;
 pub mod function_calling_agent;
 pub mod agent_step;
+pub mod answer;
+pub mod budget;
+pub mod delegation;
+pub mod dry_run;
+pub mod hooks;
+pub mod loop_detector;
+pub mod reflection;
+pub mod run_logger;
+pub mod run_report;
+pub mod runtime_facts;
+pub mod session;
+pub mod step_strategy;
 #[cfg(feature = "mcp")]
 pub mod mcp_agent;
 pub use agent_trait::*;
@@ -12,5 +24,17 @@ pub use multistep_agent::*;
 pub use code_agent::*;
 pub use function_calling_agent::*;
 pub use agent_step::*;
+pub use answer::*;
+pub use budget::*;
+pub use delegation::*;
+pub use dry_run::*;
+pub use hooks::*;
+pub use loop_detector::*;
+pub use reflection::*;
+pub use run_logger::*;
+pub use run_report::*;
+pub use runtime_facts::*;
+pub use session::*;
+pub use step_strategy::*;
 #[cfg(feature = "mcp")]
 pub use mcp_agent::*;
\ No newline at end of file
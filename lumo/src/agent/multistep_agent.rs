@@ -1,22 +1,44 @@
-use std::collections::HashMap;
-
+use crate::artifacts::{truncate_observation, ArtifactStore};
+use crate::context::RunContext;
 use crate::errors::AgentError;
 use crate::logger::LOGGER;
-use crate::models::model_traits::Model;
-use crate::models::types::{Message, MessageRole};
+use crate::models::model_traits::{Model, ModelResponse};
+use crate::models::openai::FunctionCall;
+use crate::models::prefill::Prefill;
+use crate::models::types::{
+    join_image_data_urls, split_image_data_urls, GenerationConfig, Message, MessageRole, Usage,
+};
 use crate::prompts::{
-    user_prompt_plan, SYSTEM_PROMPT_FACTS, SYSTEM_PROMPT_PLAN, TOOL_CALLING_SYSTEM_PROMPT,
+    render_template, user_prompt_plan, FINAL_STEP_PROMPT, SYSTEM_PROMPT_FACTS, SYSTEM_PROMPT_PLAN,
+    TOOL_CALLING_SYSTEM_PROMPT,
+};
+use crate::telemetry::metrics::record_tool_cache;
+use crate::tools::{
+    AnyTool, AsyncTool, FinalAnswerTool, ToolGroup, ToolInfo, ToolRetryPolicy, ToolSelector,
 };
-use crate::tools::{AsyncTool, FinalAnswerTool, ToolGroup, ToolInfo};
 use anyhow::Result;
 use async_trait::async_trait;
 use colored::Colorize;
 use log::info;
+use std::sync::Arc;
+
+#[cfg(feature = "stream")]
+use crate::models::stream::{run_into_sink, ChunkSink};
 
 use super::agent_step::Step;
 use super::agent_trait::Agent;
+use super::budget::Budget;
+use super::delegation::Delegation;
+use super::hooks::AgentHooks;
+use super::answer::Answer;
+use super::loop_detector::LoopDetector;
+use super::reflection::ReflectionConfig;
+use super::runtime_facts::RuntimeFacts;
 use super::AgentStep;
 
+/// The default maximum number of characters of an observation that is added to the agent memory.
+pub const DEFAULT_MAX_OBSERVATION_SIZE: usize = 30000;
+
 const DEFAULT_TOOL_DESCRIPTION_TEMPLATE: &str = r#"
 {{ tool.name }}: {{ tool.description }}
     Takes inputs: {{tool.inputs}}
@@ -40,17 +62,20 @@ pub fn get_tool_descriptions(tools: &[ToolInfo]) -> Vec<String> {
     tools.iter().map(get_tool_description_with_args).collect()
 }
 pub fn format_prompt_with_tools(tools: Vec<ToolInfo>, prompt_template: &str) -> String {
-    let tool_descriptions = get_tool_descriptions(&tools);
-    let mut prompt = prompt_template.to_string();
-    prompt = prompt.replace("{{tool_descriptions}}", &tool_descriptions.join("\n"));
-    if prompt.contains("{{tool_names}}") {
-        let tool_names: Vec<String> = tools
-            .iter()
-            .map(|tool| tool.function.name.to_string())
-            .collect();
-        prompt = prompt.replace("{{tool_names}}", &tool_names.join(", "));
-    }
-    prompt
+    let tool_descriptions = get_tool_descriptions(&tools).join("\n");
+    let tool_names = tools
+        .iter()
+        .map(|tool| tool.function.name.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    render_template(
+        prompt_template,
+        [
+            ("tool_descriptions", tool_descriptions.as_str()),
+            ("tools", tool_descriptions.as_str()),
+            ("tool_names", tool_names.as_str()),
+        ],
+    )
 }
 
 pub fn show_agents_description(managed_agents: &Vec<Box<dyn Agent>>) -> String {
@@ -72,15 +97,19 @@ pub fn format_prompt_with_managed_agent_description(
 ) -> Result<String> {
     let agent_descriptions_placeholder =
         agent_descriptions_placeholder.unwrap_or("{{managed_agents_descriptions}}");
-    
-    if managed_agents.len() > 0 {
-        Ok(prompt_template.replace(
-            agent_descriptions_placeholder,
-            &show_agents_description(managed_agents),
-        ))
+    let name = agent_descriptions_placeholder
+        .trim_start_matches("{{")
+        .trim_end_matches("}}")
+        .trim();
+    let description = if managed_agents.len() > 0 {
+        show_agents_description(managed_agents)
     } else {
-        Ok(prompt_template.replace(agent_descriptions_placeholder, ""))
-    }
+        String::new()
+    };
+    Ok(render_template(
+        &prompt_template,
+        [(name, description.as_str())],
+    ))
 }
 
 pub struct MultiStepAgent<M>
@@ -101,9 +130,51 @@ where
     pub planning_interval: Option<usize>,
     pub history: Option<Vec<Message>>,
     pub logging_level: Option<log::LevelFilter>,
+    pub hooks: AgentHooks,
+    pub generation_config: GenerationConfig,
+    /// How many times the model is asked to repair a tool call or code block that could not be parsed before the step
+    /// fails.
+    pub parse_retry: usize,
+    pub budget: Option<Budget>,
+    /// Tokens used by every model call of the agent, including its planning steps and managed agents.
+    pub usage: Usage,
+    /// Observations longer than this many characters are truncated before they are added to the memory.
+    pub max_observation_size: usize,
+    /// Where the full output of truncated observations is saved.
+    pub artifact_store: Option<Arc<dyn ArtifactStore>>,
+    pub tool_retry: ToolRetryPolicy,
+    /// The maximum number of tool calls of one step that run at the same time. `None` runs them all at once.
+    pub max_parallel_tools: Option<usize>,
+    /// The message sent to the model on the final step, once the agent has used all of its steps.
+    pub final_step_prompt: String,
+    /// Where the model stops generating on an action step, so that it does not make up the observation of its own
+    /// tool call. Defaults to `Observation:`.
+    pub stop_sequences: Vec<String>,
+    /// Shared by the tools of the agent and rendered into its prompts as `{{context.<key>}}`.
+    pub context: RunContext,
+    pub run_id: Option<String>,
+    /// The id of the run of the manager agent, when the agent runs as a managed agent.
+    pub parent_run_id: Option<String>,
+    pub reflection: Option<ReflectionConfig>,
+    pub delegation: Delegation,
+    /// Picks the tools sent with each request when the agent has more tools than the provider accepts.
+    pub tool_selector: Option<ToolSelector>,
+    pub runtime_facts: Option<RuntimeFacts>,
+    /// Whether tool calls are only recorded, not made. See [`Agent::dry_run`].
+    pub dry_run: bool,
+    pub prefill: Option<Prefill>,
+    /// Answers repeated tool calls with their earlier result instead of making them again.
+    pub loop_detector: Option<LoopDetector>,
+    /// Whether the observations have ids that the final answer cites. See [`Answer`].
+    pub citations: bool,
+    /// Where the chunks of the model calls are sent while the agent runs in
+    /// [`AgentStream::stream_events`](super::AgentStream::stream_events).
+    #[cfg(feature = "stream")]
+    pub chunk_sink: Option<ChunkSink>,
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl<M> Agent for MultiStepAgent<M>
 where
     M: Model + Send + Sync + 'static,
@@ -141,6 +212,9 @@ where
     fn reset_step_number(&mut self) {
         self.step_number = 0;
     }
+    fn get_logs(&self) -> &[Step] {
+        &self.logs
+    }
     fn get_logs_mut(&mut self) -> &mut Vec<Step> {
         &mut self.logs
     }
@@ -150,6 +224,79 @@ where
     fn model(&self) -> &dyn Model {
         &self.model
     }
+    fn get_generation_config(&self) -> GenerationConfig {
+        self.generation_config.clone()
+    }
+    fn get_history(&self) -> Option<Vec<Message>> {
+        self.history.clone()
+    }
+    fn set_history(&mut self, history: Option<Vec<Message>>) {
+        self.history = history;
+    }
+    fn get_usage(&self) -> Usage {
+        self.usage
+    }
+    fn add_usage(&mut self, usage: Usage) {
+        self.usage += usage;
+    }
+    fn get_budget(&self) -> Option<Budget> {
+        self.budget.clone()
+    }
+    fn get_hooks(&self) -> AgentHooks {
+        self.hooks.clone()
+    }
+    fn get_final_step_prompt(&self) -> &str {
+        &self.final_step_prompt
+    }
+    fn get_context(&self) -> RunContext {
+        self.context.clone()
+    }
+    fn set_context(&mut self, context: RunContext) {
+        self.context = context;
+    }
+    fn get_run_id(&self) -> Option<String> {
+        self.run_id.clone()
+    }
+    fn set_run_id(&mut self, run_id: String) {
+        self.run_id = Some(run_id);
+    }
+    fn get_parent_run_id(&self) -> Option<String> {
+        self.parent_run_id.clone()
+    }
+    fn set_parent_run_id(&mut self, parent_run_id: Option<String>) {
+        self.parent_run_id = parent_run_id;
+    }
+    fn get_reflection(&self) -> Option<ReflectionConfig> {
+        self.reflection.clone()
+    }
+    fn get_runtime_facts(&self) -> Option<RuntimeFacts> {
+        self.runtime_facts.clone()
+    }
+    fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+    fn cites_sources(&self) -> bool {
+        self.citations
+    }
+    fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+    #[cfg(feature = "stream")]
+    fn set_chunk_sink(&mut self, sink: Option<ChunkSink>) {
+        self.chunk_sink = sink;
+    }
+    fn get_delegation(&self) -> Delegation {
+        self.delegation.clone()
+    }
+    fn set_delegation(&mut self, delegation: Delegation) {
+        self.delegation = delegation;
+    }
+    fn get_final_answer_tool(&self) -> Option<ToolInfo> {
+        self.tools
+            .iter()
+            .find(|tool| tool.name() == "final_answer")
+            .map(|tool| tool.tool_info())
+    }
     async fn planning_step(
         &mut self,
         task: &str,
@@ -222,12 +369,76 @@ where
             planning_interval,
             history,
             logging_level,
+            hooks: AgentHooks::default(),
+            generation_config: GenerationConfig::default(),
+            parse_retry: 0,
+            budget: None,
+            usage: Usage::default(),
+            max_observation_size: DEFAULT_MAX_OBSERVATION_SIZE,
+            artifact_store: None,
+            tool_retry: ToolRetryPolicy::default(),
+            max_parallel_tools: None,
+            final_step_prompt: FINAL_STEP_PROMPT.to_string(),
+            stop_sequences: vec!["Observation:".to_string()],
+            context: RunContext::new(),
+            run_id: None,
+            parent_run_id: None,
+            reflection: None,
+            delegation: Delegation::default(),
+            tool_selector: None,
+            runtime_facts: None,
+            dry_run: false,
+            prefill: None,
+            loop_detector: None,
+            citations: false,
+            #[cfg(feature = "stream")]
+            chunk_sink: None,
         };
 
         agent.initialize_system_prompt()?;
         Ok(agent)
     }
 
+    /// The prefill of the current step, if the agent has one for it.
+    pub fn step_prefill(&self) -> Option<String> {
+        self.prefill
+            .as_ref()
+            .and_then(|prefill| prefill.for_step(self.step_number))
+    }
+
+    /// Calls the model on `messages` with the history of the agent. During a streamed run, the answer is streamed
+    /// and its chunks are sent to the chunk sink of the run.
+    pub async fn call_model(
+        &self,
+        messages: Vec<Message>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        #[cfg(feature = "stream")]
+        // A sink is closed once the run it streams to is dropped
+        if let Some(sink) = self.chunk_sink.as_ref().filter(|sink| !sink.is_closed()) {
+            return run_into_sink(
+                &self.model,
+                sink,
+                messages,
+                self.history.clone(),
+                tools,
+                config,
+            )
+            .await;
+        }
+        self.model
+            .run(messages, self.history.clone(), tools, config)
+            .await
+    }
+
+    /// The context passed to the tools of the agent. It names the run and the delegation of the agent, so that a
+    /// sub-agent started by a tool is nested under it.
+    pub fn tool_context(&self) -> RunContext {
+        self.context
+            .with_caller(self.run_id.clone(), &self.delegation)
+    }
+
     fn initialize_system_prompt(&mut self) -> Result<String> {
         let tools = self.tools.tool_info();
         self.system_prompt_template = format_prompt_with_tools(tools, &self.system_prompt_template);
@@ -236,12 +447,65 @@ where
             &self.managed_agents,
             None,
         )?;
-        self.system_prompt_template = self
-            .system_prompt_template
-            .replace("{{current_time}}", &chrono::Local::now().to_string());
+        self.system_prompt_template = format_prompt_with_managed_agent_description(
+            self.system_prompt_template.clone(),
+            &self.managed_agents,
+            Some("{{managed_agents}}"),
+        )?;
+        let now = chrono::Local::now();
+        self.system_prompt_template = render_template(
+            &self.system_prompt_template,
+            [
+                ("current_time", now.to_string().as_str()),
+                ("current_date", now.format("%Y-%m-%d").to_string().as_str()),
+            ],
+        );
         Ok(self.system_prompt_template.clone())
     }
 
+    /// The observation of a tool call that the loop detector does not let through, since the task already has
+    /// enough calls like it. The check is recorded as a lookup in the cache of the tool, see [`record_tool_cache`].
+    pub fn repeated_call(&self, call: &FunctionCall) -> Option<String> {
+        let repeated = self.loop_detector.as_ref()?.check(&self.logs, call);
+        if call.name != "final_answer" {
+            record_tool_cache(&call.name, repeated.is_some());
+        }
+        repeated
+    }
+
+    /// The final answer `text`, with the observations it cites if the agent cites its sources.
+    pub fn new_answer(&self, text: &str) -> Answer {
+        if self.citations {
+            Answer::with_citations_from(text, &self.logs)
+        } else {
+            Answer::new(text)
+        }
+    }
+
+    /// Truncates an observation that is longer than `max_observation_size`. The full observation is saved in the
+    /// artifact store, if there is one, so that the model can read the rest of it.
+    /// Base64 images do not count towards the size and are kept whole, so that they can be sent to the model as
+    /// images.
+    pub async fn limit_observation(&self, observation: String) -> String {
+        let (text, images) = split_image_data_urls(&observation);
+        if text.chars().count() <= self.max_observation_size {
+            return observation;
+        }
+        let artifact_id = match &self.artifact_store {
+            Some(store) => match store.put(observation.clone()).await {
+                Ok(id) => Some(id),
+                Err(e) => {
+                    tracing::warn!(error = %e, "Could not save the observation as an artifact");
+                    None
+                }
+            },
+            None => None,
+        };
+        let truncated =
+            truncate_observation(&text, self.max_observation_size, artifact_id.as_deref());
+        join_image_data_urls(&truncated, &images)
+    }
+
     pub async fn planning_step(
         &mut self,
         task: &str,
@@ -254,6 +518,8 @@ where
                 content: SYSTEM_PROMPT_FACTS.to_string(),
                 tool_call_id: None,
                 tool_calls: None,
+                parts: vec![],
+                name: None,
             };
             let message_prompt_task = Message {
                 role: MessageRole::User,
@@ -267,6 +533,8 @@ where
                 ),
                 tool_call_id: None,
                 tool_calls: None,
+                parts: vec![],
+                name: None,
             };
             let previous_messages = self.write_inner_memory_from_logs(None)?[1..].to_vec();
 
@@ -276,21 +544,18 @@ where
                 .collect();
             let answer_facts = self
                 .model
-                .run(
-                    input_messages,
-                    None,
-                    vec![],
-                    None,
-                    None,
-                )
-                .await?
-                .get_response()?;
+                .run(input_messages, None, vec![], self.generation_config.clone())
+                .await?;
+            self.usage += answer_facts.get_usage().unwrap_or_default();
+            let answer_facts = answer_facts.get_response()?;
             log::info!("Facts: {}", answer_facts);
             let message_system_prompt_plan = Message {
                 role: MessageRole::System,
                 content: SYSTEM_PROMPT_PLAN.to_string(),
                 tool_call_id: None,
                 tool_calls: None,
+                parts: vec![],
+                name: None,
             };
             let tool_descriptions = serde_json::to_string(
                 &self
@@ -310,6 +575,8 @@ where
                 ),
                 tool_call_id: None,
                 tool_calls: None,
+                parts: vec![],
+                name: None,
             };
             let answer_plan = self
                 .model
@@ -317,14 +584,15 @@ where
                     vec![message_system_prompt_plan, message_user_prompt_plan],
                     None,
                     vec![],
-                    None,
-                    Some(HashMap::from([(
-                        "stop".to_string(),
-                        vec!["Observation:".to_string(), "<end_plan>".to_string()],
-                    )])),
+                    GenerationConfig::new()
+                        .with_stop(
+                            [self.stop_sequences.clone(), vec!["<end_plan>".to_string()]].concat(),
+                        )
+                        .merge(&self.generation_config),
                 )
-                .await?
-                .get_response()?;
+                .await?;
+            self.usage += answer_plan.get_usage().unwrap_or_default();
+            let answer_plan = answer_plan.get_response()?;
             let final_plan_redaction = format!(
                 "Here is the plan of action that I will follow for the task: \n{}",
                 answer_plan
@@ -345,3 +613,40 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::FunctionCallingAgentBuilder, models::testing::ScriptedModel};
+
+    #[test]
+    fn test_format_prompt_with_spaced_placeholders() {
+        let tools = vec![FinalAnswerTool::new().tool_info()];
+        let prompt = format_prompt_with_tools(tools, "Tools: {{ tools }}\nNames: {{ tool_names }}");
+        assert!(prompt.starts_with("Tools: \nfinal_answer: Provides a final answer"));
+        assert!(prompt.ends_with("Names: final_answer"));
+
+        let prompt = format_prompt_with_managed_agent_description(
+            "Team: {{ managed_agents }}.".to_string(),
+            &vec![],
+            Some("{{managed_agents}}"),
+        )
+        .unwrap();
+        assert_eq!(prompt, "Team: .");
+
+        let managed_agents: Vec<Box<dyn Agent>> = vec![Box::new(
+            FunctionCallingAgentBuilder::new(ScriptedModel::new())
+                .with_name(Some("researcher"))
+                .build()
+                .unwrap(),
+        )];
+        let prompt = format_prompt_with_managed_agent_description(
+            "Team: {{ managed_agents_descriptions }}".to_string(),
+            &managed_agents,
+            None,
+        )
+        .unwrap();
+        assert!(prompt.starts_with("Team: You can also give requests to team members."));
+        assert!(prompt.contains("researcher: "));
+    }
+}
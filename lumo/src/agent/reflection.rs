@@ -0,0 +1,145 @@
+//! Self-critique of the agent: every few steps, or before it returns its final answer, the agent asks the model to
+//! review its own trajectory.
+//!
+//! The model either accepts the work, in which case the run goes on or ends as it would have, or returns a critique.
+//! The critique is added to the memory of the agent as an observation, and a final answer it rejects is dropped, so
+//! that the agent keeps going with the critique in mind.
+//!
+//! ```rust,ignore
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_tools(tools)
+//!     .with_reflection(ReflectionConfig::new().with_interval(5).with_max_revisions(1))
+//!     .build()?;
+//! ```
+
+use crate::prompts::{render_template, REFLECTION_PROMPT};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflectionConfig {
+    /// Reviews the trajectory every `interval` steps. Off when `None`.
+    pub interval: Option<usize>,
+    /// Reviews the final answer before the run returns it.
+    pub before_final_answer: bool,
+    /// The number of times a final answer can be rejected in one run. Further answers are accepted without a review,
+    /// so that a critical model does not keep the agent from ever finishing.
+    pub max_revisions: usize,
+    /// The critique prompt, with the `{{task}}` and `{{answer}}` placeholders of [`REFLECTION_PROMPT`].
+    pub prompt: String,
+}
+
+impl Default for ReflectionConfig {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            before_final_answer: true,
+            max_revisions: 2,
+            prompt: REFLECTION_PROMPT.to_string(),
+        }
+    }
+}
+
+impl ReflectionConfig {
+    /// Reviews the final answer, up to two rejections per run.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_interval(mut self, interval: usize) -> Self {
+        self.interval = Some(interval).filter(|interval| *interval > 0);
+        self
+    }
+
+    pub fn with_before_final_answer(mut self, before_final_answer: bool) -> Self {
+        self.before_final_answer = before_final_answer;
+        self
+    }
+
+    pub fn with_max_revisions(mut self, max_revisions: usize) -> Self {
+        self.max_revisions = max_revisions;
+        self
+    }
+
+    pub fn with_prompt(mut self, prompt: &str) -> Self {
+        self.prompt = prompt.to_string();
+        self
+    }
+
+    /// Whether a review is due after step `step`, which gave `answer` if it was a final answer. `revisions` is the
+    /// number of final answers rejected so far in the run.
+    pub fn is_due(&self, step: usize, answer: Option<&str>, revisions: usize) -> bool {
+        match answer {
+            Some(_) => self.before_final_answer && revisions < self.max_revisions,
+            None => self.interval.is_some_and(|interval| step % interval == 0),
+        }
+    }
+
+    pub fn render_prompt(&self, task: &str, answer: Option<&str>) -> String {
+        let answer = match answer {
+            Some(answer) => format!("Your proposed final answer is:\n```\n{}\n```", answer),
+            None => "You have not given a final answer yet.".to_string(),
+        };
+        render_template(&self.prompt, [("task", task), ("answer", answer.as_str())])
+    }
+}
+
+/// Reads the reply of the model to the critique prompt. Returns the critique, or `None` if the model accepted the
+/// work.
+pub fn parse_critique(reply: &str) -> Option<String> {
+    let reply = reply.trim();
+    if reply.is_empty() || reply.to_uppercase().starts_with("ACCEPT") {
+        return None;
+    }
+    let critique = match reply.get(..6) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("REVISE") => reply[6..]
+            .trim_start_matches(|c: char| c == ':' || c.is_whitespace())
+            .to_string(),
+        _ => reply.to_string(),
+    };
+    Some(critique)
+}
+
+/// The observation that carries a critique into the memory of the agent.
+pub fn critique_observation(critique: &str, answer_rejected: bool) -> String {
+    if answer_rejected {
+        format!(
+            "Your final answer was not accepted yet. Review of your work:\n{}\n\nAddress the critique, then give your final answer again.",
+            critique
+        )
+    } else {
+        format!(
+            "Review of your work so far:\n{}\n\nTake the critique into account in the next steps.",
+            critique
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reflection() {
+        let config = ReflectionConfig::new()
+            .with_interval(3)
+            .with_max_revisions(1);
+        assert!(config.is_due(3, None, 0));
+        assert!(!config.is_due(4, None, 0));
+        assert!(config.is_due(4, Some("42"), 0));
+        assert!(!config.is_due(4, Some("42"), 1));
+
+        let prompt = config.render_prompt("Add 40 and 2", Some("42"));
+        assert!(prompt.contains("Add 40 and 2"));
+        assert!(prompt.contains("```\n42\n```"));
+
+        assert_eq!(parse_critique("ACCEPT"), None);
+        assert_eq!(parse_critique("  accept."), None);
+        assert_eq!(
+            parse_critique("REVISE: the sum was not checked").as_deref(),
+            Some("the sum was not checked")
+        );
+        assert_eq!(
+            parse_critique("The source is outdated.").as_deref(),
+            Some("The source is outdated.")
+        );
+    }
+}
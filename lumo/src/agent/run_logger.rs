@@ -0,0 +1,261 @@
+//! Writes every run of an agent to a JSONL file, one event per line, for offline analysis.
+//!
+//! A run file starts with a `run_start` event, has a `step` event for every step and ends with a `run_end` event.
+//! Every line carries the [`RUN_LOG_VERSION`] of the schema; fields are only added within a version, so readers of
+//! an older version keep working.
+//!
+//! ```rust,ignore
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_hook(RunLogger::new("runs"))
+//!     .build()?;
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{agent_step::AgentStep, answer::Answer, hooks::AgentHook};
+use crate::{errors::AgentError, models::types::Usage};
+
+/// The version of the run file schema.
+pub const RUN_LOG_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunLogEntry {
+    pub version: u32,
+    pub run_id: String,
+    /// When the event was written, in RFC 3339.
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub event: RunLogEvent,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RunLogEvent {
+    RunStart {
+        agent: String,
+        task: String,
+    },
+    Step {
+        step: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        llm_output: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        tool_calls: Vec<LoggedToolCall>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        observations: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        final_answer: Option<Answer>,
+        usage: Usage,
+        duration_ms: u64,
+    },
+    RunEnd {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        answer: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        usage: Usage,
+        duration_ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedToolCall {
+    pub name: String,
+    pub arguments: Value,
+}
+
+struct RunFile {
+    run_id: String,
+    path: PathBuf,
+    file: File,
+}
+
+/// An [`AgentHook`] that writes each run to a new file `<dir>/<date>_<run id>.jsonl`. A run logger should only be
+/// attached to one agent, since runs of several agents at the same time would end up in the same file.
+pub struct RunLogger {
+    dir: PathBuf,
+    current: Mutex<Option<RunFile>>,
+}
+
+impl RunLogger {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            current: Mutex::new(None),
+        }
+    }
+
+    /// The file of the current run, or of the last run once it is over.
+    pub fn current_path(&self) -> Option<PathBuf> {
+        self.current
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|run| run.path.clone())
+    }
+
+    /// Reads the events of a run file.
+    pub fn read(path: impl AsRef<Path>) -> Result<Vec<RunLogEntry>> {
+        let file = File::open(path)?;
+        BufReader::new(file)
+            .lines()
+            .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+
+    fn start(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let run_id = nanoid::nanoid!(12);
+        let path = self.dir.join(format!(
+            "{}_{}.jsonl",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+            run_id
+        ));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        *self.current.lock().unwrap() = Some(RunFile { run_id, path, file });
+        Ok(())
+    }
+
+    fn write(&self, event: RunLogEvent) {
+        let mut current = self.current.lock().unwrap();
+        let Some(run) = current.as_mut() else {
+            return;
+        };
+        let entry = RunLogEntry {
+            version: RUN_LOG_VERSION,
+            run_id: run.run_id.clone(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            event,
+        };
+        let result = serde_json::to_string(&entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(run.file, "{}", line)?));
+        if let Err(e) = result {
+            tracing::warn!(error = %e, path = ?run.path, "Could not write to the run file");
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AgentHook for RunLogger {
+    async fn on_run_start(&self, agent: &str, task: &str) -> Result<(), AgentError> {
+        // The run is not stopped when its log cannot be written.
+        if let Err(e) = self.start() {
+            tracing::warn!(error = %e, dir = ?self.dir, "Could not create the run file");
+        }
+        self.write(RunLogEvent::RunStart {
+            agent: agent.to_string(),
+            task: task.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn on_step_end(
+        &self,
+        step: &AgentStep,
+        usage: &Usage,
+        duration: Duration,
+    ) -> Result<(), AgentError> {
+        self.write(RunLogEvent::Step {
+            step: step.step,
+            llm_output: step.llm_output.clone(),
+            tool_calls: step
+                .tool_call
+                .iter()
+                .flatten()
+                .map(|tool_call| LoggedToolCall {
+                    name: tool_call.function.name.clone(),
+                    arguments: tool_call.function.arguments.clone(),
+                })
+                .collect(),
+            observations: step.observations.clone().unwrap_or_default(),
+            error: step.error.as_ref().map(|e| e.to_string()),
+            final_answer: step.final_answer.clone(),
+            usage: *usage,
+            duration_ms: duration.as_millis() as u64,
+        });
+        Ok(())
+    }
+
+    async fn on_run_end(
+        &self,
+        result: &Result<String, AgentError>,
+        usage: &Usage,
+        duration: Duration,
+    ) -> Result<(), AgentError> {
+        self.write(RunLogEvent::RunEnd {
+            answer: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            usage: *usage,
+            duration_ms: duration.as_millis() as u64,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_logger_writes_events() {
+        let dir = std::env::temp_dir().join(format!("lumo_runs_{}", nanoid::nanoid!(8)));
+        let logger = RunLogger::new(&dir);
+        logger.on_run_start("agent", "task").await.unwrap();
+        let step = AgentStep {
+            step: 1,
+            llm_output: Some("thinking".to_string()),
+            observations: Some(vec!["observation".to_string()]),
+            ..Default::default()
+        };
+        logger
+            .on_step_end(&step, &Usage::new(10, 2), Duration::from_millis(5))
+            .await
+            .unwrap();
+        logger
+            .on_run_end(
+                &Ok("answer".to_string()),
+                &Usage::new(10, 2),
+                Duration::from_millis(7),
+            )
+            .await
+            .unwrap();
+
+        let entries = RunLogger::read(logger.current_path().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.version == RUN_LOG_VERSION && entry.run_id == entries[0].run_id));
+        assert!(matches!(
+            &entries[1].event,
+            RunLogEvent::Step {
+                step: 1,
+                duration_ms: 5,
+                ..
+            }
+        ));
+        assert_eq!(
+            entries[2].event,
+            RunLogEvent::RunEnd {
+                answer: Some("answer".to_string()),
+                error: None,
+                usage: Usage::new(10, 2),
+                duration_ms: 7,
+            }
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! A read-only report of a run, to display or archive it without going through the internal step types.
+//!
+//! ```rust,ignore
+//! let answer = agent.run("What is the population of Eindhoven?", true).await?;
+//! let report = agent.run_report();
+//! println!("{}", report.to_markdown());
+//! std::fs::write("run.json", report.to_json()?)?;
+//! ```
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::agent_step::Step;
+use super::answer::Answer;
+use crate::models::types::Usage;
+
+/// The longest observation shown in full by [`RunReport::to_markdown`].
+const MARKDOWN_OBSERVATION_CHARS: usize = 2000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub agent: String,
+    pub task: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    pub steps: Vec<StepReport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_answer: Option<Answer>,
+    /// The tokens of every model call the agent has made so far, including its planning steps and managed agents.
+    pub usage: Usage,
+    /// The time of the action steps together.
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StepReport {
+    Planning { facts: String, plan: String },
+    Action(ActionReport),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionReport {
+    pub step: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub llm_output: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCallReport>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub observations: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_answer: Option<Answer>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallReport {
+    pub name: String,
+    pub arguments: Value,
+}
+
+impl RunReport {
+    /// Builds the report of the steps recorded in `logs`, with the run id of the last run in them. The final answer is the one of the last step, so an answer
+    /// that was rejected by the reflection of the agent is not reported as final.
+    pub fn from_logs(agent: &str, task: &str, logs: &[Step], usage: Usage) -> Self {
+        let mut steps = Vec::new();
+        let mut run_id = None;
+        for log in logs {
+            match log {
+                Step::PlanningStep(facts, plan) => steps.push(StepReport::Planning {
+                    facts: facts.clone(),
+                    plan: plan.clone(),
+                }),
+                Step::ActionStep(step) => {
+                    if step.run_id.is_some() {
+                        run_id = step.run_id.clone();
+                    }
+                    steps.push(StepReport::Action(ActionReport {
+                        step: step.step,
+                        llm_output: step.llm_output.clone().filter(|output| !output.is_empty()),
+                        tool_calls: step
+                            .tool_call
+                            .iter()
+                            .flatten()
+                            .map(|tool_call| ToolCallReport {
+                                name: tool_call.function.name.clone(),
+                                arguments: tool_call.function.arguments.clone(),
+                            })
+                            .collect(),
+                        observations: step.observations.clone().unwrap_or_default(),
+                        error: step.error.as_ref().map(|error| error.to_string()),
+                        final_answer: step.final_answer.clone(),
+                        usage: step.usage,
+                        duration_ms: step.duration.map(|duration| duration.as_millis() as u64),
+                    }))
+                }
+                Step::TaskStep(_) | Step::SystemPromptStep(_) | Step::ToolCall(_) => {}
+            }
+        }
+        let final_answer = match steps.last() {
+            Some(StepReport::Action(step)) => step.final_answer.clone(),
+            _ => None,
+        };
+        let duration_ms = steps
+            .iter()
+            .filter_map(|step| match step {
+                StepReport::Action(step) => step.duration_ms,
+                StepReport::Planning { .. } => None,
+            })
+            .sum();
+        Self {
+            agent: agent.to_string(),
+            task: task.to_string(),
+            run_id,
+            steps,
+            final_answer,
+            usage,
+            duration_ms,
+        }
+    }
+
+    pub fn action_steps(&self) -> impl Iterator<Item = &ActionReport> {
+        self.steps.iter().filter_map(|step| match step {
+            StepReport::Action(step) => Some(step),
+            StepReport::Planning { .. } => None,
+        })
+    }
+
+    pub fn tool_calls(&self) -> impl Iterator<Item = &ToolCallReport> {
+        self.action_steps().flat_map(|step| step.tool_calls.iter())
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// A summary of the run for people to read. Long observations are shortened.
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n\n**Task:** {}\n\n", self.agent, self.task);
+        markdown.push_str(&format!(
+            "**Steps:** {} · **Tokens:** {} in, {} out · **Time:** {:.1}s\n",
+            self.action_steps().count(),
+            self.usage.input_tokens,
+            self.usage.output_tokens,
+            self.duration_ms as f64 / 1000.0
+        ));
+        for step in &self.steps {
+            match step {
+                StepReport::Planning { facts, plan } => {
+                    markdown.push_str(&format!(
+                        "\n## Plan\n\n{}\n\n### Facts\n\n{}\n",
+                        plan.trim(),
+                        facts.trim()
+                    ));
+                }
+                StepReport::Action(step) => {
+                    markdown.push_str(&format!("\n## Step {}\n", step.step));
+                    if let Some(output) = &step.llm_output {
+                        markdown.push_str(&format!("\n{}\n", output.trim()));
+                    }
+                    for tool_call in &step.tool_calls {
+                        markdown.push_str(&format!(
+                            "\n- **{}** `{}`",
+                            tool_call.name, tool_call.arguments
+                        ));
+                    }
+                    if !step.tool_calls.is_empty() {
+                        markdown.push('\n');
+                    }
+                    for observation in &step.observations {
+                        markdown.push_str(&format!(
+                            "\n```\n{}\n```\n",
+                            shorten(observation.trim(), MARKDOWN_OBSERVATION_CHARS)
+                        ));
+                    }
+                    if let Some(error) = &step.error {
+                        markdown.push_str(&format!("\n**Error:** {}\n", error));
+                    }
+                }
+            }
+        }
+        if let Some(answer) = &self.final_answer {
+            markdown.push_str(&format!("\n## Final answer\n\n{}\n", answer.trim()));
+            for citation in &answer.citations {
+                markdown.push_str(&format!(
+                    "\n- [{}] `{}`: {}",
+                    citation.id,
+                    citation.tool,
+                    shorten(&citation.observation, MARKDOWN_OBSERVATION_CHARS)
+                ));
+            }
+            if !answer.citations.is_empty() {
+                markdown.push('\n');
+            }
+        }
+        markdown
+    }
+}
+
+fn shorten(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        agent::AgentStep,
+        models::openai::{FunctionCall, ToolCall},
+    };
+    use serde_json::json;
+
+    #[test]
+    fn test_run_report() {
+        let logs = vec![
+            Step::SystemPromptStep("You are an agent".to_string()),
+            Step::PlanningStep("No facts".to_string(), "1. Search".to_string()),
+            Step::ActionStep(AgentStep {
+                tool_call: Some(vec![ToolCall {
+                    id: Some("call_1".to_string()),
+                    call_type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: "search".to_string(),
+                        arguments: json!({"query": "Eindhoven population"}),
+                    },
+                }]),
+                observations: Some(vec!["About 240,000".to_string()]),
+                usage: Some(Usage::new(100, 20)),
+                duration: Some(Duration::from_millis(1500)),
+                run_id: Some("run_1".to_string()),
+                ..AgentStep::new(1, None)
+            }),
+            Step::ActionStep(AgentStep {
+                final_answer: Some("240,000".into()),
+                duration: Some(Duration::from_millis(500)),
+                ..AgentStep::new(2, None)
+            }),
+        ];
+        let report = RunReport::from_logs("agent", "Population?", &logs, Usage::new(150, 30));
+        assert_eq!(report.steps.len(), 3);
+        assert_eq!(report.final_answer.as_deref(), Some("240,000"));
+        assert_eq!(report.run_id.as_deref(), Some("run_1"));
+        assert_eq!(report.duration_ms, 2000);
+        assert_eq!(report.tool_calls().next().unwrap().name, "search");
+
+        let markdown = report.to_markdown();
+        assert!(markdown.contains("## Step 1"));
+        assert!(markdown.contains("- **search**"));
+        assert!(markdown.ends_with("## Final answer\n\n240,000\n"));
+        assert_eq!(
+            RunReport::from_json(&report.to_json().unwrap()).unwrap(),
+            report
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! Facts about the environment of a run, such as the current date, that the model cannot know on its own.
+//!
+//! Without them, models answer questions about "today" or "the latest version" with the date of their training
+//! data. The facts are gathered again at every step, so that the time stays current in a long run, and are sent as a
+//! user message of their own right after the system prompt, or added to the system prompt. The message keeps the
+//! system prompt the same from one step to the next, which lets providers cache it. The time is given to the minute,
+//! so that the messages of the steps within a minute are the same too.
+//!
+//! ```rust,ignore
+//! let facts = RuntimeFacts::new()
+//!     .with_working_directory(false)
+//!     .with_fact("user", "Alice, who works in the Amsterdam office")
+//!     .with_dynamic_fact("open tickets", || tickets::count().to_string());
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_runtime_facts(facts)
+//!     .build()?;
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Where the facts go in the messages sent to the model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FactsPlacement {
+    /// At the end of the system prompt, which then changes whenever a fact does.
+    SystemPrompt,
+    /// In a user message of their own after the system prompt.
+    #[default]
+    Message,
+}
+
+type FactProvider = Arc<dyn Fn() -> String + Send + Sync>;
+
+#[derive(Clone)]
+pub struct RuntimeFacts {
+    pub date_time: bool,
+    pub timezone: bool,
+    pub locale: bool,
+    pub os: bool,
+    pub working_directory: bool,
+    pub placement: FactsPlacement,
+    facts: Vec<(String, String)>,
+    providers: Vec<(String, FactProvider)>,
+}
+
+impl Default for RuntimeFacts {
+    fn default() -> Self {
+        Self {
+            date_time: true,
+            timezone: true,
+            locale: true,
+            os: true,
+            working_directory: true,
+            placement: FactsPlacement::default(),
+            facts: Vec::new(),
+            providers: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for RuntimeFacts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuntimeFacts")
+            .field("date_time", &self.date_time)
+            .field("timezone", &self.timezone)
+            .field("locale", &self.locale)
+            .field("os", &self.os)
+            .field("working_directory", &self.working_directory)
+            .field("placement", &self.placement)
+            .field("facts", &self.facts)
+            .field(
+                "providers",
+                &self
+                    .providers
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl RuntimeFacts {
+    /// The date and time, timezone, locale, operating system and working directory, in a message after the system
+    /// prompt.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// No facts but the ones added with [`RuntimeFacts::with_fact`] and [`RuntimeFacts::with_dynamic_fact`].
+    pub fn empty() -> Self {
+        Self {
+            date_time: false,
+            timezone: false,
+            locale: false,
+            os: false,
+            working_directory: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_date_time(mut self, date_time: bool) -> Self {
+        self.date_time = date_time;
+        self
+    }
+
+    pub fn with_timezone(mut self, timezone: bool) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn with_locale(mut self, locale: bool) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    pub fn with_os(mut self, os: bool) -> Self {
+        self.os = os;
+        self
+    }
+
+    /// Turn the working directory off when its path may reveal the user name of the machine.
+    pub fn with_working_directory(mut self, working_directory: bool) -> Self {
+        self.working_directory = working_directory;
+        self
+    }
+
+    pub fn with_placement(mut self, placement: FactsPlacement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Adds a fact that does not change during the run.
+    pub fn with_fact(mut self, name: &str, value: &str) -> Self {
+        self.facts.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds a fact that `provider` works out again at every step.
+    pub fn with_dynamic_fact(
+        mut self,
+        name: &str,
+        provider: impl Fn() -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.providers.push((name.to_string(), Arc::new(provider)));
+        self
+    }
+
+    /// The facts as they are now, in the order they are rendered.
+    pub fn collect(&self) -> Vec<(String, String)> {
+        let now = chrono::Local::now();
+        let mut facts = Vec::new();
+        if self.date_time {
+            facts.push((
+                "Current date and time".to_string(),
+                now.format("%A %Y-%m-%d %H:%M").to_string(),
+            ));
+        }
+        if self.timezone {
+            facts.push(("Timezone".to_string(), format!("UTC{}", now.format("%:z"))));
+        }
+        if self.locale {
+            let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+                .iter()
+                .filter_map(|name| std::env::var(name).ok())
+                .find(|value| !value.is_empty());
+            if let Some(locale) = locale {
+                facts.push(("Locale".to_string(), locale));
+            }
+        }
+        if self.os {
+            facts.push((
+                "Operating system".to_string(),
+                format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH),
+            ));
+        }
+        if self.working_directory {
+            if let Ok(directory) = std::env::current_dir() {
+                facts.push((
+                    "Working directory".to_string(),
+                    directory.display().to_string(),
+                ));
+            }
+        }
+        facts.extend(self.facts.iter().cloned());
+        facts.extend(
+            self.providers
+                .iter()
+                .map(|(name, provider)| (name.clone(), provider())),
+        );
+        facts
+    }
+
+    /// The facts as a list for the model. Empty if there are none.
+    pub fn render(&self) -> String {
+        let facts = self.collect();
+        if facts.is_empty() {
+            return String::new();
+        }
+        let facts = facts
+            .iter()
+            .map(|(name, value)| format!("- {}: {}", name, value))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            "Facts about the current environment, use them instead of your own assumptions:\n{}",
+            facts
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_facts() {
+        let facts = RuntimeFacts::empty()
+            .with_date_time(true)
+            .with_fact("User", "Alice")
+            .with_dynamic_fact("Open tickets", || "3".to_string());
+        let collected = facts.collect();
+        assert_eq!(
+            collected
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Current date and time", "User", "Open tickets"]
+        );
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+        assert!(collected[0].1.contains(&today));
+        assert_eq!(collected[0].1.matches(':').count(), 1);
+
+        let rendered = facts.render();
+        assert!(rendered.ends_with("- User: Alice\n- Open tickets: 3"));
+        assert_eq!(RuntimeFacts::empty().render(), "");
+    }
+}
@@ -0,0 +1,206 @@
+//! A multi-turn chat session on top of an agent.
+//!
+//! Every call to [`Session::send`] runs the agent on the user message with the conversation so far as history, and
+//! appends the user message and the reply to that history. A [`HistoryPolicy`] keeps the history from growing
+//! without bounds.
+
+use crate::{
+    errors::AgentError,
+    models::types::{Message, MessageRole},
+};
+
+use super::agent_trait::Agent;
+
+/// What to do with the history of a session once it grows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum HistoryPolicy {
+    /// Keep every message.
+    #[default]
+    Unbounded,
+    /// Keep only the last `n` messages.
+    LastMessages(usize),
+    /// Once the history is longer than `max_messages`, ask the model to summarize everything except the last
+    /// `keep_last` messages and replace those messages with the summary.
+    Summarize { max_messages: usize, keep_last: usize },
+}
+
+impl HistoryPolicy {
+    /// Drops the messages that should not be kept. The summarize policy is applied by the session since it needs a
+    /// model, so it keeps every message here.
+    pub fn truncate(&self, history: &mut Vec<Message>) {
+        if let HistoryPolicy::LastMessages(n) = self {
+            if history.len() > *n {
+                history.drain(..history.len() - n);
+            }
+        }
+    }
+}
+
+pub struct Session<A: Agent> {
+    agent: A,
+    history: Vec<Message>,
+    policy: HistoryPolicy,
+}
+
+impl<A: Agent> Session<A> {
+    pub fn new(agent: A) -> Self {
+        let history = agent.get_history().unwrap_or_default();
+        Self {
+            agent,
+            history,
+            policy: HistoryPolicy::default(),
+        }
+    }
+
+    pub fn with_policy(mut self, policy: HistoryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Starts the session from a previous conversation.
+    pub fn with_history(mut self, history: Vec<Message>) -> Self {
+        self.history = history;
+        self
+    }
+
+    pub fn history(&self) -> &[Message] {
+        &self.history
+    }
+
+    pub fn agent(&self) -> &A {
+        &self.agent
+    }
+
+    pub fn agent_mut(&mut self) -> &mut A {
+        &mut self.agent
+    }
+
+    pub fn into_agent(self) -> A {
+        self.agent
+    }
+
+    /// Forgets the conversation so far.
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+
+    /// Sends a user message to the agent and returns its reply.
+    pub async fn send(&mut self, message: &str) -> Result<String, AgentError> {
+        self.agent.set_history(if self.history.is_empty() {
+            None
+        } else {
+            Some(self.history.clone())
+        });
+        let reply = self.agent.run(message, true).await?;
+
+        self.history.push(Message::new(MessageRole::User, message));
+        self.history.push(Message::new(MessageRole::Assistant, &reply));
+        self.apply_policy().await?;
+        Ok(reply)
+    }
+
+    async fn apply_policy(&mut self) -> Result<(), AgentError> {
+        match self.policy {
+            HistoryPolicy::Summarize {
+                max_messages,
+                keep_last,
+            } if self.history.len() > max_messages => {
+                // The history is only replaced once the summary is there, so a failed model call keeps it whole.
+                let split = self.history.len().saturating_sub(keep_last);
+                let summary = self.summarize(&self.history[..split]).await?;
+                self.history.splice(
+                    ..split,
+                    [Message::new(
+                        MessageRole::User,
+                        &format!("Summary of the conversation so far:\n{}", summary),
+                    )],
+                );
+            }
+            _ => self.policy.truncate(&mut self.history),
+        }
+        Ok(())
+    }
+
+    async fn summarize(&self, messages: &[Message]) -> Result<String, AgentError> {
+        let transcript = messages
+            .iter()
+            .map(|message| format!("{}: {}", message.role, message.content))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let prompt = format!(
+            "Summarize the following conversation between a user and an assistant. Keep every fact, decision and open question that later messages may refer to.\n\n{}",
+            transcript
+        );
+        self.agent
+            .model()
+            .run(
+                vec![Message::new(MessageRole::User, &prompt)],
+                None,
+                vec![],
+                self.agent.get_generation_config(),
+            )
+            .await?
+            .get_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::FunctionCallingAgentBuilder, models::testing::ScriptedModel};
+
+    #[test]
+    fn test_last_messages_policy() {
+        let mut history = (0..5)
+            .map(|i| Message::new(MessageRole::User, &i.to_string()))
+            .collect::<Vec<_>>();
+        HistoryPolicy::LastMessages(2).truncate(&mut history);
+        assert_eq!(
+            history.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(),
+            vec!["3", "4"]
+        );
+
+        HistoryPolicy::Unbounded.truncate(&mut history);
+        assert_eq!(history.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_summarize_policy() {
+        let policy = HistoryPolicy::Summarize {
+            max_messages: 1,
+            keep_last: 1,
+        };
+        let model = ScriptedModel::new()
+            .with_final_answer("Hello!")
+            .with_response("The user greeted the assistant.");
+        let agent = FunctionCallingAgentBuilder::new(model).build().unwrap();
+        let mut session = Session::new(agent).with_policy(policy.clone());
+        assert_eq!(session.send("Hi").await.unwrap(), "Hello!");
+        assert_eq!(
+            session
+                .history()
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Summary of the conversation so far:\nThe user greeted the assistant.",
+                "Hello!"
+            ]
+        );
+
+        let model = ScriptedModel::new()
+            .with_final_answer("Hello!")
+            .with_error("The model is down");
+        let agent = FunctionCallingAgentBuilder::new(model).build().unwrap();
+        let mut session = Session::new(agent).with_policy(policy);
+        assert!(session.send("Hi").await.is_err());
+        assert_eq!(
+            session
+                .history()
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Hi", "Hello!"]
+        );
+    }
+}
@@ -0,0 +1,628 @@
+//! How the function calling agent chooses the action of a step.
+//!
+//! The agent runs the same loop whatever the strategy: it writes its memory, asks the strategy for a response, calls
+//! the tools in it and records the observations. The strategy only decides what the response is:
+//!
+//! - [`ReAct`], the default, asks the model once per step, on everything the agent has seen so far.
+//! - [`PlanAndExecute`] asks the model for a plan of tool calls up front and makes one call per step without asking
+//!   the model again. When a call fails, it asks for a new plan. Once the plan is done, the model takes over with the
+//!   results in front of it.
+//! - [`BestOfN`] asks the model `n` times and keeps the response that a [`Scorer`] rates highest.
+//!
+//! ```rust,ignore
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_tools(tools)
+//!     .with_strategy(BestOfN::new(3, ModelJudge::new(judge_model)).with_temperature(0.9))
+//!     .build()?;
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use opentelemetry::{trace::FutureExt, Context};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::function_calling_agent::{malformed_tool_call_error, parse_retry_messages};
+use crate::{
+    errors::AgentError,
+    models::{
+        model_traits::{Model, ModelResponse},
+        openai::{FunctionCall, ToolCall},
+        replay::RecordedResponse,
+        types::{GenerationConfig, Message, Usage},
+    },
+    prompts::{plan_and_execute_prompt, score_candidate_prompt},
+    tools::ToolInfo,
+};
+
+#[cfg(feature = "stream")]
+use crate::models::stream::{run_into_sink, ChunkSink};
+
+/// What the model is asked in a step, and the tokens the strategy used to answer it.
+pub struct StepContext<'a> {
+    pub model: &'a dyn Model,
+    pub task: &'a str,
+    pub step: usize,
+    /// The memory of the agent, as it is sent to the model.
+    pub messages: Vec<Message>,
+    pub history: Option<Vec<Message>>,
+    pub tools: Vec<ToolInfo>,
+    pub config: GenerationConfig,
+    /// How many times a malformed tool call is sent back to the model to repair.
+    pub parse_retry: usize,
+    usage: Usage,
+    cx: Context,
+    #[cfg(feature = "stream")]
+    chunk_sink: Option<ChunkSink>,
+}
+
+impl<'a> StepContext<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        model: &'a dyn Model,
+        task: &'a str,
+        step: usize,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+        parse_retry: usize,
+    ) -> Self {
+        Self {
+            model,
+            task,
+            step,
+            messages,
+            history,
+            tools,
+            config,
+            parse_retry,
+            usage: Usage::default(),
+            cx: Context::current(),
+            #[cfg(feature = "stream")]
+            chunk_sink: None,
+        }
+    }
+
+    /// Runs the model calls of the strategy in the telemetry context of the step.
+    pub fn with_telemetry_context(mut self, cx: Context) -> Self {
+        self.cx = cx;
+        self
+    }
+
+    /// Streams the answers of [`respond`](Self::respond) into `sink`, during a streamed run. The other calls of a
+    /// strategy, such as the candidates of [`BestOfN`], are not streamed.
+    #[cfg(feature = "stream")]
+    pub fn with_chunk_sink(mut self, sink: Option<ChunkSink>) -> Self {
+        self.chunk_sink = sink;
+        self
+    }
+
+    /// The tokens of every model call made through the context.
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// Calls the model with the history of the agent, and counts the tokens towards the step.
+    pub async fn call(
+        &mut self,
+        messages: Vec<Message>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let response = self
+            .model
+            .run(messages, self.history.clone(), tools, config)
+            .with_context(self.cx.clone())
+            .await?;
+        self.usage += response.get_usage().unwrap_or_default();
+        Ok(response)
+    }
+
+    /// Like [`call`](Self::call), but sends the chunks of the answer to the chunk sink, if there is one.
+    async fn call_streamed(
+        &mut self,
+        messages: Vec<Message>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        #[cfg(feature = "stream")]
+        if let Some(sink) = self.chunk_sink.clone().filter(|sink| !sink.is_closed()) {
+            let response = run_into_sink(
+                self.model,
+                &sink,
+                messages,
+                self.history.clone(),
+                tools,
+                config,
+            )
+            .with_context(self.cx.clone())
+            .await?;
+            self.usage += response.get_usage().unwrap_or_default();
+            return Ok(response);
+        }
+        self.call(messages, tools, config).await
+    }
+
+    /// Asks the model for the next action on the memory of the agent, and sends malformed tool calls back to it to
+    /// repair up to `parse_retry` times. This is one step of [`ReAct`].
+    pub async fn respond(&mut self, config: GenerationConfig) -> Result<StepDecision, AgentError> {
+        let mut messages = self.messages.clone();
+        let mut retries = 0;
+        loop {
+            let response = self
+                .call_streamed(messages.clone(), self.tools.clone(), config.clone())
+                .await?;
+            let text = response.get_response().unwrap_or_default();
+            let tool_calls = response.get_tools_used().unwrap_or_default();
+            match malformed_tool_call_error(&text, &tool_calls) {
+                Some(error) if retries < self.parse_retry => {
+                    retries += 1;
+                    tracing::warn!(
+                        error = %error,
+                        retry = retries,
+                        "Could not parse tool call, asking the model to repair it"
+                    );
+                    messages.extend(parse_retry_messages(&text, &tool_calls, &error));
+                }
+                Some(error) if self.parse_retry > 0 => {
+                    return Ok(StepDecision::Malformed {
+                        response: text,
+                        error,
+                    })
+                }
+                _ => return Ok(StepDecision::Respond(response)),
+            }
+        }
+    }
+}
+
+pub enum StepDecision {
+    /// The response the agent acts on: its tool calls are made, or its text is the final answer.
+    Respond(Box<dyn ModelResponse>),
+    /// The model kept making tool calls that cannot be parsed. The step fails with the error.
+    Malformed { response: String, error: AgentError },
+}
+
+/// Chooses the response the function calling agent acts on in each step.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait StepStrategy: Send + Sync {
+    async fn decide(&mut self, context: &mut StepContext<'_>) -> Result<StepDecision, AgentError>;
+
+    /// Called with the tool calls of the step and their observations, in the same order. `failed` is set when a
+    /// call returned an error.
+    fn observe(&mut self, _tool_calls: &[ToolCall], _observations: &[String], _failed: bool) {}
+
+    /// Called when the agent starts a new task.
+    fn reset(&mut self) {}
+}
+
+/// Thinks, acts and observes: one model call per step, on everything the agent has seen so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReAct;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl StepStrategy for ReAct {
+    async fn decide(&mut self, context: &mut StepContext<'_>) -> Result<StepDecision, AgentError> {
+        let config = context.config.clone();
+        context.respond(config).await
+    }
+}
+
+#[derive(Deserialize)]
+struct PlannedCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+/// Asks the model for a plan of tool calls once, then makes one of them per step without asking the model again.
+/// When a call fails, the rest of the plan is dropped and the model makes a new one, up to `max_replans` times. When
+/// the plan is done or cannot be parsed, the agent goes on as with [`ReAct`].
+#[derive(Debug, Clone)]
+pub struct PlanAndExecute {
+    plan: VecDeque<ToolCall>,
+    planned: bool,
+    replan: bool,
+    replans: usize,
+    max_replans: usize,
+}
+
+impl Default for PlanAndExecute {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlanAndExecute {
+    pub fn new() -> Self {
+        Self {
+            plan: VecDeque::new(),
+            planned: false,
+            replan: false,
+            replans: 0,
+            max_replans: 2,
+        }
+    }
+
+    pub fn with_max_replans(mut self, max_replans: usize) -> Self {
+        self.max_replans = max_replans;
+        self
+    }
+
+    /// The tool calls of the plan that are still to be made.
+    pub fn remaining(&self) -> impl Iterator<Item = &ToolCall> {
+        self.plan.iter()
+    }
+
+    /// Asks the model for a plan and returns it as text for the logs. Plans that cannot be parsed or that call
+    /// unknown tools are dropped.
+    async fn make_plan(&mut self, context: &mut StepContext<'_>) -> Result<String, AgentError> {
+        let tool_descriptions = context
+            .tools
+            .iter()
+            .filter(|tool| tool.function.name != "final_answer")
+            .map(|tool| {
+                format!(
+                    "- {}: {} Arguments: {}",
+                    tool.function.name, tool.function.description, tool.function.parameters
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut messages = context.messages.clone();
+        messages.push(Message::user(&plan_and_execute_prompt(&tool_descriptions)));
+        let mut config = context.config.clone();
+        config.prefill = None;
+        let response = context.call(messages, vec![], config).await?;
+        let text = response.get_response().unwrap_or_default();
+        let calls = match (text.find('['), text.rfind(']')) {
+            (Some(start), Some(end)) if start < end => {
+                serde_json::from_str::<Vec<PlannedCall>>(&text[start..=end]).ok()
+            }
+            _ => None,
+        };
+        let known = |name: &str| context.tools.iter().any(|tool| tool.function.name == name);
+        match calls {
+            Some(calls) if calls.iter().all(|call| known(&call.name)) => {
+                self.plan = calls
+                    .into_iter()
+                    .filter(|call| call.name != "final_answer")
+                    .map(|call| ToolCall {
+                        id: Some(format!("call_{}", nanoid::nanoid!())),
+                        call_type: Some("function".to_string()),
+                        function: FunctionCall {
+                            name: call.name,
+                            arguments: call.arguments,
+                        },
+                    })
+                    .collect();
+                Ok(format!("Plan:\n{}", text.trim()))
+            }
+            _ => {
+                tracing::warn!(plan = %text, "Could not parse the plan, going on without one");
+                self.plan.clear();
+                Ok(String::new())
+            }
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl StepStrategy for PlanAndExecute {
+    async fn decide(&mut self, context: &mut StepContext<'_>) -> Result<StepDecision, AgentError> {
+        let mut plan = None;
+        if !self.planned || self.replan {
+            if self.planned {
+                self.replans += 1;
+            }
+            plan = Some(self.make_plan(context).await?);
+            self.planned = true;
+            self.replan = false;
+        }
+        match self.plan.pop_front() {
+            Some(tool_call) => Ok(StepDecision::Respond(Box::new(RecordedResponse {
+                content: plan.filter(|plan| !plan.is_empty()),
+                tool_calls: vec![tool_call],
+                usage: None,
+                error: None,
+            }))),
+            None => {
+                let config = context.config.clone();
+                context.respond(config).await
+            }
+        }
+    }
+
+    fn observe(&mut self, _tool_calls: &[ToolCall], _observations: &[String], failed: bool) {
+        if failed {
+            self.plan.clear();
+            self.replan = self.replans < self.max_replans;
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new().with_max_replans(self.max_replans);
+    }
+}
+
+/// A response of the model that [`BestOfN`] may pick.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub response: String,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+impl Candidate {
+    fn from_response(response: &dyn ModelResponse) -> Self {
+        Self {
+            response: response.get_response().unwrap_or_default(),
+            tool_calls: response.get_tools_used().unwrap_or_default(),
+        }
+    }
+
+    /// The candidate as text: its response followed by its tool calls.
+    pub fn render(&self) -> String {
+        let mut text = self.response.trim().to_string();
+        for tool_call in &self.tool_calls {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&format!(
+                "{}({})",
+                tool_call.function.name, tool_call.function.arguments
+            ));
+        }
+        text
+    }
+}
+
+/// The score of a candidate, higher is better, and the tokens used to score it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rating {
+    pub score: f64,
+    pub usage: Usage,
+}
+
+impl From<f64> for Rating {
+    fn from(score: f64) -> Self {
+        Self {
+            score,
+            usage: Usage::default(),
+        }
+    }
+}
+
+/// Rates a candidate response. Any `Fn(&Candidate) -> f64` is a scorer.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait Scorer: Send + Sync {
+    async fn score(&self, task: &str, messages: &[Message], candidate: &Candidate) -> Rating;
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<F> Scorer for F
+where
+    F: Fn(&Candidate) -> f64 + Send + Sync,
+{
+    async fn score(&self, _task: &str, _messages: &[Message], candidate: &Candidate) -> Rating {
+        self(candidate).into()
+    }
+}
+
+/// Asks a model to rate each candidate from 0 to 10. A rating that cannot be parsed counts as 0. The tokens of the
+/// judge count towards the step.
+pub struct ModelJudge {
+    model: Arc<dyn Model>,
+}
+
+impl ModelJudge {
+    pub fn new(model: impl Model) -> Self {
+        Self::from_arc(Arc::new(model))
+    }
+
+    /// Judges with a model that is also used elsewhere, e.g. by the agent.
+    pub fn from_arc(model: Arc<dyn Model>) -> Self {
+        Self { model }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Scorer for ModelJudge {
+    async fn score(&self, task: &str, _messages: &[Message], candidate: &Candidate) -> Rating {
+        let prompt = score_candidate_prompt(task, &candidate.render());
+        let config = GenerationConfig::new()
+            .with_temperature(0.0)
+            .with_max_tokens(10);
+        let response = match self
+            .model
+            .run(vec![Message::user(&prompt)], None, vec![], config)
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!(error = %e, "Could not score the candidate");
+                return Rating::default();
+            }
+        };
+        let score = response
+            .get_response()
+            .unwrap_or_default()
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .find_map(|number| number.parse::<f64>().ok())
+            .unwrap_or(0.0);
+        Rating {
+            score,
+            usage: response.get_usage().unwrap_or_default(),
+        }
+    }
+}
+
+/// Asks the model `n` times in each step and keeps the response the scorer rates highest. Responses with malformed
+/// tool calls are only kept when every response has them. Set a temperature, as the samples are only as different as
+/// the sampling lets them be.
+pub struct BestOfN {
+    n: usize,
+    scorer: Arc<dyn Scorer>,
+    temperature: Option<f32>,
+}
+
+impl BestOfN {
+    pub fn new(n: usize, scorer: impl Scorer + 'static) -> Self {
+        Self {
+            n: n.max(1),
+            scorer: Arc::new(scorer),
+            temperature: None,
+        }
+    }
+
+    /// The temperature of the samples, instead of the one of the agent.
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl StepStrategy for BestOfN {
+    async fn decide(&mut self, context: &mut StepContext<'_>) -> Result<StepDecision, AgentError> {
+        let mut config = context.config.clone();
+        if let Some(temperature) = self.temperature {
+            config.temperature = Some(temperature);
+        }
+        let mut best: Option<(f64, Box<dyn ModelResponse>)> = None;
+        let mut malformed = None;
+        for _ in 0..self.n {
+            match context.respond(config.clone()).await? {
+                StepDecision::Respond(response) => {
+                    let candidate = Candidate::from_response(response.as_ref());
+                    let rating = self
+                        .scorer
+                        .score(context.task, &context.messages, &candidate)
+                        .await;
+                    context.usage += rating.usage;
+                    let score = rating.score;
+                    tracing::debug!(score, candidate = %candidate.render(), "Scored a candidate");
+                    let better = match &best {
+                        Some((best, _)) => score > *best,
+                        None => true,
+                    };
+                    if better {
+                        best = Some((score, response));
+                    }
+                }
+                decision => {
+                    malformed.get_or_insert(decision);
+                }
+            }
+        }
+        match (best, malformed) {
+            (Some((_, response)), _) => Ok(StepDecision::Respond(response)),
+            (None, Some(decision)) => Ok(decision),
+            (None, None) => Err(AgentError::Execution(
+                "BestOfN got no candidate to choose from".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::testing::ScriptedModel,
+        tools::{ToolFunctionInfo, ToolType},
+    };
+    use serde_json::json;
+
+    fn tool(name: &str) -> ToolInfo {
+        ToolInfo {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo {
+                name: name.to_string(),
+                description: format!("The {} tool.", name),
+                parameters: json!({}),
+            },
+        }
+    }
+
+    fn context<'a>(model: &'a dyn Model) -> StepContext<'a> {
+        StepContext::new(
+            model,
+            "What is the population of Eindhoven?",
+            1,
+            vec![Message::user("What is the population of Eindhoven?")],
+            None,
+            vec![tool("search"), tool("final_answer")],
+            GenerationConfig::default(),
+            0,
+        )
+    }
+
+    fn response(decision: StepDecision) -> Box<dyn ModelResponse> {
+        match decision {
+            StepDecision::Respond(response) => response,
+            StepDecision::Malformed { error, .. } => panic!("malformed: {}", error),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_step_strategies() {
+        let model = ScriptedModel::new()
+            .with_response(r#"[{"name": "search", "arguments": {"query": "Eindhoven"}}, {"name": "search", "arguments": {"query": "Eindhoven 2024"}}]"#)
+            .with_response(r#"[{"name": "search", "arguments": {"query": "Eindhoven population"}}]"#)
+            .with_response("About 240,000")
+            .with_usage(Usage::new(10, 5));
+        let mut strategy = PlanAndExecute::new().with_max_replans(1);
+        let mut step = context(&model);
+        let first = response(strategy.decide(&mut step).await.unwrap());
+        let calls = first.get_tools_used().unwrap();
+        assert_eq!(calls[0].function.arguments["query"], "Eindhoven");
+        assert!(first.get_response().unwrap().starts_with("Plan:"));
+        assert_eq!(strategy.remaining().count(), 1);
+
+        strategy.observe(&calls, &["Error: rate limited".to_string()], true);
+        let second = response(strategy.decide(&mut step).await.unwrap());
+        assert_eq!(
+            second.get_tools_used().unwrap()[0].function.arguments["query"],
+            "Eindhoven population"
+        );
+        strategy.observe(&calls, &["About 240,000".to_string()], false);
+        let third = response(strategy.decide(&mut step).await.unwrap());
+        assert_eq!(third.get_response().unwrap(), "About 240,000");
+        assert_eq!(step.usage(), Usage::new(30, 15));
+
+        let model = ScriptedModel::new()
+            .with_response("short")
+            .with_response("the longest answer")
+            .with_response("medium one")
+            .with_usage(Usage::new(10, 5));
+        let mut strategy = BestOfN::new(3, |candidate: &Candidate| candidate.response.len() as f64);
+        let mut step = context(&model);
+        let best = response(strategy.decide(&mut step).await.unwrap());
+        assert_eq!(best.get_response().unwrap(), "the longest answer");
+        assert_eq!(step.usage(), Usage::new(30, 15));
+
+        let model = ScriptedModel::new()
+            .with_response("short")
+            .with_response("the longest answer")
+            .with_usage(Usage::new(10, 5));
+        let judge = ScriptedModel::new()
+            .with_response("3")
+            .with_response("Rating: 8")
+            .with_usage(Usage::new(20, 1));
+        let mut strategy = BestOfN::new(2, ModelJudge::new(judge));
+        let mut step = context(&model);
+        let best = response(strategy.decide(&mut step).await.unwrap());
+        assert_eq!(best.get_response().unwrap(), "the longest answer");
+        assert_eq!(step.usage(), Usage::new(60, 12));
+    }
+}
@@ -0,0 +1,131 @@
+//! Storage for tool outputs that are too large to put in the agent memory.
+//!
+//! When an observation is longer than the agent's maximum observation size, the agent keeps only the start and the
+//! end of it in memory. If the agent has an [`ArtifactStore`], the full output is saved there and the truncated
+//! observation refers to it by id, so that the model can read the rest with the
+//! [`ReadArtifactTool`](crate::tools::ReadArtifactTool).
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait ArtifactStore: Send + Sync + 'static {
+    /// Saves `content` and returns the id it can be read back with.
+    async fn put(&self, content: String) -> Result<String>;
+    /// Returns the content saved under `id`, if any.
+    async fn get(&self, id: &str) -> Result<Option<String>>;
+}
+
+/// An artifact store that keeps everything in memory for the lifetime of the store.
+#[derive(Debug, Default)]
+pub struct InMemoryArtifactStore {
+    artifacts: RwLock<HashMap<String, String>>,
+}
+
+impl InMemoryArtifactStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.artifacts.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl ArtifactStore for InMemoryArtifactStore {
+    async fn put(&self, content: String) -> Result<String> {
+        let id = format!("artifact_{}", nanoid::nanoid!(8));
+        self.artifacts.write().unwrap().insert(id.clone(), content);
+        Ok(id)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<String>> {
+        Ok(self.artifacts.read().unwrap().get(id).cloned())
+    }
+}
+
+/// Shortens `observation` to about `max_chars` characters. The start and the end of the observation are kept since
+/// that is where results and errors usually are, and cuts are made at line breaks when there is one close by.
+pub fn truncate_observation(
+    observation: &str,
+    max_chars: usize,
+    artifact_id: Option<&str>,
+) -> String {
+    let total = observation.chars().count();
+    if total <= max_chars {
+        return observation.to_string();
+    }
+
+    let head_chars = max_chars * 2 / 3;
+    let tail_chars = max_chars - head_chars;
+    let head_end = char_boundary(observation, head_chars);
+    let tail_start = char_boundary(observation, total - tail_chars);
+
+    // Prefer to cut at a line break if it does not lose more than a tenth of the kept text.
+    let slack = max_chars / 10;
+    let head = match observation[..head_end].rfind('\n') {
+        Some(i) if observation[i..head_end].chars().count() <= slack => &observation[..i],
+        _ => &observation[..head_end],
+    };
+    let tail = match observation[tail_start..].find('\n') {
+        Some(i) if observation[tail_start..tail_start + i].chars().count() <= slack => {
+            &observation[tail_start + i + 1..]
+        }
+        _ => &observation[tail_start..],
+    };
+
+    let omitted = total - head.chars().count() - tail.chars().count();
+    let note = match artifact_id {
+        Some(id) => format!(
+            "[... {} characters omitted. The full output was saved as artifact `{}`, use the read_artifact tool to read the rest ...]",
+            omitted, id
+        ),
+        None => format!(
+            "[... {} characters omitted due to the {} character limit ...]",
+            omitted, max_chars
+        ),
+    };
+    format!("{}\n{}\n{}", head, note, tail)
+}
+
+/// The byte index of the `n`th character of `text`.
+fn char_boundary(text: &str, n: usize) -> usize {
+    text.char_indices()
+        .nth(n)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_observation() {
+        assert_eq!(truncate_observation("short", 10, None), "short");
+
+        let observation = (0..100)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let truncated = truncate_observation(&observation, 90, Some("artifact_1"));
+        assert!(truncated.starts_with("line 0\n"));
+        assert!(truncated.ends_with("line 99"));
+        assert!(truncated.contains("artifact `artifact_1`"));
+        assert!(!truncated.contains("line 50"));
+
+        let truncated = truncate_observation(&"é".repeat(100), 30, None);
+        assert!(truncated.starts_with(&"é".repeat(20)));
+        assert!(truncated.contains("70 characters omitted"));
+    }
+}
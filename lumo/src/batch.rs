@@ -0,0 +1,349 @@
+//! Runs many tasks through an agent at the same time, for pipelines that process a data set item by item.
+//!
+//! Agents keep the memory of their run, so every task gets a fresh agent from the factory given to the
+//! [`BatchRunner`], and at most `concurrency` of them run at once.
+//!
+//! ```rust,ignore
+//! let runner = BatchRunner::new(|| {
+//!     FunctionCallingAgentBuilder::new(model.clone())
+//!         .with_tools(vec![Box::new(VisitWebsiteTool::new())])
+//!         .build()
+//! })
+//! .with_concurrency(8);
+//! let report = runner
+//!     .run(urls.iter().map(|url| format!("Summarize {}", url)).collect())
+//!     .await;
+//! for result in report.results.iter().filter(|result| result.error.is_some()) {
+//!     eprintln!("{} failed: {}", result.index, result.error.as_ref().unwrap());
+//! }
+//! ```
+
+use std::time::Duration;
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::{Agent, Step},
+    models::types::Usage,
+    runtime::Instant,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    /// The position of the task in the batch.
+    pub index: usize,
+    pub task: String,
+    pub answer: Option<String>,
+    /// The error the run failed with, if it failed.
+    pub error: Option<String>,
+    /// Whether the run failed with an error that may go away when the task is run again, such as a rate limit.
+    pub retryable: bool,
+    pub latency: Duration,
+    pub steps: usize,
+    pub usage: Usage,
+}
+
+impl BatchResult {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchReport {
+    /// The results in the order of the tasks.
+    pub results: Vec<BatchResult>,
+}
+
+impl BatchReport {
+    pub fn succeeded(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.is_success())
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.succeeded()
+    }
+
+    /// The answers in the order of the tasks, `None` for the tasks that failed.
+    pub fn answers(&self) -> Vec<Option<&str>> {
+        self.results
+            .iter()
+            .map(|result| result.answer.as_deref())
+            .collect()
+    }
+
+    /// The tasks that failed with a retryable error, to run them again in a new batch.
+    pub fn retryable_tasks(&self) -> Vec<String> {
+        self.results
+            .iter()
+            .filter(|result| result.retryable)
+            .map(|result| result.task.clone())
+            .collect()
+    }
+
+    pub fn total_usage(&self) -> Usage {
+        self.results
+            .iter()
+            .fold(Usage::default(), |total, result| total + result.usage)
+    }
+}
+
+/// Runs every task of a batch on a fresh agent from `make_agent`.
+pub struct BatchRunner<F> {
+    make_agent: F,
+    concurrency: usize,
+}
+
+impl<A, F> BatchRunner<F>
+where
+    A: Agent,
+    F: Fn() -> Result<A>,
+{
+    pub fn new(make_agent: F) -> Self {
+        Self {
+            make_agent,
+            concurrency: 1,
+        }
+    }
+
+    /// Runs up to `concurrency` tasks at the same time. Defaults to one.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Runs every task. A task that fails does not stop the others, its error is in its result.
+    pub async fn run(&self, tasks: Vec<String>) -> BatchReport {
+        // A slow task must not hold back the tasks after it, so the runs finish in any order and are sorted after
+        let mut results = stream::iter(tasks.into_iter().enumerate())
+            .map(|(index, task)| self.run_task(index, task))
+            .buffer_unordered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+        results.sort_by_key(|result| result.index);
+        BatchReport { results }
+    }
+
+    async fn run_task(&self, index: usize, task: String) -> BatchResult {
+        let mut result = BatchResult {
+            index,
+            task,
+            answer: None,
+            error: None,
+            retryable: false,
+            latency: Duration::ZERO,
+            steps: 0,
+            usage: Usage::default(),
+        };
+        let mut agent = match (self.make_agent)() {
+            Ok(agent) => agent,
+            Err(e) => {
+                result.error = Some(e.to_string());
+                return result;
+            }
+        };
+
+        let started = Instant::now();
+        let answer = agent.run(&result.task, true).await;
+        result.latency = started.elapsed();
+        result.steps = agent
+            .get_logs_mut()
+            .iter()
+            .filter(|step| matches!(step, Step::ActionStep(_)))
+            .count();
+        match answer {
+            Ok(answer) => {
+                result.answer = Some(answer);
+                result.usage = agent.get_usage();
+            }
+            Err(e) => {
+                result.error = Some(e.to_string());
+                result.retryable = e.error.is_retryable();
+                result.usage = e.usage;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+
+    use crate::{
+        agent::FunctionCallingAgentBuilder,
+        errors::AgentError,
+        models::{
+            model_traits::{Model, ModelResponse},
+            testing::ScriptedModel,
+            types::{GenerationConfig, Message},
+        },
+        tools::ToolInfo,
+    };
+
+    /// Answers every task after a short wait, fails the tasks that ask for it, and counts how many calls run at once.
+    #[derive(Debug, Clone, Default)]
+    struct CountingModel {
+        running: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Model for CountingModel {
+        async fn run(
+            &self,
+            input_messages: Vec<Message>,
+            history: Option<Vec<Message>>,
+            tools: Vec<ToolInfo>,
+            config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+
+            let task = input_messages
+                .iter()
+                .find_map(|message| message.content.strip_prefix("New Task: "))
+                .unwrap_or_default()
+                .to_string();
+            let model = if task.contains("fail") {
+                ScriptedModel::new().with_error("The model is down")
+            } else {
+                ScriptedModel::new().with_final_answer(&format!("Done: {}", task))
+            };
+            model.run(input_messages, history, tools, config).await
+        }
+    }
+
+    /// Answers the tasks that ask to be slow after a long wait and the others right away, and records the tasks in
+    /// the order their answers are done.
+    #[derive(Debug, Clone, Default)]
+    struct SlowModel {
+        finished: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Model for SlowModel {
+        async fn run(
+            &self,
+            input_messages: Vec<Message>,
+            history: Option<Vec<Message>>,
+            tools: Vec<ToolInfo>,
+            config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            let task = input_messages
+                .iter()
+                .find_map(|message| message.content.strip_prefix("New Task: "))
+                .unwrap_or_default()
+                .to_string();
+            let wait = if task.contains("slow") { 200 } else { 10 };
+            tokio::time::sleep(Duration::from_millis(wait)).await;
+            self.finished.lock().unwrap().push(task.clone());
+            ScriptedModel::new()
+                .with_final_answer(&format!("Done: {}", task))
+                .run(input_messages, history, tools, config)
+                .await
+        }
+    }
+
+    fn result(index: usize, error: Option<&str>, retryable: bool) -> BatchResult {
+        BatchResult {
+            index,
+            task: format!("task {}", index),
+            answer: error.is_none().then(|| format!("answer {}", index)),
+            error: error.map(|error| error.to_string()),
+            retryable,
+            latency: Duration::from_secs(1),
+            steps: 2,
+            usage: Usage::new(10, 5),
+        }
+    }
+
+    #[test]
+    fn test_batch_report() {
+        let report = BatchReport {
+            results: vec![
+                result(0, None, false),
+                result(1, Some("Rate limited"), true),
+                result(2, Some("Max steps"), false),
+            ],
+        };
+        assert_eq!(report.succeeded(), 1);
+        assert_eq!(report.failed(), 2);
+        assert_eq!(report.answers(), vec![Some("answer 0"), None, None]);
+        assert_eq!(report.retryable_tasks(), vec!["task 1"]);
+        assert_eq!(report.total_usage(), Usage::new(30, 15));
+    }
+
+    #[tokio::test]
+    async fn test_batch_runner() {
+        let model = CountingModel::default();
+        let runner = BatchRunner::new(|| FunctionCallingAgentBuilder::new(model.clone()).build())
+            .with_concurrency(2);
+        let tasks = ["task 0", "task 1", "task 2 should fail", "task 3", "task 4"]
+            .iter()
+            .map(|task| task.to_string())
+            .collect::<Vec<_>>();
+        let report = runner.run(tasks.clone()).await;
+
+        assert_eq!(model.peak.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            report
+                .results
+                .iter()
+                .map(|result| result.task.clone())
+                .collect::<Vec<_>>(),
+            tasks
+        );
+        assert_eq!(
+            report
+                .results
+                .iter()
+                .map(|result| result.index)
+                .collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(report.answers()[0], Some("Done: task 0"));
+        assert_eq!(report.answers()[4], Some("Done: task 4"));
+        assert_eq!(report.succeeded(), 4);
+        let failed = &report.results[2];
+        assert!(failed.answer.is_none());
+        assert!(failed.error.as_ref().unwrap().contains("The model is down"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_task_does_not_hold_back_the_batch() {
+        let model = SlowModel::default();
+        let runner = BatchRunner::new(|| FunctionCallingAgentBuilder::new(model.clone()).build())
+            .with_concurrency(2);
+        let tasks = ["task 0 is slow", "task 1", "task 2", "task 3"]
+            .iter()
+            .map(|task| task.to_string())
+            .collect::<Vec<_>>();
+        let report = runner.run(tasks.clone()).await;
+
+        assert_eq!(
+            *model.finished.lock().unwrap(),
+            vec!["task 1", "task 2", "task 3", "task 0 is slow"]
+        );
+        assert_eq!(
+            report
+                .results
+                .iter()
+                .map(|result| result.task.clone())
+                .collect::<Vec<_>>(),
+            tasks
+        );
+        assert_eq!(report.answers()[0], Some("Done: task 0 is slow"));
+    }
+}
@@ -0,0 +1,433 @@
+//! Declarative agents, described in a TOML, YAML or JSON file instead of in code.
+//!
+//! ```toml
+//! name = "researcher"
+//! tools = ["duckduckgo_search", "visit_website"]
+//! max_steps = 8
+//!
+//! [model]
+//! provider = "openai"
+//! model_id = "gpt-4o-mini"
+//!
+//! [[managed_agents]]
+//! name = "coder"
+//! description = "Writes and runs Python code"
+//! agent_type = "code"
+//! tools = ["python_interpreter"]
+//! ```
+//!
+//! ```rust,ignore
+//! let config = AgentConfig::from_file("researcher.toml")?;
+//! let mut agent = FunctionCallingAgent::from_config(&config)?;
+//! ```
+//!
+//! API keys are never part of the file, they are read from the environment variable named by `api_key_env`, or from
+//! the default variable of the provider.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::{Agent, FunctionCallingAgentBuilder},
+    errors::AgentError,
+    models::{
+        gemini::{GeminiServerModel, GeminiServerModelBuilder},
+        info::ModelInfo,
+        model_traits::{Model, ModelResponse},
+        ollama::{OllamaModel, OllamaModelBuilder},
+        openai::{OpenAIServerModel, OpenAIServerModelBuilder},
+        openai_compatible::{
+            GenericOpenAICompatibleModel, GenericOpenAICompatibleModelBuilder, Provider,
+        },
+        tokenizer::Tokenizer,
+        types::{GenerationConfig, Message},
+    },
+    tools::{AsyncTool, ToolInfo, ToolRegistry, ToolSelection},
+};
+
+#[cfg(feature = "stream")]
+use crate::models::stream::ModelStream;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentKind {
+    #[default]
+    FunctionCalling,
+    /// Needs the `code-agent` feature.
+    Code,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelProvider {
+    OpenAI,
+    Gemini,
+    Ollama,
+    Groq,
+    Together,
+    OpenRouter,
+    DeepSeek,
+    Vllm,
+}
+
+impl ModelProvider {
+    /// The preset of [`GenericOpenAICompatibleModel`] for the provider, if it is served through it.
+    fn compatible(self) -> Option<Provider> {
+        match self {
+            ModelProvider::OpenAI | ModelProvider::Gemini | ModelProvider::Ollama => None,
+            ModelProvider::Groq => Some(Provider::Groq),
+            ModelProvider::Together => Some(Provider::Together),
+            ModelProvider::OpenRouter => Some(Provider::OpenRouter),
+            ModelProvider::DeepSeek => Some(Provider::DeepSeek),
+            ModelProvider::Vllm => Some(Provider::Vllm),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    pub provider: ModelProvider,
+    pub model_id: String,
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// The environment variable that holds the API key. Defaults to the variable of the provider, such as
+    /// `OPENAI_API_KEY`.
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// The context length of an Ollama model.
+    #[serde(default)]
+    pub ctx_length: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentConfig {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Required for managed agents, so that the manager knows what to delegate to them.
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub agent_type: AgentKind,
+    /// Required for the top level agent. A managed agent without a model uses the model of its manager.
+    #[serde(default)]
+    pub model: Option<ModelConfig>,
+    /// The names of tools in the global [`ToolRegistry`], such as `duckduckgo_search` or `visit_website`.
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// Adds every tool of the registry with one of these tags, such as `search`.
+    #[serde(default)]
+    pub tool_tags: Vec<String>,
+    #[serde(default)]
+    pub system_prompt: Option<String>,
+    #[serde(default)]
+    pub max_steps: Option<usize>,
+    #[serde(default)]
+    pub planning_interval: Option<usize>,
+    #[serde(default)]
+    pub generation_config: Option<GenerationConfig>,
+    /// Replaces the default `Observation:` stop sequence of the action steps. An empty list sends none.
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(default)]
+    pub managed_agents: Vec<AgentConfig>,
+}
+
+impl AgentConfig {
+    /// Loads a `.toml`, `.yaml`, `.yml` or `.json` file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read agent config: {:?}", path))?;
+        let config: AgentConfig = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => toml::from_str(&content)
+                .with_context(|| format!("Failed to parse agent config: {:?}", path))?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content)
+                .with_context(|| format!("Failed to parse agent config: {:?}", path))?,
+            Some("json") => serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse agent config: {:?}", path))?,
+            _ => bail!(
+                "Agent config must be a .toml, .yaml, .yml or .json file: {:?}",
+                path
+            ),
+        };
+        config.validate(true)?;
+        Ok(config)
+    }
+
+    fn validate(&self, top_level: bool) -> Result<()> {
+        if top_level && self.model.is_none() {
+            bail!("The agent config has no model");
+        }
+        for agent in &self.managed_agents {
+            if agent.name.is_none() || agent.description.is_none() {
+                bail!("Every managed agent needs a name and a description");
+            }
+            agent.validate(false)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn model_config(&self) -> Result<&ModelConfig> {
+        self.model
+            .as_ref()
+            .ok_or_else(|| anyhow!("The agent config has no model"))
+    }
+
+    pub fn build_tools(&self) -> Result<Vec<Box<dyn AsyncTool>>> {
+        let selection = ToolSelection {
+            names: self.tools.clone(),
+            tags: self.tool_tags.clone(),
+        };
+        ToolRegistry::global()
+            .read()
+            .map_err(|_| anyhow!("The tool registry is poisoned"))?
+            .select(&selection)
+    }
+
+    /// Builds the managed agents, giving the model of this agent to the ones that have none.
+    pub fn build_managed_agents(&self) -> Result<Vec<Box<dyn Agent>>> {
+        let model = self.model_config()?;
+        self.managed_agents
+            .iter()
+            .map(|agent| {
+                let mut agent = agent.clone();
+                agent.model.get_or_insert_with(|| model.clone());
+                agent.build_agent()
+            })
+            .collect()
+    }
+
+    /// Builds the agent as the kind set in `agent_type`.
+    pub fn build_agent(&self) -> Result<Box<dyn Agent>> {
+        match self.agent_type {
+            AgentKind::FunctionCalling => Ok(Box::new(
+                crate::agent::FunctionCallingAgent::from_config(self)?,
+            )),
+            #[cfg(feature = "code-agent")]
+            AgentKind::Code => Ok(Box::new(crate::agent::CodeAgent::from_config(self)?)),
+            #[cfg(not(feature = "code-agent"))]
+            AgentKind::Code => bail!("Code agents need the code-agent feature"),
+        }
+    }
+
+    pub(crate) fn function_calling_builder(
+        &self,
+    ) -> Result<FunctionCallingAgentBuilder<'_, ConfiguredModel>> {
+        let mut builder = FunctionCallingAgentBuilder::new(self.model_config()?.build()?)
+            .with_name(self.name.as_deref())
+            .with_description(self.description.as_deref())
+            .with_tools(self.build_tools()?)
+            .with_system_prompt(self.system_prompt.as_deref())
+            .with_managed_agents(self.build_managed_agents()?)
+            .with_max_steps(self.max_steps)
+            .with_planning_interval(self.planning_interval)
+            .with_generation_config(self.generation_config.clone());
+        if let Some(stop_sequences) = &self.stop_sequences {
+            builder = builder.with_stop_sequences(stop_sequences.clone());
+        }
+        Ok(builder)
+    }
+
+    #[cfg(feature = "code-agent")]
+    pub(crate) fn code_builder(
+        &self,
+    ) -> Result<crate::agent::CodeAgentBuilder<'_, ConfiguredModel>> {
+        let mut builder = crate::agent::CodeAgentBuilder::new(self.model_config()?.build()?)
+            .with_name(self.name.as_deref())
+            .with_description(self.description.as_deref())
+            .with_tools(self.build_tools()?)
+            .with_system_prompt(self.system_prompt.as_deref())
+            .with_managed_agents(self.build_managed_agents()?)
+            .with_max_steps(self.max_steps)
+            .with_planning_interval(self.planning_interval)
+            .with_generation_config(self.generation_config.clone());
+        if let Some(stop_sequences) = &self.stop_sequences {
+            builder = builder.with_stop_sequences(stop_sequences.clone());
+        }
+        Ok(builder)
+    }
+}
+
+impl ModelConfig {
+    fn api_key(&self, default_env: &str) -> Result<Option<String>> {
+        let name = self.api_key_env.as_deref().unwrap_or(default_env);
+        match std::env::var(name) {
+            Ok(api_key) => Ok(Some(api_key)),
+            // Keyless providers such as a local vLLM server only fail on an explicitly named variable.
+            Err(_) if self.api_key_env.is_none() && self.provider.compatible().is_some() => {
+                Ok(None)
+            }
+            Err(_) => Err(anyhow!("{} must be set", name)),
+        }
+    }
+
+    pub fn build(&self) -> Result<ConfiguredModel> {
+        let model = match self.provider {
+            ModelProvider::OpenAI => ConfiguredModel::OpenAI(
+                OpenAIServerModelBuilder::new(&self.model_id)
+                    .with_base_url(self.base_url.as_deref())
+                    .with_temperature(self.temperature)
+                    .with_api_key(self.api_key("OPENAI_API_KEY")?.as_deref())
+                    .build()?,
+            ),
+            ModelProvider::Gemini => ConfiguredModel::Gemini(
+                GeminiServerModelBuilder::new(&self.model_id)
+                    .with_base_url(self.base_url.as_deref())
+                    .with_temperature(self.temperature)
+                    .with_api_key(self.api_key("GOOGLE_API_KEY")?.as_deref())
+                    .build()?,
+            ),
+            ModelProvider::Ollama => {
+                let mut builder = OllamaModelBuilder::new()
+                    .model_id(&self.model_id)
+                    .temperature(self.temperature)
+                    .with_native_tools(true);
+                if let Some(base_url) = &self.base_url {
+                    builder = builder.url(base_url);
+                }
+                if let Some(ctx_length) = self.ctx_length {
+                    builder = builder.ctx_length(ctx_length);
+                }
+                ConfiguredModel::Ollama(builder.build())
+            }
+            provider => {
+                let provider = provider
+                    .compatible()
+                    .expect("an OpenAI-compatible provider");
+                ConfiguredModel::OpenAICompatible(
+                    GenericOpenAICompatibleModelBuilder::new(provider, &self.model_id)
+                        .with_base_url(self.base_url.as_deref())
+                        .with_temperature(self.temperature)
+                        .with_api_key(self.api_key(provider.api_key_env())?.as_deref())
+                        .build()?,
+                )
+            }
+        };
+        Ok(model)
+    }
+}
+
+/// A model built from a [`ModelConfig`].
+#[derive(Debug)]
+pub enum ConfiguredModel {
+    OpenAI(OpenAIServerModel),
+    Gemini(GeminiServerModel),
+    Ollama(OllamaModel),
+    OpenAICompatible(GenericOpenAICompatibleModel),
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for ConfiguredModel {
+    fn info(&self) -> ModelInfo {
+        match self {
+            ConfiguredModel::OpenAI(model) => model.info(),
+            ConfiguredModel::Gemini(model) => model.info(),
+            ConfiguredModel::Ollama(model) => model.info(),
+            ConfiguredModel::OpenAICompatible(model) => model.info(),
+        }
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        match self {
+            ConfiguredModel::OpenAI(model) => model.tokenizer(),
+            ConfiguredModel::Gemini(model) => model.tokenizer(),
+            ConfiguredModel::Ollama(model) => model.tokenizer(),
+            ConfiguredModel::OpenAICompatible(model) => model.tokenizer(),
+        }
+    }
+
+    async fn run(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        match self {
+            ConfiguredModel::OpenAI(model) => model.run(messages, history, tools, config).await,
+            ConfiguredModel::Gemini(model) => model.run(messages, history, tools, config).await,
+            ConfiguredModel::Ollama(model) => model.run(messages, history, tools, config).await,
+            ConfiguredModel::OpenAICompatible(model) => {
+                model.run(messages, history, tools, config).await
+            }
+        }
+    }
+
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        match self {
+            ConfiguredModel::OpenAI(model) => {
+                model.run_stream(messages, history, tools, config).await
+            }
+            ConfiguredModel::Gemini(model) => {
+                model.run_stream(messages, history, tools, config).await
+            }
+            ConfiguredModel::Ollama(model) => {
+                model.run_stream(messages, history, tools, config).await
+            }
+            ConfiguredModel::OpenAICompatible(model) => {
+                model.run_stream(messages, history, tools, config).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_agent_config() {
+        let toml_config: AgentConfig = toml::from_str(
+            r#"
+            name = "researcher"
+            tools = ["duckduckgo_search", "visit_website"]
+            max_steps = 8
+
+            [model]
+            provider = "openrouter"
+            model_id = "meta-llama/llama-3.1-70b-instruct"
+            api_key_env = "MY_OPENROUTER_KEY"
+
+            [[managed_agents]]
+            name = "coder"
+            description = "Writes and runs Python code"
+            agent_type = "code"
+            "#,
+        )
+        .unwrap();
+        let yaml_config: AgentConfig = serde_yaml::from_str(
+            "name: researcher\ntools: [duckduckgo_search, visit_website]\nmax_steps: 8\nmodel:\n  provider: openrouter\n  model_id: meta-llama/llama-3.1-70b-instruct\n  api_key_env: MY_OPENROUTER_KEY\nmanaged_agents:\n  - name: coder\n    description: Writes and runs Python code\n    agent_type: code\n",
+        )
+        .unwrap();
+        assert_eq!(toml_config, yaml_config);
+        assert!(toml_config.validate(true).is_ok());
+        assert_eq!(toml_config.agent_type, AgentKind::FunctionCalling);
+        assert_eq!(toml_config.managed_agents[0].agent_type, AgentKind::Code);
+        assert_eq!(
+            toml_config.model_config().unwrap().provider.compatible(),
+            Some(Provider::OpenRouter)
+        );
+
+        let mut unnamed = toml_config.clone();
+        unnamed.managed_agents[0].name = None;
+        assert!(unnamed.validate(true).is_err());
+
+        let mut unknown_tool = toml_config.clone();
+        unknown_tool.tools.push("unknown_tool".to_string());
+        assert!(unknown_tool.build_tools().is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! A key-value store shared by the tools and prompts of a run.
+//!
+//! Tools are otherwise stateless: the only way for one tool to hand data to the next is through the model, which
+//! costs tokens and may change it on the way. A [`RunContext`] is passed to every tool call, so a tool can save an
+//! intermediate result that later tools read back, and the prompts of the agent can refer to its values as
+//! `{{context.<key>}}`.
+//!
+//! ```rust,ignore
+//! let context = RunContext::new().with_value("user_id", json!("u-42"));
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_system_prompt(Some("You help the user {{context.user_id}}. {{tools}}"))
+//!     .with_context(context.clone())
+//!     .build()?;
+//!
+//! #[async_trait]
+//! impl Tool for LookupOrdersTool {
+//!     type Params = LookupOrdersParams;
+//!     async fn forward(&self, arguments: LookupOrdersParams) -> Result<String> {
+//!         self.forward_with_context(arguments, &RunContext::new()).await
+//!     }
+//!     async fn forward_with_context(
+//!         &self,
+//!         _arguments: LookupOrdersParams,
+//!         context: &RunContext,
+//!     ) -> Result<String> {
+//!         let user_id: String = context.get("user_id")?.context("No user")?;
+//!         let orders = self.client.orders(&user_id).await?;
+//!         context.set("orders", &orders)?;
+//!         Ok(format!("The user has {} orders", orders.len()))
+//!     }
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::agent::Delegation;
+use crate::prompts::render_template;
+
+/// The values of a run. Clones share the same values, so the context handed to the builder of an agent is the one
+/// its tools write to, and the caller can read it after the run. The values are kept between runs until they are
+/// removed or the context is cleared.
+///
+/// Clones also share the cancellation: a caller that keeps a clone can [`cancel`](RunContext::cancel) the run, and
+/// the agent stops before its next step with [`AgentError::Cancelled`](crate::errors::AgentError::Cancelled).
+///
+/// The context handed to a tool also names the agent that called it, so that an
+/// [`AgentTool`](crate::tools::AgentTool) nests its sub-agent under that agent. The caller belongs to the clone,
+/// it is not shared.
+#[derive(Debug, Clone, Default)]
+pub struct RunContext {
+    values: Arc<RwLock<BTreeMap<String, Value>>>,
+    cancelled: Arc<AtomicBool>,
+    caller_run_id: Option<String>,
+    caller_delegation: Option<Delegation>,
+}
+
+impl RunContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a value before the context is handed to an agent.
+    pub fn with_value(self, key: &str, value: Value) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Saves `value` under `key`, replacing the value saved before.
+    pub fn set(&self, key: &str, value: impl Serialize) -> Result<()> {
+        self.insert(key, serde_json::to_value(value)?);
+        Ok(())
+    }
+
+    pub fn insert(&self, key: &str, value: Value) -> Option<Value> {
+        self.values.write().unwrap().insert(key.to_string(), value)
+    }
+
+    /// Reads the value saved under `key` as a `T`. Fails if the value is not a `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get_value(key) {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_value(&self, key: &str) -> Option<Value> {
+        self.values.read().unwrap().get(key).cloned()
+    }
+
+    pub fn remove(&self, key: &str) -> Option<Value> {
+        self.values.write().unwrap().remove(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.values.read().unwrap().contains_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.values.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.read().unwrap().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.values.write().unwrap().clear();
+    }
+
+    /// Stops the runs that use the context before their next step. The context stays cancelled until
+    /// [`resume`](RunContext::resume) is called.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Lets runs use the context again after it was cancelled.
+    pub fn resume(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    /// A clone of the context for the tools called by the run `run_id`, whose agent is at `delegation`.
+    pub(crate) fn with_caller(&self, run_id: Option<String>, delegation: &Delegation) -> Self {
+        Self {
+            caller_run_id: run_id,
+            caller_delegation: Some(delegation.clone()),
+            ..self.clone()
+        }
+    }
+
+    /// The run of the agent whose tool got the context.
+    pub fn caller_run_id(&self) -> Option<&str> {
+        self.caller_run_id.as_deref()
+    }
+
+    /// Where the agent whose tool got the context is in its delegation hierarchy.
+    pub fn caller_delegation(&self) -> Option<&Delegation> {
+        self.caller_delegation.as_ref()
+    }
+
+    /// A copy of all the values, e.g. to save them after a run.
+    pub fn snapshot(&self) -> BTreeMap<String, Value> {
+        self.values.read().unwrap().clone()
+    }
+
+    /// Replaces every `{{context.<key>}}` placeholder in `template` with the value saved under the key. Strings are
+    /// inserted as they are and other values as JSON. Placeholders of keys without a value are left as they are.
+    pub fn render(&self, template: &str) -> String {
+        if !template.contains("context.") {
+            return template.to_string();
+        }
+        let variables = self
+            .values
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                (format!("context.{}", key), value)
+            })
+            .collect::<Vec<_>>();
+        render_template(
+            template,
+            variables
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+    }
+}
+
+impl From<BTreeMap<String, Value>> for RunContext {
+    fn from(values: BTreeMap<String, Value>) -> Self {
+        Self {
+            values: Arc::new(RwLock::new(values)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_run_context() {
+        let context = RunContext::new().with_value("user_id", json!("u-42"));
+        let shared = context.clone();
+        shared.set("orders", vec![3, 5]).unwrap();
+
+        assert_eq!(context.get::<Vec<u32>>("orders").unwrap(), Some(vec![3, 5]));
+        assert!(context.get::<String>("orders").is_err());
+        assert_eq!(context.get::<String>("missing").unwrap(), None);
+        assert_eq!(
+            context.render("User {{context.user_id}} has {{ context.orders }}, {{context.other}}"),
+            "User u-42 has [3,5], {{context.other}}"
+        );
+
+        context.clear();
+        assert!(shared.is_empty());
+    }
+}
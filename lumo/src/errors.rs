@@ -1,34 +1,325 @@
 use std::fmt;
+use std::sync::Arc;
 
 use serde::Serialize;
 
+use crate::{
+    agent::Step,
+    models::types::{RateLimit, Usage},
+};
+
+/// The errors of agents, models and tools. [`AgentError::is_retryable`] tells whether trying again may succeed, and
+/// [`std::error::Error::source`] leads to the underlying error where there is one.
 #[derive(Debug, Clone, Serialize)]
 pub enum AgentError {
-    Parsing(String),
+    /// The model output could not be parsed into a tool call or code block.
+    Parsing(ParsingError),
     Execution(String),
-    MaxSteps(String),
-    Generation(String),
+    /// The agent used all of its steps without giving an answer.
+    MaxStepsExceeded(Box<MaxStepsExceededError>),
+    /// The model provider could not be reached or answered with an error.
+    Model(ModelError),
+    BudgetExceeded(Box<BudgetExceededError>),
+    Tool(ToolError),
+    /// A managed agent would be nested too deep, or the managed agents took more steps than their budget.
+    DelegationLimit(DelegationLimitError),
+    /// The run was cancelled before it finished.
+    Cancelled,
+}
+
+/// An error of a model provider.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelError {
+    /// The provider of the model, such as `openai` or `ollama`.
+    pub provider: String,
+    /// The HTTP status the provider answered with, if it answered.
+    pub status: Option<u16>,
+    pub message: String,
+    /// Whether the same request may succeed later, e.g. after a rate limit, a server error or a timeout.
+    pub retryable: bool,
+    /// The rate limit headers of the response, if the provider sent them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(skip)]
+    source: Option<Arc<dyn std::error::Error + Send + Sync>>,
 }
 
-impl std::error::Error for AgentError {}
+impl ModelError {
+    pub fn new(provider: &str, message: impl Into<String>) -> Self {
+        Self {
+            provider: provider.to_string(),
+            status: None,
+            message: message.into(),
+            retryable: false,
+            rate_limit: None,
+            source: None,
+        }
+    }
+
+    /// An error for an HTTP status of the provider. Rate limits, timeouts and server errors are retryable.
+    pub fn from_status(provider: &str, status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status: Some(status),
+            retryable: status == 408 || status == 429 || status >= 500,
+            ..Self::new(provider, message)
+        }
+    }
+
+    /// An error for a request that failed. Timeouts and failed connections are retryable.
+    pub fn from_request(provider: &str, message: impl Into<String>, error: reqwest::Error) -> Self {
+        let mut model_error = match error.status() {
+            Some(status) => Self::from_status(provider, status.as_u16(), message),
+            None => Self::new(provider, message),
+        };
+        model_error.retryable |= error.is_timeout() || error.is_connect();
+        model_error.with_source(error)
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: Option<RateLimit>) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn with_source(mut self, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ModelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<ModelError> for AgentError {
+    fn from(error: ModelError) -> Self {
+        AgentError::Model(error)
+    }
+}
+
+/// Model output that could not be parsed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ParsingError {
+    pub message: String,
+    /// The output of the model, to find out what it did wrong.
+    pub raw_output: Option<String>,
+}
+
+impl ParsingError {
+    pub fn new(message: impl Into<String>, raw_output: Option<&str>) -> Self {
+        Self {
+            message: message.into(),
+            raw_output: raw_output.map(|output| output.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParsingError {}
+
+impl From<ParsingError> for AgentError {
+    fn from(error: ParsingError) -> Self {
+        AgentError::Parsing(error)
+    }
+}
+
+/// An error returned by a tool. Tools return it through `anyhow`, e.g. `Err(ToolError::Fatal(msg).into())`, and the
+/// agent uses the variant to decide what to do: timeouts and rate limits are retried, fatal errors abort the run
+/// and everything else is shown to the model as the observation so that it can correct itself. Errors that are not
+/// a `ToolError` are treated as [`ToolError::Recoverable`].
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub enum ToolError {
+    /// The arguments do not match the inputs of the tool.
+    InvalidArguments(String),
+    Timeout(String),
+    RateLimited {
+        message: String,
+        /// How long to wait before trying again, if the service said so.
+        retry_after: Option<std::time::Duration>,
+    },
+    /// The run cannot continue, e.g. because credentials are missing or were revoked.
+    Fatal(String),
+    Recoverable(String),
+}
+
+impl ToolError {
+    pub fn message(&self) -> &str {
+        match self {
+            Self::InvalidArguments(msg) => msg,
+            Self::Timeout(msg) => msg,
+            Self::RateLimited { message, .. } => message,
+            Self::Fatal(msg) => msg,
+            Self::Recoverable(msg) => msg,
+        }
+    }
+
+    /// Whether calling the tool again with the same arguments may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Timeout(_) | Self::RateLimited { .. })
+    }
+
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Fatal(_))
+    }
+}
+
+impl std::error::Error for ToolError {}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidArguments(msg) => write!(f, "{}", msg),
+            Self::Timeout(msg) => write!(f, "Tool call timed out: {}", msg),
+            Self::RateLimited { message, .. } => {
+                write!(f, "Tool call was rate limited: {}", message)
+            }
+            Self::Fatal(msg) => write!(f, "Fatal tool error: {}", msg),
+            Self::Recoverable(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<ToolError> for AgentError {
+    fn from(error: ToolError) -> Self {
+        AgentError::Tool(error)
+    }
+}
+
+/// Returned when a run goes over its [`Budget`](crate::agent::Budget). Carries what the agent did so far so that
+/// the caller can still show or resume the partial work.
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetExceededError {
+    pub message: String,
+    /// Tokens used by the run.
+    pub usage: Usage,
+    /// Dollar cost of the run, if the budget has a price for the model.
+    pub cost: Option<f64>,
+    pub elapsed: std::time::Duration,
+    /// The last non-empty model output of the run.
+    pub partial_answer: Option<String>,
+    pub logs: Vec<Step>,
+}
+
+/// Returned when an agent used all of its steps and the final step did not give an answer either.
+#[derive(Debug, Clone, Serialize)]
+pub struct MaxStepsExceededError {
+    pub message: String,
+    pub max_steps: usize,
+    pub logs: Vec<Step>,
+}
+
+/// Returned when delegation to managed agents goes over its
+/// [`DelegationLimits`](crate::agent::DelegationLimits).
+#[derive(Debug, Clone, Serialize)]
+pub struct DelegationLimitError {
+    pub message: String,
+    /// The depth of the agent that went over the limit. Zero for the top-level agent.
+    pub depth: usize,
+    /// The steps the managed agents of the hierarchy took so far.
+    pub sub_agent_steps: usize,
+}
+
+/// Returned by [`Agent::run`](crate::agent::Agent::run): the error the run failed with and the steps the agent took
+/// before, so that the caller can still show what happened.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunError {
+    pub error: AgentError,
+    pub logs: Vec<Step>,
+    /// Tokens used by the run until it failed.
+    pub usage: Usage,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<RunError> for AgentError {
+    fn from(error: RunError) -> Self {
+        error.error
+    }
+}
+
+impl std::error::Error for AgentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Model(error) => std::error::Error::source(error),
+            _ => None,
+        }
+    }
+}
 
 impl AgentError {
     pub fn message(&self) -> &str {
         match self {
-            Self::Parsing(msg) => msg,
+            Self::Parsing(error) => &error.message,
             Self::Execution(msg) => msg,
-            Self::MaxSteps(msg) => msg,
-            Self::Generation(msg) => msg,
+            Self::MaxStepsExceeded(error) => &error.message,
+            Self::Model(error) => &error.message,
+            Self::BudgetExceeded(error) => &error.message,
+            Self::Tool(error) => error.message(),
+            Self::DelegationLimit(error) => &error.message,
+            Self::Cancelled => "The run was cancelled",
+        }
+    }
+
+    /// A [`ParsingError`] for the output of a model.
+    pub fn parsing(message: impl Into<String>, raw_output: &str) -> Self {
+        AgentError::Parsing(ParsingError::new(message, Some(raw_output)))
+    }
+
+    /// Whether the failed call may succeed when it is made again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Model(error) => error.retryable,
+            Self::Tool(error) => error.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Whether a managed agent failing with this error stops its manager too.
+    ///
+    /// Any other error is shown to the manager as the observation of its call.
+    pub fn stops_manager(&self) -> bool {
+        match self {
+            Self::DelegationLimit(_) | Self::Cancelled => true,
+            Self::Tool(error) => error.is_fatal(),
+            _ => false,
         }
     }
 }
 impl std::fmt::Display for AgentError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Parsing(msg) => write!(f, "{}", msg),
+            Self::Parsing(error) => write!(f, "{}", error),
             Self::Execution(msg) => write!(f, "{}", msg),
-            Self::MaxSteps(msg) => write!(f, "{}", msg),
-            Self::Generation(msg) => write!(f, "{}", msg),
+            Self::MaxStepsExceeded(error) => write!(f, "{}", error.message),
+            Self::Model(error) => write!(f, "{}", error),
+            Self::BudgetExceeded(error) => write!(f, "{}", error.message),
+            Self::Tool(error) => write!(f, "{}", error),
+            Self::DelegationLimit(error) => write!(f, "{}", error.message),
+            Self::Cancelled => write!(f, "{}", self.message()),
         }
     }
 }
@@ -68,3 +359,36 @@ impl fmt::Display for InterpreterError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn test_model_error_retryable_and_source() {
+        assert!(ModelError::from_status("openai", 429, "Rate limited").retryable);
+        assert!(ModelError::from_status("openai", 503, "Unavailable").retryable);
+        assert!(!ModelError::from_status("openai", 401, "Unauthorized").retryable);
+
+        let io_error = std::io::Error::new(std::io::ErrorKind::Other, "connection reset");
+        let error: AgentError = ModelError::new("ollama", "Failed to get response")
+            .with_source(io_error)
+            .into();
+        assert!(!error.is_retryable());
+        assert_eq!(error.source().unwrap().to_string(), "connection reset");
+
+        let run_error = RunError {
+            error: AgentError::parsing("No valid action JSON found", "Thought: hmm"),
+            logs: vec![Step::TaskStep("task".to_string())],
+            usage: Usage::default(),
+        };
+        assert_eq!(run_error.to_string(), "No valid action JSON found");
+        match AgentError::from(run_error) {
+            AgentError::Parsing(error) => {
+                assert_eq!(error.raw_output.as_deref(), Some("Thought: hmm"))
+            }
+            error => panic!("unexpected error: {:?}", error),
+        }
+    }
+}
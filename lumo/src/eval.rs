@@ -0,0 +1,310 @@
+//! Runs an agent over a suite of tasks and scores the answers, to compare prompts, models and tool sets.
+//!
+//! A suite is a YAML or JSON file with a list of tasks:
+//!
+//! ```yaml
+//! tasks:
+//!   - id: capital
+//!     task: What is the capital of the Netherlands?
+//!     expected: Amsterdam
+//!   - id: haiku
+//!     task: Write a haiku about the sea.
+//!     judge: Is the answer a haiku, with three lines of five, seven and five syllables?
+//! ```
+//!
+//! A task with an `expected` answer passes when the answer contains it, ignoring case and whitespace. A task with a
+//! `judge` prompt is scored by the judge model given to the [`Evaluator`]. A task with neither passes when the run
+//! succeeds.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::{Agent, Step},
+    models::{
+        model_traits::Model,
+        types::{GenerationConfig, Message, MessageRole, Usage},
+    },
+    runtime::Instant,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalTask {
+    /// Names the task in the report. Defaults to the position of the task in the suite.
+    #[serde(default)]
+    pub id: Option<String>,
+    pub task: String,
+    #[serde(default)]
+    pub expected: Option<String>,
+    /// Instructions for the judge model on how to decide whether the answer passes.
+    #[serde(default)]
+    pub judge: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EvalSuite {
+    pub tasks: Vec<EvalTask>,
+}
+
+impl EvalSuite {
+    /// Loads a suite from a `.yaml`, `.yml` or `.json` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read eval suite: {:?}", path))?;
+        let suite = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => serde_json::from_str(&content)?,
+            _ => serde_yaml::from_str(&content)?,
+        };
+        Ok(suite)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalResult {
+    pub id: String,
+    pub task: String,
+    pub answer: Option<String>,
+    /// The error the run failed with, if it failed.
+    pub error: Option<String>,
+    pub passed: bool,
+    /// Why the judge passed or failed the answer.
+    pub judgement: Option<String>,
+    pub latency: Duration,
+    pub steps: usize,
+    pub usage: Usage,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EvalReport {
+    pub results: Vec<EvalResult>,
+}
+
+impl EvalReport {
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|result| result.passed).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    pub fn pass_rate(&self) -> f64 {
+        if self.results.is_empty() {
+            return 0.0;
+        }
+        self.passed() as f64 / self.results.len() as f64
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        if self.results.is_empty() {
+            return Duration::ZERO;
+        }
+        self.results
+            .iter()
+            .map(|result| result.latency)
+            .sum::<Duration>()
+            / self.results.len() as u32
+    }
+
+    pub fn total_usage(&self) -> Usage {
+        self.results
+            .iter()
+            .fold(Usage::default(), |total, result| total + result.usage)
+    }
+}
+
+impl std::fmt::Display for EvalReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for result in &self.results {
+            writeln!(
+                f,
+                "{} {}: {:.1}s, {} steps, {} tokens{}",
+                if result.passed { "PASS" } else { "FAIL" },
+                result.id,
+                result.latency.as_secs_f64(),
+                result.steps,
+                result.usage.total_tokens(),
+                result
+                    .error
+                    .as_ref()
+                    .map(|error| format!(" ({})", error))
+                    .unwrap_or_default()
+            )?;
+        }
+        write!(
+            f,
+            "{}/{} passed ({:.0}%), mean latency {:.1}s, {} tokens",
+            self.passed(),
+            self.results.len(),
+            self.pass_rate() * 100.0,
+            self.mean_latency().as_secs_f64(),
+            self.total_usage().total_tokens()
+        )
+    }
+}
+
+/// Runs a fresh agent from `make_agent` for every task of a suite.
+pub struct Evaluator<F> {
+    make_agent: F,
+    concurrency: usize,
+    judge: Option<Box<dyn Model>>,
+}
+
+impl<A, F> Evaluator<F>
+where
+    A: Agent,
+    F: Fn() -> Result<A>,
+{
+    pub fn new(make_agent: F) -> Self {
+        Self {
+            make_agent,
+            concurrency: 1,
+            judge: None,
+        }
+    }
+
+    /// Runs up to `concurrency` tasks at the same time. Defaults to one.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// The model that scores the tasks that have a judge prompt.
+    pub fn with_judge(mut self, judge: Box<dyn Model>) -> Self {
+        self.judge = Some(judge);
+        self
+    }
+
+    /// Runs every task of the suite. The results are in the order of the tasks.
+    pub async fn run(&self, suite: &EvalSuite) -> EvalReport {
+        let results = stream::iter(suite.tasks.iter().enumerate())
+            .map(|(index, task)| self.run_task(index, task))
+            .buffered(self.concurrency)
+            .collect::<Vec<_>>()
+            .await;
+        EvalReport { results }
+    }
+
+    async fn run_task(&self, index: usize, task: &EvalTask) -> EvalResult {
+        let mut result = EvalResult {
+            id: task.id.clone().unwrap_or_else(|| (index + 1).to_string()),
+            task: task.task.clone(),
+            answer: None,
+            error: None,
+            passed: false,
+            judgement: None,
+            latency: Duration::ZERO,
+            steps: 0,
+            usage: Usage::default(),
+        };
+        let mut agent = match (self.make_agent)() {
+            Ok(agent) => agent,
+            Err(e) => {
+                result.error = Some(e.to_string());
+                return result;
+            }
+        };
+
+        let started = Instant::now();
+        let answer = agent.run(&task.task, true).await;
+        result.latency = started.elapsed();
+        result.steps = agent
+            .get_logs_mut()
+            .iter()
+            .filter(|step| matches!(step, Step::ActionStep(_)))
+            .count();
+        result.usage = agent.get_usage();
+
+        let answer = match answer {
+            Ok(answer) => answer,
+            Err(e) => {
+                result.error = Some(e.to_string());
+                return result;
+            }
+        };
+        match (&task.judge, &task.expected) {
+            (Some(judge_prompt), expected) => match self
+                .score_with_judge(task, judge_prompt, expected.as_deref(), &answer)
+                .await
+            {
+                Ok((passed, judgement)) => {
+                    result.passed = passed;
+                    result.judgement = Some(judgement);
+                }
+                Err(e) => result.error = Some(format!("Judge failed: {}", e)),
+            },
+            (None, Some(expected)) => result.passed = answer_matches(&answer, expected),
+            (None, None) => result.passed = true,
+        }
+        result.answer = Some(answer);
+        result
+    }
+
+    async fn score_with_judge(
+        &self,
+        task: &EvalTask,
+        judge_prompt: &str,
+        expected: Option<&str>,
+        answer: &str,
+    ) -> Result<(bool, String)> {
+        let judge = self
+            .judge
+            .as_ref()
+            .context("The task has a judge prompt but the evaluator has no judge model")?;
+        let prompt = format!(
+            "You are grading the answer of an assistant to a task.\n\nTask:\n{}\n\n{}Answer:\n{}\n\nGrading instructions:\n{}\n\nStart your reply with PASS or FAIL, followed by a short explanation.",
+            task.task,
+            expected
+                .map(|expected| format!("Expected answer:\n{}\n\n", expected))
+                .unwrap_or_default(),
+            answer,
+            judge_prompt
+        );
+        let judgement = judge
+            .run(
+                vec![Message::new(MessageRole::User, &prompt)],
+                None,
+                vec![],
+                GenerationConfig::new().with_temperature(0.0),
+            )
+            .await?
+            .get_response()?;
+        let passed = judgement.trim_start().to_uppercase().starts_with("PASS");
+        Ok((passed, judgement))
+    }
+}
+
+/// Whether `answer` contains `expected`, ignoring case and whitespace.
+fn answer_matches(answer: &str, expected: &str) -> bool {
+    let normalize = |text: &str| {
+        text.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+    normalize(answer).contains(&normalize(expected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_suite_and_match() {
+        let suite: EvalSuite = serde_yaml::from_str(
+            "tasks:\n  - id: capital\n    task: What is the capital of the Netherlands?\n    expected: Amsterdam\n  - task: Write a haiku.\n    judge: Is it a haiku?\n",
+        )
+        .unwrap();
+        assert_eq!(suite.tasks.len(), 2);
+        assert_eq!(suite.tasks[0].expected.as_deref(), Some("Amsterdam"));
+        assert!(suite.tasks[1].id.is_none());
+
+        assert!(answer_matches("The capital is\n  AMSTERDAM.", "amsterdam"));
+        assert!(!answer_matches("Rotterdam", "Amsterdam"));
+    }
+}
@@ -42,12 +42,27 @@
 
 //! ```
 
+#[cfg(all(target_arch = "wasm32", not(feature = "wasm")))]
+compile_error!("lumo needs the `wasm` feature to build for wasm32");
+
+pub mod artifacts;
+pub mod batch;
+pub mod config;
+pub mod context;
 #[cfg(feature = "code-agent")]
 pub mod local_python_interpreter;
 pub(crate) mod logger;
 pub mod models;
 pub mod prompts;
+pub mod retrieval;
+pub mod runtime;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sandbox;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod telemetry;
 pub mod tools;
+pub mod workflow;
 pub mod agent;
 pub mod errors;
+pub mod eval;
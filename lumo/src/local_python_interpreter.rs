@@ -1,5 +1,6 @@
+use crate::context::RunContext;
 use crate::errors::InterpreterError;
-use crate::tools::tool_traits::AsyncTool;
+use crate::tools::tool_traits::{retry_tool_call, AsyncTool, ToolRetryPolicy};
 use crate::tools::ToolInfo;
 use anyhow::Result;
 use pyo3::types::{IntoPyDict, PyDict, PyModule, PyTuple};
@@ -313,6 +314,8 @@ impl PythonToolFunction {
 fn setup_custom_tools(
     tools: &[Box<dyn AsyncTool>],
     runtime: &Runtime,
+    context: &RunContext,
+    tool_retry: &ToolRetryPolicy,
 ) -> HashMap<String, PythonToolFunction> {
     let mut tools_map = HashMap::new();
     for tool in tools {
@@ -320,6 +323,8 @@ fn setup_custom_tools(
         let tool_name = tool.name().to_string();
         let tool_info = tool.tool_info();
         let runtime = runtime.handle().clone();
+        let context = context.clone();
+        let tool_retry = tool_retry.clone();
         tools_map.insert(
             tool_name.clone(),
             PythonToolFunction {
@@ -332,7 +337,9 @@ fn setup_custom_tools(
 
                     let tool_clone = tool.clone_box();
                     // Execute the async operation synchronously
-                    let result = runtime.block_on(async { tool_clone.forward_json(args).await });
+                    let result = runtime.block_on(retry_tool_call(&tool_name, &tool_retry, || {
+                        tool_clone.call(args.clone(), &context)
+                    }));
 
                     match result {
                         Ok(result) => Ok(CustomConstant::Str(result)),
@@ -403,8 +410,11 @@ fn evaluate_python_code(
     static_tools: &HashMap<&'static str, &'static str>,
     state: &mut HashMap<String, Py<PyAny>>,
     runtime: Option<&Runtime>,
+    context: &RunContext,
+    tool_retry: &ToolRetryPolicy,
 ) -> Result<String, InterpreterError> {
-    let custom_tools = custom_tools.map(|tools| setup_custom_tools(tools, runtime.unwrap()));
+    let custom_tools =
+        custom_tools.map(|tools| setup_custom_tools(tools, runtime.unwrap(), context, tool_retry));
     let code = code.to_string();
     let static_tools = static_tools.clone();
     let state_clone: HashMap<String, Py<PyAny>> = Python::with_gil(|py| {
@@ -497,6 +507,8 @@ pub struct LocalPythonInterpreter {
     custom_tools: Option<Vec<Box<dyn AsyncTool>>>,
     state: HashMap<String, PyObject>,
     runtime: Option<Runtime>,
+    context: RunContext,
+    tool_retry: ToolRetryPolicy,
 }
 
 impl LocalPythonInterpreter {
@@ -519,9 +531,21 @@ impl LocalPythonInterpreter {
             custom_tools,
             state: HashMap::new(),
             runtime,
+            context: RunContext::new(),
+            tool_retry: ToolRetryPolicy::default(),
         }
     }
 
+    /// The [`RunContext`] passed to the tools that the code calls.
+    pub fn set_context(&mut self, context: RunContext) {
+        self.context = context;
+    }
+
+    /// How the tools that the code calls are retried when they time out or are rate limited.
+    pub fn set_tool_retry(&mut self, tool_retry: ToolRetryPolicy) {
+        self.tool_retry = tool_retry;
+    }
+
     pub fn forward(&mut self, code: &str) -> Result<(String, String), InterpreterError> {
         let execution_logs = evaluate_python_code(
             code,
@@ -529,6 +553,8 @@ impl LocalPythonInterpreter {
             &self.static_tools,
             &mut self.state,
             self.runtime.as_ref(),
+            &self.context,
+            &self.tool_retry,
         )?;
 
         Ok(("".to_string(), execution_logs.to_string()))
@@ -1,6 +1,7 @@
 use colored::Colorize;
 use log::{Level, Metadata, Record};
 use std::io::Write;
+#[cfg(not(target_arch = "wasm32"))]
 use terminal_size::{self, Width};
 
 pub struct ColoredLogger;
@@ -19,11 +20,14 @@ impl log::Log for ColoredLogger {
             writeln!(stdout).unwrap();
 
             // Get terminal width
+            #[cfg(not(target_arch = "wasm32"))]
             let width = if let Some((Width(w), _)) = terminal_size::terminal_size() {
                 w as usize - 2 // Subtract 2 for the side borders
             } else {
                 78 // fallback width if terminal size cannot be determined
             };
+            #[cfg(target_arch = "wasm32")]
+            let width = 78;
 
             // Box drawing characters
             let top_border = format!("╔{}═", "═".repeat(width));
@@ -0,0 +1,227 @@
+//! A builder for the messages of a conversation, checked against the rules the chat APIs enforce.
+//!
+//! Providers reject a conversation whose tool results do not answer the tool calls before them, and the error they
+//! return rarely says which message is wrong. [`Conversation::validate`] finds the message before the request is
+//! sent.
+//!
+//! ```rust,ignore
+//! let conversation = Conversation::new()
+//!     .system("You are a support agent.")
+//!     .user_named("alice", "My order has not arrived.")
+//!     .tool_calls("", vec![lookup_order_call])
+//!     .tool_result("call_1", "Order 42 was shipped yesterday.")
+//!     .assistant("Your order was shipped yesterday.");
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_conversation(conversation)
+//!     .build()?;
+//! ```
+
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    openai::ToolCall,
+    types::{Message, MessageRole},
+};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Conversation {
+    messages: Vec<Message>,
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(mut self, message: Message) -> Self {
+        self.messages.push(message);
+        self
+    }
+
+    pub fn system(self, content: &str) -> Self {
+        self.push(Message::system(content))
+    }
+
+    pub fn user(self, content: &str) -> Self {
+        self.push(Message::user(content))
+    }
+
+    /// A user message from the participant `name`, for conversations with several users.
+    pub fn user_named(self, name: &str, content: &str) -> Self {
+        self.push(Message::user(content).with_name(name))
+    }
+
+    pub fn assistant(self, content: &str) -> Self {
+        self.push(Message::assistant(content))
+    }
+
+    pub fn tool_calls(self, content: &str, tool_calls: Vec<ToolCall>) -> Self {
+        self.push(Message::tool_calls(content, tool_calls))
+    }
+
+    pub fn tool_result(self, tool_call_id: &str, content: &str) -> Self {
+        self.push(Message::tool_result(tool_call_id, content))
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn into_messages(self) -> Vec<Message> {
+        self.messages
+    }
+
+    /// Checks the conversation against the rules of the chat APIs.
+    pub fn validate(&self) -> Result<()> {
+        validate_messages(&self.messages)
+    }
+}
+
+impl From<Conversation> for Vec<Message> {
+    fn from(conversation: Conversation) -> Self {
+        conversation.messages
+    }
+}
+
+impl TryFrom<Vec<Message>> for Conversation {
+    type Error = anyhow::Error;
+
+    fn try_from(messages: Vec<Message>) -> Result<Self> {
+        validate_messages(&messages)?;
+        Ok(Self { messages })
+    }
+}
+
+/// Checks that system messages come first, that every tool result has the id of a tool call of the assistant
+/// message before it, and that every tool call is answered before the conversation goes on.
+pub fn validate_messages(messages: &[Message]) -> Result<()> {
+    let mut seen_other = false;
+    // The ids of the tool calls of the last assistant message that are not answered yet.
+    let mut open_calls: Option<BTreeSet<String>> = None;
+    for (index, message) in messages.iter().enumerate() {
+        if message.role == MessageRole::ToolResponse {
+            let Some(open) = open_calls.as_mut() else {
+                bail!(
+                    "Message {} is a tool result that does not follow an assistant message with tool calls",
+                    index
+                );
+            };
+            let Some(id) = message.tool_call_id.as_deref() else {
+                bail!("Message {} is a tool result without a tool_call_id", index);
+            };
+            if !open.remove(id) {
+                bail!(
+                    "Message {} answers the tool call {}, which the assistant message before it did not make or \
+                     which was already answered",
+                    index,
+                    id
+                );
+            }
+            continue;
+        }
+        if let Some(open) = open_calls.take() {
+            if !open.is_empty() {
+                bail!(
+                    "Message {} follows tool calls that were not answered: {}",
+                    index,
+                    open.into_iter().collect::<Vec<_>>().join(", ")
+                );
+            }
+        }
+        match message.role {
+            MessageRole::System if seen_other => {
+                bail!(
+                    "Message {} is a system message after the start of the conversation",
+                    index
+                );
+            }
+            MessageRole::System => {}
+            _ => seen_other = true,
+        }
+        if let Some(tool_calls) = message
+            .tool_calls
+            .as_ref()
+            .filter(|calls| !calls.is_empty())
+        {
+            let mut ids = BTreeSet::new();
+            for tool_call in tool_calls {
+                let Some(id) = tool_call.id.clone() else {
+                    bail!("Message {} has a tool call without an id", index);
+                };
+                ids.insert(id);
+            }
+            open_calls = Some(ids);
+        }
+    }
+    if let Some(open) = open_calls.filter(|open| !open.is_empty()) {
+        bail!(
+            "The conversation ends with tool calls that were not answered: {}",
+            open.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::openai::FunctionCall;
+    use serde_json::json;
+
+    fn call(id: &str) -> ToolCall {
+        ToolCall {
+            id: Some(id.to_string()),
+            call_type: Some("function".to_string()),
+            function: FunctionCall {
+                name: "search".to_string(),
+                arguments: json!({"query": "rust"}),
+            },
+        }
+    }
+
+    #[test]
+    fn test_validate_conversation() {
+        let conversation = Conversation::new()
+            .system("You are a helpful agent.")
+            .user_named("alice", "Search for rust")
+            .tool_calls("", vec![call("call_1"), call("call_2")])
+            .tool_result("call_2", "Second")
+            .tool_result("call_1", "First")
+            .assistant("Done");
+        assert!(conversation.validate().is_ok());
+        assert_eq!(conversation.messages()[1].name.as_deref(), Some("alice"));
+
+        let unanswered = Conversation::new()
+            .user("Search")
+            .tool_calls("", vec![call("call_1"), call("call_2")])
+            .tool_result("call_1", "First")
+            .assistant("Done");
+        assert!(unanswered.validate().is_err());
+
+        let orphan = Conversation::new()
+            .user("Search")
+            .tool_result("call_1", "First");
+        assert!(orphan.validate().is_err());
+
+        let late_system = Conversation::new().user("Hi").system("Be brief.");
+        assert!(late_system.validate().is_err());
+
+        let pending = vec![
+            Message::user("Search"),
+            Message::tool_calls("", vec![call("call_1")]),
+        ];
+        assert!(Conversation::try_from(pending).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! This module contains the embedding models. Embedding models turn text into vectors that can be stored in a
+//! [`VectorStore`](crate::retrieval::VectorStore) and searched semantically.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::wire_log::SendLogged;
+use crate::errors::{AgentError, ModelError};
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait EmbeddingModel: Send + Sync + 'static {
+    /// Embeds a batch of texts. The returned vectors are in the same order as the input texts.
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AgentError>;
+
+    /// Embeds a single text.
+    async fn embed_query(&self, text: &str) -> Result<Vec<f32>, AgentError> {
+        self.embed(vec![text.to_string()])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| ModelError::new("embeddings", "No embedding returned for query").into())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbeddingResponse {
+    data: Vec<OpenAIEmbeddingData>,
+}
+
+/// Embedding model for the OpenAI embeddings API and any provider exposing the same endpoint.
+#[derive(Debug, Clone)]
+pub struct OpenAIEmbeddingModel {
+    pub base_url: String,
+    pub model_id: String,
+    pub client: Client,
+    pub api_key: String,
+    pub dimensions: Option<usize>,
+}
+
+impl OpenAIEmbeddingModel {
+    pub fn new(
+        base_url: Option<&str>,
+        model_id: Option<&str>,
+        api_key: Option<String>,
+        dimensions: Option<usize>,
+    ) -> Result<Self> {
+        let api_key = match api_key {
+            Some(api_key) => api_key,
+            None => std::env::var("OPENAI_API_KEY")
+                .context("OPENAI_API_KEY must be set when no API key is given")?,
+        };
+        Ok(OpenAIEmbeddingModel {
+            base_url: base_url
+                .unwrap_or("https://api.openai.com/v1/embeddings")
+                .to_string(),
+            model_id: model_id.unwrap_or("text-embedding-3-small").to_string(),
+            client: Client::new(),
+            api_key,
+            dimensions,
+        })
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl EmbeddingModel for OpenAIEmbeddingModel {
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AgentError> {
+        if texts.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut body = json!({
+            "model": self.model_id,
+            "input": texts,
+        });
+        if let Some(dimensions) = self.dimensions {
+            body["dimensions"] = json!(dimensions);
+        }
+        let response = self
+            .client
+            .post(&self.base_url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&body)
+            .send_logged("openai")
+            .await
+            .map_err(|e| {
+                ModelError::from_request(
+                    "openai",
+                    format!("Failed to get embeddings from OpenAI: {}", e),
+                    e,
+                )
+            })?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(ModelError::from_status(
+                "openai",
+                status.as_u16(),
+                format!(
+                    "Failed to get embeddings from OpenAI: {} {}",
+                    status,
+                    response.text().await.unwrap_or_default(),
+                ),
+            )
+            .into());
+        }
+        let mut response = response
+            .json::<OpenAIEmbeddingResponse>()
+            .await
+            .map_err(|e| {
+                ModelError::from_request(
+                    "openai",
+                    format!("Failed to parse embeddings from OpenAI: {}", e),
+                    e,
+                )
+            })?;
+        response.data.sort_by_key(|data| data.index);
+        Ok(response.data.into_iter().map(|data| data.embedding).collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaEmbeddingResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// Embedding model served by a local Ollama instance.
+#[derive(Debug, Clone)]
+pub struct OllamaEmbeddingModel {
+    pub model_id: String,
+    pub url: String,
+    pub client: Client,
+}
+
+impl OllamaEmbeddingModel {
+    pub fn new(model_id: Option<&str>, url: Option<&str>) -> Self {
+        OllamaEmbeddingModel {
+            model_id: model_id.unwrap_or("nomic-embed-text").to_string(),
+            url: url.unwrap_or("http://localhost:11434").to_string(),
+            client: Client::new(),
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl EmbeddingModel for OllamaEmbeddingModel {
+    async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AgentError> {
+        if texts.is_empty() {
+            return Ok(vec![]);
+        }
+        let response = self
+            .client
+            .post(format!("{}/api/embed", self.url))
+            .json(&json!({
+                "model": self.model_id,
+                "input": texts,
+            }))
+            .send_logged("ollama")
+            .await
+            .map_err(|e| {
+                ModelError::from_request(
+                    "ollama",
+                    format!("Failed to get embeddings from Ollama: {}", e),
+                    e,
+                )
+            })?;
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(ModelError::from_status(
+                "ollama",
+                status.as_u16(),
+                format!(
+                    "Failed to get embeddings from Ollama: {}",
+                    response.text().await.unwrap_or_default(),
+                ),
+            )
+            .into());
+        }
+        let response = response
+            .json::<OllamaEmbeddingResponse>()
+            .await
+            .map_err(|e| {
+                ModelError::from_request(
+                    "ollama",
+                    format!("Failed to parse embeddings from Ollama: {}", e),
+                    e,
+                )
+            })?;
+        Ok(response.embeddings)
+    }
+}
@@ -0,0 +1,303 @@
+//! A chain of models that fails over to the next model when a provider is unavailable.
+//!
+//! [`FallbackModel`] sends every call to the first model of the chain. When the call fails with an error that may
+//! go away later, such as a rate limit, a server error or a timeout, the same call is sent to the next model, and so
+//! on. Other errors, such as an invalid request, are returned right away, since the next provider would reject the
+//! request too.
+//!
+//! ```rust,ignore
+//! let model = FallbackModel::new(vec![Box::new(primary), Box::new(secondary)])
+//!     .with_names(&["gpt-4o", "claude-via-openrouter"])
+//!     .with_config(1, GenerationConfig::new().with_max_tokens(2048));
+//! let agent = FunctionCallingAgentBuilder::new(model).with_tools(tools).build()?;
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use opentelemetry::trace::TraceContextExt;
+
+use crate::{
+    errors::{AgentError, ModelError},
+    models::{
+        info::{shared_info, ModelInfo},
+        model_traits::{Model, ModelResponse},
+        tokenizer::{HeuristicTokenizer, Tokenizer},
+        types::{GenerationConfig, Message},
+    },
+    tools::ToolInfo,
+};
+
+#[cfg(feature = "stream")]
+use crate::models::stream::ModelStream;
+
+/// One model of a [`FallbackModel`].
+pub struct FallbackTarget {
+    /// The name the model is reported under in logs and traces.
+    pub name: String,
+    pub model: Box<dyn Model>,
+    /// Overrides the config of the calls sent to this model, e.g. a lower `max_tokens` for a smaller model.
+    pub config: Option<GenerationConfig>,
+}
+
+pub struct FallbackModel {
+    targets: Vec<FallbackTarget>,
+    /// The name of the model that answered the last call.
+    last_served_by: Mutex<Option<String>>,
+}
+
+impl FallbackModel {
+    /// Tries the models in order. The models are named `model_0`, `model_1`, and so on, until they are named with
+    /// [`FallbackModel::with_names`].
+    pub fn new(models: Vec<Box<dyn Model>>) -> Self {
+        Self::from_targets(
+            models
+                .into_iter()
+                .enumerate()
+                .map(|(index, model)| FallbackTarget {
+                    name: format!("model_{}", index),
+                    model,
+                    config: None,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn from_targets(targets: Vec<FallbackTarget>) -> Self {
+        Self {
+            targets,
+            last_served_by: Mutex::new(None),
+        }
+    }
+
+    /// Names the models in order. Models without a name here keep their default name.
+    pub fn with_names(mut self, names: &[&str]) -> Self {
+        for (target, name) in self.targets.iter_mut().zip(names) {
+            target.name = name.to_string();
+        }
+        self
+    }
+
+    /// Overrides the config of the calls sent to the model at `index`. The fields that `config` leaves unset are
+    /// taken from the config of the call.
+    pub fn with_config(mut self, index: usize, config: GenerationConfig) -> Self {
+        if let Some(target) = self.targets.get_mut(index) {
+            target.config = Some(config);
+        }
+        self
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.targets
+            .iter()
+            .map(|target| target.name.as_str())
+            .collect()
+    }
+
+    /// The name of the model that answered the last successful call.
+    pub fn last_served_by(&self) -> Option<String> {
+        self.last_served_by.lock().unwrap().clone()
+    }
+
+    fn config_for(target: &FallbackTarget, config: &GenerationConfig) -> GenerationConfig {
+        match &target.config {
+            Some(target_config) => target_config.clone().merge(config),
+            None => config.clone(),
+        }
+    }
+
+    /// Records which model answered, in the logs and on the span of the current step.
+    fn served_by(&self, index: usize) {
+        let name = &self.targets[index].name;
+        if index > 0 {
+            tracing::warn!(model = %name, attempt = index + 1, "Model call served by a fallback model");
+        } else {
+            tracing::debug!(model = %name, "Model call served");
+        }
+        opentelemetry::Context::current()
+            .span()
+            .set_attribute(opentelemetry::KeyValue::new(
+                "model.served_by",
+                name.clone(),
+            ));
+        *self.last_served_by.lock().unwrap() = Some(name.clone());
+    }
+
+    /// Whether the next model should be tried after `error`.
+    fn should_fail_over(&self, index: usize, error: &AgentError) -> bool {
+        let fail_over = index + 1 < self.targets.len() && error.is_retryable();
+        if fail_over {
+            tracing::warn!(
+                model = %self.targets[index].name,
+                next = %self.targets[index + 1].name,
+                error = %error,
+                "Model call failed, trying the next model"
+            );
+        }
+        fail_over
+    }
+
+    fn no_models_error() -> AgentError {
+        ModelError::new("fallback", "The fallback model has no models to call").into()
+    }
+}
+
+impl fmt::Debug for FallbackModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FallbackModel")
+            .field("models", &self.names())
+            .finish()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for FallbackModel {
+    fn info(&self) -> ModelInfo {
+        shared_info(
+            self.targets
+                .iter()
+                .map(|target| target.model.info())
+                .collect(),
+        )
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        match self.targets.first() {
+            Some(target) => target.model.tokenizer(),
+            None => Arc::new(HeuristicTokenizer::default()),
+        }
+    }
+
+    async fn run(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        for (index, target) in self.targets.iter().enumerate() {
+            let result = target
+                .model
+                .run(
+                    input_messages.clone(),
+                    history.clone(),
+                    tools.clone(),
+                    Self::config_for(target, &config),
+                )
+                .await;
+            match result {
+                Ok(response) => {
+                    self.served_by(index);
+                    return Ok(response);
+                }
+                Err(e) if self.should_fail_over(index, &e) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Self::no_models_error())
+    }
+
+    /// Fails over when the stream cannot be started. A stream that breaks after it started is not restarted on the
+    /// next model, since part of the answer was already sent.
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        for (index, target) in self.targets.iter().enumerate() {
+            let result = target
+                .model
+                .run_stream(
+                    input_messages.clone(),
+                    history.clone(),
+                    tools.clone(),
+                    Self::config_for(target, &config),
+                )
+                .await;
+            match result {
+                Ok(stream) => {
+                    self.served_by(index);
+                    return Ok(stream);
+                }
+                Err(e) if self.should_fail_over(index, &e) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Self::no_models_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::replay::RecordedResponse;
+
+    /// Fails with the status `status`, or answers with its name when there is none.
+    #[derive(Debug)]
+    struct StatusModel {
+        name: &'static str,
+        status: Option<u16>,
+    }
+
+    #[async_trait]
+    impl Model for StatusModel {
+        async fn run(
+            &self,
+            _input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            if let Some(status) = self.status {
+                return Err(ModelError::from_status(self.name, status, "Failed").into());
+            }
+            Ok(Box::new(RecordedResponse {
+                content: Some(format!("{} {:?}", self.name, config.max_tokens)),
+                tool_calls: vec![],
+                usage: None,
+                error: None,
+            }))
+        }
+    }
+
+    fn model(name: &'static str, status: Option<u16>) -> Box<dyn Model> {
+        Box::new(StatusModel { name, status })
+    }
+
+    async fn answer(model: &FallbackModel) -> Result<String, AgentError> {
+        model
+            .run(
+                vec![Message::user("Hi")],
+                None,
+                vec![],
+                GenerationConfig::new(),
+            )
+            .await?
+            .get_response()
+    }
+
+    #[tokio::test]
+    async fn test_fallback_model() {
+        let chain = FallbackModel::new(vec![
+            model("primary", Some(429)),
+            model("secondary", Some(503)),
+            model("tertiary", None),
+        ])
+        .with_names(&["primary", "secondary", "tertiary"])
+        .with_config(2, GenerationConfig::new().with_max_tokens(100));
+        assert_eq!(answer(&chain).await.unwrap(), "tertiary Some(100)");
+        assert_eq!(chain.last_served_by().as_deref(), Some("tertiary"));
+
+        // An invalid request is not sent to the next model.
+        let chain = FallbackModel::new(vec![model("primary", Some(400)), model("secondary", None)]);
+        assert!(answer(&chain).await.is_err());
+
+        let chain = FallbackModel::new(vec![model("primary", Some(500))]);
+        assert!(answer(&chain).await.unwrap_err().is_retryable());
+    }
+}
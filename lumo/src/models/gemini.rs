@@ -1,12 +1,11 @@
-use std::collections::HashMap;
-
 use crate::{
-    errors::AgentError,
-    models::types::{Message, MessageRole},
+    errors::{AgentError, ModelError},
+    models::types::{GenerationConfig, Message, MessageRole, ToolChoice, Usage},
     tools::ToolInfo,
 };
 use anyhow::Result;
 use async_trait::async_trait;
+use std::sync::Arc;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -14,8 +13,15 @@ use serde_json::{json, Value};
 use super::{
     model_traits::{Model, ModelResponse},
     openai::{FunctionCall, ToolCall},
+    prefill::{prefill_messages, PrefilledResponse},
+    info::{model_info, ModelInfo},
+    tokenizer::{tokenizer_for_model, Tokenizer},
+    wire_log::SendLogged,
 };
 
+#[cfg(feature = "stream")]
+use super::stream::{response_events, ChatChunk, ModelStream, SseDecoder};
+
 /// Text content within a chat message
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -60,6 +66,15 @@ struct GeminiGenerationConfig {
     /// Stop sequences
     #[serde(rename = "stopSequences", skip_serializing_if = "Option::is_none")]
     stop_sequences: Option<Vec<String>>,
+    /// Penalty for tokens that already appeared in the response
+    #[serde(rename = "presencePenalty", skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    /// Penalty proportional to how often tokens appeared in the response
+    #[serde(rename = "frequencyPenalty", skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    /// Seed used for decoding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
 }
 
 /// Individual completion candidate
@@ -73,6 +88,7 @@ struct GeminiCandidate {
 #[derive(Deserialize, Debug)]
 struct GeminiResponseContent {
     /// Parts making up the content
+    #[serde(default)]
     parts: Vec<GeminiResponsePart>,
 }
 
@@ -82,6 +98,19 @@ struct GeminiFunctionCall {
     args: Value,
 }
 
+impl From<GeminiFunctionCall> for ToolCall {
+    fn from(function_call: GeminiFunctionCall) -> Self {
+        ToolCall {
+            id: Some(function_call.name.clone()),
+            call_type: Some("function".to_string()),
+            function: FunctionCall {
+                name: function_call.name,
+                arguments: function_call.args,
+            },
+        }
+    }
+}
+
 /// Individual part of response content
 #[derive(Deserialize, Debug)]
 struct GeminiResponsePart {
@@ -97,6 +126,44 @@ struct GeminiResponsePart {
 struct GeminiChatResponse {
     /// Generated completion candidates
     candidates: Vec<GeminiCandidate>,
+    /// Token counts of the request and the response
+    #[serde(rename = "usageMetadata", default)]
+    usage_metadata: Option<GeminiUsageMetadata>,
+}
+
+/// A chunk of a streamed response. The last chunks may have no candidates, only the usage.
+#[cfg(feature = "stream")]
+#[derive(Deserialize, Debug)]
+struct GeminiStreamChunk {
+    #[serde(default)]
+    candidates: Vec<GeminiStreamCandidate>,
+    #[serde(rename = "usageMetadata", default)]
+    usage_metadata: Option<GeminiUsageMetadata>,
+}
+
+#[cfg(feature = "stream")]
+#[derive(Deserialize, Debug)]
+struct GeminiStreamCandidate {
+    #[serde(default)]
+    content: Option<GeminiResponseContent>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeminiUsageMetadata {
+    #[serde(rename = "promptTokenCount", default)]
+    prompt_token_count: usize,
+    #[serde(rename = "candidatesTokenCount", default)]
+    candidates_token_count: usize,
+    /// The prompt tokens served from the implicit or explicit context cache.
+    #[serde(rename = "cachedContentTokenCount", default)]
+    cached_content_token_count: usize,
+}
+
+impl GeminiUsageMetadata {
+    fn to_usage(&self) -> Usage {
+        Usage::new(self.prompt_token_count, self.candidates_token_count)
+            .with_cache(self.cached_content_token_count, 0)
+    }
 }
 
 impl ModelResponse for GeminiChatResponse {
@@ -112,16 +179,14 @@ impl ModelResponse for GeminiChatResponse {
             .parts
             .iter()
             .filter_map(|part| part.function_call.clone())
-            .map(|function_call| ToolCall {
-                id: Some(function_call.name.clone()),
-                call_type: Some("function".to_string()),
-                function: FunctionCall {
-                    name: function_call.name,
-                    arguments: function_call.args,
-                },
-            })
+            .map(ToolCall::from)
             .collect())
     }
+    fn get_usage(&self) -> Option<Usage> {
+        self.usage_metadata
+            .as_ref()
+            .map(GeminiUsageMetadata::to_usage)
+    }
 }
 
 #[derive(Debug)]
@@ -212,23 +277,23 @@ impl GeminiServerModelBuilder {
     }
 }
 
-#[async_trait]
-impl Model for GeminiServerModel {
-    async fn run(
+impl GeminiServerModel {
+    fn request_body(
         &self,
         messages: Vec<Message>,
         history: Option<Vec<Message>>,
         tools_to_call_from: Vec<ToolInfo>,
-        max_tokens: Option<usize>,
-        args: Option<HashMap<String, Vec<String>>>,
-    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        config: GenerationConfig,
+    ) -> Value {
+        let mut messages = messages;
+        prefill_messages(&mut messages, config.prefill.as_deref(), false);
         let mut chat_contents = Vec::with_capacity(messages.len());
 
         if let Some(history) = history {
             for message in history {
                 chat_contents.push(GeminiChatContent {
                     role: message.role.to_string(),
-                    parts: vec![GeminiContentPart::Text(message.content)],
+                    parts: vec![GeminiContentPart::Text(message.content_with_name())],
                 });
             }
         }
@@ -237,66 +302,98 @@ impl Model for GeminiServerModel {
                 if message.role == MessageRole::System {
                     chat_contents.push(GeminiChatContent {
                         role: "user".to_string(),
-                        parts: vec![GeminiContentPart::Text(message.content)],
+                        parts: vec![GeminiContentPart::Text(message.content_with_name())],
                     });
                 } else if message.role == MessageRole::Assistant {
                     chat_contents.push(GeminiChatContent {
                         role: "model".to_string(),
-                        parts: vec![GeminiContentPart::Text(message.content)],
+                        parts: vec![GeminiContentPart::Text(message.content_with_name())],
                     });
                 } else {
                     chat_contents.push(GeminiChatContent {
                         role: message.role.to_string(),
-                        parts: vec![GeminiContentPart::Text(message.content)],
+                        parts: vec![GeminiContentPart::Text(message.content_with_name())],
                     });
                 }
             }
         }
 
+        let tool_choice = config.tool_choice.clone().unwrap_or(ToolChoice::Required);
         let tools_to_call_from = if tools_to_call_from.is_empty() {
             None
         } else {
             Some(tools_to_call_from)
         };
 
-        let stop_sequences = args.map(|args| args.get("stop").unwrap_or(&vec![]).to_vec());
         let request = GeminiChatRequest {
             contents: chat_contents,
             tools: tools_to_call_from.as_ref().map(|tools| GeminiTool {
-                function_declarations: tools
-                    .iter()
-                    .map(|tool| {
-                        let mut parameters = json!(tool.function.parameters.clone());
-                        if let Value::Object(ref mut map) = parameters {
-                            map.remove("$schema");
-                            map.remove("title");
-                            map.remove("additionalProperties");
-                        }
-                        json!({
-                            "name": tool.function.name,
-                            "description": tool.function.description,
-                            "parameters": parameters
-                        })
-                    })
-                    .collect(),
+                function_declarations: tools.iter().map(ToolInfo::to_gemini).collect(),
             }),
             generation_config: GeminiGenerationConfig {
-                max_output_tokens: Some(max_tokens.unwrap_or(4500) as u32),
-                temperature: Some(self.temperature),
-                top_p: None,
+                max_output_tokens: Some(config.max_tokens.unwrap_or(4500) as u32),
+                temperature: Some(config.temperature.unwrap_or(self.temperature)),
+                top_p: config.top_p,
                 top_k: None,
-                stop_sequences,
+                stop_sequences: config.stop,
+                presence_penalty: config.presence_penalty,
+                frequency_penalty: config.frequency_penalty,
+                seed: config.seed,
             },
         };
 
         let mut request = json!(request);
         if let Some(tools) = tools_to_call_from.as_ref() {
+            let function_calling_config = match tool_choice {
+                ToolChoice::Auto => json!({ "mode": "AUTO" }),
+                ToolChoice::None => json!({ "mode": "NONE" }),
+                ToolChoice::Required => json!({ "mode": "ANY",
+                "allowed_function_names": tools.iter().map(|tool| tool.function.name.to_string()).collect::<Vec<String>>() }),
+                ToolChoice::Tool(name) => json!({ "mode": "ANY", "allowed_function_names": [name] }),
+            };
             request["tool_config"] = json!({
-                "function_calling_config": { "mode": "ANY",
-                "allowed_function_names": tools.iter().map(|tool| tool.function.name.to_string()).collect::<Vec<String>>() },
-
+                "function_calling_config": function_calling_config,
             });
         }
+        request
+    }
+
+    /// The URL of the streaming endpoint, if the base URL is the one of `generateContent`.
+    #[cfg(feature = "stream")]
+    fn stream_url(&self) -> Option<String> {
+        let url = self
+            .base_url
+            .replace(":generateContent", ":streamGenerateContent");
+        if url == self.base_url {
+            return None;
+        }
+        Some(match url.contains('?') {
+            true => format!("{}&alt=sse", url),
+            false => format!("{}?alt=sse", url),
+        })
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for GeminiServerModel {
+    fn info(&self) -> ModelInfo {
+        model_info(&self.model_id).with_streaming(cfg!(feature = "stream"))
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        tokenizer_for_model(&self.model_id)
+    }
+
+    async fn run(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools_to_call_from: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let prefill = config.prefill.clone();
+        let request = self.request_body(messages, history, tools_to_call_from, config);
         println!(
             "Request: {}",
             serde_json::to_string_pretty(&request).unwrap()
@@ -306,22 +403,124 @@ impl Model for GeminiServerModel {
             .client
             .post(&self.base_url)
             .json(&request)
-            .send()
+            .send_logged("gemini")
             .await
             .map_err(|e| {
-                AgentError::Generation(format!("Failed to get response from Gemini: {}", e))
+                ModelError::from_request(
+                    "gemini",
+                    format!("Failed to get response from Gemini: {}", e),
+                    e,
+                )
             })?;
         match response.status() {
             reqwest::StatusCode::OK => {
                 let response = response.json::<GeminiChatResponse>().await.unwrap();
-                Ok(Box::new(response))
+                Ok(PrefilledResponse::wrap(Box::new(response), prefill.as_deref()))
             }
-            _ => Err(AgentError::Generation(format!(
-                "Failed to get response from Gemini: {} {}",
-                response.status(),
-                response.text().await.unwrap(),
-            ))),
+            status => Err(ModelError::from_status(
+                "gemini",
+                status.as_u16(),
+                format!(
+                    "Failed to get response from Gemini: {} {}",
+                    status,
+                    response.text().await.unwrap_or_default(),
+                ),
+            )
+            .into()),
+        }
+    }
+
+    /// Streams the response from the `streamGenerateContent` endpoint. With a custom base URL that is not the one
+    /// of `generateContent`, the whole response is sent at once.
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools_to_call_from: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        let Some(url) = self.stream_url() else {
+            let response = self
+                .run(messages, history, tools_to_call_from, config)
+                .await?;
+            let chunks = response_events(response.as_ref())?;
+            return Ok(Box::pin(futures::stream::iter(chunks.into_iter().map(Ok))));
+        };
+        let request = self.request_body(messages, history, tools_to_call_from, config);
+        let mut response = self
+            .client
+            .post(url)
+            .json(&request)
+            .send_logged("gemini")
+            .await
+            .map_err(|e| {
+                ModelError::from_request(
+                    "gemini",
+                    format!("Failed to get response from Gemini: {}", e),
+                    e,
+                )
+            })?;
+        if response.status() != reqwest::StatusCode::OK {
+            let status = response.status();
+            return Err(ModelError::from_status(
+                "gemini",
+                status.as_u16(),
+                format!(
+                    "Failed to get response from Gemini: {} {}",
+                    status,
+                    response.text().await.unwrap_or_default(),
+                ),
+            )
+            .into());
         }
+        let stream = async_stream::stream! {
+            let mut decoder = SseDecoder::new();
+            let mut usage = None;
+            loop {
+                let chunk = match response.chunk().await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    Err(e) => {
+                        let message = format!("Failed to read the stream from Gemini: {}", e);
+                        yield Err(ModelError::from_request("gemini", message, e).into());
+                        return;
+                    }
+                };
+                for data in decoder.push(&chunk) {
+                    let chunk = match serde_json::from_str::<GeminiStreamChunk>(&data) {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            let message = format!("Failed to parse the stream from Gemini: {}", e);
+                            yield Err(ModelError::new("gemini", message).into());
+                            return;
+                        }
+                    };
+                    // Every chunk has the usage so far, so only the last one is sent.
+                    if let Some(metadata) = chunk.usage_metadata {
+                        usage = Some(metadata.to_usage());
+                    }
+                    let parts = chunk
+                        .candidates
+                        .into_iter()
+                        .take(1)
+                        .filter_map(|candidate| candidate.content)
+                        .flat_map(|content| content.parts);
+                    for part in parts {
+                        if let Some(text) = part.text.filter(|text| !text.is_empty()) {
+                            yield Ok(ChatChunk::TextDelta(text));
+                        }
+                        if let Some(function_call) = part.function_call {
+                            yield Ok(ChatChunk::ToolCall(function_call.into()));
+                        }
+                    }
+                }
+            }
+            if let Some(usage) = usage {
+                yield Ok(ChatChunk::Usage(usage));
+            }
+        };
+        Ok(Box::pin(stream))
     }
 }
 
@@ -343,11 +542,12 @@ mod tests {
                     content: "Hello, how are you?".to_string(),
                     tool_call_id: None,
                     tool_calls: None,
+                    parts: vec![],
+                    name: None,
                 }],
                 None,
                 vec![],
-                None,
-                None,
+                GenerationConfig::default(),
             )
             .await
             .unwrap();
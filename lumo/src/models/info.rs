@@ -0,0 +1,206 @@
+//! What is known about a model: its context length, whether it takes tools and images, whether it streams, and its
+//! price.
+//!
+//! The providers fill [`ModelInfo`] from a built-in table keyed by model id prefix. The table is only a default, since
+//! models are released and repriced often: [`register_model_info`] replaces the info of a model, or adds a model the
+//! table does not know.
+//!
+//! ```rust,ignore
+//! register_model_info(
+//!     ModelInfo::new("my-finetune")
+//!         .with_context_length(32_768)
+//!         .with_vision(false)
+//!         .with_pricing(ModelPricing::new(0.3, 1.2)),
+//! );
+//! let info = model.info();
+//! if !info.supports_vision {
+//!     // Describe the images in text
+//! }
+//! ```
+
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+
+use super::pricing::{pricing_for_model, ModelPricing};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    pub model_id: String,
+    /// The number of tokens the model reads in one call, prompt and answer together.
+    #[serde(default)]
+    pub context_length: Option<usize>,
+    #[serde(default = "default_true")]
+    pub supports_tools: bool,
+    #[serde(default)]
+    pub supports_vision: bool,
+    /// Whether the model streams its answer. Models that do not still have `run_stream`, which sends the whole
+    /// answer at once.
+    #[serde(default)]
+    pub supports_streaming: bool,
+    #[serde(default)]
+    pub pricing: Option<ModelPricing>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl ModelInfo {
+    /// The info of a model nothing is known about: it takes tools, but no images, and does not stream.
+    pub fn new(model_id: &str) -> Self {
+        Self {
+            model_id: model_id.to_string(),
+            context_length: None,
+            supports_tools: true,
+            supports_vision: false,
+            supports_streaming: false,
+            pricing: None,
+        }
+    }
+
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = Some(context_length);
+        self
+    }
+
+    pub fn with_tools(mut self, supports_tools: bool) -> Self {
+        self.supports_tools = supports_tools;
+        self
+    }
+
+    pub fn with_vision(mut self, supports_vision: bool) -> Self {
+        self.supports_vision = supports_vision;
+        self
+    }
+
+    pub fn with_streaming(mut self, supports_streaming: bool) -> Self {
+        self.supports_streaming = supports_streaming;
+        self
+    }
+
+    pub fn with_pricing(mut self, pricing: ModelPricing) -> Self {
+        self.pricing = Some(pricing);
+        self
+    }
+}
+
+/// The info of a model that sends each call to one of several models: the info of the first one, with the shortest
+/// context length and only the capabilities that every model has, since any of them may get the call.
+pub(crate) fn shared_info(infos: Vec<ModelInfo>) -> ModelInfo {
+    let mut infos = infos.into_iter();
+    let Some(mut shared) = infos.next() else {
+        return ModelInfo::new("");
+    };
+    for info in infos {
+        shared.context_length = match (shared.context_length, info.context_length) {
+            (Some(first), Some(other)) => Some(first.min(other)),
+            (first, other) => first.or(other),
+        };
+        shared.supports_tools &= info.supports_tools;
+        shared.supports_vision &= info.supports_vision;
+        shared.supports_streaming &= info.supports_streaming;
+    }
+    shared
+}
+
+/// Model id prefix, context length, whether the model takes tools and whether it takes images. Longer prefixes must
+/// come before shorter ones that they start with.
+const MODEL_TABLE: &[(&str, usize, bool, bool)] = &[
+    ("gpt-4o-mini", 128_000, true, true),
+    ("gpt-4o", 128_000, true, true),
+    ("gpt-4.1", 1_047_576, true, true),
+    ("gpt-4-turbo", 128_000, true, true),
+    ("gpt-4", 8_192, true, false),
+    ("gpt-3.5-turbo", 16_385, true, false),
+    ("o4-mini", 200_000, true, true),
+    ("o3-mini", 200_000, true, false),
+    ("o3", 200_000, true, true),
+    ("o1-mini", 128_000, false, false),
+    ("o1", 200_000, true, true),
+    ("gemini-2.5", 1_048_576, true, true),
+    ("gemini-2.0", 1_048_576, true, true),
+    ("gemini-1.5-pro", 2_097_152, true, true),
+    ("gemini-1.5-flash", 1_048_576, true, true),
+    ("claude", 200_000, true, true),
+    ("llama-3.2-90b-vision", 128_000, true, true),
+    ("llama-3.2-11b-vision", 128_000, true, true),
+    ("llama-3.3", 128_000, true, false),
+    ("llama-3.1", 128_000, true, false),
+    ("llava", 4_096, false, true),
+    ("qwen2.5", 32_768, true, false),
+    ("deepseek", 64_000, true, false),
+    ("mistral-large", 128_000, true, false),
+];
+
+/// The infos given with [`register_model_info`], newest first.
+static REGISTERED: RwLock<Vec<ModelInfo>> = RwLock::new(Vec::new());
+
+fn strip_provider(model_id: &str) -> &str {
+    model_id.rsplit('/').next().unwrap_or(model_id)
+}
+
+/// Replaces the info of every model whose id starts with `info.model_id`. Later registrations win over earlier ones.
+pub fn register_model_info(info: ModelInfo) {
+    REGISTERED.write().unwrap().insert(0, info);
+}
+
+/// Looks up the info of a model by its id: a registered info first, then the built-in table. Provider prefixes such
+/// as `openai/` are ignored. A model that neither knows gets [`ModelInfo::new`].
+pub fn model_info(model_id: &str) -> ModelInfo {
+    let id = strip_provider(model_id);
+    let registered = REGISTERED
+        .read()
+        .unwrap()
+        .iter()
+        .find(|info| id.starts_with(strip_provider(&info.model_id)))
+        .cloned();
+    if let Some(info) = registered {
+        return ModelInfo {
+            model_id: model_id.to_string(),
+            ..info
+        };
+    }
+    let mut info = ModelInfo::new(model_id);
+    if let Some((_, context_length, tools, vision)) = MODEL_TABLE
+        .iter()
+        .find(|(prefix, ..)| id.starts_with(prefix))
+    {
+        info.context_length = Some(*context_length);
+        info.supports_tools = *tools;
+        info.supports_vision = *vision;
+    }
+    info.pricing = pricing_for_model(model_id);
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_info() {
+        let info = model_info("openai/gpt-4o-mini-2024-07-18");
+        assert_eq!(info.model_id, "openai/gpt-4o-mini-2024-07-18");
+        assert_eq!(info.context_length, Some(128_000));
+        assert!(info.supports_tools && info.supports_vision);
+        assert_eq!(info.pricing, Some(ModelPricing::new(0.15, 0.6)));
+        assert_eq!(model_info("gpt-4-0613").context_length, Some(8_192));
+        assert!(!model_info("o1-mini").supports_tools);
+
+        let unknown = model_info("lumo-test-finetune-v2");
+        assert_eq!(unknown, ModelInfo::new("lumo-test-finetune-v2"));
+
+        register_model_info(
+            ModelInfo::new("lumo-test-finetune")
+                .with_context_length(32_768)
+                .with_vision(true)
+                .with_pricing(ModelPricing::new(0.3, 1.2)),
+        );
+        let info = model_info("together/lumo-test-finetune-v2");
+        assert_eq!(info.model_id, "together/lumo-test-finetune-v2");
+        assert_eq!(info.context_length, Some(32_768));
+        assert!(info.supports_vision);
+        assert_eq!(info.pricing.unwrap().output_per_million, 1.2);
+    }
+}
@@ -1,5 +1,20 @@
+pub mod conversation;
+pub mod embeddings;
+pub mod fallback;
+pub mod info;
 pub mod model_traits;
 pub mod ollama;
 pub mod openai;
+pub mod openai_compatible;
+pub mod pool;
+pub mod prefill;
+pub mod pricing;
+pub mod reasoning;
+pub mod replay;
+#[cfg(feature = "stream")]
+pub mod stream;
+pub mod testing;
+pub mod tokenizer;
 pub mod types;
+pub mod wire_log;
 pub mod gemini;
\ No newline at end of file
@@ -1,26 +1,78 @@
-use std::collections::HashMap;
-
 use crate::{
     errors::AgentError,
-    models::{openai::ToolCall, types::Message},
+    models::{
+        info::ModelInfo,
+        openai::ToolCall,
+        tokenizer::{HeuristicTokenizer, Tokenizer},
+        types::{GenerationConfig, Message, RateLimit, Usage},
+    },
     tools::tool_traits::ToolInfo,
 };
 use anyhow::Result;
 use async_trait::async_trait;
+use std::sync::Arc;
+
+#[cfg(feature = "stream")]
+use crate::models::stream::{response_events, ModelStream};
 
 pub trait ModelResponse: Send + Sync {
     fn get_response(&self) -> Result<String, AgentError>;
     fn get_tools_used(&self) -> Result<Vec<ToolCall>, AgentError>;
+    /// The tokens used by the call, if the provider reports them.
+    fn get_usage(&self) -> Option<Usage> {
+        None
+    }
+    /// The reasoning of the model, when a reasoning model returned it apart from the answer.
+    fn get_reasoning(&self) -> Option<String> {
+        None
+    }
+    /// The rate limit state of the API key after the call, if the provider reports it.
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        None
+    }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 pub trait Model: Send + Sync + 'static {
     async fn run(
         &self,
         input_messages: Vec<Message>,
         history: Option<Vec<Message>>,
         tools: Vec<ToolInfo>,
-        max_tokens: Option<usize>,
-        args: Option<HashMap<String, Vec<String>>>,
+        config: GenerationConfig,
     ) -> Result<Box<dyn ModelResponse>, AgentError>;
+
+    /// What is known about the model, see [`model_info`](crate::models::info::model_info). Models that do not
+    /// override it are unknown models.
+    fn info(&self) -> ModelInfo {
+        ModelInfo::new("")
+    }
+
+    /// The number of tokens the model reads in one call, prompt and answer together, if it is known. The agents
+    /// compact their memory to fit it.
+    fn context_length(&self) -> Option<usize> {
+        self.info().context_length
+    }
+
+    /// The tokenizer the memory of the agents is counted with.
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        Arc::new(HeuristicTokenizer::default())
+    }
+
+    /// Runs the model and streams the answer as [`ChatChunk`](crate::models::stream::ChatChunk)s: text as it is
+    /// generated, tool calls once they are complete and the usage at the end. Models that cannot stream send the
+    /// whole response at once.
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        let response = self.run(input_messages, history, tools, config).await?;
+        let events = response_events(response.as_ref())?;
+        Ok(Box::pin(futures::stream::iter(events.into_iter().map(Ok))))
+    }
 }
@@ -1,23 +1,38 @@
-use std::collections::HashMap;
-
 use opentelemetry::{global, trace::{Span, Tracer}, Context, KeyValue};
+use crate::telemetry::{generation_config_attributes, input_attributes, model_attributes, output_attributes, span_kind_attributes, SpanCategory};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::{errors::AgentError, tools::ToolInfo};
+use crate::{
+    errors::{AgentError, ModelError},
+    tools::ToolInfo,
+};
 use anyhow::Result;
 use async_trait::async_trait;
 use reqwest::Client;
 
+#[cfg(feature = "stream")]
 use super::{
+    prefill::prefix_stream,
+    stream::{process_stream, ChatChunk, JsonLinesDecoder, ModelStream},
+};
+use super::{
+    info::{model_info, ModelInfo},
     model_traits::{Model, ModelResponse},
     openai::{FunctionCall, ToolCall},
-    types::{Message, MessageRole},
+    prefill::{prefill_messages, PrefilledResponse},
+    reasoning::ModelFamily,
+    types::{GenerationConfig, Message, MessageRole, ToolChoice, Usage},
+    wire_log::SendLogged,
 };
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OllamaResponse {
     pub message: AssistantMessage,
+    #[serde(default)]
+    pub prompt_eval_count: Option<usize>,
+    #[serde(default)]
+    pub eval_count: Option<usize>,
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
@@ -26,6 +41,9 @@ pub struct AssistantMessage {
     pub content: Option<String>,
     pub tool_calls: Option<Vec<OllamaToolCall>>,
     pub refusal: Option<String>,
+    /// Returned by Ollama when thinking is enabled, or split from `<think>` tags in the content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
@@ -42,6 +60,33 @@ pub struct OllamaFunctionCall {
     pub arguments: serde_json::Value,
 }
 
+impl From<OllamaToolCall> for ToolCall {
+    fn from(tool_call: OllamaToolCall) -> Self {
+        ToolCall {
+            id: tool_call.id,
+            call_type: tool_call.call_type,
+            function: FunctionCall {
+                name: tool_call.function.name,
+                arguments: tool_call.function.arguments,
+            },
+        }
+    }
+}
+
+/// A line of a streamed `/api/chat` response. The last line has `done` set and the token counts.
+#[cfg(feature = "stream")]
+#[derive(Debug, Deserialize)]
+struct OllamaStreamChunk {
+    #[serde(default)]
+    message: Option<AssistantMessage>,
+    #[serde(default)]
+    prompt_eval_count: Option<usize>,
+    #[serde(default)]
+    eval_count: Option<usize>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct OllamaMessage {
     pub role: MessageRole,
@@ -64,16 +109,20 @@ impl ModelResponse for OllamaResponse {
             .clone()
             .unwrap_or_default()
             .into_iter()
-            .map(|tool_call| ToolCall {
-                id: tool_call.id,
-                call_type: tool_call.call_type,
-                function: FunctionCall {
-                    name: tool_call.function.name,
-                    arguments: tool_call.function.arguments,
-                },
-            })
+            .map(ToolCall::from)
             .collect())
     }
+
+    fn get_reasoning(&self) -> Option<String> {
+        self.message.thinking.clone()
+    }
+
+    fn get_usage(&self) -> Option<Usage> {
+        match (self.prompt_eval_count, self.eval_count) {
+            (None, None) => None,
+            (input, output) => Some(Usage::new(input.unwrap_or(0), output.unwrap_or(0))),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -164,24 +213,38 @@ impl OllamaModelBuilder {
     }
 }
 
-#[async_trait]
-impl Model for OllamaModel {
-    async fn run(
+impl OllamaModel {
+    fn request_body(
         &self,
         messages: Vec<Message>,
         history: Option<Vec<Message>>,
-        tools_to_call_from: Vec<ToolInfo>,
-        max_tokens: Option<usize>,
-        args: Option<HashMap<String, Vec<String>>>,
-    ) -> Result<Box<dyn ModelResponse>, AgentError> {
-        let tools = json!(tools_to_call_from);
+        tools_to_call_from: &[ToolInfo],
+        config: &GenerationConfig,
+    ) -> serde_json::Value {
+        let max_tokens = config.max_tokens.unwrap_or(self.max_tokens);
+        let temperature = config.temperature.unwrap_or(self.temperature);
+        // Ollama has no tool choice, so a specific tool is forced by only sending that tool and `None` by sending none.
+        let tools = match &config.tool_choice {
+            Some(ToolChoice::None) => json!([]),
+            Some(ToolChoice::Tool(name)) => json!(tools_to_call_from
+                .iter()
+                .filter(|tool| &tool.function.name == name)
+                .map(ToolInfo::to_openai)
+                .collect::<Vec<_>>()),
+            _ => json!(tools_to_call_from
+                .iter()
+                .map(ToolInfo::to_openai)
+                .collect::<Vec<_>>()),
+        };
         let mut messages = messages;
         if let Some(history) = history {
             messages = [history, messages].concat();
         }
+        // Ollama continues a trailing assistant message instead of starting a new one
+        prefill_messages(&mut messages, config.prefill.as_deref(), true);
         let messages = messages.into_iter().map(|m| OllamaMessage {
             role: m.role,
-            content: m.content.into(),
+            content: Some(m.content_with_name()),
             tool_calls: m.tool_calls.map(|tool_calls| {
                 tool_calls.into_iter().map(|tc| OllamaToolCall {
                     id: tc.id,
@@ -199,63 +262,195 @@ impl Model for OllamaModel {
         let mut body = json!({
             "model": self.model_id,
             "messages": messages,
-            "temperature": self.temperature,
             "stream": false,
             "options": json!({
                 "num_ctx": self.ctx_length,
+                "num_predict": max_tokens,
+                "temperature": temperature,
             }),
-            "max_tokens": max_tokens.unwrap_or(self.max_tokens),
         });
-
-        
-        let parent_cx = Context::current();
-        let tracer = global::tracer("lumo");
-        let mut span = tracer.span_builder("OllamaModel::run").with_start_time(std::time::SystemTime::now()).start_with_context(&tracer, &parent_cx);
-        span.set_attributes(vec![
-            KeyValue::new("input.value", serde_json::to_string(&messages).unwrap()),
-            KeyValue::new("llm.model_name", self.model_id.clone()),
-            KeyValue::new("gen_ai.request.temperature", self.temperature.to_string()),
-            KeyValue::new("gen_ai.request.max_tokens", max_tokens.unwrap_or(self.max_tokens).to_string()),
-            KeyValue::new("timestamp", chrono::Utc::now().to_rfc3339()),
-        ]);
-
-        if let Some(args) = args {
-            for (key, value) in args {
-                body["options"][key] = json!(value);
-            }
+        if let Some(top_p) = config.top_p {
+            body["options"]["top_p"] = json!(top_p);
         }
-        if self.native_tools {
+        if let Some(frequency_penalty) = config.frequency_penalty {
+            body["options"]["frequency_penalty"] = json!(frequency_penalty);
+        }
+        if let Some(presence_penalty) = config.presence_penalty {
+            body["options"]["presence_penalty"] = json!(presence_penalty);
+        }
+        if let Some(seed) = config.seed {
+            body["options"]["seed"] = json!(seed);
+        }
+        if let Some(stop) = config.stop.as_ref().filter(|stop| !stop.is_empty()) {
+            body["options"]["stop"] = json!(stop);
+        }
+        if self.native_tools && tools.as_array().is_some_and(|tools| !tools.is_empty()) {
             body["tools"] = tools;
-            body["tool_choice"] = json!("auto");
-            span.set_attribute(KeyValue::new(
-                "gen_ai.request.tool_choice",
-                serde_json::to_string(&body["tool_choice"]).unwrap(),
-            ));
+            body["tool_choice"] = match config.tool_choice {
+                Some(ToolChoice::Required) => json!("required"),
+                _ => json!("auto"),
+            };
         }
+        body
+    }
 
+    async fn send(&self, body: &serde_json::Value) -> Result<reqwest::Response, AgentError> {
         let response = self
             .client
             .post(format!("{}/api/chat", self.url))
             .header("Content-Type", "application/json")
-            .json(&body)
-            .send()
+            .json(body)
+            .send_logged("ollama")
             .await
             .map_err(|e| {
-                AgentError::Generation(format!("Failed to get response from Ollama: {}", e))
+                ModelError::from_request(
+                    "ollama",
+                    format!("Failed to get response from Ollama: {}", e),
+                    e,
+                )
             })?;
         let status = response.status();
         if status.is_client_error() {
             let error_message = response.text().await.unwrap_or_default();
-            return Err(AgentError::Generation(format!(
-                "Failed to get response from Ollama: {}",
-                error_message
-            )));
+            return Err(ModelError::from_status(
+                "ollama",
+                status.as_u16(),
+                format!("Failed to get response from Ollama: {}", error_message),
+            )
+            .into());
         }
-        let output = response.json::<OllamaResponse>().await.map_err(|e| {
-            AgentError::Generation(format!("Failed to parse response from Ollama: {}", e))
+        Ok(response)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for OllamaModel {
+    /// The context length is the `num_ctx` the model runs with, which Ollama cuts longer prompts to.
+    fn info(&self) -> ModelInfo {
+        model_info(&self.model_id)
+            .with_context_length(self.ctx_length)
+            .with_streaming(cfg!(feature = "stream"))
+    }
+
+    async fn run(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools_to_call_from: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let max_tokens = config.max_tokens.unwrap_or(self.max_tokens);
+        let temperature = config.temperature.unwrap_or(self.temperature);
+        let body = self.request_body(messages, history, &tools_to_call_from, &config);
+
+        let parent_cx = Context::current();
+        let tracer = global::tracer("lumo");
+        let mut span = tracer.span_builder("OllamaModel::run").with_start_time(crate::runtime::now()).start_with_context(&tracer, &parent_cx);
+        span.set_attributes(span_kind_attributes(SpanCategory::Llm));
+        span.set_attributes(input_attributes(serde_json::to_string(&body["messages"]).unwrap()));
+        span.set_attributes(model_attributes("ollama", &self.model_id));
+        span.set_attributes(vec![
+            KeyValue::new("gen_ai.request.temperature", temperature.to_string()),
+            KeyValue::new("gen_ai.request.max_tokens", max_tokens.to_string()),
+            KeyValue::new("timestamp", chrono::Utc::now().to_rfc3339()),
+        ]);
+        span.set_attributes(generation_config_attributes(&config));
+
+        if let Some(tool_choice) = body.get("tool_choice") {
+            span.set_attribute(KeyValue::new(
+                "gen_ai.request.tool_choice",
+                serde_json::to_string(tool_choice).unwrap(),
+            ));
+        }
+
+        let response = self.send(&body).await?;
+        let mut output = response.json::<OllamaResponse>().await.map_err(|e| {
+            ModelError::from_request(
+                "ollama",
+                format!("Failed to parse response from Ollama: {}", e),
+                e,
+            )
         })?;
-        span.set_attribute(KeyValue::new("output.value", serde_json::to_string_pretty(&output).unwrap()));
-        span.end_with_timestamp(std::time::SystemTime::now());
-        Ok(Box::new(output))
+        // Ollama applies the stop sequences of every model itself, so only the reasoning is left to split off.
+        if let Some(content) = &output.message.content {
+            let family = ModelFamily::from_model_id(&self.model_id);
+            let (thinking, content) = family.process(content, None);
+            output.message.content = Some(content);
+            if output.message.thinking.is_none() {
+                output.message.thinking = thinking;
+            }
+        }
+        span.set_attributes(output_attributes(serde_json::to_string_pretty(&output).unwrap()));
+        span.end_with_timestamp(crate::runtime::now());
+        Ok(PrefilledResponse::wrap(Box::new(output), config.prefill.as_deref()))
+    }
+
+    /// Streams the text and reasoning as Ollama generates them. Ollama sends tool calls whole, and the `<think>`
+    /// blocks of models that write their reasoning in the text are streamed as reasoning.
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools_to_call_from: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        let mut body = self.request_body(messages, history, &tools_to_call_from, &config);
+        body["stream"] = json!(true);
+        let mut response = self.send(&body).await?;
+        let stream = async_stream::stream! {
+            let mut decoder = JsonLinesDecoder::new();
+            loop {
+                let lines = match response.chunk().await {
+                    Ok(Some(chunk)) => decoder.push(&chunk),
+                    Ok(None) => match decoder.finish() {
+                        Some(line) => vec![line],
+                        None => break,
+                    },
+                    Err(e) => {
+                        let message = format!("Failed to read the stream from Ollama: {}", e);
+                        yield Err(ModelError::from_request("ollama", message, e).into());
+                        return;
+                    }
+                };
+                for line in lines {
+                    let chunk = match serde_json::from_str::<OllamaStreamChunk>(&line) {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            let message = format!("Failed to parse the stream from Ollama: {}", e);
+                            yield Err(ModelError::new("ollama", message).into());
+                            return;
+                        }
+                    };
+                    if let Some(error) = chunk.error {
+                        yield Err(ModelError::new("ollama", error).into());
+                        return;
+                    }
+                    if let Some(message) = chunk.message {
+                        if let Some(thinking) = message.thinking.filter(|t| !t.is_empty()) {
+                            yield Ok(ChatChunk::ReasoningDelta(thinking));
+                        }
+                        if let Some(content) = message.content.filter(|c| !c.is_empty()) {
+                            yield Ok(ChatChunk::TextDelta(content));
+                        }
+                        for tool_call in message.tool_calls.unwrap_or_default() {
+                            yield Ok(ChatChunk::ToolCall(tool_call.into()));
+                        }
+                    }
+                    if chunk.prompt_eval_count.is_some() || chunk.eval_count.is_some() {
+                        yield Ok(ChatChunk::Usage(Usage::new(
+                            chunk.prompt_eval_count.unwrap_or(0),
+                            chunk.eval_count.unwrap_or(0),
+                        )));
+                    }
+                }
+            }
+        };
+        let family = ModelFamily::from_model_id(&self.model_id);
+        Ok(prefix_stream(
+            process_stream(Box::pin(stream), family, None),
+            config.prefill.as_deref(),
+        ))
     }
 }
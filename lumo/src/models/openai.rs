@@ -1,24 +1,74 @@
-use std::collections::HashMap;
-
 use crate::{
-    errors::AgentError,
+    errors::{AgentError, ModelError},
     models::{
         model_traits::{Model, ModelResponse},
-        types::{Message, MessageRole},
+        prefill::{prefill_messages, PrefilledResponse},
+        reasoning::ModelFamily,
+        info::{model_info, ModelInfo},
+        tokenizer::{tokenizer_for_model, Tokenizer},
+        types::{GenerationConfig, Message, MessageRole, RateLimit, ToolChoice, Usage},
+        wire_log::SendLogged,
     },
     tools::tool_traits::ToolInfo,
 };
+use std::sync::Arc;
 use anyhow::Result;
 use async_trait::async_trait;
 use nanoid::nanoid;
-use opentelemetry::{global, trace::{Span, Tracer}, Context, KeyValue};
+use opentelemetry::{global::{self, BoxedSpan}, trace::{Span, Tracer}, Context, KeyValue};
+use crate::telemetry::{generation_config_attributes, input_attributes, model_attributes, output_attributes, span_kind_attributes, SpanCategory};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
+#[cfg(feature = "stream")]
+use crate::models::stream::{openai_event_stream, process_stream, traced_stream, ModelStream};
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct OpenAIResponse {
     pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub usage: Option<OpenAIUsage>,
+    /// Read from the response headers.
+    #[serde(skip)]
+    pub rate_limit: Option<RateLimit>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OpenAIUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+    /// The cached prompt tokens as reported by DeepSeek.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_cache_hit_tokens: Option<usize>,
+    /// The prompt tokens written to the cache as reported by Anthropic-style providers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct PromptTokensDetails {
+    #[serde(default)]
+    pub cached_tokens: Option<usize>,
+    #[serde(default)]
+    pub cache_write_tokens: Option<usize>,
+}
+
+impl OpenAIUsage {
+    pub fn to_usage(&self) -> Usage {
+        let details = self.prompt_tokens_details.as_ref();
+        let cache_read = details
+            .and_then(|details| details.cached_tokens)
+            .or(self.prompt_cache_hit_tokens)
+            .unwrap_or(0);
+        let cache_write = details
+            .and_then(|details| details.cache_write_tokens)
+            .or(self.cache_creation_input_tokens)
+            .unwrap_or(0);
+        Usage::new(self.prompt_tokens, self.completion_tokens).with_cache(cache_read, cache_write)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -32,6 +82,10 @@ pub struct AssistantMessage {
     pub content: Option<String>,
     pub tool_calls: Option<Vec<ToolCall>>,
     pub refusal: Option<String>,
+    /// Returned by DeepSeek as `reasoning_content` and by OpenRouter as `reasoning`, or split from `<think>` tags in
+    /// the content.
+    #[serde(default, alias = "reasoning", skip_serializing_if = "Option::is_none")]
+    pub reasoning_content: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone,)]
@@ -112,14 +166,27 @@ where
     }
 }
 
+impl OpenAIResponse {
+    /// Splits the reasoning of `family` from the content and applies the stop sequences the provider was not given.
+    pub(crate) fn process(&mut self, family: ModelFamily, client_stop: Option<&[String]>) {
+        for choice in &mut self.choices {
+            if let Some(content) = &choice.message.content {
+                let (reasoning, content) = family.process(content, client_stop);
+                choice.message.content = Some(content);
+                if choice.message.reasoning_content.is_none() {
+                    choice.message.reasoning_content = reasoning;
+                }
+            }
+        }
+    }
+}
+
 impl ModelResponse for OpenAIResponse {
     fn get_response(&self) -> Result<String, AgentError> {
         Ok(self
             .choices
             .first()
-            .ok_or(AgentError::Generation(
-                "No message returned from OpenAI".to_string(),
-            ))?
+            .ok_or_else(|| ModelError::new("openai", "No message returned from OpenAI"))?
             .message
             .content
             .clone()
@@ -130,16 +197,83 @@ impl ModelResponse for OpenAIResponse {
         Ok(self
             .choices
             .first()
-            .ok_or(AgentError::Generation(
-                "No message returned from OpenAI".to_string(),
-            ))?
+            .ok_or_else(|| ModelError::new("openai", "No message returned from OpenAI"))?
             .message
             .tool_calls
             .clone()
             .unwrap_or_default())
     }
+
+    fn get_reasoning(&self) -> Option<String> {
+        self.choices
+            .first()
+            .and_then(|choice| choice.message.reasoning_content.clone())
+    }
+
+    fn get_usage(&self) -> Option<Usage> {
+        self.usage
+            .as_ref()
+            .map(OpenAIUsage::to_usage)
+    }
+
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
+}
+
+/// Converts a message to the chat completions format, where a message with images has a list of content parts.
+pub(crate) fn to_openai_message(message: &Message) -> Value {
+    let mut value = json!(message);
+    if let Some(object) = value.as_object_mut() {
+        object.remove("parts");
+        if !message.parts.is_empty() {
+            object.insert("content".to_string(), json!(message.content_parts()));
+        }
+    }
+    value
+}
+
+/// Converts a tool choice to the `tool_choice` parameter of the chat completions API.
+pub(crate) fn to_openai_tool_choice(tool_choice: &ToolChoice) -> Value {
+    match tool_choice {
+        ToolChoice::Auto => json!("auto"),
+        ToolChoice::Required => json!("required"),
+        ToolChoice::None => json!("none"),
+        ToolChoice::Tool(name) => json!({"type": "function", "function": {"name": name}}),
+    }
 }
 
+/// Marks the system prompt and the tool definitions of a chat completions request body as cacheable, for providers
+/// that only cache the parts of a prompt that end in a `cache_control` breakpoint, such as Anthropic models behind
+/// OpenRouter. The whole prompt up to a breakpoint is cached, so only the last tool and the last system message are
+/// marked.
+pub(crate) fn mark_cacheable(body: &mut Value) {
+    let breakpoint = json!({"type": "ephemeral"});
+    let last_tool = body
+        .get_mut("tools")
+        .and_then(Value::as_array_mut)
+        .and_then(|tools| tools.last_mut());
+    if let Some(tool) = last_tool {
+        tool["cache_control"] = breakpoint.clone();
+    }
+    let system = body
+        .get_mut("messages")
+        .and_then(Value::as_array_mut)
+        .and_then(|messages| messages.iter_mut().rev().find(|message| message["role"] == "system"));
+    if let Some(system) = system {
+        // Breakpoints are set on content parts, so a text content is turned into a single text part.
+        if let Some(text) = system["content"].as_str().map(str::to_string) {
+            system["content"] = json!([{"type": "text", "text": text}]);
+        }
+        if let Some(part) = system["content"].as_array_mut().and_then(|parts| parts.last_mut()) {
+            part["cache_control"] = breakpoint;
+        }
+    }
+}
+
+/// The most stop sequences the chat completions API accepts.
+const MAX_STOP_SEQUENCES: usize = 4;
+
 #[derive(Debug, Clone)]
 pub struct OpenAIServerModel {
     pub base_url: String,
@@ -224,92 +358,215 @@ impl OpenAIServerModelBuilder {
     }
 }
 
-#[async_trait]
-impl Model for OpenAIServerModel {
-    async fn run(
+impl OpenAIServerModel {
+    /// Builds the request body of the chat completions API.
+    fn request_body(
         &self,
-        messages: Vec<Message>,
-        history: Option<Vec<Message>>,
-        tools_to_call_from: Vec<ToolInfo>,
-        max_tokens: Option<usize>,
-        args: Option<HashMap<String, Vec<String>>>,
-    ) -> Result<Box<dyn ModelResponse>, AgentError> {
-        let max_tokens = max_tokens.unwrap_or(4500);
-        let mut messages = messages;
-        if let Some(history) = history {
-            messages = [history, messages].concat();
-        }
-        // let messages = messages
-        //     .iter()
-        //     .map(|message| {
-        //         json!({
-        //             "role": message.role,
-        //             "content": message.content,
-        //         })
-        //     })
-        //     .collect::<Vec<Value>>();
+        messages: &[Value],
+        tools_to_call_from: &[ToolInfo],
+        config: &GenerationConfig,
+    ) -> Value {
         let mut body = json!({
             "model": self.model_id,
             "messages": messages,
-            "temperature": self.temperature,
-            "max_tokens": max_tokens,
+            "temperature": config.temperature.unwrap_or(self.temperature),
+            "max_tokens": config.max_tokens.unwrap_or(4500),
         });
+        if let Some(top_p) = config.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(frequency_penalty) = config.frequency_penalty {
+            body["frequency_penalty"] = json!(frequency_penalty);
+        }
+        if let Some(presence_penalty) = config.presence_penalty {
+            body["presence_penalty"] = json!(presence_penalty);
+        }
+        if let Some(seed) = config.seed {
+            body["seed"] = json!(seed);
+        }
+        let family = ModelFamily::from_model_id(&self.model_id);
+        if let Some(stop) = config.stop.as_ref().filter(|stop| !stop.is_empty() && family.supports_stop()) {
+            if stop.len() > MAX_STOP_SEQUENCES {
+                tracing::warn!(
+                    model = %self.model_id,
+                    given = stop.len(),
+                    "OpenAI accepts at most {} stop sequences, the others are not sent",
+                    MAX_STOP_SEQUENCES
+                );
+            }
+            body["stop"] = json!(stop.iter().take(MAX_STOP_SEQUENCES).collect::<Vec<_>>());
+        }
+        if !tools_to_call_from.is_empty() {
+            body["tools"] = json!(tools_to_call_from
+                .iter()
+                .map(ToolInfo::to_openai)
+                .collect::<Vec<_>>());
+            body["tool_choice"] =
+                to_openai_tool_choice(config.tool_choice.as_ref().unwrap_or(&ToolChoice::Required));
+        }
+        body
+    }
 
+    /// Starts the span of a request, with the messages, the tools and the generation config it is sent with.
+    fn request_span(
+        &self,
+        name: &'static str,
+        messages: &[Value],
+        tools_to_call_from: &[ToolInfo],
+        body: &Value,
+        config: &GenerationConfig,
+    ) -> BoxedSpan {
         let parent_cx = Context::current();
         let tracer = global::tracer("lumo");
-        let mut span = tracer.span_builder("OpenAIServerModel::run").with_start_time(std::time::SystemTime::now()).start_with_context(&tracer, &parent_cx);
+        let mut span = tracer.span_builder(name).with_start_time(crate::runtime::now()).start_with_context(&tracer, &parent_cx);
+        span.set_attributes(span_kind_attributes(SpanCategory::Llm));
+        span.set_attributes(input_attributes(serde_json::to_string(messages).unwrap()));
+        span.set_attributes(model_attributes("openai", &self.model_id));
         span.set_attributes(vec![
-            KeyValue::new("input.value", serde_json::to_string(&messages).unwrap()),
-            KeyValue::new("llm.model_name", self.model_id.clone()),
-            KeyValue::new("gen_ai.request.temperature", self.temperature.to_string()),
-            KeyValue::new("gen_ai.request.max_tokens", max_tokens.to_string()),
+            KeyValue::new("gen_ai.request.temperature", config.temperature.unwrap_or(self.temperature).to_string()),
+            KeyValue::new("gen_ai.request.max_tokens", config.max_tokens.unwrap_or(4500).to_string()),
             KeyValue::new("timestamp", chrono::Utc::now().to_rfc3339()),
         ]);
-
-        if let Some(args) = &args {
-            for (key, value) in args {
-                if key != "messages" && key != "tools" {
-                    span.set_attributes(vec![KeyValue::new(
-                        format!("gen_ai.request.{}", key),
-                        serde_json::to_string(value).unwrap(),
-                    ), KeyValue::new("gen_ai.tools", serde_json::to_string(&tools_to_call_from).unwrap()),]);
-                }
-            }
-        }
-
+        span.set_attributes(generation_config_attributes(config));
         if !tools_to_call_from.is_empty() {
-  
-            body["tools"] = json!(tools_to_call_from);
-            body["tool_choice"] = json!("required");
+            span.set_attribute(KeyValue::new(
+                "gen_ai.tools",
+                serde_json::to_string(tools_to_call_from).unwrap(),
+            ));
             span.set_attribute(KeyValue::new(
                 "gen_ai.request.tool_choice",
                 serde_json::to_string(&body["tool_choice"]).unwrap(),
             ));
         }
-        
+        span
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for OpenAIServerModel {
+    fn info(&self) -> ModelInfo {
+        model_info(&self.model_id).with_streaming(cfg!(feature = "stream"))
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        tokenizer_for_model(&self.model_id)
+    }
+
+    async fn run(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools_to_call_from: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let mut messages = messages;
+        if let Some(history) = history {
+            messages = [history, messages].concat();
+        }
+        // OpenAI starts a new assistant turn after a trailing assistant message, so the prefill is asked for
+        prefill_messages(&mut messages, config.prefill.as_deref(), false);
+        let messages = messages.iter().map(to_openai_message).collect::<Vec<Value>>();
+        let body = self.request_body(&messages, &tools_to_call_from, &config);
+        let family = ModelFamily::from_model_id(&self.model_id);
+
+        let mut span = self.request_span("OpenAIServerModel::run", &messages, &tools_to_call_from, &body, &config);
+
         let response = self
             .client
             .post(&self.base_url)
             .header("Authorization", format!("Bearer {}", self.api_key))
             .json(&body)
-            .send()
+            .send_logged("openai")
             .await
             .map_err(|e| {
-                AgentError::Generation(format!("Failed to get response from OpenAI: {}", e))
+                ModelError::from_request(
+                    "openai",
+                    format!("Failed to get response from OpenAI: {}", e),
+                    e,
+                )
             })?;
 
+        let rate_limit = RateLimit::from_headers(response.headers());
         match response.status() {
             reqwest::StatusCode::OK => {
-                let response = response.json::<OpenAIResponse>().await.unwrap();
-                span.set_attribute(KeyValue::new("output.value", serde_json::to_string_pretty(&response).unwrap()));
-                span.end_with_timestamp(std::time::SystemTime::now());
-                Ok(Box::new(response))
+                let mut response = response.json::<OpenAIResponse>().await.unwrap();
+                response.rate_limit = rate_limit;
+                response.process(
+                    family,
+                    config.stop.as_deref().filter(|_| !family.supports_stop()),
+                );
+                span.set_attributes(output_attributes(serde_json::to_string_pretty(&response).unwrap()));
+                span.end_with_timestamp(crate::runtime::now());
+                Ok(PrefilledResponse::wrap(Box::new(response), config.prefill.as_deref()))
             }
-            _ => Err(AgentError::Generation(format!(
-                "Failed to get response from OpenAI: {} {}",
-                response.status(),
-                response.text().await.unwrap(),
-            ))),
+            status => Err(ModelError::from_status(
+                "openai",
+                status.as_u16(),
+                format!(
+                    "Failed to get response from OpenAI: {} {}",
+                    status,
+                    response.text().await.unwrap_or_default(),
+                ),
+            )
+            .with_rate_limit(rate_limit)
+            .into()),
+        }
+    }
+
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools_to_call_from: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        let mut messages = messages;
+        if let Some(history) = history {
+            messages = [history, messages].concat();
+        }
+        // OpenAI starts a new assistant turn after a trailing assistant message, so the prefill is asked for
+        prefill_messages(&mut messages, config.prefill.as_deref(), false);
+        let messages = messages.iter().map(to_openai_message).collect::<Vec<Value>>();
+        let mut body = self.request_body(&messages, &tools_to_call_from, &config);
+        body["stream"] = json!(true);
+        body["stream_options"] = json!({"include_usage": true});
+        let family = ModelFamily::from_model_id(&self.model_id);
+        let client_stop = config.stop.clone().filter(|_| !family.supports_stop());
+        let span = self.request_span("OpenAIServerModel::run_stream", &messages, &tools_to_call_from, &body, &config);
+
+        let response = self
+            .client
+            .post(&self.base_url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&body)
+            .send_logged("openai")
+            .await
+            .map_err(|e| {
+                ModelError::from_request(
+                    "openai",
+                    format!("Failed to get response from OpenAI: {}", e),
+                    e,
+                )
+            })?;
+        let rate_limit = RateLimit::from_headers(response.headers());
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let stream = openai_event_stream("openai".to_string(), response);
+                Ok(traced_stream(process_stream(stream, family, client_stop), span))
+            }
+            status => Err(ModelError::from_status(
+                "openai",
+                status.as_u16(),
+                format!(
+                    "Failed to get response from OpenAI: {} {}",
+                    status,
+                    response.text().await.unwrap_or_default(),
+                ),
+            )
+            .with_rate_limit(rate_limit)
+            .into()),
         }
     }
 }
@@ -338,6 +595,27 @@ mod tests {
         ]
     }
 
+    #[test]
+    fn test_to_openai_message() {
+        let message = Message::new(MessageRole::User, "Describe the image");
+        assert_eq!(
+            to_openai_message(&message),
+            json!({"role": "user", "content": "Describe the image"})
+        );
+
+        let message = message.with_image_base64("iVBORw0KGgo=", "image/png");
+        let value = to_openai_message(&message);
+        assert!(value.get("parts").is_none());
+        assert_eq!(
+            value["content"][0],
+            json!({"type": "text", "text": "Describe the image"})
+        );
+        assert_eq!(
+            value["content"][1]["image_url"]["url"],
+            "data:image/png;base64,iVBORw0KGgo="
+        );
+    }
+
     #[tokio::test]
     async fn test_openai_with_tool_response() {
         let model = OpenAIServerModelBuilder::new("gpt-4o-mini")
@@ -349,8 +627,7 @@ mod tests {
                 create_tool_response_message_from_tool_call(),
                 None,
                 vec![],
-                None,
-                None,
+                GenerationConfig::default(),
             )
             .await
             .unwrap();
@@ -372,8 +649,7 @@ mod tests {
                 create_tool_response_message_from_tool_call(),
                 None,
                 vec![],
-                None,
-                None,
+                GenerationConfig::default(),
             )
             .await
             .unwrap();
@@ -0,0 +1,636 @@
+//! A model for providers that serve an OpenAI-compatible chat completions API.
+//!
+//! The compatibility is rarely complete: some providers reject `tool_choice: "required"`, some only accept a
+//! limited number of tools, and some do not take images or the `parallel_tool_calls` parameter. A [`Provider`]
+//! preset knows the base url, the API key variable and these deviations, so that the same agent runs on any of them.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use opentelemetry::{
+    global,
+    trace::{Span, Tracer},
+    Context, KeyValue,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    errors::{AgentError, ModelError},
+    models::{
+        model_traits::{Model, ModelResponse},
+        openai::{mark_cacheable, to_openai_message, to_openai_tool_choice, OpenAIResponse},
+        reasoning::ModelFamily,
+        prefill::{prefill_messages, PrefilledResponse},
+        info::{model_info, ModelInfo},
+        tokenizer::{tokenizer_for_model, Tokenizer},
+        types::{GenerationConfig, Message, RateLimit, ToolChoice},
+        wire_log::SendLogged,
+    },
+    telemetry::{
+        generation_config_attributes, input_attributes, model_attributes, output_attributes,
+        span_kind_attributes, SpanCategory,
+    },
+    tools::ToolInfo,
+};
+
+#[cfg(feature = "stream")]
+use crate::models::{
+    prefill::prefix_stream,
+    stream::{openai_event_stream, process_stream, ModelStream},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Groq,
+    Together,
+    OpenRouter,
+    DeepSeek,
+    /// A self-hosted vLLM server.
+    Vllm,
+}
+
+/// What a provider does differently from the OpenAI API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderQuirks {
+    /// The maximum number of tools in one request.
+    pub max_tools: Option<usize>,
+    /// The maximum number of stop sequences in one request. Zero if the provider does not accept stop sequences.
+    pub max_stop: Option<usize>,
+    /// Whether `tool_choice` can be `"required"`. When it cannot, `"auto"` is sent instead.
+    pub tool_choice_required: bool,
+    /// Whether the `parallel_tool_calls` parameter is accepted.
+    pub parallel_tool_calls: bool,
+    /// Whether messages can have image content parts. When they cannot, only the text is sent.
+    pub images: bool,
+    /// Whether requests fail without an API key.
+    pub api_key_required: bool,
+    /// Whether the prompt is only cached up to `cache_control` breakpoints. Providers that cache on their own, such
+    /// as DeepSeek, leave it off.
+    pub cache_control: bool,
+    /// Whether the model continues a trailing assistant message, which makes the [prefill](crate::models::prefill)
+    /// the start of its answer. When it does not, the model is asked to start its answer with the prefill.
+    pub assistant_prefill: bool,
+}
+
+impl Provider {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::Groq => "groq",
+            Provider::Together => "together",
+            Provider::OpenRouter => "openrouter",
+            Provider::DeepSeek => "deepseek",
+            Provider::Vllm => "vllm",
+        }
+    }
+
+    pub fn base_url(&self) -> &'static str {
+        match self {
+            Provider::Groq => "https://api.groq.com/openai/v1/chat/completions",
+            Provider::Together => "https://api.together.xyz/v1/chat/completions",
+            Provider::OpenRouter => "https://openrouter.ai/api/v1/chat/completions",
+            Provider::DeepSeek => "https://api.deepseek.com/chat/completions",
+            Provider::Vllm => "http://localhost:8000/v1/chat/completions",
+        }
+    }
+
+    /// The environment variable the API key is read from when none is given.
+    pub fn api_key_env(&self) -> &'static str {
+        match self {
+            Provider::Groq => "GROQ_API_KEY",
+            Provider::Together => "TOGETHER_API_KEY",
+            Provider::OpenRouter => "OPENROUTER_API_KEY",
+            Provider::DeepSeek => "DEEPSEEK_API_KEY",
+            Provider::Vllm => "VLLM_API_KEY",
+        }
+    }
+
+    pub fn quirks(&self) -> ProviderQuirks {
+        match self {
+            Provider::Groq => ProviderQuirks {
+                max_tools: Some(128),
+                max_stop: Some(4),
+                tool_choice_required: true,
+                parallel_tool_calls: true,
+                images: true,
+                api_key_required: true,
+                cache_control: false,
+                assistant_prefill: true,
+            },
+            Provider::Together => ProviderQuirks {
+                max_tools: None,
+                max_stop: None,
+                tool_choice_required: false,
+                parallel_tool_calls: false,
+                images: true,
+                api_key_required: true,
+                cache_control: false,
+                assistant_prefill: false,
+            },
+            Provider::OpenRouter => ProviderQuirks {
+                max_tools: None,
+                max_stop: None,
+                tool_choice_required: true,
+                parallel_tool_calls: true,
+                images: true,
+                api_key_required: true,
+                cache_control: true,
+                assistant_prefill: true,
+            },
+            Provider::DeepSeek => ProviderQuirks {
+                max_tools: Some(128),
+                max_stop: Some(16),
+                tool_choice_required: true,
+                parallel_tool_calls: false,
+                images: false,
+                api_key_required: true,
+                cache_control: false,
+                assistant_prefill: false,
+            },
+            Provider::Vllm => ProviderQuirks {
+                max_tools: None,
+                max_stop: None,
+                tool_choice_required: false,
+                parallel_tool_calls: false,
+                images: true,
+                api_key_required: false,
+                cache_control: false,
+                assistant_prefill: true,
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "groq" => Ok(Provider::Groq),
+            "together" => Ok(Provider::Together),
+            "openrouter" => Ok(Provider::OpenRouter),
+            "deepseek" => Ok(Provider::DeepSeek),
+            "vllm" => Ok(Provider::Vllm),
+            _ => Err(anyhow::anyhow!("Unknown provider: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GenericOpenAICompatibleModel {
+    pub provider: Provider,
+    pub quirks: ProviderQuirks,
+    pub base_url: String,
+    pub model_id: String,
+    pub client: Client,
+    pub temperature: f32,
+    pub api_key: Option<String>,
+    /// Extra headers sent with every request, such as `HTTP-Referer` and `X-Title` for OpenRouter.
+    pub headers: Vec<(String, String)>,
+}
+
+pub struct GenericOpenAICompatibleModelBuilder {
+    provider: Provider,
+    model_id: String,
+    base_url: Option<String>,
+    api_key: Option<String>,
+    temperature: Option<f32>,
+    headers: Vec<(String, String)>,
+    quirks: Option<ProviderQuirks>,
+}
+
+impl GenericOpenAICompatibleModelBuilder {
+    pub fn new(provider: Provider, model_id: &str) -> Self {
+        Self {
+            provider,
+            model_id: model_id.to_string(),
+            base_url: None,
+            api_key: None,
+            temperature: None,
+            headers: vec![],
+            quirks: None,
+        }
+    }
+    /// Overrides the base url of the provider, for example for a vLLM server on another host.
+    pub fn with_base_url(mut self, base_url: Option<&str>) -> Self {
+        self.base_url = base_url.map(|s| s.to_string());
+        self
+    }
+    /// Defaults to the environment variable of the provider.
+    pub fn with_api_key(mut self, api_key: Option<&str>) -> Self {
+        self.api_key = api_key.map(|s| s.to_string());
+        self
+    }
+    pub fn with_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+    /// Replaces the quirks of the provider preset, for a model that deviates from the rest of its provider.
+    pub fn with_quirks(mut self, quirks: ProviderQuirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+    pub fn build(self) -> Result<GenericOpenAICompatibleModel> {
+        let quirks = self.quirks.unwrap_or_else(|| self.provider.quirks());
+        let api_key = self
+            .api_key
+            .or_else(|| std::env::var(self.provider.api_key_env()).ok());
+        if api_key.is_none() && quirks.api_key_required {
+            return Err(anyhow::anyhow!(
+                "{} must be set to use {}",
+                self.provider.api_key_env(),
+                self.provider.name()
+            ));
+        }
+        Ok(GenericOpenAICompatibleModel {
+            provider: self.provider,
+            quirks,
+            base_url: self
+                .base_url
+                .unwrap_or_else(|| self.provider.base_url().to_string()),
+            model_id: self.model_id,
+            client: Client::new(),
+            temperature: self.temperature.unwrap_or(0.5),
+            api_key,
+            headers: self.headers,
+        })
+    }
+}
+
+impl GenericOpenAICompatibleModel {
+    fn family(&self) -> ModelFamily {
+        ModelFamily::from_model_id(&self.model_id)
+    }
+
+    /// Whether the stop sequences are sent to the provider. Either the model family or a `max_stop` of zero can
+    /// rule them out, in which case they are applied to the response instead.
+    fn supports_stop(&self) -> bool {
+        self.family().supports_stop() && self.quirks.max_stop != Some(0)
+    }
+
+    /// Builds the request body, adjusted to the quirks of the provider.
+    fn request_body(
+        &self,
+        messages: &[Message],
+        tools_to_call_from: &[ToolInfo],
+        config: &GenerationConfig,
+    ) -> Result<Value, AgentError> {
+        let mut messages = messages.to_vec();
+        prefill_messages(
+            &mut messages,
+            config.prefill.as_deref(),
+            self.quirks.assistant_prefill,
+        );
+        let messages = messages
+            .iter()
+            .map(|message| {
+                if !self.quirks.images && !message.parts.is_empty() {
+                    tracing::warn!(
+                        provider = self.provider.name(),
+                        "The provider does not accept images, only the text of the message is sent"
+                    );
+                    let mut message = message.clone();
+                    message.parts.clear();
+                    to_openai_message(&message)
+                } else {
+                    to_openai_message(message)
+                }
+            })
+            .collect::<Vec<Value>>();
+        let mut body = json!({
+            "model": self.model_id,
+            "messages": messages,
+            "temperature": config.temperature.unwrap_or(self.temperature),
+            "max_tokens": config.max_tokens.unwrap_or(4500),
+        });
+        if let Some(top_p) = config.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if let Some(frequency_penalty) = config.frequency_penalty {
+            body["frequency_penalty"] = json!(frequency_penalty);
+        }
+        if let Some(presence_penalty) = config.presence_penalty {
+            body["presence_penalty"] = json!(presence_penalty);
+        }
+        if let Some(seed) = config.seed {
+            body["seed"] = json!(seed);
+        }
+        if let Some(stop) = config.stop.as_ref().filter(|stop| !stop.is_empty() && self.supports_stop()) {
+            let max_stop = self.quirks.max_stop.unwrap_or(stop.len());
+            body["stop"] = json!(stop.iter().take(max_stop).collect::<Vec<_>>());
+        }
+
+        if !tools_to_call_from.is_empty() {
+            if let Some(max_tools) = self.quirks.max_tools {
+                if tools_to_call_from.len() > max_tools {
+                    return Err(ModelError::new(
+                        self.provider.name(),
+                        format!(
+                            "{} accepts at most {} tools, but {} were given",
+                            self.provider.name(),
+                            max_tools,
+                            tools_to_call_from.len()
+                        ),
+                    )
+                    .into());
+                }
+            }
+            let tool_choice = match config.tool_choice.clone().unwrap_or(ToolChoice::Required) {
+                ToolChoice::Required if !self.quirks.tool_choice_required => ToolChoice::Auto,
+                tool_choice => tool_choice,
+            };
+            body["tools"] = json!(tools_to_call_from
+                .iter()
+                .map(ToolInfo::to_openai)
+                .collect::<Vec<_>>());
+            body["tool_choice"] = to_openai_tool_choice(&tool_choice);
+            if self.quirks.parallel_tool_calls {
+                body["parallel_tool_calls"] = json!(true);
+            }
+        }
+        if self.quirks.cache_control && config.cache_prompt.unwrap_or(false) {
+            mark_cacheable(&mut body);
+        }
+        let prefilled = config.prefill.as_ref().is_some_and(|prefill| !prefill.is_empty());
+        if self.provider == Provider::Vllm && self.quirks.assistant_prefill && prefilled {
+            // vLLM starts a new assistant turn unless it is told to continue the last one
+            body["continue_final_message"] = json!(true);
+            body["add_generation_prompt"] = json!(false);
+        }
+        Ok(body)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for GenericOpenAICompatibleModel {
+    fn info(&self) -> ModelInfo {
+        let info = model_info(&self.model_id).with_streaming(cfg!(feature = "stream"));
+        let supports_vision = info.supports_vision && self.quirks.images;
+        info.with_vision(supports_vision)
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        tokenizer_for_model(&self.model_id)
+    }
+
+    async fn run(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools_to_call_from: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let mut messages = messages;
+        if let Some(history) = history {
+            messages = [history, messages].concat();
+        }
+        let body = self.request_body(&messages, &tools_to_call_from, &config)?;
+
+        let parent_cx = Context::current();
+        let tracer = global::tracer("lumo");
+        let mut span = tracer
+            .span_builder("GenericOpenAICompatibleModel::run")
+            .with_start_time(crate::runtime::now())
+            .start_with_context(&tracer, &parent_cx);
+        span.set_attributes(span_kind_attributes(SpanCategory::Llm));
+        span.set_attributes(input_attributes(
+            serde_json::to_string(&body["messages"]).unwrap(),
+        ));
+        span.set_attributes(model_attributes(self.provider.name(), &self.model_id));
+        span.set_attributes(generation_config_attributes(&config));
+        if !tools_to_call_from.is_empty() {
+            span.set_attribute(KeyValue::new(
+                "gen_ai.request.tool_choice",
+                serde_json::to_string(&body["tool_choice"]).unwrap(),
+            ));
+        }
+
+        let mut request = self.client.post(&self.base_url).json(&body);
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send_logged(self.provider.name()).await.map_err(|e| {
+            ModelError::from_request(
+                self.provider.name(),
+                format!("Failed to get response from {}: {}", self.provider.name(), e),
+                e,
+            )
+        })?;
+
+        let rate_limit = RateLimit::from_headers(response.headers());
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let mut response = response.json::<OpenAIResponse>().await.map_err(|e| {
+                    ModelError::from_request(
+                        self.provider.name(),
+                        format!("Failed to parse response from {}: {}", self.provider.name(), e),
+                        e,
+                    )
+                })?;
+                response.rate_limit = rate_limit;
+                response.process(
+                    self.family(),
+                    config.stop.as_deref().filter(|_| !self.supports_stop()),
+                );
+                span.set_attributes(output_attributes(
+                    serde_json::to_string_pretty(&response).unwrap(),
+                ));
+                span.end_with_timestamp(crate::runtime::now());
+                Ok(PrefilledResponse::wrap(
+                    Box::new(response),
+                    config.prefill.as_deref(),
+                ))
+            }
+            status => Err(ModelError::from_status(
+                self.provider.name(),
+                status.as_u16(),
+                format!(
+                    "Failed to get response from {}: {} {}",
+                    self.provider.name(),
+                    status,
+                    response.text().await.unwrap_or_default(),
+                ),
+            )
+            .with_rate_limit(rate_limit)
+            .into()),
+        }
+    }
+
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools_to_call_from: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        let mut messages = messages;
+        if let Some(history) = history {
+            messages = [history, messages].concat();
+        }
+        let mut body = self.request_body(&messages, &tools_to_call_from, &config)?;
+        body["stream"] = json!(true);
+        body["stream_options"] = json!({"include_usage": true});
+
+        let mut request = self.client.post(&self.base_url).json(&body);
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send_logged(self.provider.name()).await.map_err(|e| {
+            ModelError::from_request(
+                self.provider.name(),
+                format!("Failed to get response from {}: {}", self.provider.name(), e),
+                e,
+            )
+        })?;
+        let rate_limit = RateLimit::from_headers(response.headers());
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let stream = openai_event_stream(self.provider.name().to_string(), response);
+                let client_stop = config.stop.clone().filter(|_| !self.supports_stop());
+                Ok(prefix_stream(
+                    process_stream(stream, self.family(), client_stop),
+                    config.prefill.as_deref().filter(|_| self.quirks.assistant_prefill),
+                ))
+            }
+            status => Err(ModelError::from_status(
+                self.provider.name(),
+                status.as_u16(),
+                format!(
+                    "Failed to get response from {}: {} {}",
+                    self.provider.name(),
+                    status,
+                    response.text().await.unwrap_or_default(),
+                ),
+            )
+            .with_rate_limit(rate_limit)
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::types::MessageRole,
+        tools::{ToolFunctionInfo, ToolType},
+    };
+
+    fn tool(name: &str) -> ToolInfo {
+        ToolInfo {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo {
+                name: name.to_string(),
+                description: String::new(),
+                parameters: json!({}),
+            },
+        }
+    }
+
+    #[test]
+    fn test_request_body_follows_quirks() {
+        let model = GenericOpenAICompatibleModelBuilder::new(Provider::Vllm, "qwen")
+            .build()
+            .unwrap();
+        let messages = vec![Message::new(MessageRole::User, "Hi")];
+        let body = model
+            .request_body(&messages, &[tool("search")], &GenerationConfig::default())
+            .unwrap();
+        assert_eq!(body["tool_choice"], "auto");
+        assert!(body.get("parallel_tool_calls").is_none());
+
+        let model = GenericOpenAICompatibleModelBuilder::new(Provider::DeepSeek, "deepseek-chat")
+            .with_api_key(Some("key"))
+            .build()
+            .unwrap();
+        let messages =
+            vec![Message::new(MessageRole::User, "Hi").with_image_url("https://example.com/a.png")];
+        let body = model
+            .request_body(&messages, &[], &GenerationConfig::default())
+            .unwrap();
+        assert_eq!(body["messages"][0]["content"], "Hi");
+
+        let tools = (0..129)
+            .map(|i| tool(&format!("tool_{}", i)))
+            .collect::<Vec<_>>();
+        assert!(model
+            .request_body(&messages, &tools, &GenerationConfig::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_prefill_follows_quirks() {
+        let messages = vec![Message::new(MessageRole::User, "Hi")];
+        let config = GenerationConfig::new().with_prefill("Thought:");
+        let model = GenericOpenAICompatibleModelBuilder::new(Provider::Vllm, "qwen")
+            .build()
+            .unwrap();
+        let body = model.request_body(&messages, &[], &config).unwrap();
+        assert_eq!(body["messages"][1]["role"], "assistant");
+        assert_eq!(body["messages"][1]["content"], "Thought:");
+        assert_eq!(body["continue_final_message"], true);
+
+        let model = GenericOpenAICompatibleModelBuilder::new(Provider::Together, "llama")
+            .with_api_key(Some("key"))
+            .build()
+            .unwrap();
+        let body = model.request_body(&messages, &[], &config).unwrap();
+        assert_eq!(body["messages"][1]["role"], "user");
+        assert!(body.get("continue_final_message").is_none());
+    }
+
+    #[test]
+    fn test_prompt_caching() {
+        let model_id = "anthropic/claude-3.5-sonnet";
+        let model = GenericOpenAICompatibleModelBuilder::new(Provider::OpenRouter, model_id)
+            .with_api_key(Some("key"))
+            .build()
+            .unwrap();
+        let messages = vec![
+            Message::new(MessageRole::System, "You are a helpful agent."),
+            Message::new(MessageRole::User, "Hi"),
+        ];
+        let tools = [tool("search"), tool("final_answer")];
+        let body = model
+            .request_body(&messages, &tools, &GenerationConfig::default())
+            .unwrap();
+        assert!(body["tools"][1].get("cache_control").is_none());
+
+        let config = GenerationConfig::new().with_cache_prompt(true);
+        let body = model.request_body(&messages, &tools, &config).unwrap();
+        assert!(body["tools"][0].get("cache_control").is_none());
+        assert_eq!(body["tools"][1]["cache_control"]["type"], "ephemeral");
+        let system = &body["messages"][0]["content"][0];
+        assert_eq!(system["text"], "You are a helpful agent.");
+        assert_eq!(system["cache_control"]["type"], "ephemeral");
+        assert_eq!(body["messages"][1]["content"], "Hi");
+
+        let response: OpenAIResponse = serde_json::from_value(json!({
+            "choices": [],
+            "usage": {
+                "prompt_tokens": 1200,
+                "completion_tokens": 20,
+                "prompt_tokens_details": {"cached_tokens": 1000}
+            }
+        }))
+        .unwrap();
+        let usage = response.usage.unwrap().to_usage();
+        assert_eq!(usage.cache_read_tokens, 1000);
+        assert_eq!(usage.input_tokens, 1200);
+    }
+}
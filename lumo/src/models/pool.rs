@@ -0,0 +1,500 @@
+//! A pool of models of the same provider, e.g. one per API key or endpoint, that share the load of many requests.
+//!
+//! [`ModelPool`] sends each call to one model of the pool, picked in turn or by the number of calls it is already
+//! running. It reads the rate limit headers of every response: a model whose key has no requests left, or that was
+//! rate limited, is skipped until its limit resets, and a call that is rate limited is sent to another model.
+//!
+//! ```rust,ignore
+//! let models = api_keys
+//!     .iter()
+//!     .map(|key| {
+//!         let model = OpenAIServerModel::new(None, Some("gpt-4o-mini"), None, Some(key.clone()), None);
+//!         Box::new(model) as Box<dyn Model>
+//!     })
+//!     .collect();
+//! let pool = Arc::new(ModelPool::new(models).with_strategy(PoolStrategy::LeastLoaded));
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::{
+    errors::{AgentError, ModelError},
+    models::{
+        info::{shared_info, ModelInfo},
+        model_traits::{Model, ModelResponse},
+        tokenizer::{HeuristicTokenizer, Tokenizer},
+        types::{GenerationConfig, Message, RateLimit},
+    },
+    runtime::Instant,
+    tools::ToolInfo,
+};
+
+#[cfg(feature = "stream")]
+use crate::models::stream::ModelStream;
+
+/// How long a model is skipped after a rate limit error that did not say how long to wait.
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PoolStrategy {
+    /// Each call goes to the next model.
+    #[default]
+    RoundRobin,
+    /// Each call goes to the model running the fewest calls, and among those to the one with the most requests left.
+    LeastLoaded,
+}
+
+#[derive(Debug, Default)]
+struct MemberState {
+    rate_limit: Option<RateLimit>,
+    /// The model is skipped until then.
+    cooldown_until: Option<Instant>,
+    requests: usize,
+    rate_limited: usize,
+}
+
+struct PoolMember {
+    name: String,
+    model: Box<dyn Model>,
+    in_flight: AtomicUsize,
+    state: Mutex<MemberState>,
+}
+
+impl PoolMember {
+    fn cooldown(&self, now: Instant) -> Option<Duration> {
+        self.state
+            .lock()
+            .unwrap()
+            .cooldown_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+
+    fn remaining_requests(&self) -> Option<u64> {
+        self.state
+            .lock()
+            .unwrap()
+            .rate_limit
+            .and_then(|rate_limit| rate_limit.remaining_requests)
+    }
+
+    fn record(&self, rate_limit: Option<RateLimit>, rate_limited: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.requests += 1;
+        if let Some(rate_limit) = rate_limit {
+            state.rate_limit = Some(rate_limit);
+        }
+        let wait = rate_limit
+            .and_then(|rate_limit| rate_limit.wait())
+            .or(rate_limited.then_some(DEFAULT_COOLDOWN));
+        if rate_limited {
+            state.rate_limited += 1;
+        }
+        if let Some(wait) = wait {
+            state.cooldown_until = Some(Instant::now() + wait);
+        }
+    }
+}
+
+/// Decrements the calls in flight of a model when the call ends, even if it is cancelled.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn start(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The state of one model of a pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolMemberStats {
+    pub name: String,
+    pub in_flight: usize,
+    pub requests: usize,
+    /// The calls that were rate limited.
+    pub rate_limited: usize,
+    pub rate_limit: Option<RateLimit>,
+    /// How long the model is still skipped for.
+    pub cooldown: Option<Duration>,
+}
+
+pub struct ModelPool {
+    members: Vec<PoolMember>,
+    strategy: PoolStrategy,
+    next: AtomicUsize,
+}
+
+impl ModelPool {
+    /// Spreads the calls over `models`. The models are named `model_0`, `model_1`, and so on, until they are named
+    /// with [`ModelPool::with_names`].
+    pub fn new(models: Vec<Box<dyn Model>>) -> Self {
+        Self {
+            members: models
+                .into_iter()
+                .enumerate()
+                .map(|(index, model)| PoolMember {
+                    name: format!("model_{}", index),
+                    model,
+                    in_flight: AtomicUsize::new(0),
+                    state: Mutex::new(MemberState::default()),
+                })
+                .collect(),
+            strategy: PoolStrategy::default(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_strategy(mut self, strategy: PoolStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Names the models in order, e.g. after the key they use. Never use the key itself, since the names are logged.
+    pub fn with_names(mut self, names: &[&str]) -> Self {
+        for (member, name) in self.members.iter_mut().zip(names) {
+            member.name = name.to_string();
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn stats(&self) -> Vec<PoolMemberStats> {
+        let now = Instant::now();
+        self.members
+            .iter()
+            .map(|member| {
+                let state = member.state.lock().unwrap();
+                PoolMemberStats {
+                    name: member.name.clone(),
+                    in_flight: member.in_flight.load(Ordering::SeqCst),
+                    requests: state.requests,
+                    rate_limited: state.rate_limited,
+                    rate_limit: state.rate_limit,
+                    cooldown: state
+                        .cooldown_until
+                        .filter(|until| *until > now)
+                        .map(|until| until - now),
+                }
+            })
+            .collect()
+    }
+
+    /// The model for the next call, skipping the models in `tried`. When every model is cooling down, returns the
+    /// one that is ready first and how long to wait for it.
+    fn pick(&self, tried: &[usize]) -> Option<(usize, Option<Duration>)> {
+        let now = Instant::now();
+        let candidates = (0..self.members.len())
+            .filter(|index| !tried.contains(index))
+            .collect::<Vec<_>>();
+        let ready = candidates
+            .iter()
+            .copied()
+            .filter(|index| self.members[*index].cooldown(now).is_none())
+            .collect::<Vec<_>>();
+        if ready.is_empty() {
+            return candidates
+                .into_iter()
+                .filter_map(|index| Some((index, self.members[index].cooldown(now)?)))
+                .min_by_key(|(_, wait)| *wait)
+                .map(|(index, wait)| (index, Some(wait)));
+        }
+        let index = match self.strategy {
+            PoolStrategy::RoundRobin => {
+                let turn = self.next.fetch_add(1, Ordering::SeqCst);
+                ready
+                    .iter()
+                    .copied()
+                    .find(|index| *index >= turn % self.members.len())
+                    .unwrap_or(ready[0])
+            }
+            PoolStrategy::LeastLoaded => ready
+                .iter()
+                .copied()
+                .min_by_key(|index| {
+                    let member = &self.members[*index];
+                    (
+                        member.in_flight.load(Ordering::SeqCst),
+                        std::cmp::Reverse(member.remaining_requests().unwrap_or(u64::MAX)),
+                    )
+                })
+                .unwrap_or(ready[0]),
+        };
+        Some((index, None))
+    }
+}
+
+impl fmt::Debug for ModelPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModelPool")
+            .field("strategy", &self.strategy)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for ModelPool {
+    fn info(&self) -> ModelInfo {
+        shared_info(
+            self.members
+                .iter()
+                .map(|member| member.model.info())
+                .collect(),
+        )
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        match self.members.first() {
+            Some(member) => member.model.tokenizer(),
+            None => Arc::new(HeuristicTokenizer::default()),
+        }
+    }
+
+    /// Sends the call to one model of the pool, and to the next one if it is rate limited, until every model was
+    /// tried once.
+    async fn run(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let mut tried = Vec::new();
+        let mut last_error = None;
+        while let Some((index, wait)) = self.pick(&tried) {
+            let member = &self.members[index];
+            if let Some(wait) = wait {
+                tracing::info!(model = %member.name, wait = ?wait, "Every model of the pool is rate limited, waiting");
+                crate::runtime::sleep(wait).await;
+            }
+            tried.push(index);
+            let result = {
+                let _in_flight = InFlight::start(&member.in_flight);
+                member
+                    .model
+                    .run(
+                        input_messages.clone(),
+                        history.clone(),
+                        tools.clone(),
+                        config.clone(),
+                    )
+                    .await
+            };
+            match result {
+                Ok(response) => {
+                    member.record(response.get_rate_limit(), false);
+                    return Ok(response);
+                }
+                Err(AgentError::Model(error)) if error.status == Some(429) => {
+                    tracing::warn!(model = %member.name, "Model of the pool is rate limited, trying another one");
+                    member.record(error.rate_limit, true);
+                    last_error = Some(AgentError::Model(error));
+                }
+                Err(error) => {
+                    member.record(None, false);
+                    return Err(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            ModelError::new("pool", "The model pool has no models to call").into()
+        }))
+    }
+
+    /// Starts the stream on one model of the pool, like [`ModelPool::run`]. The call counts as running until the
+    /// stream has started.
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        let mut tried = Vec::new();
+        let mut last_error = None;
+        while let Some((index, wait)) = self.pick(&tried) {
+            let member = &self.members[index];
+            if let Some(wait) = wait {
+                tracing::info!(model = %member.name, wait = ?wait, "Every model of the pool is rate limited, waiting");
+                crate::runtime::sleep(wait).await;
+            }
+            tried.push(index);
+            let result = {
+                let _in_flight = InFlight::start(&member.in_flight);
+                member
+                    .model
+                    .run_stream(
+                        input_messages.clone(),
+                        history.clone(),
+                        tools.clone(),
+                        config.clone(),
+                    )
+                    .await
+            };
+            match result {
+                Ok(stream) => {
+                    member.record(None, false);
+                    return Ok(stream);
+                }
+                Err(AgentError::Model(error)) if error.status == Some(429) => {
+                    tracing::warn!(model = %member.name, "Model of the pool is rate limited, trying another one");
+                    member.record(error.rate_limit, true);
+                    last_error = Some(AgentError::Model(error));
+                }
+                Err(error) => {
+                    member.record(None, false);
+                    return Err(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            ModelError::new("pool", "The model pool has no models to call").into()
+        }))
+    }
+}
+
+/// A pool shared by several agents, e.g. the agents of a [`crate::batch::BatchRunner`].
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for Arc<ModelPool> {
+    fn info(&self) -> ModelInfo {
+        self.as_ref().info()
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        self.as_ref().tokenizer()
+    }
+
+    async fn run(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        self.as_ref()
+            .run(input_messages, history, tools, config)
+            .await
+    }
+
+    #[cfg(feature = "stream")]
+    async fn run_stream(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<ModelStream, AgentError> {
+        self.as_ref()
+            .run_stream(input_messages, history, tools, config)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::replay::RecordedResponse;
+
+    /// Answers with its name, or fails with a rate limit once its calls are used up.
+    #[derive(Debug)]
+    struct KeyModel {
+        name: &'static str,
+        calls: AtomicUsize,
+        limit: usize,
+    }
+
+    #[async_trait]
+    impl Model for KeyModel {
+        async fn run(
+            &self,
+            _input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            _config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) >= self.limit {
+                let rate_limit = RateLimit {
+                    retry_after: Some(Duration::from_secs(60)),
+                    ..RateLimit::default()
+                };
+                return Err(ModelError::from_status(self.name, 429, "Rate limited")
+                    .with_rate_limit(Some(rate_limit))
+                    .into());
+            }
+            Ok(Box::new(RecordedResponse {
+                content: Some(self.name.to_string()),
+                tool_calls: vec![],
+                usage: None,
+                error: None,
+            }))
+        }
+    }
+
+    fn model(name: &'static str, limit: usize) -> Box<dyn Model> {
+        Box::new(KeyModel {
+            name,
+            calls: AtomicUsize::new(0),
+            limit,
+        })
+    }
+
+    async fn answer(pool: &ModelPool) -> String {
+        pool.run(
+            vec![Message::user("Hi")],
+            None,
+            vec![],
+            GenerationConfig::new(),
+        )
+        .await
+        .unwrap()
+        .get_response()
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_model_pool() {
+        let pool = ModelPool::new(vec![model("a", 1), model("b", 10)]);
+        assert_eq!(answer(&pool).await, "a");
+        assert_eq!(answer(&pool).await, "b");
+        // `a` is rate limited, so the call goes to `b`, and `a` is skipped afterwards.
+        assert_eq!(answer(&pool).await, "b");
+        assert_eq!(answer(&pool).await, "b");
+        let stats = pool.stats();
+        assert_eq!(stats[0].rate_limited, 1);
+        assert!(stats[0].cooldown.is_some());
+        assert_eq!(stats[1].requests, 3);
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-ratelimit-remaining-requests", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset-requests", "1m30s".parse().unwrap());
+        let rate_limit = RateLimit::from_headers(&headers).unwrap();
+        assert_eq!(rate_limit.wait(), Some(Duration::from_secs(90)));
+        assert_eq!(
+            RateLimit::from_headers(&reqwest::header::HeaderMap::new()),
+            None
+        );
+    }
+}
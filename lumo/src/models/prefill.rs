@@ -0,0 +1,195 @@
+//! Assistant prefill, which makes the model start its answer with a given text.
+//!
+//! Parsers that expect an answer in a fixed format, such as a JSON object or a `Thought:` line, fail when the model
+//! opens with something else. A prefill primes the answer with the start of that format. Providers that can continue
+//! an assistant turn get the prefill as the beginning of that turn, and the model writes the rest. The others are
+//! asked to start their answer with it. Either way the response starts with the prefill, so the parsers see the whole
+//! answer.
+//!
+//! ```rust,ignore
+//! let config = GenerationConfig::new().with_prefill("{\"name\": \"");
+//! let agent = CodeAgentBuilder::new(model)
+//!     .with_prefill(Prefill::per_step(|step| (step > 1).then(|| "Thought:".to_string())))
+//!     .build()?;
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::{
+    model_traits::ModelResponse,
+    openai::ToolCall,
+    types::{Message, RateLimit, Usage},
+};
+use crate::errors::AgentError;
+
+#[cfg(feature = "stream")]
+use super::stream::{ChatChunk, ModelStream};
+
+type PrefillProvider = Arc<dyn Fn(usize) -> Option<String> + Send + Sync>;
+
+/// The prefill of each step of an agent, set with `with_prefill` on the agent builders.
+#[derive(Clone)]
+pub struct Prefill(PrefillProvider);
+
+impl Prefill {
+    /// The same prefill at every step.
+    pub fn new(prefill: &str) -> Self {
+        let prefill = prefill.to_string();
+        Self(Arc::new(move |_| Some(prefill.clone())))
+    }
+
+    /// The prefill of a step by its number, which starts at 1. Steps for which `prefill` returns `None` are not
+    /// prefilled.
+    pub fn per_step(prefill: impl Fn(usize) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(prefill))
+    }
+
+    pub fn for_step(&self, step: usize) -> Option<String> {
+        (self.0)(step).filter(|prefill| !prefill.is_empty())
+    }
+}
+
+impl fmt::Debug for Prefill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Prefill").finish_non_exhaustive()
+    }
+}
+
+/// Adds the prefill to the end of `messages`: as an assistant message that the model continues when the provider
+/// supports it, and as an instruction otherwise.
+pub(crate) fn prefill_messages(messages: &mut Vec<Message>, prefill: Option<&str>, native: bool) {
+    let Some(prefill) = prefill.filter(|prefill| !prefill.is_empty()) else {
+        return;
+    };
+    if native {
+        messages.push(Message::assistant(prefill));
+    } else {
+        messages.push(Message::user(&format!(
+            "Start your answer with exactly the following text, with nothing before it:\n{}",
+            prefill
+        )));
+    }
+}
+
+/// `content` with the prefill in front of it, unless the model already wrote it. An empty answer next to tool calls is
+/// left empty.
+pub(crate) fn prepend_prefill(content: &str, prefill: &str, has_tool_calls: bool) -> String {
+    if content.is_empty() && has_tool_calls {
+        return String::new();
+    }
+    let trimmed = content.trim_start();
+    if trimmed.starts_with(prefill.trim_start()) {
+        return trimmed.to_string();
+    }
+    format!("{}{}", prefill, content)
+}
+
+/// A response whose text starts with the prefill it was primed with.
+pub(crate) struct PrefilledResponse {
+    response: Box<dyn ModelResponse>,
+    prefill: String,
+}
+
+impl PrefilledResponse {
+    /// Wraps `response` when there is a prefill, and returns it as it is otherwise.
+    pub(crate) fn wrap(
+        response: Box<dyn ModelResponse>,
+        prefill: Option<&str>,
+    ) -> Box<dyn ModelResponse> {
+        match prefill.filter(|prefill| !prefill.is_empty()) {
+            Some(prefill) => Box::new(Self {
+                response,
+                prefill: prefill.to_string(),
+            }),
+            None => response,
+        }
+    }
+}
+
+impl ModelResponse for PrefilledResponse {
+    fn get_response(&self) -> Result<String, AgentError> {
+        let content = self.response.get_response()?;
+        let has_tool_calls = self
+            .response
+            .get_tools_used()
+            .is_ok_and(|tool_calls| !tool_calls.is_empty());
+        Ok(prepend_prefill(&content, &self.prefill, has_tool_calls))
+    }
+
+    fn get_tools_used(&self) -> Result<Vec<ToolCall>, AgentError> {
+        self.response.get_tools_used()
+    }
+
+    fn get_usage(&self) -> Option<Usage> {
+        self.response.get_usage()
+    }
+
+    fn get_reasoning(&self) -> Option<String> {
+        self.response.get_reasoning()
+    }
+
+    fn get_rate_limit(&self) -> Option<RateLimit> {
+        self.response.get_rate_limit()
+    }
+}
+
+/// Sends the prefill as the first text of a stream from a provider that continued it, which only streams the rest.
+#[cfg(feature = "stream")]
+pub(crate) fn prefix_stream(stream: ModelStream, prefill: Option<&str>) -> ModelStream {
+    match prefill.filter(|prefill| !prefill.is_empty()) {
+        Some(prefill) => {
+            let first = futures::stream::once(futures::future::ready(Ok(ChatChunk::TextDelta(
+                prefill.to_string(),
+            ))));
+            Box::pin(futures::StreamExt::chain(first, stream))
+        }
+        None => stream,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{replay::RecordedResponse, types::MessageRole};
+
+    #[test]
+    fn test_prefill() {
+        let mut messages = vec![Message::user("Call a tool")];
+        prefill_messages(&mut messages, Some("{\"name\": \""), true);
+        assert_eq!(messages[1].role, MessageRole::Assistant);
+        assert_eq!(messages[1].content, "{\"name\": \"");
+        prefill_messages(&mut messages, Some(""), true);
+        assert_eq!(messages.len(), 2);
+
+        let mut messages = vec![Message::user("Call a tool")];
+        prefill_messages(&mut messages, Some("Thought:"), false);
+        assert_eq!(messages[1].role, MessageRole::User);
+        assert!(messages[1].content.ends_with("\nThought:"));
+
+        assert_eq!(
+            prepend_prefill("search\"}", "{\"name\": \"", false),
+            "{\"name\": \"search\"}"
+        );
+        assert_eq!(
+            prepend_prefill("\nThought: I will search", "Thought:", false),
+            "Thought: I will search"
+        );
+        assert_eq!(prepend_prefill("", "Thought:", true), "");
+
+        let response = PrefilledResponse::wrap(
+            Box::new(RecordedResponse {
+                content: Some(" I will search".to_string()),
+                tool_calls: vec![],
+                usage: None,
+                error: None,
+            }),
+            Some("Thought:"),
+        );
+        assert_eq!(response.get_response().unwrap(), "Thought: I will search");
+        assert_eq!(
+            Prefill::per_step(|step| (step > 1).then(|| "Thought:".to_string())).for_step(1),
+            None
+        );
+    }
+}
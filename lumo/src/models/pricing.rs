@@ -0,0 +1,83 @@
+//! Per-token prices of common hosted models, used to turn token usage into a dollar cost.
+//!
+//! Prices change often, so the table is only a default: pass your own [`ModelPricing`] when a model is missing or
+//! its price is out of date.
+
+use serde::{Deserialize, Serialize};
+
+use super::types::Usage;
+
+/// Price in US dollars per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPricing {
+    pub fn new(input_per_million: f64, output_per_million: f64) -> Self {
+        Self {
+            input_per_million,
+            output_per_million,
+        }
+    }
+
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.input_tokens as f64 * self.input_per_million
+            + usage.output_tokens as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Model id prefix, input price, output price. Longer prefixes must come before shorter ones that they start with.
+const PRICING_TABLE: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("o4-mini", 1.1, 4.4),
+    ("o3-mini", 1.1, 4.4),
+    ("o3", 2.0, 8.0),
+    ("o1-mini", 1.1, 4.4),
+    ("o1", 15.0, 60.0),
+    ("gemini-2.5-pro", 1.25, 10.0),
+    ("gemini-2.5-flash", 0.3, 2.5),
+    ("gemini-2.0-flash-lite", 0.075, 0.3),
+    ("gemini-2.0-flash", 0.1, 0.4),
+    ("gemini-1.5-pro", 1.25, 5.0),
+    ("gemini-1.5-flash", 0.075, 0.3),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("llama-3.3-70b-versatile", 0.59, 0.79),
+    ("llama-3.1-8b-instant", 0.05, 0.08),
+];
+
+/// Looks up the price of a model by its id. Provider prefixes such as `openai/` are ignored.
+pub fn pricing_for_model(model_id: &str) -> Option<ModelPricing> {
+    let model_id = model_id.rsplit('/').next().unwrap_or(model_id);
+    PRICING_TABLE
+        .iter()
+        .find(|(prefix, _, _)| model_id.starts_with(prefix))
+        .map(|(_, input, output)| ModelPricing::new(*input, *output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pricing_for_model() {
+        let pricing = pricing_for_model("openai/gpt-4o-mini-2024-07-18").unwrap();
+        assert_eq!(pricing, ModelPricing::new(0.15, 0.6));
+        assert_eq!(pricing_for_model("gpt-4o").unwrap().input_per_million, 2.5);
+        assert!(pricing_for_model("qwen2.5").is_none());
+
+        let cost = pricing.cost(&Usage::new(1_000_000, 500_000));
+        assert!((cost - 0.45).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,158 @@
+//! Differences between model families in how they return their reasoning and in which stop sequences they accept.
+//!
+//! Reasoning models either write their reasoning in `<think>` tags before the answer, which would otherwise end up
+//! in the memory of the agent and confuse the parsers, or they reject stop sequences altogether. The models detect
+//! their [`ModelFamily`] from the model id, strip the reasoning from the answer and, when the provider does not
+//! accept stop sequences, cut the answer at the first stop sequence themselves.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelFamily {
+    /// Answers directly and accepts stop sequences.
+    Standard,
+    /// The OpenAI o-series, such as `o1` and `o3-mini`. They reason internally and reject stop sequences.
+    OpenAIReasoning,
+    /// Writes its reasoning in `<think>` tags before the answer, such as DeepSeek-R1, QwQ and Qwen3.
+    ThinkTags,
+}
+
+impl ModelFamily {
+    /// Detects the family from a model id such as `o3-mini`, `deepseek-r1:14b` or `qwen/qwq-32b`.
+    pub fn from_model_id(model_id: &str) -> Self {
+        let id = model_id
+            .rsplit('/')
+            .next()
+            .unwrap_or(model_id)
+            .to_lowercase();
+        let is_o_series = ["o1", "o3", "o4"]
+            .iter()
+            .any(|prefix| id == *prefix || id.starts_with(&format!("{}-", prefix)));
+        if is_o_series {
+            ModelFamily::OpenAIReasoning
+        } else if ["deepseek-r1", "deepseek-reasoner", "qwq", "qwen3", "-r1"]
+            .iter()
+            .any(|marker| id.contains(marker))
+        {
+            ModelFamily::ThinkTags
+        } else {
+            ModelFamily::Standard
+        }
+    }
+
+    pub fn supports_stop(self) -> bool {
+        !matches!(self, ModelFamily::OpenAIReasoning)
+    }
+
+    /// The tags around the reasoning in the text of an answer, if the family writes its reasoning into the text.
+    pub fn reasoning_tags(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            ModelFamily::ThinkTags => Some(("<think>", "</think>")),
+            _ => None,
+        }
+    }
+
+    /// Splits the text of an answer into its reasoning and the answer itself. `client_stop` are stop sequences that
+    /// were not sent to the provider and are applied to the answer instead.
+    pub fn process(self, text: &str, client_stop: Option<&[String]>) -> (Option<String>, String) {
+        let (reasoning, answer) = match self.reasoning_tags() {
+            Some((open, close)) => split_reasoning(text, open, close),
+            None => (None, text.to_string()),
+        };
+        let answer = match client_stop {
+            Some(stop) => truncate_at_stop(&answer, stop).to_string(),
+            None => answer,
+        };
+        (reasoning, answer)
+    }
+}
+
+/// Splits the reasoning blocks between `open` and `close` from the rest of `text`. Text before a `close` without an
+/// `open` is reasoning, since some chat templates already put the opening tag into the prompt, and a block that is
+/// never closed, because the model ran out of tokens or hit a stop sequence, is reasoning until the end.
+pub fn split_reasoning(text: &str, open: &str, close: &str) -> (Option<String>, String) {
+    let mut reasoning = vec![];
+    let mut answer = String::new();
+    let mut rest = text;
+    if let Some(end) = rest.find(close) {
+        if !rest[..end].contains(open) {
+            reasoning.push(rest[..end].trim().to_string());
+            rest = &rest[end + close.len()..];
+        }
+    }
+    while let Some(start) = rest.find(open) {
+        answer.push_str(&rest[..start]);
+        let block = &rest[start + open.len()..];
+        match block.find(close) {
+            Some(end) => {
+                reasoning.push(block[..end].trim().to_string());
+                rest = &block[end + close.len()..];
+            }
+            None => {
+                reasoning.push(block.trim().to_string());
+                rest = "";
+            }
+        }
+    }
+    answer.push_str(rest);
+    reasoning.retain(|block| !block.is_empty());
+    let reasoning = (!reasoning.is_empty()).then(|| reasoning.join("\n\n"));
+    (reasoning, answer.trim().to_string())
+}
+
+/// Cuts `text` at the first of the `stop` sequences.
+pub fn truncate_at_stop<'a>(text: &'a str, stop: &[String]) -> &'a str {
+    let end = stop
+        .iter()
+        .filter(|sequence| !sequence.is_empty())
+        .filter_map(|sequence| text.find(sequence.as_str()))
+        .min()
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_family() {
+        assert_eq!(
+            ModelFamily::from_model_id("o3-mini"),
+            ModelFamily::OpenAIReasoning
+        );
+        assert_eq!(
+            ModelFamily::from_model_id("openai/o1"),
+            ModelFamily::OpenAIReasoning
+        );
+        assert_eq!(
+            ModelFamily::from_model_id("deepseek-r1:14b"),
+            ModelFamily::ThinkTags
+        );
+        assert_eq!(
+            ModelFamily::from_model_id("gpt-4o-mini"),
+            ModelFamily::Standard
+        );
+        assert!(!ModelFamily::OpenAIReasoning.supports_stop());
+    }
+
+    #[test]
+    fn test_strip_reasoning_and_stop() {
+        let (reasoning, answer) = ModelFamily::ThinkTags
+            .process("<think>\nLet me think.\n</think>\n\nThe answer is 4.", None);
+        assert_eq!(reasoning.as_deref(), Some("Let me think."));
+        assert_eq!(answer, "The answer is 4.");
+
+        let (reasoning, answer) =
+            split_reasoning("Opened by the template</think>42", "<think>", "</think>");
+        assert_eq!(reasoning.as_deref(), Some("Opened by the template"));
+        assert_eq!(answer, "42");
+
+        let (reasoning, answer) = split_reasoning("<think>Cut off", "<think>", "</think>");
+        assert_eq!(reasoning.as_deref(), Some("Cut off"));
+        assert_eq!(answer, "");
+
+        let stop = vec!["Observation:".to_string()];
+        let (_, answer) = ModelFamily::OpenAIReasoning
+            .process("Thought: search\nObservation: made up", Some(&stop));
+        assert_eq!(answer, "Thought: search\n");
+    }
+}
@@ -0,0 +1,296 @@
+//! Recording and replaying of model calls.
+//!
+//! [`RecordingModel`] wraps a model and writes every request and response to a cassette file. [`ReplayModel`]
+//! reads the cassette and answers the calls in the same order without contacting the provider, so that agent runs
+//! can be tested deterministically and bug reports can be reproduced without API keys.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{AgentError, ModelError},
+    models::{
+        info::ModelInfo,
+        model_traits::{Model, ModelResponse},
+        openai::ToolCall,
+        tokenizer::Tokenizer,
+        types::{GenerationConfig, Message, Usage},
+    },
+    tools::ToolInfo,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<Message>>,
+    /// The names of the tools given to the model.
+    #[serde(default)]
+    pub tools: Vec<String>,
+    #[serde(default)]
+    pub config: GenerationConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// The error the model call failed with, if it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ModelResponse for RecordedResponse {
+    fn get_response(&self) -> Result<String, AgentError> {
+        Ok(self.content.clone().unwrap_or_default())
+    }
+
+    fn get_tools_used(&self) -> Result<Vec<ToolCall>, AgentError> {
+        Ok(self.tool_calls.clone())
+    }
+
+    fn get_usage(&self) -> Option<Usage> {
+        self.usage
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// The model calls of one or more runs, in the order they were made.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read cassette: {:?}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse cassette: {:?}", path))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write cassette: {:?}", path))
+    }
+}
+
+/// Wraps a model and saves every call to a cassette file. The file is rewritten after each call, so a run that
+/// crashes still leaves the calls made so far.
+#[derive(Debug)]
+pub struct RecordingModel<M: Model> {
+    model: M,
+    path: PathBuf,
+    cassette: Mutex<Cassette>,
+}
+
+impl<M: Model> RecordingModel<M> {
+    /// Starts a new cassette at `path`, replacing the file if it exists.
+    pub fn new(model: M, path: impl Into<PathBuf>) -> Self {
+        Self {
+            model,
+            path: path.into(),
+            cassette: Mutex::new(Cassette::default()),
+        }
+    }
+
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+
+    pub fn into_inner(self) -> M {
+        self.model
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<M: Model> Model for RecordingModel<M> {
+    fn info(&self) -> ModelInfo {
+        self.model.info()
+    }
+
+    fn tokenizer(&self) -> Arc<dyn Tokenizer> {
+        self.model.tokenizer()
+    }
+
+    async fn run(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let request = RecordedRequest {
+            messages: input_messages.clone(),
+            history: history.clone(),
+            tools: tools
+                .iter()
+                .map(|tool| tool.function.name.clone())
+                .collect(),
+            config: config.clone(),
+        };
+        let result = self.model.run(input_messages, history, tools, config).await;
+        let response = match &result {
+            Ok(response) => RecordedResponse {
+                content: response.get_response().ok(),
+                tool_calls: response.get_tools_used().unwrap_or_default(),
+                usage: response.get_usage(),
+                error: None,
+            },
+            Err(e) => RecordedResponse {
+                content: None,
+                tool_calls: vec![],
+                usage: None,
+                error: Some(e.to_string()),
+            },
+        };
+
+        let cassette = {
+            let mut cassette = self.cassette.lock().unwrap();
+            cassette
+                .interactions
+                .push(Interaction { request, response });
+            cassette.clone()
+        };
+        if let Err(e) = cassette.save(&self.path) {
+            tracing::warn!(error = %e, "Could not save the cassette");
+        }
+        result
+    }
+}
+
+/// Answers model calls from a cassette, in the order they were recorded. The requests are not compared with the
+/// recorded ones, so a replay only reproduces a run as long as the agent makes the same calls.
+#[derive(Debug)]
+pub struct ReplayModel {
+    cassette: Cassette,
+    position: AtomicUsize,
+}
+
+impl ReplayModel {
+    pub fn new(cassette: Cassette) -> Self {
+        Self {
+            cassette,
+            position: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(Cassette::load(path)?))
+    }
+
+    /// The number of recorded calls that have not been served yet.
+    pub fn remaining(&self) -> usize {
+        self.cassette
+            .interactions
+            .len()
+            .saturating_sub(self.position.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for ReplayModel {
+    async fn run(
+        &self,
+        _input_messages: Vec<Message>,
+        _history: Option<Vec<Message>>,
+        _tools: Vec<ToolInfo>,
+        _config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let position = self.position.fetch_add(1, Ordering::SeqCst);
+        let interaction = self.cassette.interactions.get(position).ok_or_else(|| {
+            ModelError::new(
+                "replay",
+                format!(
+                    "The cassette has no response left for model call {}, it only has {}",
+                    position + 1,
+                    self.cassette.interactions.len()
+                ),
+            )
+        })?;
+        match &interaction.response.error {
+            Some(error) => Err(ModelError::new("replay", error.clone()).into()),
+            None => Ok(Box::new(interaction.response.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::types::MessageRole;
+
+    struct EchoModel;
+
+    #[async_trait]
+    impl Model for EchoModel {
+        async fn run(
+            &self,
+            input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            _config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            Ok(Box::new(RecordedResponse {
+                content: Some(input_messages[0].content.clone()),
+                tool_calls: vec![],
+                usage: Some(Usage::new(3, 1)),
+                error: None,
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("lumo_cassette_{}.json", nanoid::nanoid!(8)));
+        let model = RecordingModel::new(EchoModel, &path);
+        for text in ["first", "second"] {
+            model
+                .run(
+                    vec![Message::new(MessageRole::User, text)],
+                    None,
+                    vec![],
+                    GenerationConfig::default(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let replay = ReplayModel::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replay.remaining(), 2);
+        for expected in ["first", "second"] {
+            let response = replay
+                .run(vec![], None, vec![], GenerationConfig::default())
+                .await
+                .unwrap();
+            assert_eq!(response.get_response().unwrap(), expected);
+            assert_eq!(response.get_usage(), Some(Usage::new(3, 1)));
+        }
+        assert!(replay
+            .run(vec![], None, vec![], GenerationConfig::default())
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,684 @@
+//! Streaming model responses. [`Model::run_stream`](crate::models::model_traits::Model::run_stream) returns the
+//! answer as a stream of [`ChatChunk`]s, the same for every provider: text and reasoning as they are generated, the
+//! fragments of tool calls as they arrive, each tool call once it is complete, and the usage at the end.
+//!
+//! OpenAI-style providers stream the name and arguments of a tool call in fragments, spread over many chunks and
+//! interleaved between calls by their index. A [`ToolCallAccumulator`] assembles the fragments into complete
+//! [`ToolCall`]s. Providers that send tool calls whole only send [`ChatChunk::ToolCall`].
+
+use std::collections::BTreeMap;
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+use opentelemetry::{
+    global::BoxedSpan,
+    trace::{Span, Status},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    errors::{AgentError, ModelError},
+    models::{
+        model_traits::{Model, ModelResponse},
+        openai::{FunctionCall, OpenAIUsage, ToolCall},
+        reasoning::ModelFamily,
+        types::{GenerationConfig, Message, Usage},
+    },
+    telemetry::output_attributes,
+    tools::ToolInfo,
+};
+
+#[derive(Debug, Clone)]
+pub enum ChatChunk {
+    /// A piece of the text of the answer.
+    TextDelta(String),
+    /// A piece of the reasoning of a reasoning model that returns it apart from the answer.
+    ReasoningDelta(String),
+    /// A fragment of a tool call, to show the call as it is written. The complete call follows as a
+    /// [`ChatChunk::ToolCall`].
+    ToolCallDelta(ToolCallDelta),
+    /// A tool call whose name and arguments are complete.
+    ToolCall(ToolCall),
+    /// The tokens used by the call, if the provider reports them. Sent at the end of the stream.
+    Usage(Usage),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type ModelStream = Pin<Box<dyn Stream<Item = Result<ChatChunk, AgentError>> + Send>>;
+/// The response body of the fetch-based client of the browser is not `Send`.
+#[cfg(target_arch = "wasm32")]
+pub type ModelStream = Pin<Box<dyn Stream<Item = Result<ChatChunk, AgentError>>>>;
+
+/// Where an agent sends the chunks of its model calls during a streamed run, see
+/// [`AgentStream::stream_events`](crate::agent::AgentStream::stream_events).
+pub type ChunkSink = futures::channel::mpsc::UnboundedSender<ChatChunk>;
+
+/// A fragment of a tool call in a streamed chunk. The first fragment of a call has its id and name, the following
+/// ones only carry more of the arguments.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ToolCallDelta {
+    #[serde(default)]
+    pub index: usize,
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub call_type: Option<String>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: Option<String>,
+    call_type: Option<String>,
+    name: String,
+    arguments: String,
+}
+
+impl PartialToolCall {
+    fn finish(self) -> ToolCall {
+        let arguments = if self.arguments.trim().is_empty() {
+            Value::Object(Default::default())
+        } else {
+            // Arguments that are not valid JSON are kept as a string, like in a response that is not streamed,
+            // so that the agent can show the model its mistake.
+            serde_json::from_str(&self.arguments).unwrap_or(Value::String(self.arguments))
+        };
+        ToolCall {
+            id: Some(
+                self.id
+                    .filter(|id| !id.is_empty())
+                    .unwrap_or_else(|| nanoid::nanoid!(16)),
+            ),
+            call_type: Some(self.call_type.unwrap_or_else(|| "function".to_string())),
+            function: FunctionCall {
+                name: self.name,
+                arguments,
+            },
+        }
+    }
+}
+
+/// Assembles streamed tool call fragments into complete tool calls.
+///
+/// Most providers send the calls one after the other, so a call is complete once a fragment of a call with a higher
+/// index arrives and its arguments are whole JSON. Some providers interleave the fragments of the calls, so a call
+/// whose arguments are still cut off stays open, and so do the calls after it, to keep the calls in order. The calls
+/// that are still open when the stream ends are returned by [`finish`](Self::finish).
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    calls: BTreeMap<usize, PartialToolCall>,
+}
+
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a fragment and returns the calls it completed.
+    pub fn push(&mut self, delta: ToolCallDelta) -> Vec<ToolCall> {
+        let completed = self.take_before(delta.index);
+        let call = self.calls.entry(delta.index).or_default();
+        if let Some(id) = delta.id.filter(|id| !id.is_empty()) {
+            call.id = Some(id);
+        }
+        if let Some(call_type) = delta.call_type {
+            call.call_type = Some(call_type);
+        }
+        if let Some(function) = delta.function {
+            if let Some(name) = function.name {
+                call.name.push_str(&name);
+            }
+            if let Some(arguments) = function.arguments {
+                call.arguments.push_str(&arguments);
+            }
+        }
+        completed
+    }
+
+    /// Whether a call is still being assembled.
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Returns the calls that are still open, in the order of their index.
+    pub fn finish(&mut self) -> Vec<ToolCall> {
+        std::mem::take(&mut self.calls)
+            .into_values()
+            .map(PartialToolCall::finish)
+            .collect()
+    }
+
+    fn take_before(&mut self, index: usize) -> Vec<ToolCall> {
+        let complete = self
+            .calls
+            .range(..index)
+            .take_while(|(_, call)| {
+                serde_json::from_str::<Value>(&call.arguments).is_ok_and(|value| value.is_object())
+            })
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+        complete
+            .into_iter()
+            .filter_map(|index| self.calls.remove(&index))
+            .map(PartialToolCall::finish)
+            .collect()
+    }
+}
+
+/// Takes the first complete line out of `buffer`. The bytes are only decoded once the line is complete, since a
+/// chunk can end in the middle of a character.
+fn take_line(buffer: &mut Vec<u8>) -> Option<String> {
+    let end = buffer.iter().position(|byte| *byte == b'\n')?;
+    let line = buffer.drain(..=end).collect::<Vec<_>>();
+    Some(String::from_utf8_lossy(&line[..end]).into_owned())
+}
+
+/// Splits a server-sent event stream into the payloads of its `data:` lines. Chunks can end in the middle of a
+/// line, so the incomplete rest is kept until the next chunk.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut data = vec![];
+        while let Some(line) = take_line(&mut self.buffer) {
+            if let Some(payload) = line.trim_end_matches('\r').strip_prefix("data:") {
+                data.push(payload.trim_start().to_string());
+            }
+        }
+        data
+    }
+}
+
+/// Splits a stream of newline-delimited JSON, as sent by Ollama, into its lines.
+#[derive(Debug, Default)]
+pub struct JsonLinesDecoder {
+    buffer: Vec<u8>,
+}
+
+impl JsonLinesDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut lines = vec![];
+        while let Some(line) = take_line(&mut self.buffer) {
+            let line = line.trim();
+            if !line.is_empty() {
+                lines.push(line.to_string());
+            }
+        }
+        lines
+    }
+
+    /// The last line, when the stream does not end with a newline.
+    pub fn finish(&mut self) -> Option<String> {
+        let buffer = std::mem::take(&mut self.buffer);
+        let line = String::from_utf8_lossy(&buffer).trim().to_string();
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIStreamChunk {
+    #[serde(default)]
+    choices: Vec<OpenAIStreamChoice>,
+    #[serde(default)]
+    usage: Option<OpenAIUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIStreamChoice {
+    #[serde(default)]
+    delta: OpenAIStreamDelta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct OpenAIStreamDelta {
+    content: Option<String>,
+    #[serde(default, alias = "reasoning")]
+    reasoning_content: Option<String>,
+    tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+/// Turns a streamed chat completions response (`"stream": true`) into chat chunks.
+pub(crate) fn openai_event_stream(
+    provider: String,
+    mut response: reqwest::Response,
+) -> ModelStream {
+    let stream = async_stream::stream! {
+        let mut decoder = SseDecoder::new();
+        let mut accumulator = ToolCallAccumulator::new();
+        let mut usage = None;
+        'read: loop {
+            let chunk = match response.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(e) => {
+                    let message = format!("Failed to read the stream from {}: {}", provider, e);
+                    yield Err(ModelError::from_request(&provider, message, e).into());
+                    return;
+                }
+            };
+            for data in decoder.push(&chunk) {
+                if data == "[DONE]" {
+                    break 'read;
+                }
+                let chunk = match serde_json::from_str::<OpenAIStreamChunk>(&data) {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        let message =
+                            format!("Failed to parse the stream from {}: {}", provider, e);
+                        yield Err(ModelError::new(&provider, message).into());
+                        return;
+                    }
+                };
+                if let Some(chunk_usage) = chunk.usage {
+                    usage = Some(chunk_usage.to_usage());
+                }
+                for choice in chunk.choices {
+                    let delta = choice.delta;
+                    if let Some(reasoning) = delta.reasoning_content.filter(|r| !r.is_empty()) {
+                        yield Ok(ChatChunk::ReasoningDelta(reasoning));
+                    }
+                    if let Some(content) = delta.content.filter(|c| !c.is_empty()) {
+                        yield Ok(ChatChunk::TextDelta(content));
+                    }
+                    for delta in delta.tool_calls.unwrap_or_default() {
+                        yield Ok(ChatChunk::ToolCallDelta(delta.clone()));
+                        for tool_call in accumulator.push(delta) {
+                            yield Ok(ChatChunk::ToolCall(tool_call));
+                        }
+                    }
+                }
+            }
+        }
+        for tool_call in accumulator.finish() {
+            yield Ok(ChatChunk::ToolCall(tool_call));
+        }
+        if let Some(usage) = usage {
+            yield Ok(ChatChunk::Usage(usage));
+        }
+    };
+    Box::pin(stream)
+}
+
+/// Applies [`ModelFamily::process`] to the text of a stream: the reasoning a model writes into its text is sent as
+/// [`ChatChunk::ReasoningDelta`]s, and the text ends at the first of `client_stop`, the stop sequences the provider
+/// was not given. Streams that need neither are returned as they are.
+pub(crate) fn process_stream(
+    mut stream: ModelStream,
+    family: ModelFamily,
+    client_stop: Option<Vec<String>>,
+) -> ModelStream {
+    if family.reasoning_tags().is_none() && client_stop.is_none() {
+        return stream;
+    }
+    let mut processor = TextProcessor {
+        family,
+        client_stop,
+        text: String::new(),
+        reasoning: String::new(),
+        answer: String::new(),
+    };
+    Box::pin(async_stream::stream! {
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(ChatChunk::TextDelta(text)) => {
+                    for chunk in processor.push(&text) {
+                        yield Ok(chunk);
+                    }
+                }
+                chunk => yield chunk,
+            }
+        }
+        for chunk in processor.finish() {
+            yield Ok(chunk);
+        }
+    })
+}
+
+/// The text of a stream so far, and how much of its reasoning and answer was sent.
+struct TextProcessor {
+    family: ModelFamily,
+    client_stop: Option<Vec<String>>,
+    text: String,
+    reasoning: String,
+    answer: String,
+}
+
+impl TextProcessor {
+    fn push(&mut self, text: &str) -> Vec<ChatChunk> {
+        self.text.push_str(text);
+        if let Some((open, close)) = self.family.reasoning_tags() {
+            // Text before a closing tag without an opening one is reasoning, so the text waits for either tag
+            if !self.text.contains(open) && !self.text.contains(close) {
+                return vec![];
+            }
+        }
+        // The end of the text is held back while it could be the start of a tag or of a stop sequence
+        let tags = self
+            .family
+            .reasoning_tags()
+            .into_iter()
+            .flat_map(|(open, close)| [open, close]);
+        let stop = self.client_stop.iter().flatten().map(String::as_str);
+        let held = tags
+            .chain(stop)
+            .map(|pattern| partial_match(&self.text, pattern))
+            .max()
+            .unwrap_or(0);
+        let end = self.text.len() - held;
+        let text = self.text[..end].to_string();
+        self.process(&text)
+    }
+
+    fn finish(&mut self) -> Vec<ChatChunk> {
+        let text = std::mem::take(&mut self.text);
+        self.process(&text)
+    }
+
+    fn process(&mut self, text: &str) -> Vec<ChatChunk> {
+        let (reasoning, answer) = self.family.process(text, self.client_stop.as_deref());
+        let mut chunks = vec![];
+        if let Some(reasoning) =
+            reasoning.and_then(|reasoning| new_part(&mut self.reasoning, reasoning))
+        {
+            chunks.push(ChatChunk::ReasoningDelta(reasoning));
+        }
+        if let Some(answer) = new_part(&mut self.answer, answer) {
+            chunks.push(ChatChunk::TextDelta(answer));
+        }
+        chunks
+    }
+}
+
+/// The length of the longest start of `pattern` that `text` ends with, without the whole of it.
+fn partial_match(text: &str, pattern: &str) -> usize {
+    (1..pattern.len())
+        .rev()
+        .find(|len| pattern.is_char_boundary(*len) && text.ends_with(&pattern[..*len]))
+        .unwrap_or(0)
+}
+
+/// The part of `text` that was not sent yet, if `text` goes on from what was sent.
+fn new_part(sent: &mut String, text: String) -> Option<String> {
+    let part = text.strip_prefix(sent.as_str())?.to_string();
+    *sent = text;
+    (!part.is_empty()).then_some(part)
+}
+
+/// Ends `span` when the stream ends, with the text and the tool calls of the response as its output.
+pub(crate) fn traced_stream(mut stream: ModelStream, mut span: BoxedSpan) -> ModelStream {
+    Box::pin(async_stream::stream! {
+        let mut response = StreamedResponse::default();
+        while let Some(chunk) = stream.next().await {
+            match &chunk {
+                Ok(chunk) => response.push(chunk.clone()),
+                Err(e) => span.set_status(Status::error(e.to_string())),
+            }
+            yield chunk;
+        }
+        span.set_attributes(output_attributes(
+            serde_json::to_string_pretty(&json!({
+                "content": response.text,
+                "tool_calls": response.tool_calls,
+            }))
+            .unwrap(),
+        ));
+        span.end_with_timestamp(crate::runtime::now());
+    })
+}
+
+/// Streams the answer of `model`, sends each chunk to `sink` as it arrives and returns the whole response.
+pub async fn run_into_sink(
+    model: &dyn Model,
+    sink: &ChunkSink,
+    messages: Vec<Message>,
+    history: Option<Vec<Message>>,
+    tools: Vec<ToolInfo>,
+    config: GenerationConfig,
+) -> Result<Box<dyn ModelResponse>, AgentError> {
+    let mut stream = model.run_stream(messages, history, tools, config).await?;
+    let mut response = StreamedResponse::default();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        // The run may have stopped listening, the response is still needed
+        let _ = sink.unbounded_send(chunk.clone());
+        response.push(chunk);
+    }
+    Ok(Box::new(response))
+}
+
+/// The chunks of a response that was not streamed, for models that do not stream.
+pub fn response_events(response: &dyn ModelResponse) -> Result<Vec<ChatChunk>, AgentError> {
+    let mut chunks = vec![];
+    if let Some(reasoning) = response.get_reasoning() {
+        chunks.push(ChatChunk::ReasoningDelta(reasoning));
+    }
+    let text = response.get_response()?;
+    if !text.is_empty() {
+        chunks.push(ChatChunk::TextDelta(text));
+    }
+    for tool_call in response.get_tools_used()? {
+        chunks.push(ChatChunk::ToolCall(tool_call));
+    }
+    if let Some(usage) = response.get_usage() {
+        chunks.push(ChatChunk::Usage(usage));
+    }
+    Ok(chunks)
+}
+
+/// A streamed response collected into a whole, so that it can be used wherever a [`ModelResponse`] is expected.
+#[derive(Debug, Clone, Default)]
+pub struct StreamedResponse {
+    pub text: String,
+    pub reasoning: Option<String>,
+    pub tool_calls: Vec<ToolCall>,
+    pub usage: Option<Usage>,
+}
+
+impl StreamedResponse {
+    pub fn push(&mut self, chunk: ChatChunk) {
+        match chunk {
+            ChatChunk::TextDelta(text) => self.text.push_str(&text),
+            ChatChunk::ReasoningDelta(reasoning) => self
+                .reasoning
+                .get_or_insert_with(String::new)
+                .push_str(&reasoning),
+            ChatChunk::ToolCallDelta(_) => {}
+            ChatChunk::ToolCall(tool_call) => self.tool_calls.push(tool_call),
+            ChatChunk::Usage(usage) => self.usage = Some(usage),
+        }
+    }
+
+    /// Reads the stream to its end.
+    pub async fn collect(mut stream: ModelStream) -> Result<Self, AgentError> {
+        let mut response = Self::default();
+        while let Some(chunk) = stream.next().await {
+            response.push(chunk?);
+        }
+        Ok(response)
+    }
+}
+
+impl ModelResponse for StreamedResponse {
+    fn get_response(&self) -> Result<String, AgentError> {
+        Ok(self.text.clone())
+    }
+
+    fn get_tools_used(&self) -> Result<Vec<ToolCall>, AgentError> {
+        Ok(self.tool_calls.clone())
+    }
+
+    fn get_usage(&self) -> Option<Usage> {
+        self.usage
+    }
+
+    fn get_reasoning(&self) -> Option<String> {
+        self.reasoning.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn delta(value: Value) -> ToolCallDelta {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_tool_call_accumulator() {
+        let mut accumulator = ToolCallAccumulator::new();
+        let fragments = [
+            json!({"index": 0, "id": "call_1", "type": "function", "function": {"name": "search", "arguments": ""}}),
+            json!({"index": 0, "function": {"arguments": "{\"query\": "}}),
+            json!({"index": 0, "function": {"arguments": "\"rust\"}"}}),
+            json!({"index": 1, "id": "call_2", "function": {"name": "final_answer"}}),
+        ];
+        let mut completed = vec![];
+        for fragment in fragments {
+            completed.extend(accumulator.push(delta(fragment)));
+        }
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].id.as_deref(), Some("call_1"));
+        assert_eq!(completed[0].function.name, "search");
+        assert_eq!(completed[0].function.arguments, json!({"query": "rust"}));
+
+        let open = accumulator.finish();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].function.name, "final_answer");
+        assert_eq!(open[0].function.arguments, json!({}));
+        assert!(accumulator.is_empty());
+    }
+
+    #[test]
+    fn test_interleaved_tool_calls() {
+        let mut accumulator = ToolCallAccumulator::new();
+        let fragments = [
+            json!({"index": 0, "id": "call_1", "function": {"name": "search", "arguments": "{\"query\": "}}),
+            json!({"index": 1, "id": "call_2", "function": {"name": "search", "arguments": "{\"query\": "}}),
+            json!({"index": 0, "function": {"arguments": "\"rust\"}"}}),
+            json!({"index": 1, "function": {"arguments": "\"wasm\"}"}}),
+        ];
+        let mut completed = vec![];
+        for fragment in fragments {
+            completed.extend(accumulator.push(delta(fragment)));
+        }
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].id.as_deref(), Some("call_1"));
+        assert_eq!(completed[0].function.arguments, json!({"query": "rust"}));
+
+        let open = accumulator.finish();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].id.as_deref(), Some("call_2"));
+        assert_eq!(open[0].function.arguments, json!({"query": "wasm"}));
+    }
+
+    async fn processed(
+        family: ModelFamily,
+        stop: Option<Vec<String>>,
+        text: &[&str],
+    ) -> Vec<ChatChunk> {
+        let chunks = text
+            .iter()
+            .map(|text| Ok(ChatChunk::TextDelta(text.to_string())))
+            .collect::<Vec<_>>();
+        process_stream(Box::pin(futures::stream::iter(chunks)), family, stop)
+            .map(Result::unwrap)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_process_stream() {
+        let chunks = processed(
+            ModelFamily::ThinkTags,
+            None,
+            &[
+                "<thi",
+                "nk>Let me",
+                " think.</th",
+                "ink>\n\nThe answer",
+                " is 4.",
+            ],
+        )
+        .await;
+        let response = chunks
+            .iter()
+            .fold(StreamedResponse::default(), |mut response, chunk| {
+                response.push(chunk.clone());
+                response
+            });
+        assert_eq!(response.reasoning.as_deref(), Some("Let me think."));
+        assert_eq!(response.text, "The answer is 4.");
+        assert!(
+            chunks
+                .iter()
+                .filter(|chunk| matches!(chunk, ChatChunk::TextDelta(_)))
+                .count()
+                > 1
+        );
+
+        let stop = vec!["Observation:".to_string()];
+        let chunks = processed(
+            ModelFamily::OpenAIReasoning,
+            Some(stop),
+            &["Thought: search\nObser", "vation: made", " up"],
+        )
+        .await;
+        let text = chunks
+            .iter()
+            .map(|chunk| match chunk {
+                ChatChunk::TextDelta(text) => text.as_str(),
+                _ => "",
+            })
+            .collect::<String>();
+        assert_eq!(text, "Thought: search\n");
+    }
+
+    #[test]
+    fn test_sse_decoder() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.push(b"data: {\"a\"").is_empty());
+        assert_eq!(
+            decoder.push(b": 1}\r\n\r\n: comment\ndata: [DONE]\n"),
+            vec!["{\"a\": 1}", "[DONE]"]
+        );
+
+        let text = "data: caf\u{e9}\n".as_bytes();
+        let (start, end) = text.split_at(10);
+        assert!(decoder.push(start).is_empty());
+        assert_eq!(decoder.push(end), vec!["caf\u{e9}"]);
+    }
+
+    #[test]
+    fn test_json_lines_decoder() {
+        let mut decoder = JsonLinesDecoder::new();
+        assert_eq!(decoder.push(b"{\"a\": 1}\n\n{\"b\""), vec!["{\"a\": 1}"]);
+        assert_eq!(decoder.push(b": 2}").len(), 0);
+        assert_eq!(decoder.finish().as_deref(), Some("{\"b\": 2}"));
+
+        let line = "{\"content\": \"café\"}\n".as_bytes();
+        let (start, end) = line.split_at(17);
+        assert_eq!(decoder.push(start).len(), 0);
+        assert_eq!(decoder.push(end), vec!["{\"content\": \"café\"}"]);
+    }
+}
@@ -0,0 +1,261 @@
+//! A model for unit tests that answers from a script.
+//!
+//! [`ScriptedModel`] returns the responses it was given, in order, and records every call it receives with its
+//! messages, tool schemas and generation config. Tests of agent wiring, hooks or custom tools can then run a real
+//! agent without a provider and check what the agent sent.
+//!
+//! ```rust,ignore
+//! let model = ScriptedModel::new()
+//!     .with_tool_call("search", json!({"query": "population of Eindhoven"}))
+//!     .with_final_answer("About 240,000");
+//! let mut agent = FunctionCallingAgentBuilder::new(model.clone())
+//!     .with_tools(vec![Box::new(SearchTool::new())])
+//!     .build()?;
+//! assert_eq!(agent.run("What is the population of Eindhoven?", true).await?, "About 240,000");
+//! assert!(model.calls()[0].tool_names().contains(&"search".to_string()));
+//! ```
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+use crate::{
+    errors::{AgentError, ModelError},
+    models::{
+        model_traits::{Model, ModelResponse},
+        openai::{FunctionCall, ToolCall},
+        replay::RecordedResponse,
+        types::{GenerationConfig, Message, Usage},
+    },
+    tools::ToolInfo,
+};
+
+/// A call received by a [`ScriptedModel`].
+#[derive(Debug, Clone)]
+pub struct ReceivedCall {
+    pub messages: Vec<Message>,
+    pub history: Option<Vec<Message>>,
+    pub tools: Vec<ToolInfo>,
+    pub config: GenerationConfig,
+}
+
+impl ReceivedCall {
+    pub fn tool_names(&self) -> Vec<String> {
+        self.tools
+            .iter()
+            .map(|tool| tool.function.name.clone())
+            .collect()
+    }
+
+    /// The text of the last message, which is usually the task or the latest observation.
+    pub fn last_message(&self) -> Option<&str> {
+        self.messages.last().map(|message| message.content.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    responses: VecDeque<RecordedResponse>,
+    calls: Vec<ReceivedCall>,
+    usage: Option<Usage>,
+    tool_calls: usize,
+}
+
+/// Answers model calls with a fixed sequence of responses. Clones share the script and the recorded calls, so a test
+/// can keep a clone to inspect after giving the model to an agent.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedModel {
+    script: Arc<Mutex<Script>>,
+}
+
+impl ScriptedModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a response to the end of the script.
+    pub fn with(self, response: RecordedResponse) -> Self {
+        self.script.lock().unwrap().responses.push_back(response);
+        self
+    }
+
+    /// Adds a text response.
+    pub fn with_response(self, text: &str) -> Self {
+        self.with(RecordedResponse {
+            content: Some(text.to_string()),
+            tool_calls: vec![],
+            usage: None,
+            error: None,
+        })
+    }
+
+    /// Adds a response with one native tool call.
+    pub fn with_tool_call(self, name: &str, arguments: Value) -> Self {
+        self.with_tool_calls(&[(name, arguments)])
+    }
+
+    /// Adds a response with several native tool calls, which the agent makes in the same step.
+    pub fn with_tool_calls(self, calls: &[(&str, Value)]) -> Self {
+        let tool_calls = {
+            let mut script = self.script.lock().unwrap();
+            calls
+                .iter()
+                .map(|(name, arguments)| {
+                    script.tool_calls += 1;
+                    ToolCall {
+                        id: Some(format!("call_{}", script.tool_calls)),
+                        call_type: Some("function".to_string()),
+                        function: FunctionCall {
+                            name: name.to_string(),
+                            arguments: arguments.clone(),
+                        },
+                    }
+                })
+                .collect()
+        };
+        self.with(RecordedResponse {
+            content: None,
+            tool_calls,
+            usage: None,
+            error: None,
+        })
+    }
+
+    /// Adds a call of the `final_answer` tool.
+    pub fn with_final_answer(self, answer: &str) -> Self {
+        self.with_tool_call("final_answer", json!({ "answer": answer }))
+    }
+
+    /// Adds a failed call, which returns a [`ModelError`] with this message.
+    pub fn with_error(self, message: &str) -> Self {
+        self.with(RecordedResponse {
+            content: None,
+            tool_calls: vec![],
+            usage: None,
+            error: Some(message.to_string()),
+        })
+    }
+
+    /// The usage reported by the responses that do not have their own.
+    pub fn with_usage(self, usage: Usage) -> Self {
+        self.script.lock().unwrap().usage = Some(usage);
+        self
+    }
+
+    /// The calls received so far, in order.
+    pub fn calls(&self) -> Vec<ReceivedCall> {
+        self.script.lock().unwrap().calls.clone()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.script.lock().unwrap().calls.len()
+    }
+
+    pub fn last_call(&self) -> Option<ReceivedCall> {
+        self.script.lock().unwrap().calls.last().cloned()
+    }
+
+    /// The number of responses that have not been returned yet.
+    pub fn remaining(&self) -> usize {
+        self.script.lock().unwrap().responses.len()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Model for ScriptedModel {
+    async fn run(
+        &self,
+        input_messages: Vec<Message>,
+        history: Option<Vec<Message>>,
+        tools: Vec<ToolInfo>,
+        config: GenerationConfig,
+    ) -> Result<Box<dyn ModelResponse>, AgentError> {
+        let mut script = self.script.lock().unwrap();
+        script.calls.push(ReceivedCall {
+            messages: input_messages,
+            history,
+            tools,
+            config,
+        });
+        let call = script.calls.len();
+        let mut response = script.responses.pop_front().ok_or_else(|| {
+            ModelError::new(
+                "scripted",
+                format!("The script has no response left for model call {}", call),
+            )
+        })?;
+        if let Some(error) = response.error {
+            return Err(ModelError::new("scripted", error).into());
+        }
+        response.usage = response.usage.or(script.usage);
+        Ok(Box::new(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{ToolFunctionInfo, ToolType};
+
+    #[tokio::test]
+    async fn test_scripted_model() {
+        let model = ScriptedModel::new()
+            .with_tool_call("search", json!({"query": "Eindhoven"}))
+            .with_error("rate limited")
+            .with_final_answer("About 240,000")
+            .with_usage(Usage::new(10, 2));
+        let handle = model.clone();
+        let tools = vec![ToolInfo {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo {
+                name: "search".to_string(),
+                description: "Searches the web".to_string(),
+                parameters: json!({"type": "object"}),
+            },
+        }];
+
+        let response = model
+            .run(
+                vec![Message::user("Population of Eindhoven?")],
+                None,
+                tools,
+                GenerationConfig::new().with_max_tokens(100),
+            )
+            .await
+            .unwrap();
+        let tool_calls = response.get_tools_used().unwrap();
+        assert_eq!(tool_calls[0].function.name, "search");
+        assert_eq!(tool_calls[0].id.as_deref(), Some("call_1"));
+        assert_eq!(response.get_usage(), Some(Usage::new(10, 2)));
+
+        let error = model
+            .run(vec![], None, vec![], GenerationConfig::default())
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("rate limited"));
+        let response = model
+            .run(vec![], None, vec![], GenerationConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            response.get_tools_used().unwrap()[0].function.arguments["answer"],
+            "About 240,000"
+        );
+        assert!(model
+            .run(vec![], None, vec![], GenerationConfig::default())
+            .await
+            .is_err());
+
+        let calls = handle.calls();
+        assert_eq!(handle.call_count(), 4);
+        assert_eq!(handle.remaining(), 0);
+        assert_eq!(calls[0].tool_names(), vec!["search"]);
+        assert_eq!(calls[0].last_message(), Some("Population of Eindhoven?"));
+        assert_eq!(calls[0].config.max_tokens, Some(100));
+        assert_eq!(calls[0].tools[0].function.parameters["type"], "object");
+    }
+}
@@ -0,0 +1,239 @@
+//! Token counts of messages, used to keep the memory of an agent within the context window of its model.
+//!
+//! OpenAI models are counted with their own encodings when the `tiktoken` feature is enabled. Other models, and
+//! OpenAI models without the feature, are counted at about four characters per token, which is close for English text
+//! and code but may be off by a fair amount for other languages.
+
+use std::sync::Arc;
+
+use super::types::{ContentPart, Message, MessageRole};
+
+#[cfg(feature = "tiktoken")]
+use std::sync::OnceLock;
+
+/// The tokens that every message adds for its role and delimiters.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// The tokens of an image part. Providers count images by their size, which is not known here, so this is the count of
+/// a mid-sized image.
+const IMAGE_TOKENS: usize = 765;
+
+/// The content of a message that was removed to fit the context window.
+const COMPACTED: &str = "[Removed to fit the context window]";
+
+/// The end of a message that was cut short to fit the context window.
+const CUT_SHORT: &str = "\n[Cut short to fit the context window]";
+
+/// The messages at the end of the memory that are never compacted, which are the latest step of the agent.
+const KEEP_LAST: usize = 2;
+
+pub trait Tokenizer: Send + Sync {
+    fn count_tokens(&self, text: &str) -> usize;
+
+    fn count_message_tokens(&self, message: &Message) -> usize {
+        let tool_calls = message
+            .tool_calls
+            .as_ref()
+            .map(|tool_calls| {
+                self.count_tokens(&serde_json::to_string(tool_calls).unwrap_or_default())
+            })
+            .unwrap_or(0);
+        let parts = message
+            .parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => self.count_tokens(text),
+                ContentPart::ImageUrl { .. } => IMAGE_TOKENS,
+            })
+            .sum::<usize>();
+        MESSAGE_OVERHEAD + self.count_tokens(&message.content) + tool_calls + parts
+    }
+
+    fn count_messages_tokens(&self, messages: &[Message]) -> usize {
+        messages
+            .iter()
+            .map(|message| self.count_message_tokens(message))
+            .sum()
+    }
+}
+
+/// Counts tokens from the length of the text.
+#[derive(Debug, Clone, Copy)]
+pub struct HeuristicTokenizer {
+    chars_per_token: f32,
+}
+
+impl HeuristicTokenizer {
+    pub fn new(chars_per_token: f32) -> Self {
+        Self {
+            chars_per_token: chars_per_token.max(0.1),
+        }
+    }
+}
+
+impl Default for HeuristicTokenizer {
+    fn default() -> Self {
+        Self::new(4.0)
+    }
+}
+
+impl Tokenizer for HeuristicTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        (text.chars().count() as f32 / self.chars_per_token).ceil() as usize
+    }
+}
+
+/// Counts tokens with the encoding of an OpenAI model.
+#[cfg(feature = "tiktoken")]
+#[derive(Clone, Copy)]
+pub struct TiktokenTokenizer {
+    bpe: &'static tiktoken_rs::CoreBPE,
+}
+
+#[cfg(feature = "tiktoken")]
+impl TiktokenTokenizer {
+    /// The tokenizer of an OpenAI model, or `None` if the model is not an OpenAI model. Provider prefixes such as
+    /// `openai/` are ignored.
+    pub fn for_model(model_id: &str) -> Option<Self> {
+        static O200K: OnceLock<Option<tiktoken_rs::CoreBPE>> = OnceLock::new();
+        static CL100K: OnceLock<Option<tiktoken_rs::CoreBPE>> = OnceLock::new();
+        let model_id = model_id.rsplit('/').next().unwrap_or(model_id);
+        let bpe = if ["gpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "o1", "o3", "o4"]
+            .iter()
+            .any(|prefix| model_id.starts_with(prefix))
+        {
+            O200K.get_or_init(|| tiktoken_rs::o200k_base().ok())
+        } else if ["gpt-4", "gpt-3.5", "text-embedding-3", "text-embedding-ada"]
+            .iter()
+            .any(|prefix| model_id.starts_with(prefix))
+        {
+            CL100K.get_or_init(|| tiktoken_rs::cl100k_base().ok())
+        } else {
+            return None;
+        };
+        bpe.as_ref().map(|bpe| Self { bpe })
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+impl Tokenizer for TiktokenTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+/// The tokenizer of a model: its own encoding for OpenAI models with the `tiktoken` feature, and a
+/// [`HeuristicTokenizer`] otherwise.
+pub fn tokenizer_for_model(model_id: &str) -> Arc<dyn Tokenizer> {
+    #[cfg(feature = "tiktoken")]
+    if let Some(tokenizer) = TiktokenTokenizer::for_model(model_id) {
+        return Arc::new(tokenizer);
+    }
+    let _ = model_id;
+    Arc::new(HeuristicTokenizer::default())
+}
+
+/// Compacts `messages` until they take at most `max_tokens`, and returns how many messages were compacted.
+///
+/// The content of the oldest messages is replaced first. Messages are never removed, so every tool call keeps its
+/// response, and the system messages, the first task and the latest step are kept as they are. If that is not
+/// enough, the longest message that is left is cut short.
+pub fn fit_to_context(
+    messages: &mut [Message],
+    tokenizer: &dyn Tokenizer,
+    max_tokens: usize,
+) -> usize {
+    let mut total = tokenizer.count_messages_tokens(messages);
+    if total <= max_tokens {
+        return 0;
+    }
+    let first_task = messages
+        .iter()
+        .position(|message| message.role == MessageRole::User);
+    let keep_from = messages.len().saturating_sub(KEEP_LAST);
+    let compactable = (0..keep_from)
+        .filter(|&index| messages[index].role != MessageRole::System && Some(index) != first_task)
+        .collect::<Vec<_>>();
+
+    let mut compacted = 0;
+    for &index in &compactable {
+        if total <= max_tokens {
+            break;
+        }
+        let message = &mut messages[index];
+        let content = match message.role {
+            MessageRole::ToolResponse => format!("Observation: {}", COMPACTED),
+            _ => COMPACTED.to_string(),
+        };
+        if message.content == content && message.parts.is_empty() {
+            continue;
+        }
+        let before = tokenizer.count_message_tokens(message);
+        message.content = content;
+        message.parts.clear();
+        total = total + tokenizer.count_message_tokens(message) - before;
+        compacted += 1;
+    }
+
+    if total > max_tokens {
+        let longest = (0..messages.len())
+            .filter(|&index| messages[index].role != MessageRole::System)
+            .max_by_key(|&index| tokenizer.count_tokens(&messages[index].content));
+        if let Some(index) = longest {
+            let message = &mut messages[index];
+            let tokens = tokenizer.count_tokens(&message.content).max(1);
+            // One more token for the rounding of the counts of the two pieces.
+            let keep_tokens =
+                tokens.saturating_sub(total - max_tokens + tokenizer.count_tokens(CUT_SHORT) + 1);
+            let chars = message.content.chars().count();
+            message.content = message
+                .content
+                .chars()
+                .take(chars * keep_tokens / tokens)
+                .collect::<String>()
+                + CUT_SHORT;
+            compacted += 1;
+        }
+    }
+    compacted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_to_context() {
+        let tokenizer = HeuristicTokenizer::default();
+        assert_eq!(tokenizer.count_tokens("abcdefgh"), 2);
+        assert_eq!(tokenizer.count_message_tokens(&Message::user("abcd")), 5);
+
+        let observation = "x".repeat(4_000);
+        let mut messages = vec![
+            Message::system("You are an agent"),
+            Message::user("New Task: find the file"),
+            Message::assistant("I will search"),
+            Message::user(&format!("Observations: {}", observation)),
+            Message::assistant("I will read it"),
+            Message::user(&format!("Observations: {}", observation)),
+        ];
+        let untouched = messages
+            .iter()
+            .map(|message| message.content.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(fit_to_context(&mut messages, &tokenizer, 100_000), 0);
+        assert_eq!(messages[3].content, untouched[3]);
+
+        assert_eq!(fit_to_context(&mut messages, &tokenizer, 1_100), 2);
+        assert!(tokenizer.count_messages_tokens(&messages) <= 1_100);
+        assert_eq!(messages[1].content, untouched[1]);
+        assert_eq!(messages[3].content, COMPACTED);
+        assert_eq!(messages[5].content, untouched[5]);
+
+        fit_to_context(&mut messages, &tokenizer, 500);
+        assert!(tokenizer.count_messages_tokens(&messages) <= 500);
+        assert!(messages[5]
+            .content
+            .ends_with("[Cut short to fit the context window]"));
+    }
+}
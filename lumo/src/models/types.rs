@@ -35,7 +35,79 @@ pub struct Message {
     pub tool_call_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
+    /// Content sent after `content`, such as images.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ContentPart>,
+    /// The name of the participant who wrote the message, to tell apart several users or agents in one
+    /// conversation. Providers without a name field get the name as a prefix of the content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// A part of a message. Only models that support vision read image parts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    /// A link to the image or a `data:` url with the image encoded in base64.
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl ContentPart {
+    pub fn text(text: &str) -> Self {
+        ContentPart::Text {
+            text: text.to_string(),
+        }
+    }
+
+    pub fn image_url(url: &str) -> Self {
+        ContentPart::ImageUrl {
+            image_url: ImageUrl {
+                url: url.to_string(),
+                detail: None,
+            },
+        }
+    }
+
+    /// An image from base64 encoded `data`, e.g. `image_base64(data, "image/png")`.
+    pub fn image_base64(data: &str, mime_type: &str) -> Self {
+        Self::image_url(&format!("data:{};base64,{}", mime_type, data))
+    }
+}
 
+fn image_data_url_regex() -> regex::Regex {
+    regex::Regex::new(r"data:image/[a-zA-Z0-9.+-]+;base64,[A-Za-z0-9+/]+=*").unwrap()
+}
+
+/// Takes the base64 images out of a tool output, so that they can be sent to the model as images instead of text.
+/// Each image is replaced by an `[image N]` placeholder.
+pub fn split_image_data_urls(text: &str) -> (String, Vec<ContentPart>) {
+    let mut images = Vec::new();
+    let text = image_data_url_regex()
+        .replace_all(text, |captures: &regex::Captures| {
+            images.push(ContentPart::image_url(&captures[0]));
+            format!("[image {}]", images.len())
+        })
+        .into_owned();
+    (text, images)
+}
+
+/// The inverse of [`split_image_data_urls`]: puts the images back in place of their placeholders.
+pub fn join_image_data_urls(text: &str, images: &[ContentPart]) -> String {
+    let mut text = text.to_string();
+    for (i, image) in images.iter().enumerate() {
+        if let ContentPart::ImageUrl { image_url } = image {
+            text = text.replace(&format!("[image {}]", i + 1), &image_url.url);
+        }
+    }
+    text
 }
 
 pub struct MessageBuilder {
@@ -43,6 +115,8 @@ pub struct MessageBuilder {
     content: String,
     tool_call_id: Option<String>,
     tool_calls: Option<Vec<ToolCall>>,
+    parts: Vec<ContentPart>,
+    name: Option<String>,
 }
 
 impl MessageBuilder {
@@ -52,6 +126,8 @@ impl MessageBuilder {
             content: content.to_string(),
             tool_call_id: None,
             tool_calls: None,
+            parts: vec![],
+            name: None,
         }
     }
     pub fn with_tool_call_id(mut self, tool_call_id: &str) -> Self {
@@ -62,12 +138,22 @@ impl MessageBuilder {
         self.tool_calls = Some(tool_calls);
         self
     }
+    pub fn with_part(mut self, part: ContentPart) -> Self {
+        self.parts.push(part);
+        self
+    }
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
     pub fn build(self) -> Message {
         Message {
             role: self.role,
             content: self.content,
             tool_call_id: self.tool_call_id,
             tool_calls: self.tool_calls,
+            parts: self.parts,
+            name: self.name,
         }
     }
 }
@@ -86,6 +172,429 @@ impl Message {
             content: content.to_string(),
             tool_call_id: None,
             tool_calls: None,
+            parts: vec![],
+            name: None,
+        }
+    }
+
+    pub fn system(content: &str) -> Self {
+        Self::new(MessageRole::System, content)
+    }
+
+    pub fn user(content: &str) -> Self {
+        Self::new(MessageRole::User, content)
+    }
+
+    pub fn assistant(content: &str) -> Self {
+        Self::new(MessageRole::Assistant, content)
+    }
+
+    /// An assistant message that calls tools. Each call must be answered by a [`tool_result`](Self::tool_result).
+    pub fn tool_calls(content: &str, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            tool_calls: Some(tool_calls),
+            ..Self::new(MessageRole::Assistant, content)
+        }
+    }
+
+    /// The output of the tool call with the id `tool_call_id`.
+    pub fn tool_result(tool_call_id: &str, content: &str) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..Self::new(MessageRole::ToolResponse, content)
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// The content prefixed with the name of the participant, for providers that have no name field.
+    pub fn content_with_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{}: {}", name, self.content),
+            None => self.content.clone(),
+        }
+    }
+
+    pub fn with_image_url(mut self, url: &str) -> Self {
+        self.parts.push(ContentPart::image_url(url));
+        self
+    }
+
+    pub fn with_image_base64(mut self, data: &str, mime_type: &str) -> Self {
+        self.parts.push(ContentPart::image_base64(data, mime_type));
+        self
+    }
+
+    /// The text of the message followed by its other parts.
+    pub fn content_parts(&self) -> Vec<ContentPart> {
+        let mut parts = vec![ContentPart::text(&self.content)];
+        parts.extend(self.parts.iter().cloned());
+        parts
+    }
+}
+
+/// How the model may use the tools it is given.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolChoice {
+    /// The model decides whether to call a tool or answer in text.
+    Auto,
+    /// The model must call at least one tool.
+    Required,
+    /// The model must answer in text, even though tools are given.
+    None,
+    /// The model must call the tool with this name.
+    Tool(String),
+}
+
+impl ToolChoice {
+    pub fn tool(name: &str) -> Self {
+        ToolChoice::Tool(name.to_string())
+    }
+}
+
+/// Sampling parameters for a model call. Fields that are `None` fall back to the defaults of the model provider.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    /// Falls back to the default of the model, which is [`ToolChoice::Required`] for the OpenAI and Gemini models and
+    /// [`ToolChoice::Auto`] for Ollama.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    /// Marks the system prompt and the tool definitions as cacheable, for providers that only cache the parts of a
+    /// prompt that are marked. Providers that cache on their own ignore it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_prompt: Option<bool>,
+    /// The text the answer of the model starts with, see [`prefill`](crate::models::prefill). The response includes
+    /// it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefill: Option<String>,
+}
+
+impl GenerationConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+    pub fn with_presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = Some(presence_penalty);
+        self
+    }
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+    pub fn with_tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.tool_choice = Some(tool_choice);
+        self
+    }
+    pub fn with_cache_prompt(mut self, cache_prompt: bool) -> Self {
+        self.cache_prompt = Some(cache_prompt);
+        self
+    }
+    pub fn with_prefill(mut self, prefill: &str) -> Self {
+        self.prefill = Some(prefill.to_string());
+        self
+    }
+
+    /// Returns `self` with every unset field taken from `defaults`. Stop sequences are combined, so that the stop
+    /// sequences an agent relies on are kept when the user adds their own.
+    pub fn merge(mut self, defaults: &GenerationConfig) -> Self {
+        self.temperature = self.temperature.or(defaults.temperature);
+        self.max_tokens = self.max_tokens.or(defaults.max_tokens);
+        self.top_p = self.top_p.or(defaults.top_p);
+        self.frequency_penalty = self.frequency_penalty.or(defaults.frequency_penalty);
+        self.presence_penalty = self.presence_penalty.or(defaults.presence_penalty);
+        self.seed = self.seed.or(defaults.seed);
+        self.tool_choice = self.tool_choice.or_else(|| defaults.tool_choice.clone());
+        self.cache_prompt = self.cache_prompt.or(defaults.cache_prompt);
+        self.prefill = self.prefill.or_else(|| defaults.prefill.clone());
+        self.stop = match (self.stop, &defaults.stop) {
+            (Some(mut stop), Some(default_stop)) => {
+                for sequence in default_stop {
+                    if !stop.contains(sequence) {
+                        stop.push(sequence.clone());
+                    }
+                }
+                Some(stop)
+            }
+            (stop, default_stop) => stop.or_else(|| default_stop.clone()),
+        };
+        self
+    }
+}
+
+/// Number of tokens used by one or more model calls.
+///
+/// Providers that cache the start of a prompt report how many of the input tokens were read from the cache and how
+/// many were written to it. Both are part of `input_tokens`, not in addition to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub cache_read_tokens: usize,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub cache_write_tokens: usize,
+}
+
+fn is_zero(tokens: &usize) -> bool {
+    *tokens == 0
+}
+
+impl Usage {
+    pub fn new(input_tokens: usize, output_tokens: usize) -> Self {
+        Self {
+            input_tokens,
+            output_tokens,
+            cache_read_tokens: 0,
+            cache_write_tokens: 0,
+        }
+    }
+
+    pub fn with_cache(mut self, cache_read_tokens: usize, cache_write_tokens: usize) -> Self {
+        self.cache_read_tokens = cache_read_tokens;
+        self.cache_write_tokens = cache_write_tokens;
+        self
+    }
+
+    pub fn total_tokens(&self) -> usize {
+        self.input_tokens + self.output_tokens
+    }
+
+    /// The share of the input tokens that was read from the prompt cache, between 0 and 1.
+    pub fn cache_hit_rate(&self) -> f64 {
+        if self.input_tokens == 0 {
+            0.0
+        } else {
+            self.cache_read_tokens as f64 / self.input_tokens as f64
+        }
+    }
+
+    /// The tokens used since `earlier`, a snapshot of the same counter.
+    pub fn since(&self, earlier: &Usage) -> Usage {
+        Usage {
+            input_tokens: self.input_tokens.saturating_sub(earlier.input_tokens),
+            output_tokens: self.output_tokens.saturating_sub(earlier.output_tokens),
+            cache_read_tokens: self
+                .cache_read_tokens
+                .saturating_sub(earlier.cache_read_tokens),
+            cache_write_tokens: self
+                .cache_write_tokens
+                .saturating_sub(earlier.cache_write_tokens),
+        }
+    }
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+    fn add(self, other: Usage) -> Usage {
+        Usage {
+            input_tokens: self.input_tokens + other.input_tokens,
+            output_tokens: self.output_tokens + other.output_tokens,
+            cache_read_tokens: self.cache_read_tokens + other.cache_read_tokens,
+            cache_write_tokens: self.cache_write_tokens + other.cache_write_tokens,
+        }
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        *self = *self + other;
+    }
+}
+
+/// The rate limit state of an API key, as reported in the headers of a provider response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// The requests left before the limit resets.
+    pub remaining_requests: Option<u64>,
+    /// The tokens left before the limit resets.
+    pub remaining_tokens: Option<u64>,
+    /// How long until the request limit resets.
+    pub reset_requests: Option<std::time::Duration>,
+    /// How long the provider asked to wait before the next request, after a rate limit error.
+    pub retry_after: Option<std::time::Duration>,
+}
+
+impl RateLimit {
+    /// Reads the `x-ratelimit-*` and `retry-after` headers sent by OpenAI and the providers that copy its API.
+    /// Returns `None` if there are none.
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let rate_limit = Self {
+            remaining_requests: header("x-ratelimit-remaining-requests")
+                .and_then(|value| value.trim().parse().ok()),
+            remaining_tokens: header("x-ratelimit-remaining-tokens")
+                .and_then(|value| value.trim().parse().ok()),
+            reset_requests: header("x-ratelimit-reset-requests").and_then(parse_reset),
+            retry_after: header("retry-after")
+                .and_then(|value| value.trim().parse::<f64>().ok())
+                .and_then(|seconds| std::time::Duration::try_from_secs_f64(seconds).ok()),
+        };
+        (rate_limit != Self::default()).then_some(rate_limit)
+    }
+
+    /// How long to wait before the key can be used again, if it cannot be used now.
+    pub fn wait(&self) -> Option<std::time::Duration> {
+        self.retry_after.or(match self.remaining_requests {
+            Some(0) => self.reset_requests,
+            _ => None,
+        })
+    }
+}
+
+/// Reads a reset time such as `1s`, `6m0s`, `20ms` or `0.5s`. Returns `None` for a time that is not a duration, e.g.
+/// a negative or infinite one.
+fn parse_reset(value: &str) -> Option<std::time::Duration> {
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    let mut chars = value.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        seconds += match c {
+            'h' => amount * 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                amount / 1000.0
+            }
+            'm' => amount * 60.0,
+            's' => amount,
+            _ => return None,
+        };
+    }
+    if !number.is_empty() {
+        seconds += number.parse::<f64>().ok()?;
+    }
+    std::time::Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generation_config_merge() {
+        let agent_config = GenerationConfig::new()
+            .with_temperature(0.2)
+            .with_seed(7)
+            .with_stop(vec!["END".to_string()]);
+        let config = GenerationConfig::new()
+            .with_temperature(0.9)
+            .with_stop(vec!["Observation:".to_string()])
+            .merge(&agent_config);
+        assert_eq!(config.temperature, Some(0.9));
+        assert_eq!(config.seed, Some(7));
+        assert_eq!(config.max_tokens, None);
+        assert_eq!(
+            config.stop,
+            Some(vec!["Observation:".to_string(), "END".to_string()])
+        );
+        assert_eq!(config.tool_choice, None);
+
+        let agent_config = agent_config.with_tool_choice(ToolChoice::tool("final_answer"));
+        let config = GenerationConfig::new().merge(&agent_config);
+        assert_eq!(config.tool_choice, Some(ToolChoice::tool("final_answer")));
+        assert_eq!(
+            serde_json::to_value(&config.tool_choice).unwrap(),
+            serde_json::json!({"tool": "final_answer"})
+        );
+    }
+
+    #[test]
+    fn test_split_image_data_urls() {
+        let observation =
+            "Screenshot: data:image/png;base64,iVBORw0KGgo= and data:image/jpeg;base64,/9j/4AAQ";
+        let (text, images) = split_image_data_urls(observation);
+        assert_eq!(text, "Screenshot: [image 1] and [image 2]");
+        assert_eq!(
+            images[0],
+            ContentPart::image_base64("iVBORw0KGgo=", "image/png")
+        );
+        assert_eq!(join_image_data_urls(&text, &images), observation);
+
+        let message = Message::new(MessageRole::User, "What is in this image?")
+            .with_image_url("https://example.com/cat.png");
+        let value = serde_json::to_value(message.content_parts()).unwrap();
+        assert_eq!(value[0]["type"], "text");
+        assert_eq!(value[1]["image_url"]["url"], "https://example.com/cat.png");
+    }
+
+    #[test]
+    fn test_rate_limit_from_headers() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        use std::time::Duration;
+
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+        let rate_limit = RateLimit::from_headers(&headers(&[
+            ("x-ratelimit-remaining-requests", "0"),
+            ("x-ratelimit-remaining-tokens", "1200"),
+            ("x-ratelimit-reset-requests", "6m0.5s"),
+        ]))
+        .unwrap();
+        assert_eq!(rate_limit.remaining_tokens, Some(1200));
+        assert_eq!(rate_limit.wait(), Some(Duration::from_millis(360_500)));
+        assert_eq!(
+            RateLimit::from_headers(&headers(&[("retry-after", "1.5")]))
+                .unwrap()
+                .wait(),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_reset("20ms"), Some(Duration::from_millis(20)));
+
+        for retry_after in ["-1", "inf", "NaN", "1e400"] {
+            assert_eq!(
+                RateLimit::from_headers(&headers(&[("retry-after", retry_after)])),
+                None
+            );
         }
+        assert_eq!(parse_reset("not a time"), None);
+        assert_eq!(parse_reset(&format!("{}h", "9".repeat(400))), None);
     }
 }
@@ -0,0 +1,382 @@
+//! Wire logging, which records the HTTP requests that the providers send and the responses they get, for debugging.
+//!
+//! A provider that returns a malformed tool call or an unexpected error is easiest to diagnose from the exact bytes
+//! it sent. Wire logging is off by default, and [`enable_wire_log`] turns it on for every provider at once, at any
+//! point of a run. Each request and its response become a [`WireEntry`], with the same `id`, that goes to a
+//! [`WireSink`]: the `lumo::wire` tracing target, a JSON lines file, or memory.
+//!
+//! API keys are never written: the headers and query parameters that carry them are redacted, as are the headers and
+//! body fields given with [`WireLog::with_redacted_header`] and [`WireLog::with_redacted_field`]. Streamed responses
+//! are logged without their body, which is read by the provider as it arrives.
+//!
+//! ```rust,ignore
+//! enable_wire_log(
+//!     WireLog::new(FileSink::new("wire.jsonl")?).with_redacted_field("messages"),
+//! );
+//! let result = agent.run("What is the population of Eindhoven?", true).await;
+//! disable_wire_log();
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use serde::Serialize;
+use serde_json::Value;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Headers that carry credentials, which are always redacted.
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "x-api-key",
+    "api-key",
+    "x-goog-api-key",
+    "cookie",
+];
+
+/// Query parameters that carry credentials, which are always redacted.
+const SECRET_PARAMETERS: &[&str] = &["key", "api_key", "api-key", "access_token", "token"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireDirection {
+    Request,
+    Response,
+}
+
+/// A request sent by a provider, or the response it got.
+#[derive(Debug, Clone, Serialize)]
+pub struct WireEntry {
+    /// The same for a request and its response.
+    pub id: u64,
+    pub provider: String,
+    pub direction: WireDirection,
+    /// The method of a request.
+    pub method: Option<String>,
+    pub url: String,
+    /// The status of a response.
+    pub status: Option<u16>,
+    pub headers: BTreeMap<String, String>,
+    /// The body as JSON, or as a string when it is not JSON. `None` for streamed responses and requests without a
+    /// body.
+    pub body: Option<Value>,
+    /// When the request was sent or the response came in, in RFC 3339.
+    pub timestamp: String,
+}
+
+/// Where wire entries go.
+pub trait WireSink: Send + Sync {
+    fn record(&self, entry: &WireEntry);
+}
+
+/// Logs every entry as JSON on the `lumo::wire` tracing target, at the debug level.
+#[derive(Debug, Clone, Default)]
+pub struct TracingSink;
+
+impl WireSink for TracingSink {
+    fn record(&self, entry: &WireEntry) {
+        tracing::debug!(
+            target: "lumo::wire",
+            id = entry.id,
+            provider = %entry.provider,
+            direction = ?entry.direction,
+            "{}",
+            serde_json::to_string(entry).unwrap_or_default()
+        );
+    }
+}
+
+/// Appends every entry to a file as a line of JSON.
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl WireSink for FileSink {
+    fn record(&self, entry: &WireEntry) {
+        let Ok(line) = serde_json::to_string(entry) else {
+            return;
+        };
+        let mut file = self.file.lock().unwrap();
+        if let Err(error) = writeln!(file, "{}", line) {
+            tracing::warn!("Failed to write the wire log: {}", error);
+        }
+    }
+}
+
+/// Keeps the entries in memory. Clones share the entries.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    entries: Arc<Mutex<Vec<WireEntry>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> Vec<WireEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+impl WireSink for MemorySink {
+    fn record(&self, entry: &WireEntry) {
+        self.entries.lock().unwrap().push(entry.clone());
+    }
+}
+
+/// A sink with the headers and body fields to redact in it.
+#[derive(Clone)]
+pub struct WireLog {
+    sink: Arc<dyn WireSink>,
+    redacted_headers: Vec<String>,
+    redacted_fields: Vec<String>,
+}
+
+impl WireLog {
+    pub fn new(sink: impl WireSink + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+            redacted_headers: SECRET_HEADERS.iter().map(|name| name.to_string()).collect(),
+            redacted_fields: vec![],
+        }
+    }
+
+    /// Redacts the header with this name, which is matched case-insensitively.
+    pub fn with_redacted_header(mut self, name: &str) -> Self {
+        self.redacted_headers.push(name.to_lowercase());
+        self
+    }
+
+    /// Redacts the value of every field with this name in the JSON bodies, at any depth.
+    pub fn with_redacted_field(mut self, name: &str) -> Self {
+        self.redacted_fields.push(name.to_string());
+        self
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let name = name.as_str().to_lowercase();
+                let value = if self.redacted_headers.contains(&name) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name, value)
+            })
+            .collect()
+    }
+
+    fn body(&self, bytes: &[u8]) -> Value {
+        let mut body = serde_json::from_slice(bytes)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned()));
+        redact_fields(&mut body, &self.redacted_fields);
+        body
+    }
+
+    fn request_entry(&self, id: u64, provider: &str, request: &reqwest::Request) -> WireEntry {
+        WireEntry {
+            id,
+            provider: provider.to_string(),
+            direction: WireDirection::Request,
+            method: Some(request.method().to_string()),
+            url: redact_url(request.url()),
+            status: None,
+            headers: self.headers(request.headers()),
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|bytes| self.body(bytes)),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// Records `response` and returns an equal one, since reading the body consumes it.
+    async fn log_response(
+        &self,
+        id: u64,
+        provider: &str,
+        response: reqwest::Response,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut entry = WireEntry {
+            id,
+            provider: provider.to_string(),
+            direction: WireDirection::Response,
+            method: None,
+            url: redact_url(response.url()),
+            status: Some(response.status().as_u16()),
+            headers: self.headers(response.headers()),
+            body: None,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        };
+        if is_streamed(response.headers()) {
+            self.sink.record(&entry);
+            return Ok(response);
+        }
+        let status = response.status();
+        let version = response.version();
+        let headers = response.headers().clone();
+        let bytes = response.bytes().await?;
+        entry.body = Some(self.body(&bytes));
+        self.sink.record(&entry);
+
+        let mut rebuilt = http::Response::new(bytes);
+        *rebuilt.status_mut() = status;
+        *rebuilt.version_mut() = version;
+        *rebuilt.headers_mut() = headers;
+        Ok(reqwest::Response::from(rebuilt))
+    }
+}
+
+fn is_streamed(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.starts_with("text/event-stream") || value.starts_with("application/x-ndjson")
+        })
+}
+
+fn redact_url(url: &reqwest::Url) -> String {
+    if url.query().is_none() {
+        return url.to_string();
+    }
+    let pairs = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if SECRET_PARAMETERS.contains(&name.as_ref()) {
+                REDACTED.into()
+            } else {
+                value
+            };
+            (name.into_owned(), value.into_owned())
+        })
+        .collect::<Vec<_>>();
+    let mut url = url.clone();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    url.to_string()
+}
+
+fn redact_fields(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.contains(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_fields(value, fields);
+                }
+            }
+        }
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| redact_fields(value, fields)),
+        _ => {}
+    }
+}
+
+static WIRE_LOG: RwLock<Option<WireLog>> = RwLock::new(None);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Starts logging the requests and responses of every provider to `log`, in place of the log enabled before.
+pub fn enable_wire_log(log: WireLog) {
+    *WIRE_LOG.write().unwrap() = Some(log);
+}
+
+pub fn disable_wire_log() {
+    *WIRE_LOG.write().unwrap() = None;
+}
+
+pub fn wire_log_enabled() -> bool {
+    WIRE_LOG.read().unwrap().is_some()
+}
+
+/// Sends a request of a provider, and logs it with its response when wire logging is on.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub(crate) trait SendLogged {
+    async fn send_logged(self, provider: &str) -> reqwest::Result<reqwest::Response>;
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl SendLogged for reqwest::RequestBuilder {
+    async fn send_logged(self, provider: &str) -> reqwest::Result<reqwest::Response> {
+        let log = WIRE_LOG.read().unwrap().clone();
+        let Some(log) = log else {
+            return self.send().await;
+        };
+        let (client, request) = self.build_split();
+        let request = request?;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        log.sink.record(&log.request_entry(id, provider, &request));
+        let response = client.execute(request).await?;
+        log.log_response(id, provider, response).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_wire_log() {
+        let sink = MemorySink::new();
+        let log = WireLog::new(sink.clone())
+            .with_redacted_header("X-Org")
+            .with_redacted_field("content");
+        let request = reqwest::Client::new()
+            .post("https://generativelanguage.googleapis.com/v1beta/models/gemini:generateContent?key=secret&alt=json")
+            .header("Authorization", "Bearer sk-secret")
+            .header("X-Org", "org-1")
+            .json(&json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "Hi"}]}))
+            .build()
+            .unwrap();
+        let entry = log.request_entry(1, "gemini", &request);
+        assert_eq!(entry.method.as_deref(), Some("POST"));
+        assert!(entry.url.contains("key=%5BREDACTED%5D") && entry.url.contains("alt=json"));
+        assert!(!entry.url.contains("secret"));
+        assert_eq!(entry.headers["authorization"], REDACTED);
+        assert_eq!(entry.headers["x-org"], REDACTED);
+        let body = entry.body.unwrap();
+        assert_eq!(body["model"], "gpt-4o");
+        assert_eq!(body["messages"][0]["content"], REDACTED);
+
+        let mut response = http::Response::new(r#"{"choices": []}"#);
+        *response.status_mut() = reqwest::StatusCode::BAD_REQUEST;
+        let response = log
+            .log_response(1, "gemini", reqwest::Response::from(response))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(response.text().await.unwrap(), r#"{"choices": []}"#);
+        let entries = sink.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].direction, WireDirection::Response);
+        assert_eq!(entries[0].status, Some(400));
+        assert_eq!(entries[0].body, Some(json!({"choices": []})));
+    }
+}
@@ -1,5 +1,9 @@
 //! This module contains the prompts for the agents.
 
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::{bail, Result};
+
 /// The system prompt for the code agent.
 pub const CODE_SYSTEM_PROMPT: &str = r#"You are an expert assistant who can solve any task using code blobs. You will be given a task to solve as best you can.
 To do so, you have been given access to a list of tools: these tools are basically Python functions which you can call with code.
@@ -228,6 +232,65 @@ Now begin! Write your plan below",
     )
 }
 
+/// The message sent back to the model when its tool call or code could not be parsed.
+pub fn parse_retry_prompt(error: &str) -> String {
+    format!(
+        "Error: your last response could not be parsed: {}
+Please try again. Make sure the tool call has a valid tool name and that its arguments are a valid JSON object matching the tool's inputs. Do not apologize, just send the corrected call.",
+        error
+    )
+}
+
+/// The message that asks the model for a plan of tool calls, see [`PlanAndExecute`](crate::agent::PlanAndExecute).
+pub fn plan_and_execute_prompt(tool_descriptions: &str) -> String {
+    format!(
+        "Before you act, make a plan to solve the task with these tools:
+{}
+
+Write the plan as a JSON array of the tool calls to make, in order, such as:
+[{{\"name\": \"search\", \"arguments\": {{\"query\": \"population of Eindhoven\"}}}}]
+The calls are made exactly as you write them, so their arguments cannot depend on the results of earlier calls. Only plan the calls whose arguments you know now. Do not call final_answer: once the plan is done you will see the results and give your answer. Write nothing but the JSON array.",
+        tool_descriptions
+    )
+}
+
+/// The message that asks a model to score the next action of an agent, see [`ModelJudge`](crate::agent::ModelJudge).
+pub fn score_candidate_prompt(task: &str, candidate: &str) -> String {
+    format!(
+        "An agent is working on this task:
+```
+{}
+```
+
+This is the action it proposes to take next:
+```
+{}
+```
+
+Rate from 0 to 10 how well this action moves the agent towards a correct answer, where 0 is useless or wrong and 10 is the best possible action. Answer with the number only.",
+        task, candidate
+    )
+}
+
+/// The message that asks the model for its answer once the agent has used all of its steps.
+pub const FINAL_STEP_PROMPT: &str = "You have reached the maximum number of steps. You must give your final answer to the task now, based on what you have found so far. Do not take any other action.";
+
+/// The message that asks the model to critique its own work, see [`ReflectionConfig`](crate::agent::ReflectionConfig).
+/// `{{task}}` is replaced by the task and `{{answer}}` by the proposed final answer, or by a note that there is none
+/// yet.
+pub const REFLECTION_PROMPT: &str = r#"Stop and review your work on the task so far. Do not call any tool.
+
+The task is:
+```
+{{task}}
+```
+
+{{answer}}
+
+Check whether the steps taken so far are correct and sufficient: look for mistakes, unsupported claims, parts of the task that were missed and better ways to go on.
+If nothing needs to change, reply with ACCEPT and nothing else.
+Otherwise reply with REVISE, followed by a short critique that says what is wrong or missing and what to do next."#;
+
 /// The system prompt for the tool calling agent. This prompt is used for models that do not have tool calling capabilities.
 pub const TOOL_CALLING_SYSTEM_PROMPT: &str = r#"You are an expert assistant who can solve any task using  tool calls. You will be given a task to solve as best you can.
 To do so, you have been given access to the following tools: {{tool_names}}
@@ -356,3 +419,303 @@ Here are the rules you should always follow to solve your task:
 
 Now Begin! If you solve the task correctly, you will receive a reward of $1,000,000.
 "#;
+
+/// A named part of a system prompt. Sections are rendered in the order they are declared here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PromptSection {
+    /// Who the agent is and what it is asked to do.
+    Role,
+    /// How tool calls are made and how their results come back.
+    ToolInstructions,
+    /// The format of the response, including how to give the final answer.
+    OutputFormat,
+    /// Worked examples using notional tools.
+    Examples,
+    /// The tools and team members that are actually available.
+    Tools,
+    /// The rules the agent should always follow.
+    Rules,
+}
+
+/// The variables the agent fills in when it is built, besides the ones of the template.
+pub const AGENT_VARIABLES: &[&str] = &[
+    "tools",
+    "tool_descriptions",
+    "tool_names",
+    "managed_agents",
+    "managed_agents_descriptions",
+    "current_date",
+    "current_time",
+];
+
+/// A system prompt split into [`PromptSection`]s that can be replaced one at a time.
+///
+/// Besides the variables set with [`PromptTemplate::with_variable`], the agent fills in the [`AGENT_VARIABLES`] when
+/// it is built: `{{tools}}` (the tool descriptions), `{{tool_names}}`, `{{managed_agents}}`, `{{current_date}}` and
+/// `{{current_time}}`. A section set with [`PromptTemplate::with_section`] may only use these variables and the ones
+/// of the template, so that a misspelled variable fails the build of the agent instead of reaching the model.
+///
+/// The templates only substitute variables, see [`render_template`]. There are no conditions, loops or filters.
+///
+/// ```rust
+/// use lumo::prompts::{PromptSection, PromptTemplate};
+///
+/// let template = PromptTemplate::tool_calling()
+///     .with_section(PromptSection::Role, "You are a support agent for {{product}}.")
+///     .with_variable("product", "Lumo");
+/// assert!(template.to_prompt().unwrap().starts_with("You are a support agent for Lumo."));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PromptTemplate {
+    sections: BTreeMap<PromptSection, String>,
+    /// The sections set with `with_section`, whose variables are checked.
+    replaced: BTreeSet<PromptSection>,
+    variables: BTreeMap<String, String>,
+}
+
+impl PromptTemplate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The sections of [`TOOL_CALLING_SYSTEM_PROMPT`].
+    pub fn tool_calling() -> Self {
+        Self::split(
+            TOOL_CALLING_SYSTEM_PROMPT,
+            &[
+                (
+                    PromptSection::ToolInstructions,
+                    "The tool call you write is an action",
+                ),
+                (PromptSection::OutputFormat, "To provide the final answer"),
+                (PromptSection::Examples, "Here are a few examples"),
+                (PromptSection::Tools, "Above example were"),
+                (PromptSection::Rules, "Here are the rules"),
+            ],
+        )
+    }
+
+    /// The sections of [`FUNCTION_CALLING_SYSTEM_PROMPT`].
+    pub fn function_calling() -> Self {
+        Self::split(
+            FUNCTION_CALLING_SYSTEM_PROMPT,
+            &[
+                (
+                    PromptSection::ToolInstructions,
+                    "The tool call you write is an action",
+                ),
+                (PromptSection::Rules, "Here are the rules"),
+            ],
+        )
+    }
+
+    /// The sections of [`CODE_SYSTEM_PROMPT`].
+    pub fn code() -> Self {
+        Self::split(
+            CODE_SYSTEM_PROMPT,
+            &[
+                (PromptSection::OutputFormat, "At each step,"),
+                (PromptSection::Examples, "Here are a few examples"),
+                (PromptSection::Tools, "Above example were"),
+                (PromptSection::Rules, "Here are the rules"),
+            ],
+        )
+    }
+
+    /// Splits `prompt` into sections. The text before the first marker is the role, and every other section starts
+    /// at the first line that starts with its marker.
+    fn split(prompt: &str, markers: &[(PromptSection, &str)]) -> Self {
+        let mut template = Self::new();
+        let mut section = PromptSection::Role;
+        let mut start = 0;
+        for (next, marker) in markers {
+            let end = prompt[start..]
+                .match_indices(marker)
+                .map(|(i, _)| start + i)
+                .find(|&i| i == 0 || prompt[..i].ends_with('\n'))
+                .unwrap_or_else(|| panic!("Marker {:?} is not in the prompt", marker));
+            template
+                .sections
+                .insert(section, prompt[start..end].to_string());
+            section = *next;
+            start = end;
+        }
+        template
+            .sections
+            .insert(section, prompt[start..].to_string());
+        template
+    }
+
+    /// Replaces a section, or adds it if the template does not have it.
+    pub fn with_section(mut self, section: PromptSection, text: &str) -> Self {
+        self.sections
+            .insert(section, format!("{}\n\n", text.trim_end()));
+        self.replaced.insert(section);
+        self
+    }
+
+    pub fn without_section(mut self, section: PromptSection) -> Self {
+        self.sections.remove(&section);
+        self.replaced.remove(&section);
+        self
+    }
+
+    pub fn section(&self, section: PromptSection) -> Option<&str> {
+        self.sections.get(&section).map(|text| text.as_str())
+    }
+
+    /// Sets a custom variable, used as `{{name}}` in the sections.
+    pub fn with_variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Joins the sections and fills in the custom variables. The variables the agent provides are left as
+    /// placeholders for the agent to fill in.
+    ///
+    /// Fails if a replaced section uses a variable that is neither set on the template nor one of the
+    /// [`AGENT_VARIABLES`].
+    pub fn to_prompt(&self) -> Result<String> {
+        for section in &self.replaced {
+            let text = &self.sections[section];
+            if let Some(name) = placeholders(text)
+                .into_iter()
+                .find(|name| !self.variables.contains_key(*name) && !AGENT_VARIABLES.contains(name))
+            {
+                bail!(
+                    "The {:?} section of the prompt template uses the variable `{}`, which has no value",
+                    section,
+                    name
+                );
+            }
+        }
+        let prompt = self.sections.values().cloned().collect::<String>();
+        Ok(render_template(
+            &prompt,
+            self.variables
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        ))
+    }
+}
+
+/// Replaces every `{{name}}` and `{{ name }}` placeholder in `template` with its value, in one pass, so a value
+/// that contains a placeholder is inserted as it is. Placeholders without a value are left as they are.
+///
+/// Names are made of ASCII letters, digits and underscores. Any other text between braces, such as the JSON in the
+/// examples of the prompts, is not a placeholder and is kept.
+pub fn render_template<'a>(
+    template: &str,
+    variables: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> String {
+    let variables = variables.into_iter().collect::<HashMap<_, _>>();
+    let mut prompt = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        prompt.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match placeholder(rest).and_then(|(name, len)| Some((*variables.get(name)?, len))) {
+            Some((value, len)) => {
+                prompt.push_str(value);
+                rest = &rest[len..];
+            }
+            // The next brace may start a placeholder, as in `{{{name}}}`
+            None => {
+                prompt.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    prompt.push_str(rest);
+    prompt
+}
+
+/// The name and the length of the placeholder that `text` starts with.
+fn placeholder(text: &str) -> Option<(&str, usize)> {
+    let inner = text.strip_prefix("{{")?;
+    let end = inner.find("}}")?;
+    let name = inner[..end].trim_matches(' ');
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some((name, end + 4))
+}
+
+/// The names of the placeholders in `text`.
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start..];
+        match placeholder(rest) {
+            Some((name, len)) => {
+                names.push(name);
+                rest = &rest[len..];
+            }
+            None => rest = &rest[1..],
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_template_sections() {
+        assert_eq!(
+            PromptTemplate::tool_calling().to_prompt().unwrap(),
+            TOOL_CALLING_SYSTEM_PROMPT
+        );
+        assert_eq!(
+            PromptTemplate::function_calling().to_prompt().unwrap(),
+            FUNCTION_CALLING_SYSTEM_PROMPT
+        );
+        assert_eq!(
+            PromptTemplate::code().to_prompt().unwrap(),
+            CODE_SYSTEM_PROMPT
+        );
+
+        let template = PromptTemplate::tool_calling()
+            .with_section(PromptSection::Examples, "No examples for {{ product }}.")
+            .without_section(PromptSection::Rules)
+            .with_variable("product", "Lumo");
+        let prompt = template.to_prompt().unwrap();
+        assert!(prompt.contains("\n\nNo examples for Lumo.\n\nAbove example were"));
+        assert!(prompt.contains("{{tool_descriptions}}"));
+        assert!(!prompt.contains("Here are the rules"));
+        assert!(template
+            .section(PromptSection::Role)
+            .unwrap()
+            .starts_with("You are an expert assistant"));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let rendered = render_template(
+            "{{tools}} and {{ tools }}, but not {{other}}",
+            [("tools", "search")],
+        );
+        assert_eq!(rendered, "search and search, but not {{other}}");
+
+        let rendered = render_template(
+            "{{a}} {{b}} {{{a}}} {{\"a\": 1}}",
+            [("a", "{{b}}"), ("b", "x")],
+        );
+        assert_eq!(rendered, "{{b}} x {{{b}}} {{\"a\": 1}}");
+    }
+
+    #[test]
+    fn test_prompt_template_unknown_variable() {
+        let template = PromptTemplate::tool_calling()
+            .with_section(
+                PromptSection::Role,
+                "You support {{prodcut}} with {{tools}}.",
+            )
+            .with_variable("product", "Lumo");
+        let error = template.to_prompt().unwrap_err();
+        assert!(error.to_string().contains("`prodcut`"));
+
+        let template = template.with_section(PromptSection::Role, "You support {{product}}.");
+        assert!(template.to_prompt().is_ok());
+    }
+}
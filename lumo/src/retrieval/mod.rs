@@ -0,0 +1,8 @@
+//! This module contains the retrieval subsystem: documents, vector stores and helpers to index text so that agents
+//! can search it semantically through the [`RetrieverTool`](crate::tools::RetrieverTool).
+
+pub mod qdrant;
+pub mod vector_store;
+
+pub use qdrant::*;
+pub use vector_store::*;
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::OnceCell;
+
+use super::vector_store::{Document, ScoredDocument, VectorStore};
+
+/// A vector store backed by a [Qdrant](https://qdrant.tech) collection, accessed through its REST API.
+#[derive(Debug, Clone)]
+pub struct QdrantVectorStore {
+    pub url: String,
+    pub collection: String,
+    pub api_key: Option<String>,
+    pub client: Client,
+    /// Set once the collection is known to exist, so it is only checked before the first `add`.
+    collection_ready: Arc<OnceCell<()>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QdrantSearchResponse {
+    result: Vec<QdrantScoredPoint>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QdrantScoredPoint {
+    score: f32,
+    #[serde(default)]
+    payload: Value,
+}
+
+/// Qdrant only accepts unsigned integers or UUIDs as point ids, so document ids are hashed and the original id is
+/// kept in the payload. FNV-1a is used because it is stable across builds, unlike the std hasher.
+fn point_id(id: &str) -> u64 {
+    id.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl QdrantVectorStore {
+    pub fn new(url: Option<&str>, collection: &str, api_key: Option<String>) -> Self {
+        Self {
+            url: url.unwrap_or("http://localhost:6333").to_string(),
+            collection: collection.to_string(),
+            api_key: api_key.or_else(|| std::env::var("QDRANT_API_KEY").ok()),
+            client: Client::new(),
+            collection_ready: Arc::new(OnceCell::new()),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}/collections/{}{}", self.url, self.collection, path));
+        match &self.api_key {
+            Some(key) => request.header("api-key", key),
+            None => request,
+        }
+    }
+
+    /// Creates the collection if it does not exist yet.
+    pub async fn ensure_collection(&self, dimensions: usize) -> Result<()> {
+        let exists = self.request(reqwest::Method::GET, "").send().await?;
+        if exists.status().is_success() {
+            return Ok(());
+        }
+        let response = self
+            .request(reqwest::Method::PUT, "")
+            .json(&json!({
+                "vectors": { "size": dimensions, "distance": "Cosine" }
+            }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to create Qdrant collection {}: {}",
+                self.collection,
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl VectorStore for QdrantVectorStore {
+    async fn add(&self, documents: Vec<Document>, embeddings: Vec<Vec<f32>>) -> Result<()> {
+        if documents.len() != embeddings.len() {
+            return Err(anyhow!(
+                "Got {} documents but {} embeddings",
+                documents.len(),
+                embeddings.len()
+            ));
+        }
+        let Some(dimensions) = embeddings.first().map(Vec::len) else {
+            return Ok(());
+        };
+        self.collection_ready
+            .get_or_try_init(|| self.ensure_collection(dimensions))
+            .await?;
+        let points = documents
+            .into_iter()
+            .zip(embeddings)
+            .map(|(document, embedding)| {
+                json!({
+                    "id": point_id(&document.id),
+                    "vector": embedding,
+                    "payload": {
+                        "id": document.id,
+                        "text": document.text,
+                        "metadata": document.metadata,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let response = self
+            .request(reqwest::Method::PUT, "/points?wait=true")
+            .json(&json!({ "points": points }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to upsert points into Qdrant: {}",
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+
+    async fn search(&self, embedding: &[f32], top_k: usize) -> Result<Vec<ScoredDocument>> {
+        let response = self
+            .request(reqwest::Method::POST, "/points/search")
+            .json(&json!({
+                "vector": embedding,
+                "limit": top_k,
+                "with_payload": true,
+            }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to search Qdrant: {}",
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        let response = response.json::<QdrantSearchResponse>().await?;
+        Ok(response
+            .result
+            .into_iter()
+            .map(|point| ScoredDocument {
+                document: Document {
+                    id: point.payload["id"].as_str().unwrap_or_default().to_string(),
+                    text: point.payload["text"].as_str().unwrap_or_default().to_string(),
+                    metadata: point.payload["metadata"].clone(),
+                },
+                score: point.score,
+            })
+            .collect())
+    }
+
+    async fn delete(&self, ids: &[String]) -> Result<()> {
+        let points = ids.iter().map(|id| point_id(id)).collect::<Vec<_>>();
+        let response = self
+            .request(reqwest::Method::POST, "/points/delete?wait=true")
+            .json(&json!({ "points": points }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Failed to delete points from Qdrant: {}",
+                response.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+}
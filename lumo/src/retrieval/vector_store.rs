@@ -0,0 +1,151 @@
+use std::sync::RwLock;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A piece of text stored in a vector store.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Document {
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub metadata: Value,
+}
+
+impl Document {
+    pub fn new(id: &str, text: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            text: text.to_string(),
+            metadata: Value::Null,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// A document returned by a search together with its similarity score. Higher scores are more similar.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoredDocument {
+    pub document: Document,
+    pub score: f32,
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait VectorStore: Send + Sync + 'static {
+    /// Adds documents with their embeddings. `embeddings[i]` belongs to `documents[i]`.
+    /// Documents with an id that already exists are replaced.
+    async fn add(&self, documents: Vec<Document>, embeddings: Vec<Vec<f32>>) -> Result<()>;
+    /// Returns the `top_k` documents most similar to `embedding`, most similar first.
+    async fn search(&self, embedding: &[f32], top_k: usize) -> Result<Vec<ScoredDocument>>;
+    /// Removes the documents with the given ids.
+    async fn delete(&self, ids: &[String]) -> Result<()>;
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b.iter()) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// A vector store that keeps everything in memory and searches by brute-force cosine similarity.
+/// Good enough for a few thousand documents.
+#[derive(Debug, Default)]
+pub struct InMemoryVectorStore {
+    entries: RwLock<Vec<(Document, Vec<f32>)>>,
+}
+
+impl InMemoryVectorStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl VectorStore for InMemoryVectorStore {
+    async fn add(&self, documents: Vec<Document>, embeddings: Vec<Vec<f32>>) -> Result<()> {
+        if documents.len() != embeddings.len() {
+            return Err(anyhow!(
+                "Got {} documents but {} embeddings",
+                documents.len(),
+                embeddings.len()
+            ));
+        }
+        let mut entries = self.entries.write().unwrap();
+        for (document, embedding) in documents.into_iter().zip(embeddings) {
+            entries.retain(|(existing, _)| existing.id != document.id);
+            entries.push((document, embedding));
+        }
+        Ok(())
+    }
+
+    async fn search(&self, embedding: &[f32], top_k: usize) -> Result<Vec<ScoredDocument>> {
+        let entries = self.entries.read().unwrap();
+        let mut scored = entries
+            .iter()
+            .map(|(document, vector)| ScoredDocument {
+                document: document.clone(),
+                score: cosine_similarity(embedding, vector),
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        scored.truncate(top_k);
+        Ok(scored)
+    }
+
+    async fn delete(&self, ids: &[String]) -> Result<()> {
+        self.entries
+            .write()
+            .unwrap()
+            .retain(|(document, _)| !ids.contains(&document.id));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_in_memory_vector_store_search() {
+        let store = InMemoryVectorStore::new();
+        store
+            .add(
+                vec![
+                    Document::new("rust", "Rust is a systems language"),
+                    Document::new("python", "Python is a scripting language"),
+                ],
+                vec![vec![1.0, 0.0], vec![0.0, 1.0]],
+            )
+            .await
+            .unwrap();
+        let results = store.search(&[0.9, 0.1], 1).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].document.id, "rust");
+
+        store.delete(&["rust".to_string()]).await.unwrap();
+        assert_eq!(store.len(), 1);
+    }
+}
@@ -0,0 +1,47 @@
+//! The few pieces of the async runtime and the clock that differ between native targets and `wasm32`.
+//!
+//! On `wasm32-unknown-unknown` there are no threads, no tokio timer and no system clock: `std::time::Instant::now`
+//! and `SystemTime::now` panic. The agent loop, the models and the tools take the time and sleep through this module
+//! instead, which uses tokio and the standard clock natively and the browser's timers and clock with the `wasm`
+//! feature.
+
+use std::time::{Duration, SystemTime};
+
+/// A monotonic clock that also works in the browser, where it reads `performance.now()`.
+pub use web_time::Instant;
+
+/// The current time, for span timestamps.
+pub fn now() -> SystemTime {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let since_epoch = web_time::SystemTime::now()
+            .duration_since(web_time::SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        SystemTime::UNIX_EPOCH + since_epoch
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        SystemTime::now()
+    }
+}
+
+/// Waits for `duration` without blocking the thread, or the browser's event loop.
+pub async fn sleep(duration: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::sleep(duration).await;
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_runtime() {
+        let started = Instant::now();
+        sleep(Duration::from_millis(5)).await;
+        assert!(started.elapsed() >= Duration::from_millis(5));
+        assert!(now() > SystemTime::UNIX_EPOCH);
+    }
+}
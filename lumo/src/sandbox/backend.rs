@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::errors::AgentError;
+
+#[cfg(feature = "code-agent")]
+use crate::errors::InterpreterError;
+
+/// What a piece of code printed and how it ended.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ExecutionOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when the process ended without an exit code.
+    pub exit_code: Option<i32>,
+    /// Whether the code was killed, because it took longer than the timeout or used more memory than allowed.
+    pub killed: bool,
+}
+
+impl ExecutionOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0) && !self.killed
+    }
+}
+
+/// Runs Python code in isolation from the agent.
+///
+/// A backend is started for a run, either explicitly or by its first execution, and the files it holds live until
+/// it is stopped. Variables do not outlive an execution, so code that needs a result of an earlier step reads it from
+/// a file.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait SandboxBackend: Send + Sync {
+    /// Starts the sandbox if it is not running.
+    async fn start(&self) -> Result<(), AgentError>;
+
+    /// Runs `code` with the Python interpreter of the sandbox, starting the sandbox first if needed.
+    async fn execute(&self, code: &str) -> Result<ExecutionOutput, AgentError>;
+
+    /// Copies a local file or directory into the sandbox.
+    async fn upload(&self, local: &Path, remote: &str) -> Result<(), AgentError>;
+
+    /// Copies a file or directory out of the sandbox.
+    async fn download(&self, remote: &str, local: &Path) -> Result<(), AgentError>;
+
+    /// Stops the sandbox and drops everything in it. The next execution starts a new one.
+    async fn stop(&self) -> Result<(), AgentError>;
+}
+
+/// The line that code run in a sandbox prints to give its final answer, followed by the answer as JSON.
+#[cfg(feature = "code-agent")]
+pub(crate) const FINAL_ANSWER_MARKER: &str = "__lumo_final_answer__:";
+
+/// Defines `final_answer` for the code of a code agent, since the tools of the agent do not exist in the sandbox.
+#[cfg(feature = "code-agent")]
+pub(crate) fn with_final_answer(code: &str) -> String {
+    format!(
+        "import json as __lumo_json\n\
+         def final_answer(answer):\n    \
+             print('{}' + __lumo_json.dumps(answer, default=str))\n    \
+             raise SystemExit(0)\n\n{}",
+        FINAL_ANSWER_MARKER, code
+    )
+}
+
+/// The output of code run in a sandbox as the result of the local interpreter: the final answer if the code gave one,
+/// the error if it failed, and what it printed otherwise.
+#[cfg(feature = "code-agent")]
+pub(crate) fn interpreter_result(
+    output: ExecutionOutput,
+) -> Result<(String, String), InterpreterError> {
+    let mut logs = Vec::new();
+    for line in output.stdout.lines() {
+        match line.strip_prefix(FINAL_ANSWER_MARKER) {
+            Some(answer) => {
+                let answer = match serde_json::from_str::<serde_json::Value>(answer) {
+                    Ok(serde_json::Value::String(answer)) => answer,
+                    Ok(answer) => answer.to_string(),
+                    Err(_) => answer.to_string(),
+                };
+                return Err(InterpreterError::FinalAnswer(answer));
+            }
+            None => logs.push(line),
+        }
+    }
+    let logs = logs.join("\n");
+    if output.killed {
+        return Err(InterpreterError::RuntimeError(format!(
+            "The code was killed for taking too long or using too much memory. Output before it was \
+             killed:\n{}",
+            logs
+        )));
+    }
+    if !output.success() {
+        return Err(InterpreterError::RuntimeError(
+            format!("{}\n{}", logs, output.stderr.trim_end())
+                .trim()
+                .to_string(),
+        ));
+    }
+    Ok((String::new(), logs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "code-agent")]
+    #[test]
+    fn test_interpreter_result() {
+        let code = with_final_answer("final_answer(42)");
+        assert!(code.starts_with("import json"));
+        assert!(code.ends_with("final_answer(42)"));
+
+        let output = ExecutionOutput {
+            stdout: format!("Searching\n{}\"Paris\"\n", FINAL_ANSWER_MARKER),
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            interpreter_result(output),
+            Err(InterpreterError::FinalAnswer("Paris".to_string()))
+        );
+
+        let output = ExecutionOutput {
+            stdout: "3\n".to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            interpreter_result(output),
+            Ok((String::new(), "3".to_string()))
+        );
+
+        let output = ExecutionOutput {
+            stderr: "NameError: name 'x' is not defined\n".to_string(),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        match interpreter_result(output) {
+            Err(InterpreterError::RuntimeError(error)) => assert!(error.starts_with("NameError")),
+            result => panic!("unexpected result {:?}", result),
+        }
+
+        let output = ExecutionOutput {
+            killed: true,
+            ..Default::default()
+        };
+        assert!(interpreter_result(output).is_err());
+    }
+}
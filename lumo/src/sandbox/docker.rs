@@ -0,0 +1,346 @@
+//! A [`SandboxBackend`] that runs code in a Docker container, through the `docker` command line.
+//!
+//! A sandbox has one container, which is created on the first execution and removed when the sandbox is stopped or
+//! dropped. All executions until then share the container and its files, also when the sandbox is shared by several
+//! agents. A code agent stops its sandbox at the start of every run, so each of its runs starts in a new container.
+//! The container has no network, drops all capabilities and is limited in memory, CPU and processes by default, and
+//! every execution is killed once it takes longer than the timeout.
+//!
+//! ```rust,ignore
+//! let sandbox = Arc::new(
+//!     DockerSandbox::new("python:3.12-slim")
+//!         .with_memory_limit_mb(1024)
+//!         .with_timeout(Duration::from_secs(60))
+//!         .with_upload("data/sales.csv", "/workspace/sales.csv"),
+//! );
+//! let mut agent = CodeAgentBuilder::new(model).with_sandbox(sandbox.clone()).build()?;
+//! agent.run("Plot the monthly sales to chart.png", true).await?;
+//! sandbox.download("/workspace/chart.png", Path::new("chart.png")).await?;
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use super::backend::{ExecutionOutput, SandboxBackend};
+use crate::errors::AgentError;
+
+const WORKDIR: &str = "/workspace";
+
+#[derive(Debug)]
+pub struct DockerSandbox {
+    image: String,
+    docker: String,
+    memory_limit_mb: Option<u64>,
+    cpus: Option<f32>,
+    pids_limit: Option<u32>,
+    network: bool,
+    timeout: Duration,
+    uploads: Vec<(PathBuf, String)>,
+    container: Mutex<Option<String>>,
+}
+
+impl DockerSandbox {
+    /// A sandbox that runs the `python3` of `image`, with 512 MB of memory, one CPU, 128 processes, no network and
+    /// executions of at most 30 seconds.
+    pub fn new(image: &str) -> Self {
+        Self {
+            image: image.to_string(),
+            docker: "docker".to_string(),
+            memory_limit_mb: Some(512),
+            cpus: Some(1.0),
+            pids_limit: Some(128),
+            network: false,
+            timeout: Duration::from_secs(30),
+            uploads: Vec::new(),
+            container: Mutex::new(None),
+        }
+    }
+
+    /// The command that runs Docker, e.g. `podman`, which takes the same arguments.
+    pub fn with_docker_command(mut self, docker: &str) -> Self {
+        self.docker = docker.to_string();
+        self
+    }
+
+    pub fn with_memory_limit_mb(mut self, memory_limit_mb: u64) -> Self {
+        self.memory_limit_mb = Some(memory_limit_mb);
+        self
+    }
+
+    pub fn with_cpus(mut self, cpus: f32) -> Self {
+        self.cpus = Some(cpus);
+        self
+    }
+
+    pub fn with_pids_limit(mut self, pids_limit: u32) -> Self {
+        self.pids_limit = Some(pids_limit);
+        self
+    }
+
+    /// Gives the container network access, e.g. to install packages. Off by default.
+    pub fn with_network(mut self, network: bool) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// How long one execution may take before it is killed.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Copies a local file into every new container. A `remote` path that is not absolute is relative to
+    /// `/workspace`, the directory the code runs in.
+    pub fn with_upload(mut self, local: impl Into<PathBuf>, remote: &str) -> Self {
+        self.uploads.push((local.into(), remote.to_string()));
+        self
+    }
+
+    fn run_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "run".to_string(),
+            "--detach".to_string(),
+            "--cap-drop=ALL".to_string(),
+            "--security-opt=no-new-privileges".to_string(),
+            format!("--workdir={}", WORKDIR),
+        ];
+        if !self.network {
+            args.push("--network=none".to_string());
+        }
+        if let Some(memory_limit_mb) = self.memory_limit_mb {
+            args.push(format!("--memory={}m", memory_limit_mb));
+        }
+        if let Some(cpus) = self.cpus {
+            args.push(format!("--cpus={}", cpus));
+        }
+        if let Some(pids_limit) = self.pids_limit {
+            args.push(format!("--pids-limit={}", pids_limit));
+        }
+        args.extend([
+            self.image.clone(),
+            "sleep".to_string(),
+            "infinity".to_string(),
+        ]);
+        args
+    }
+
+    fn exec_args(&self, container: &str) -> Vec<String> {
+        vec![
+            "exec".to_string(),
+            "--interactive".to_string(),
+            container.to_string(),
+            "timeout".to_string(),
+            "--signal=KILL".to_string(),
+            format!("{}s", self.timeout.as_secs().max(1)),
+            "python3".to_string(),
+            "-".to_string(),
+        ]
+    }
+
+    async fn docker(&self, args: &[String]) -> Result<String, AgentError> {
+        let output = Command::new(&self.docker)
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| AgentError::Execution(format!("Failed to run {}: {}", self.docker, e)))?;
+        if !output.status.success() {
+            return Err(AgentError::Execution(format!(
+                "{} {} failed: {}",
+                self.docker,
+                args.first().map(String::as_str).unwrap_or_default(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// The container of the sandbox, started on the first call. The lock is held while the container starts, so that
+    /// concurrent calls do not start one each.
+    async fn container(&self) -> Result<String, AgentError> {
+        let mut slot = self.container.lock().await;
+        if let Some(container) = slot.as_ref() {
+            return Ok(container.clone());
+        }
+        let container = self.docker(&self.run_args()).await?;
+        tracing::info!(container = %container, image = %self.image, "Started sandbox container");
+        for (local, remote) in &self.uploads {
+            if let Err(e) = self.copy_in(&container, local, remote).await {
+                let _ = self
+                    .docker(&["rm".to_string(), "--force".to_string(), container])
+                    .await;
+                return Err(e);
+            }
+        }
+        *slot = Some(container.clone());
+        Ok(container)
+    }
+
+    async fn copy_in(&self, container: &str, local: &Path, remote: &str) -> Result<(), AgentError> {
+        self.docker(&[
+            "cp".to_string(),
+            local.display().to_string(),
+            format!("{}:{}", container, remote_path(remote)),
+        ])
+        .await
+        .map(|_| ())
+    }
+}
+
+fn remote_path(remote: &str) -> String {
+    if remote.starts_with('/') {
+        remote.to_string()
+    } else {
+        format!("{}/{}", WORKDIR, remote)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl SandboxBackend for DockerSandbox {
+    async fn start(&self) -> Result<(), AgentError> {
+        self.container().await.map(|_| ())
+    }
+
+    async fn execute(&self, code: &str) -> Result<ExecutionOutput, AgentError> {
+        let container = self.container().await?;
+        let mut child = Command::new(&self.docker)
+            .args(self.exec_args(&container))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| AgentError::Execution(format!("Failed to run {}: {}", self.docker, e)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(code.as_bytes())
+                .await
+                .map_err(|e| AgentError::Execution(format!("Failed to send the code: {}", e)))?;
+        }
+        // `timeout` kills the code in the container; this one is for a container that does not answer at all.
+        let output = tokio::time::timeout(
+            self.timeout + Duration::from_secs(10),
+            child.wait_with_output(),
+        )
+        .await;
+        let output = match output {
+            Ok(output) => output.map_err(|e| AgentError::Execution(e.to_string()))?,
+            Err(_) => {
+                return Ok(ExecutionOutput {
+                    killed: true,
+                    ..Default::default()
+                })
+            }
+        };
+        // 137 is the status of a process killed with SIGKILL, by `timeout` or for running out of memory.
+        let exit_code = output.status.code();
+        Ok(ExecutionOutput {
+            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            exit_code,
+            killed: exit_code == Some(137),
+        })
+    }
+
+    async fn upload(&self, local: &Path, remote: &str) -> Result<(), AgentError> {
+        let container = self.container().await?;
+        self.copy_in(&container, local, remote).await
+    }
+
+    async fn download(&self, remote: &str, local: &Path) -> Result<(), AgentError> {
+        let container = self.container().await?;
+        self.docker(&[
+            "cp".to_string(),
+            format!("{}:{}", container, remote_path(remote)),
+            local.display().to_string(),
+        ])
+        .await
+        .map(|_| ())
+    }
+
+    async fn stop(&self) -> Result<(), AgentError> {
+        let container = self.container.lock().await.take();
+        if let Some(container) = container {
+            self.docker(&["rm".to_string(), "--force".to_string(), container])
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DockerSandbox {
+    fn drop(&mut self) {
+        if let Some(container) = self.container.get_mut().take() {
+            // Dropping cannot wait, so the container is removed in the background.
+            let _ = std::process::Command::new(&self.docker)
+                .args(["rm", "--force", &container])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docker_args() {
+        let sandbox = DockerSandbox::new("python:3.12-slim")
+            .with_memory_limit_mb(256)
+            .with_timeout(Duration::from_secs(5));
+        let args = sandbox.run_args();
+        assert!(args.contains(&"--network=none".to_string()));
+        assert!(args.contains(&"--memory=256m".to_string()));
+        assert!(args.contains(&"--pids-limit=128".to_string()));
+        assert_eq!(args[args.len() - 3], "python:3.12-slim");
+        assert!(!DockerSandbox::new("python:3.12-slim")
+            .with_network(true)
+            .run_args()
+            .contains(&"--network=none".to_string()));
+
+        let args = sandbox.exec_args("abc123");
+        assert_eq!(args[2], "abc123");
+        assert!(args.contains(&"5s".to_string()));
+        assert_eq!(remote_path("data.csv"), "/workspace/data.csv");
+        assert_eq!(remote_path("/tmp/data.csv"), "/tmp/data.csv");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_container_started_once() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // A `docker` that logs its commands and is slow to start a container.
+        let dir = std::env::temp_dir().join(format!("lumo_docker_{}", nanoid::nanoid!(8)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("commands.log");
+        let docker = dir.join("docker");
+        std::fs::write(
+            &docker,
+            format!(
+                "#!/bin/sh\necho \"$1\" >> {}\nif [ \"$1\" = run ]; then sleep 0.2; echo container-1; fi\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&docker, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let sandbox =
+            DockerSandbox::new("python:3.12-slim").with_docker_command(docker.to_str().unwrap());
+        let (first, second) = tokio::join!(sandbox.start(), sandbox.start());
+        first.unwrap();
+        second.unwrap();
+        sandbox.stop().await.unwrap();
+        let commands = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(commands.lines().collect::<Vec<_>>(), vec!["run", "rm"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,8 @@
+//! This module contains the sandboxes that run the code written by agents apart from the process of the agent, such
+//! as a [`DockerSandbox`] that runs it in a container with limits on memory, CPU, processes and network.
+
+pub mod backend;
+pub mod docker;
+
+pub use backend::*;
+pub use docker::*;
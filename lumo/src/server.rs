@@ -0,0 +1,690 @@
+//! An axum server that puts an agent behind an OpenAI-compatible `/v1/chat/completions` endpoint, so that chat
+//! frontends that speak the OpenAI API can talk to a lumo agent without custom integration code. Enabled with the
+//! `server` feature.
+//!
+//! The agent is built per request by an [`AgentFactory`], which gets the request and the conversation before its
+//! last user message. The last user message is the task. Requests with `"stream": true` get `chat.completion.chunk`
+//! server-sent events.
+//!
+//! ```rust,ignore
+//! use lumo::agent::{AgentStream, FunctionCallingAgentBuilder};
+//! use lumo::models::openai::OpenAIServerModelBuilder;
+//! use lumo::server::{router, ChatCompletionRequest};
+//!
+//! let app = router(|request: &ChatCompletionRequest, history| {
+//!     let model = OpenAIServerModelBuilder::new(&request.model).build()?;
+//!     let agent = FunctionCallingAgentBuilder::new(model)
+//!         .with_history(history)
+//!         .with_generation_config(Some(request.generation_config()))
+//!         .build()?;
+//!     Ok(Box::new(agent) as Box<dyn AgentStream>)
+//! });
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+//! axum::serve(listener, app).await?;
+//! ```
+
+use std::{convert::Infallible, sync::Arc};
+
+use anyhow::{bail, Result};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::post,
+    Json, Router,
+};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    agent::{AgentEvent, AgentStream, Step},
+    models::{
+        stream::ChatChunk,
+        types::{GenerationConfig, Message, MessageRole, ToolChoice, Usage},
+    },
+};
+
+/// The body of a chat completion request. The sampling fields take the OpenAI names and forms, see
+/// [`ChatCompletionRequest::generation_config`] for the config they make.
+#[derive(Debug, Deserialize)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    /// The newer name of `max_tokens`, used if both are given.
+    #[serde(default)]
+    pub max_completion_tokens: Option<usize>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
+    #[serde(default)]
+    pub tool_choice: Option<ChatToolChoice>,
+}
+
+impl ChatCompletionRequest {
+    /// The sampling parameters of the request, for the model of the agent.
+    pub fn generation_config(&self) -> GenerationConfig {
+        GenerationConfig {
+            temperature: self.temperature,
+            max_tokens: self.max_completion_tokens.or(self.max_tokens),
+            top_p: self.top_p,
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            seed: self.seed,
+            stop: self.stop.clone().map(StopSequences::into_vec),
+            tool_choice: self.tool_choice.clone().map(ToolChoice::from),
+            ..GenerationConfig::default()
+        }
+    }
+}
+
+/// The `stop` field of a request, which is one sequence or a list of them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl StopSequences {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            StopSequences::One(stop) => vec![stop],
+            StopSequences::Many(stops) => stops,
+        }
+    }
+}
+
+/// The `tool_choice` field of a request: `"auto"`, `"required"`, `"none"` or
+/// `{"type": "function", "function": {"name": ...}}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ChatToolChoice {
+    Mode(ChatToolChoiceMode),
+    Function { function: ChatFunctionName },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatToolChoiceMode {
+    Auto,
+    Required,
+    None,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatFunctionName {
+    pub name: String,
+}
+
+impl From<ChatToolChoice> for ToolChoice {
+    fn from(choice: ChatToolChoice) -> Self {
+        match choice {
+            ChatToolChoice::Mode(ChatToolChoiceMode::Auto) => ToolChoice::Auto,
+            ChatToolChoice::Mode(ChatToolChoiceMode::Required) => ToolChoice::Required,
+            ChatToolChoice::Mode(ChatToolChoiceMode::None) => ToolChoice::None,
+            ChatToolChoice::Function { function } => ToolChoice::Tool(function.name),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<ChatContent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ChatContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentPart {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+impl ChatContent {
+    /// The text of the message. Parts other than text, such as images, are left out.
+    pub fn text(&self) -> String {
+        match self {
+            ChatContent::Text(text) => text.clone(),
+            ChatContent::Parts(parts) => parts
+                .iter()
+                .filter(|part| part.kind == "text")
+                .filter_map(|part| part.text.as_deref())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletion {
+    pub id: String,
+    pub object: &'static str,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<Choice>,
+    pub usage: CompletionUsage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Choice {
+    pub index: usize,
+    pub message: ResponseMessage,
+    pub finish_reason: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseMessage {
+    pub role: &'static str,
+    pub content: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompletionUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl From<Usage> for CompletionUsage {
+    fn from(usage: Usage) -> Self {
+        Self {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: &'static str,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<ChunkChoice>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChunkChoice {
+    pub index: usize,
+    pub delta: Delta,
+    pub finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// The id, creation time and model shared by the response or all the chunks of one completion.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub id: String,
+    pub created: i64,
+    pub model: String,
+}
+
+impl Completion {
+    pub fn new(model: &str) -> Self {
+        Self {
+            id: format!(
+                "chatcmpl-{}",
+                chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+            ),
+            created: chrono::Utc::now().timestamp(),
+            model: model.to_string(),
+        }
+    }
+
+    pub fn response(&self, answer: String, usage: Usage) -> ChatCompletion {
+        ChatCompletion {
+            id: self.id.clone(),
+            object: "chat.completion",
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![Choice {
+                index: 0,
+                message: ResponseMessage {
+                    role: "assistant",
+                    content: answer,
+                },
+                finish_reason: "stop",
+            }],
+            usage: usage.into(),
+        }
+    }
+
+    pub fn chunk(&self, delta: Delta, finish_reason: Option<&'static str>) -> ChatCompletionChunk {
+        ChatCompletionChunk {
+            id: self.id.clone(),
+            object: "chat.completion.chunk",
+            created: self.created,
+            model: self.model.clone(),
+            choices: vec![ChunkChoice {
+                index: 0,
+                delta,
+                finish_reason,
+            }],
+        }
+    }
+}
+
+/// The body of an OpenAI error response.
+pub fn error_body(message: &str, kind: &str) -> serde_json::Value {
+    serde_json::json!({ "error": { "message": message, "type": kind } })
+}
+
+/// Splits the messages of a request into the task, which is the last user message, and the history before it.
+/// Tool messages are dropped since they belong to tools of the client, not of the agent.
+pub fn into_task_and_history(messages: &[ChatMessage]) -> Result<(String, Vec<Message>)> {
+    let Some(last_user) = messages.iter().rposition(|message| message.role == "user") else {
+        bail!("The request must contain a user message");
+    };
+    let text = |message: &ChatMessage| {
+        message
+            .content
+            .as_ref()
+            .map(|content| content.text())
+            .unwrap_or_default()
+    };
+    let task = text(&messages[last_user]);
+    let history = messages[..last_user]
+        .iter()
+        .filter_map(|message| {
+            let role = match message.role.as_str() {
+                "system" | "developer" => MessageRole::System,
+                "user" => MessageRole::User,
+                "assistant" => MessageRole::Assistant,
+                _ => return None,
+            };
+            Some(Message::new(role, &text(message)))
+        })
+        .collect();
+    Ok((task, history))
+}
+
+/// Builds the agent that answers one request. `history` is the conversation before the task, or `None` if the task
+/// is the first message.
+pub trait AgentFactory: Send + Sync + 'static {
+    fn create(
+        &self,
+        request: &ChatCompletionRequest,
+        history: Option<Vec<Message>>,
+    ) -> Result<Box<dyn AgentStream>>;
+}
+
+impl<F> AgentFactory for F
+where
+    F: Fn(&ChatCompletionRequest, Option<Vec<Message>>) -> Result<Box<dyn AgentStream>>
+        + Send
+        + Sync
+        + 'static,
+{
+    fn create(
+        &self,
+        request: &ChatCompletionRequest,
+        history: Option<Vec<Message>>,
+    ) -> Result<Box<dyn AgentStream>> {
+        self(request, history)
+    }
+}
+
+/// A router that serves `POST /v1/chat/completions` with the agents built by `factory`.
+pub fn router(factory: impl AgentFactory) -> Router {
+    let factory: Arc<dyn AgentFactory> = Arc::new(factory);
+    Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .with_state(factory)
+}
+
+fn error_response(status: StatusCode, message: &str, kind: &str) -> Response {
+    (status, Json(error_body(message, kind))).into_response()
+}
+
+async fn chat_completions(
+    State(factory): State<Arc<dyn AgentFactory>>,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Response {
+    let (task, history) = match into_task_and_history(&request.messages) {
+        Ok(task_and_history) => task_and_history,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                &e.to_string(),
+                "invalid_request_error",
+            )
+        }
+    };
+    let history = if history.is_empty() {
+        None
+    } else {
+        Some(history)
+    };
+    let mut agent = match factory.create(&request, history) {
+        Ok(agent) => agent,
+        Err(e) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &e.to_string(),
+                "server_error",
+            )
+        }
+    };
+    let completion = Completion::new(&request.model);
+
+    if request.stream {
+        return Sse::new(sse_events(agent, task, completion))
+            .keep_alive(KeepAlive::default())
+            .into_response();
+    }
+    match agent.run(&task, true).await {
+        Ok(answer) => Json(completion.response(answer, agent.get_usage())).into_response(),
+        Err(e) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &e.to_string(),
+            "agent_error",
+        ),
+    }
+}
+
+fn chunk_event(
+    completion: &Completion,
+    delta: Delta,
+    finish_reason: Option<&'static str>,
+) -> Event {
+    Event::default()
+        .data(serde_json::to_string(&completion.chunk(delta, finish_reason)).unwrap_or_default())
+}
+
+/// Runs the agent and sends the text the model streams as `chat.completion.chunk` events, one per delta. The final
+/// answer follows in a last chunk when the model did not write it as text, e.g. when it called the `final_answer`
+/// tool. Steps without an answer are sent as SSE comments, which clients ignore but which keep the connection alive
+/// during long runs.
+fn sse_events(
+    mut agent: Box<dyn AgentStream>,
+    task: String,
+    completion: Completion,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let content = |text: String| Delta {
+        role: None,
+        content: Some(text),
+    };
+    async_stream::stream! {
+        yield Ok(chunk_event(
+            &completion,
+            Delta {
+                role: Some("assistant"),
+                content: None,
+            },
+            None,
+        ));
+
+        let mut streamed = false;
+        let mut step_text = String::new();
+        let mut error = None;
+        match agent.stream_events(&task, true) {
+            Ok(mut events) => {
+                while let Some(event) = events.next().await {
+                    match event {
+                        Ok(AgentEvent::Chunk(ChatChunk::TextDelta(text))) => {
+                            streamed = true;
+                            step_text.push_str(&text);
+                            yield Ok(chunk_event(&completion, content(text), None));
+                        }
+                        Ok(AgentEvent::Chunk(_)) => {}
+                        Ok(AgentEvent::Step(Step::ActionStep(step))) => {
+                            match step.final_answer {
+                                Some(answer) if answer.text.trim() != step_text.trim() => {
+                                    let text = if streamed {
+                                        format!("\n\n{}", answer.text)
+                                    } else {
+                                        answer.text
+                                    };
+                                    yield Ok(chunk_event(&completion, content(text), None));
+                                }
+                                Some(_) => {}
+                                None => {
+                                    yield Ok(Event::default().comment(format!("step {}", step.step)));
+                                }
+                            }
+                            step_text.clear();
+                        }
+                        Ok(AgentEvent::Step(_)) => {}
+                        Err(e) => {
+                            error = Some(e.to_string());
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => error = Some(e.to_string()),
+        }
+
+        match error {
+            Some(message) => {
+                yield Ok(Event::default().data(error_body(&message, "agent_error").to_string()));
+            }
+            None => yield Ok(chunk_event(&completion, Delta::default(), Some("stop"))),
+        }
+        yield Ok(Event::default().data("[DONE]"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::FunctionCallingAgentBuilder,
+        errors::AgentError,
+        models::{
+            model_traits::{Model, ModelResponse},
+            stream::ModelStream,
+            testing::ScriptedModel,
+        },
+        tools::tool_traits::ToolInfo,
+    };
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_into_task_and_history() {
+        let request: ChatCompletionRequest = serde_json::from_str(
+            r#"{
+                "model": "gpt-4o-mini",
+                "stream": true,
+                "temperature": 0.2,
+                "messages": [
+                    {"role": "system", "content": "Be brief."},
+                    {"role": "user", "content": "Hi"},
+                    {"role": "assistant", "content": "Hello!"},
+                    {"role": "user", "content": [{"type": "text", "text": "What is Rust?"}, {"type": "image_url", "image_url": {"url": "x"}}]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert!(request.stream);
+        assert_eq!(request.generation_config().temperature, Some(0.2));
+
+        let (task, history) = into_task_and_history(&request.messages).unwrap();
+        assert_eq!(task, "What is Rust?");
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].role, MessageRole::System);
+        assert_eq!(history[2].content, "Hello!");
+
+        assert!(into_task_and_history(&[]).is_err());
+    }
+
+    #[test]
+    fn test_generation_config() {
+        let request: ChatCompletionRequest = serde_json::from_str(
+            r#"{
+                "model": "gpt-4o-mini",
+                "messages": [],
+                "max_tokens": 100,
+                "stop": "END",
+                "tool_choice": {"type": "function", "function": {"name": "search"}}
+            }"#,
+        )
+        .unwrap();
+        let config = request.generation_config();
+        assert_eq!(config.max_tokens, Some(100));
+        assert_eq!(config.stop, Some(vec!["END".to_string()]));
+        assert_eq!(config.tool_choice, Some(ToolChoice::tool("search")));
+
+        let request: ChatCompletionRequest = serde_json::from_str(
+            r#"{"model": "gpt-4o-mini", "messages": [], "stop": ["a", "b"], "tool_choice": "none"}"#,
+        )
+        .unwrap();
+        let config = request.generation_config();
+        assert_eq!(config.stop, Some(vec!["a".to_string(), "b".to_string()]));
+        assert_eq!(config.tool_choice, Some(ToolChoice::None));
+    }
+
+    fn app() -> Router {
+        router(|_: &ChatCompletionRequest, history| {
+            let agent = FunctionCallingAgentBuilder::new(
+                ScriptedModel::new().with_final_answer("Rust is a language."),
+            )
+            .with_history(history)
+            .build()?;
+            Ok(Box::new(agent) as Box<dyn AgentStream>)
+        })
+    }
+
+    fn request(body: &str) -> Request<Body> {
+        Request::post("/v1/chat/completions")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn body_text(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_chat_completions() {
+        let response = app()
+            .oneshot(request(
+                r#"{"model": "scripted", "messages": [{"role": "user", "content": "What is Rust?"}]}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let completion: serde_json::Value =
+            serde_json::from_str(&body_text(response).await).unwrap();
+        assert_eq!(completion["object"], "chat.completion");
+        assert_eq!(
+            completion["choices"][0]["message"]["content"],
+            "Rust is a language."
+        );
+
+        let response = app()
+            .oneshot(request(
+                r#"{"model": "scripted", "stream": true, "messages": [{"role": "user", "content": "What is Rust?"}]}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body_text(response).await;
+        assert!(body.contains(r#""content":"Rust is a language.""#));
+        assert!(body.contains(r#""finish_reason":"stop""#));
+        assert!(body.trim_end().ends_with("data: [DONE]"));
+
+        let response = app()
+            .oneshot(request(
+                r#"{"model": "scripted", "messages": [{"role": "system", "content": "Be brief."}]}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Streams its answer as text in two deltas.
+    #[derive(Debug)]
+    struct DeltaModel;
+
+    #[async_trait::async_trait]
+    impl Model for DeltaModel {
+        async fn run(
+            &self,
+            _input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            _config: GenerationConfig,
+        ) -> Result<Box<dyn ModelResponse>, AgentError> {
+            Err(AgentError::Execution("The model only streams".to_string()))
+        }
+
+        async fn run_stream(
+            &self,
+            _input_messages: Vec<Message>,
+            _history: Option<Vec<Message>>,
+            _tools: Vec<ToolInfo>,
+            _config: GenerationConfig,
+        ) -> Result<ModelStream, AgentError> {
+            Ok(Box::pin(futures::stream::iter([
+                Ok(ChatChunk::TextDelta("Rust is ".to_string())),
+                Ok(ChatChunk::TextDelta("a language.".to_string())),
+            ])))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_text_deltas() {
+        let app = router(|_: &ChatCompletionRequest, _| {
+            let agent = FunctionCallingAgentBuilder::new(DeltaModel).build()?;
+            Ok(Box::new(agent) as Box<dyn AgentStream>)
+        });
+        let response = app
+            .oneshot(request(
+                r#"{"model": "delta", "stream": true, "messages": [{"role": "user", "content": "What is Rust?"}]}"#,
+            ))
+            .await
+            .unwrap();
+        let body = body_text(response).await;
+        let contents = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+            .filter_map(|chunk| {
+                chunk["choices"][0]["delta"]["content"]
+                    .as_str()
+                    .map(String::from)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(contents, vec!["Rust is ", "a language."]);
+        assert!(body.contains(r#""finish_reason":"stop""#));
+    }
+}
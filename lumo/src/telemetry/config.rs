@@ -0,0 +1,204 @@
+//! Exporter configuration for sending agent traces to an OTLP collector such as LangFuse, Arize Phoenix or an
+//! OpenTelemetry collector.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::Result;
+use base64::Engine;
+use opentelemetry::{global, trace::TracerProvider, KeyValue};
+use opentelemetry_otlp::{WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::{
+    metrics::{PeriodicReader, SdkMeterProvider},
+    propagation::TraceContextPropagator,
+    trace::{BatchConfigBuilder, BatchSpanProcessor, SdkTracerProvider},
+    Resource,
+};
+
+use super::conventions::{set_semantic_convention, SemanticConvention};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OtlpProtocol {
+    #[default]
+    HttpBinary,
+    HttpJson,
+}
+
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    /// Full URL of the OTLP traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    pub endpoint: String,
+    /// Headers sent with every export request, e.g. `Authorization`.
+    pub headers: HashMap<String, String>,
+    pub protocol: OtlpProtocol,
+    pub convention: SemanticConvention,
+    pub service_name: String,
+    pub resource_attributes: Vec<KeyValue>,
+    pub timeout: Option<Duration>,
+    pub max_queue_size: usize,
+}
+
+impl TelemetryConfig {
+    pub fn new(endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            headers: HashMap::new(),
+            protocol: OtlpProtocol::default(),
+            convention: SemanticConvention::default(),
+            service_name: "lumo".to_string(),
+            resource_attributes: vec![],
+            timeout: None,
+            max_queue_size: 512,
+        }
+    }
+
+    /// Configuration for LangFuse. `host` is the base URL of the LangFuse instance, e.g. `https://cloud.langfuse.com`.
+    pub fn langfuse(host: &str, public_key: &str, secret_key: &str) -> Self {
+        Self::new(&format!(
+            "{}/api/public/otel/v1/traces",
+            host.trim_end_matches('/')
+        ))
+        .with_basic_auth(public_key, secret_key)
+    }
+
+    /// Configuration for Arize Phoenix. `endpoint` defaults to a local Phoenix instance.
+    pub fn phoenix(endpoint: Option<&str>, api_key: Option<&str>) -> Self {
+        let config = Self::new(endpoint.unwrap_or("http://localhost:6006/v1/traces"))
+            .with_convention(SemanticConvention::OpenInference);
+        match api_key {
+            Some(key) => config.with_header("api_key", key),
+            None => config,
+        }
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_headers(mut self, headers: HashMap<String, String>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    pub fn with_basic_auth(self, username: &str, password: &str) -> Self {
+        let credentials = base64::engine::general_purpose::STANDARD
+            .encode(format!("{}:{}", username, password));
+        self.with_header("Authorization", &format!("Basic {}", credentials))
+    }
+
+    pub fn with_protocol(mut self, protocol: OtlpProtocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn with_convention(mut self, convention: SemanticConvention) -> Self {
+        self.convention = convention;
+        self
+    }
+
+    pub fn with_service_name(mut self, service_name: &str) -> Self {
+        self.service_name = service_name.to_string();
+        self
+    }
+
+    pub fn with_resource_attribute(mut self, key: &'static str, value: String) -> Self {
+        self.resource_attributes.push(KeyValue::new(key, value));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_max_queue_size(mut self, max_queue_size: usize) -> Self {
+        self.max_queue_size = max_queue_size;
+        self
+    }
+}
+
+/// Sets up an OTLP span exporter, installs it as the global tracer provider and selects the semantic convention
+/// used by the agents and models.
+///
+/// The returned provider should be flushed and shut down before the program exits so that buffered spans are sent.
+pub fn init_tracing(config: TelemetryConfig) -> Result<SdkTracerProvider> {
+    set_semantic_convention(config.convention);
+
+    let protocol = match config.protocol {
+        OtlpProtocol::HttpBinary => opentelemetry_otlp::Protocol::HttpBinary,
+        OtlpProtocol::HttpJson => opentelemetry_otlp::Protocol::HttpJson,
+    };
+    let mut exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(config.endpoint.clone())
+        .with_protocol(protocol)
+        .with_headers(config.headers.clone());
+    if let Some(timeout) = config.timeout {
+        exporter = exporter.with_timeout(timeout);
+    }
+    let exporter = exporter.build()?;
+
+    let batch = BatchSpanProcessor::builder(exporter)
+        .with_batch_config(
+            BatchConfigBuilder::default()
+                .with_max_queue_size(config.max_queue_size)
+                .build(),
+        )
+        .build();
+
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_span_processor(batch)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .with_attributes(config.resource_attributes.clone())
+                .build(),
+        )
+        .build();
+
+    // Initialize the tracer
+    let _ = tracer_provider.tracer("lumo");
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    global::set_tracer_provider(tracer_provider.clone());
+
+    Ok(tracer_provider)
+}
+
+/// Sets up an OTLP metric exporter for the tool metrics and installs it as the global meter provider. The metrics are
+/// sent to the `/v1/metrics` endpoint next to the traces endpoint of `config`.
+///
+/// The returned provider should be shut down before the program exits so that the last metrics are sent.
+pub fn init_metrics(config: &TelemetryConfig) -> Result<SdkMeterProvider> {
+    let protocol = match config.protocol {
+        OtlpProtocol::HttpBinary => opentelemetry_otlp::Protocol::HttpBinary,
+        OtlpProtocol::HttpJson => opentelemetry_otlp::Protocol::HttpJson,
+    };
+    let endpoint = match config.endpoint.strip_suffix("/v1/traces") {
+        Some(base) => format!("{}/v1/metrics", base),
+        None => config.endpoint.clone(),
+    };
+    let mut exporter = opentelemetry_otlp::MetricExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .with_protocol(protocol)
+        .with_headers(config.headers.clone());
+    if let Some(timeout) = config.timeout {
+        exporter = exporter.with_timeout(timeout);
+    }
+    let exporter = exporter.build()?;
+
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter).build())
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .with_attributes(config.resource_attributes.clone())
+                .build(),
+        )
+        .build();
+    global::set_meter_provider(meter_provider.clone());
+
+    Ok(meter_provider)
+}
@@ -0,0 +1,217 @@
+//! Span attribute helpers for the semantic conventions understood by common LLM observability backends.
+//!
+//! [OpenInference](https://github.com/Arize-ai/openinference) is used by Arize Phoenix, the
+//! [OpenTelemetry GenAI conventions](https://opentelemetry.io/docs/specs/semconv/gen-ai/) are used by most other
+//! backends. LangFuse understands both. The convention is selected globally, usually through
+//! [`init_tracing`](super::init_tracing).
+
+use std::sync::RwLock;
+
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Serialize};
+
+use crate::models::types::GenerationConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SemanticConvention {
+    /// OpenInference attributes (`openinference.span.kind`, `input.value`, `llm.model_name`, ...)
+    OpenInference,
+    /// OpenTelemetry GenAI attributes (`gen_ai.operation.name`, `gen_ai.request.model`, ...)
+    GenAi,
+    /// Emit both sets of attributes.
+    #[default]
+    Both,
+}
+
+impl SemanticConvention {
+    fn open_inference(&self) -> bool {
+        matches!(self, Self::OpenInference | Self::Both)
+    }
+
+    fn gen_ai(&self) -> bool {
+        matches!(self, Self::GenAi | Self::Both)
+    }
+}
+
+static CONVENTION: RwLock<SemanticConvention> = RwLock::new(SemanticConvention::Both);
+
+pub fn set_semantic_convention(convention: SemanticConvention) {
+    *CONVENTION.write().unwrap() = convention;
+}
+
+pub fn semantic_convention() -> SemanticConvention {
+    *CONVENTION.read().unwrap()
+}
+
+/// The kind of work a span represents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanCategory {
+    Agent,
+    Chain,
+    Llm,
+    Tool,
+}
+
+/// The attributes of a convention. The functions below give the attributes of the global one.
+impl SemanticConvention {
+    pub fn span_kind_attributes(self, category: SpanCategory) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        if self.open_inference() {
+            let kind = match category {
+                SpanCategory::Agent => "AGENT",
+                SpanCategory::Chain => "CHAIN",
+                SpanCategory::Llm => "LLM",
+                SpanCategory::Tool => "TOOL",
+            };
+            attributes.push(KeyValue::new("openinference.span.kind", kind));
+        }
+        if self.gen_ai() {
+            let operation = match category {
+                SpanCategory::Agent => "invoke_agent",
+                SpanCategory::Chain => "agent_step",
+                SpanCategory::Llm => "chat",
+                SpanCategory::Tool => "execute_tool",
+            };
+            attributes.push(KeyValue::new("gen_ai.operation.name", operation));
+        }
+        attributes
+    }
+
+    pub fn input_attributes(self, value: String) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        if self.gen_ai() {
+            attributes.push(KeyValue::new("gen_ai.prompt", value.clone()));
+        }
+        if self.open_inference() {
+            attributes.push(KeyValue::new("input.value", value));
+        }
+        attributes
+    }
+
+    pub fn output_attributes(self, value: String) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        if self.gen_ai() {
+            attributes.push(KeyValue::new("gen_ai.completion", value.clone()));
+        }
+        if self.open_inference() {
+            attributes.push(KeyValue::new("output.value", value));
+        }
+        attributes
+    }
+
+    pub fn model_attributes(self, provider: &str, model_id: &str) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        if self.open_inference() {
+            attributes.push(KeyValue::new("llm.model_name", model_id.to_string()));
+            attributes.push(KeyValue::new("llm.provider", provider.to_string()));
+        }
+        if self.gen_ai() {
+            attributes.push(KeyValue::new("gen_ai.request.model", model_id.to_string()));
+            attributes.push(KeyValue::new("gen_ai.system", provider.to_string()));
+        }
+        attributes
+    }
+
+    pub fn generation_config_attributes(self, config: &GenerationConfig) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        if self.open_inference() {
+            attributes.push(KeyValue::new(
+                "llm.invocation_parameters",
+                serde_json::to_string(config).unwrap_or_default(),
+            ));
+        }
+        if self.gen_ai() {
+            if let Some(top_p) = config.top_p {
+                attributes.push(KeyValue::new("gen_ai.request.top_p", top_p as f64));
+            }
+            if let Some(frequency_penalty) = config.frequency_penalty {
+                attributes.push(KeyValue::new(
+                    "gen_ai.request.frequency_penalty",
+                    frequency_penalty as f64,
+                ));
+            }
+            if let Some(presence_penalty) = config.presence_penalty {
+                attributes.push(KeyValue::new(
+                    "gen_ai.request.presence_penalty",
+                    presence_penalty as f64,
+                ));
+            }
+            if let Some(seed) = config.seed {
+                attributes.push(KeyValue::new("gen_ai.request.seed", seed as i64));
+            }
+            if let Some(stop) = &config.stop {
+                attributes.push(KeyValue::new(
+                    "gen_ai.request.stop_sequences",
+                    serde_json::to_string(stop).unwrap_or_default(),
+                ));
+            }
+        }
+        attributes
+    }
+
+    pub fn tool_attributes(self, name: &str, arguments: String) -> Vec<KeyValue> {
+        let mut attributes = Vec::new();
+        if self.open_inference() {
+            attributes.push(KeyValue::new("tool.name", name.to_string()));
+            attributes.push(KeyValue::new("tool.parameters", arguments.clone()));
+        }
+        if self.gen_ai() {
+            attributes.push(KeyValue::new("gen_ai.tool.name", name.to_string()));
+            attributes.push(KeyValue::new("gen_ai.tool.arguments", arguments));
+        }
+        attributes
+    }
+}
+
+pub fn span_kind_attributes(category: SpanCategory) -> Vec<KeyValue> {
+    semantic_convention().span_kind_attributes(category)
+}
+
+pub fn input_attributes(value: String) -> Vec<KeyValue> {
+    semantic_convention().input_attributes(value)
+}
+
+pub fn output_attributes(value: String) -> Vec<KeyValue> {
+    semantic_convention().output_attributes(value)
+}
+
+pub fn model_attributes(provider: &str, model_id: &str) -> Vec<KeyValue> {
+    semantic_convention().model_attributes(provider, model_id)
+}
+
+pub fn generation_config_attributes(config: &GenerationConfig) -> Vec<KeyValue> {
+    semantic_convention().generation_config_attributes(config)
+}
+
+pub fn tool_attributes(name: &str, arguments: String) -> Vec<KeyValue> {
+    semantic_convention().tool_attributes(name, arguments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convention_selects_attributes() {
+        let keys = SemanticConvention::OpenInference
+            .span_kind_attributes(SpanCategory::Tool)
+            .into_iter()
+            .map(|kv| kv.key.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["openinference.span.kind"]);
+
+        assert_eq!(
+            SemanticConvention::Both
+                .input_attributes("hi".to_string())
+                .len(),
+            2
+        );
+        assert_eq!(
+            SemanticConvention::GenAi
+                .tool_attributes("search", "{}".to_string())
+                .len(),
+            2
+        );
+    }
+}
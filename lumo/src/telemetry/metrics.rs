@@ -0,0 +1,202 @@
+//! Metrics of the tool calls, recorded through the OpenTelemetry metrics API.
+//!
+//! - `lumo.tool.calls`: a counter of the calls, by `tool` and `status` (`ok` or `error`).
+//! - `lumo.tool.duration`: a histogram of the duration of the calls in seconds, by `tool` and `status`.
+//! - `lumo.tool.errors`: a counter of the failed calls, by `tool` and `error.type`.
+//! - `lumo.tool.cache.lookups`: a counter of the lookups of tool caches, by `tool` and `hit`. The hit rate of a tool
+//!   is the count with `hit = true` over the count of all its lookups.
+//!
+//! The instruments are made from the global meter provider on the first call, so install the provider, the OTLP
+//! exporter of [`init_metrics`](super::init_metrics), a Prometheus exporter or any other, before the agents run.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, Meter},
+    KeyValue,
+};
+
+use crate::errors::{AgentError, ToolError};
+
+const METER_NAME: &str = "lumo";
+
+/// The `error.type` of a failed tool call.
+pub fn error_category(error: &AgentError) -> &'static str {
+    match error {
+        AgentError::Tool(ToolError::InvalidArguments(_)) => "invalid_arguments",
+        AgentError::Tool(ToolError::Timeout(_)) => "timeout",
+        AgentError::Tool(ToolError::RateLimited { .. }) => "rate_limited",
+        AgentError::Tool(ToolError::Fatal(_)) => "fatal",
+        AgentError::Tool(ToolError::Recoverable(_)) => "recoverable",
+        AgentError::Execution(_) => "execution",
+        AgentError::Cancelled => "cancelled",
+        _ => "other",
+    }
+}
+
+/// The instruments of the tool metrics.
+pub struct ToolMetrics {
+    calls: Counter<u64>,
+    duration: Histogram<f64>,
+    errors: Counter<u64>,
+    cache_lookups: Counter<u64>,
+}
+
+impl ToolMetrics {
+    pub fn new(meter: &Meter) -> Self {
+        Self {
+            calls: meter
+                .u64_counter("lumo.tool.calls")
+                .with_description("Calls of the tools")
+                .build(),
+            duration: meter
+                .f64_histogram("lumo.tool.duration")
+                .with_description("Duration of the tool calls")
+                .with_unit("s")
+                .build(),
+            errors: meter
+                .u64_counter("lumo.tool.errors")
+                .with_description("Failed calls of the tools")
+                .build(),
+            cache_lookups: meter
+                .u64_counter("lumo.tool.cache.lookups")
+                .with_description("Lookups in the caches of the tools")
+                .build(),
+        }
+    }
+
+    /// The instruments of the global meter provider, made on the first call.
+    pub fn global() -> &'static Self {
+        static METRICS: OnceLock<ToolMetrics> = OnceLock::new();
+        METRICS.get_or_init(|| Self::new(&global::meter(METER_NAME)))
+    }
+
+    /// Records a call of `tool` that took `duration`, and failed with `error` if there is one.
+    pub fn record_call(&self, tool: &str, duration: Duration, error: Option<&AgentError>) {
+        let status = if error.is_some() { "error" } else { "ok" };
+        let attributes = [
+            KeyValue::new("tool", tool.to_string()),
+            KeyValue::new("status", status),
+        ];
+        self.calls.add(1, &attributes);
+        self.duration.record(duration.as_secs_f64(), &attributes);
+        if let Some(error) = error {
+            self.errors.add(
+                1,
+                &[
+                    KeyValue::new("tool", tool.to_string()),
+                    KeyValue::new("error.type", error_category(error)),
+                ],
+            );
+        }
+    }
+
+    /// Records a lookup in the cache of `tool`.
+    pub fn record_cache(&self, tool: &str, hit: bool) {
+        self.cache_lookups.add(
+            1,
+            &[
+                KeyValue::new("tool", tool.to_string()),
+                KeyValue::new("hit", hit),
+            ],
+        );
+    }
+}
+
+/// Records a call of `tool` that took `duration`, and failed with `error` if there is one.
+pub fn record_tool_call(tool: &str, duration: Duration, error: Option<&AgentError>) {
+    ToolMetrics::global().record_call(tool, duration, error);
+}
+
+/// Records a lookup in the cache of `tool`. Tools and wrappers that cache their outputs call this so that the hit rate
+/// shows up with the other tool metrics. The loop detector of an agent counts as the cache of its tools: a repeated
+/// call that gets the earlier result is a hit.
+pub fn record_tool_cache(tool: &str, hit: bool) {
+    ToolMetrics::global().record_cache(tool, hit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_metrics() {
+        assert_eq!(
+            error_category(&AgentError::Tool(ToolError::RateLimited {
+                message: "slow down".to_string(),
+                retry_after: None,
+            })),
+            "rate_limited"
+        );
+        assert_eq!(
+            error_category(&AgentError::Tool(ToolError::InvalidArguments(
+                "missing `query`".to_string()
+            ))),
+            "invalid_arguments"
+        );
+        assert_eq!(error_category(&AgentError::Cancelled), "cancelled");
+
+        // Without a meter provider the metrics go to the no-op provider.
+        record_tool_call(
+            "search",
+            Duration::from_millis(120),
+            Some(&AgentError::Execution("timed out".to_string())),
+        );
+        record_tool_cache("search", true);
+    }
+
+    #[test]
+    fn test_tool_metrics_export() {
+        use opentelemetry::metrics::MeterProvider;
+        use opentelemetry_sdk::metrics::{data::Sum, InMemoryMetricExporter, SdkMeterProvider};
+
+        let exporter = InMemoryMetricExporter::default();
+        let provider = SdkMeterProvider::builder()
+            .with_periodic_exporter(exporter.clone())
+            .build();
+        let metrics = ToolMetrics::new(&provider.meter(METER_NAME));
+        metrics.record_call("search", Duration::from_millis(120), None);
+        metrics.record_call(
+            "search",
+            Duration::from_millis(80),
+            Some(&AgentError::Tool(ToolError::Timeout("slow".to_string()))),
+        );
+        metrics.record_cache("search", false);
+        metrics.record_cache("search", true);
+        metrics.record_cache("search", true);
+        provider.force_flush().unwrap();
+
+        // The sum of the counter `name` over the data points with the attribute `key = value`.
+        let count = |name: &str, key: &str, value: &str| -> u64 {
+            let mut count = 0;
+            for resource_metrics in exporter.get_finished_metrics().unwrap() {
+                for metric in resource_metrics
+                    .scope_metrics
+                    .iter()
+                    .flat_map(|scope| &scope.metrics)
+                    .filter(|metric| metric.name == name)
+                {
+                    let sum = metric.data.as_any().downcast_ref::<Sum<u64>>().unwrap();
+                    count += sum
+                        .data_points
+                        .iter()
+                        .filter(|point| {
+                            point.attributes.iter().any(|attribute| {
+                                attribute.key.as_str() == key && attribute.value.as_str() == value
+                            })
+                        })
+                        .map(|point| point.value)
+                        .sum::<u64>();
+                }
+            }
+            count
+        };
+        assert_eq!(count("lumo.tool.calls", "status", "ok"), 1);
+        assert_eq!(count("lumo.tool.calls", "status", "error"), 1);
+        assert_eq!(count("lumo.tool.errors", "error.type", "timeout"), 1);
+        assert_eq!(count("lumo.tool.cache.lookups", "hit", "true"), 2);
+        assert_eq!(count("lumo.tool.cache.lookups", "hit", "false"), 1);
+    }
+}
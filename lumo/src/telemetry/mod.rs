@@ -1,3 +1,13 @@
+#[cfg(all(feature = "otlp", not(target_arch = "wasm32")))]
+pub mod config;
+pub mod conventions;
+pub mod metrics;
+
+#[cfg(all(feature = "otlp", not(target_arch = "wasm32")))]
+pub use config::*;
+pub use conventions::*;
+pub use metrics::*;
+
 use chrono;
 use opentelemetry::{
     global::{self},
@@ -7,11 +17,12 @@ use opentelemetry::{
 use serde_json::Value;
 use tracing;
 
-use crate::models::openai::ToolCall;
+use crate::{errors::AgentError, models::openai::ToolCall};
 
 pub struct AgentTelemetry {
     tracer_name: String,
     current_context: Option<Context>,
+    run_id: Option<String>,
 }
 
 impl AgentTelemetry {
@@ -19,9 +30,22 @@ impl AgentTelemetry {
         Self {
             tracer_name: tracer_name.to_string(),
             current_context: None,
+            run_id: None,
         }
     }
 
+    /// The run whose id is added to the step and tool spans.
+    pub fn set_run_id(&mut self, run_id: Option<String>) {
+        self.run_id = run_id;
+    }
+
+    fn run_attributes(&self) -> Vec<KeyValue> {
+        self.run_id
+            .iter()
+            .map(|run_id| KeyValue::new("lumo.run_id", run_id.clone()))
+            .collect()
+    }
+
     pub fn start_step(&mut self, step_number: i64) -> Context {
         let parent_cx = Context::current();
         let tracer_name = self.tracer_name.clone();
@@ -33,13 +57,19 @@ impl AgentTelemetry {
         let span = tracer
             .span_builder(format!("Step {}", step_number))
             .with_kind(SpanKind::Internal)
-            .with_start_time(std::time::SystemTime::now())
-            .with_attributes(vec![
-                KeyValue::new("gen_ai.operation.name", "agent_step"),
-                KeyValue::new("step_type", "action"),
-                KeyValue::new("step_number", step_number),
-                KeyValue::new("start_time", start_time),
-            ])
+            .with_start_time(crate::runtime::now())
+            .with_attributes(
+                [
+                    span_kind_attributes(SpanCategory::Chain),
+                    vec![
+                        KeyValue::new("step_type", "action"),
+                        KeyValue::new("step_number", step_number),
+                        KeyValue::new("start_time", start_time),
+                    ],
+                    self.run_attributes(),
+                ]
+                .concat(),
+            )
             .start_with_context(&tracer, &parent_cx);
 
         let cx = Context::current_with_span(span);
@@ -49,8 +79,7 @@ impl AgentTelemetry {
 
     pub fn log_agent_memory(&self, agent_memory: &Value) {
         if let Some(cx) = &self.current_context {
-            cx.span().set_attribute(KeyValue::new(
-                "input.value",
+            cx.span().set_attributes(input_attributes(
                 serde_json::to_string(agent_memory).unwrap_or_default(),
             ));
         }
@@ -87,27 +116,22 @@ impl AgentTelemetry {
         let span = tracer
             .span_builder(function_name.to_string())
             .with_kind(SpanKind::Internal)
-            .with_attributes(vec![
-                KeyValue::new("gen_ai.operation.name", "tool_calls"),
-                KeyValue::new("timestamp", chrono::Utc::now().to_rfc3339()),
-            ])
-            .with_start_time(std::time::SystemTime::now())
-            .start_with_context(&tracer, &cx);
+            .with_attributes(
+                [
+                    span_kind_attributes(SpanCategory::Tool),
+                    vec![KeyValue::new("timestamp", chrono::Utc::now().to_rfc3339())],
+                    self.run_attributes(),
+                ]
+                .concat(),
+            )
+            .with_start_time(crate::runtime::now())
+            .start_with_context(&tracer, cx);
         let cx = Context::current_with_span(span);
 
-       
+        let arguments = serde_json::to_string(arguments).unwrap_or_default();
         cx.span()
-            .set_attribute(KeyValue::new("gen_ai.tool.name", function_name.to_string()));
-        cx.span().set_attributes(vec![
-            KeyValue::new(
-                "gen_ai.tool.arguments",
-                serde_json::to_string(arguments).unwrap_or_default(),
-            ),
-            KeyValue::new(
-                "input.value",
-                serde_json::to_string(arguments).unwrap_or_default(),
-            ),
-        ]);
+            .set_attributes(tool_attributes(function_name, arguments.clone()));
+        cx.span().set_attributes(input_attributes(arguments));
         cx
     }
 
@@ -123,15 +147,23 @@ impl AgentTelemetry {
             cx.span().set_status(Status::error("Tool call failed"));
             tracing::error!("Error executing tool call: {}", result);
         }
-        cx.span()
-            .set_attribute(KeyValue::new("output.value", result.to_string()));
+        cx.span().set_attributes(output_attributes(result.to_string()));
+    }
+
+    /// Records the metrics of a tool call, see [`metrics`].
+    pub fn record_tool_call(
+        &self,
+        function_name: &str,
+        duration: std::time::Duration,
+        error: Option<&AgentError>,
+    ) {
+        record_tool_call(function_name, duration, error);
     }
 
     pub fn log_final_answer(&self, answer: &str) {
         if let Some(cx) = &self.current_context {
             tracing::info!(answer = %answer, "Final answer received");
-            cx.span()
-                .set_attribute(KeyValue::new("output.value", answer.to_string()));
+            cx.span().set_attributes(output_attributes(answer.to_string()));
         }
     }
 
@@ -146,15 +178,14 @@ impl AgentTelemetry {
             } else {
                 tracing::info!("Observation: {}", observation_text);
             }
-            cx.span()
-                .set_attribute(KeyValue::new("output.value", observation_text));
+            cx.span().set_attributes(output_attributes(observation_text));
         }
     }
 
     pub fn end_step(&mut self) {
         if let Some(cx) = self.current_context.take() {
             // End the span with the current timestamp
-            let end_time = std::time::SystemTime::now();
+            let end_time = crate::runtime::now();
             cx.span().set_attribute(KeyValue::new("end_time", chrono::Utc::now().to_rfc3339()));
             cx.span().end_with_timestamp(end_time);
 
@@ -0,0 +1,315 @@
+//! This module contains the agent tool. It lets an agent hand a task to another agent through a regular tool call,
+//! with a richer input than the single `task` string of managed agents, and get a structured report back.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::tool_traits::{AnyTool, AsyncTool, ToolFunctionInfo, ToolInfo, ToolType};
+use crate::{
+    agent::{Agent, Step},
+    context::RunContext,
+    errors::{AgentError, ToolError},
+};
+
+/// The maximum number of sources listed in a report.
+const MAX_SOURCES: usize = 20;
+
+/// What a sub-agent sends back to the agent that called it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentReport {
+    pub agent: String,
+    pub answer: String,
+    /// The number of steps the sub-agent took.
+    pub steps: usize,
+    /// The tool calls the sub-agent made, in order.
+    pub actions: Vec<String>,
+    /// The urls the sub-agent visited or found in its observations.
+    pub sources: Vec<String>,
+}
+
+impl AgentReport {
+    pub fn from_logs(agent: &str, answer: String, logs: &[Step]) -> Self {
+        let url_regex = regex::Regex::new(r#"https?://[^\s"'<>)\]]+"#).unwrap();
+        let mut steps = 0;
+        let mut actions = Vec::new();
+        let mut sources: Vec<String> = Vec::new();
+        for step in logs {
+            let Step::ActionStep(step) = step else {
+                continue;
+            };
+            steps += 1;
+            let mut texts = Vec::new();
+            for tool_call in step.tool_call.iter().flatten() {
+                if tool_call.function.name == "final_answer" {
+                    continue;
+                }
+                let arguments = tool_call.function.arguments.to_string();
+                actions.push(format!("{}({})", tool_call.function.name, arguments));
+                texts.push(arguments);
+            }
+            texts.extend(step.observations.iter().flatten().cloned());
+            for text in &texts {
+                for url in url_regex.find_iter(text) {
+                    let url = url.as_str().trim_end_matches(['.', ',', ';']);
+                    if sources.len() < MAX_SOURCES && !sources.iter().any(|source| source == url) {
+                        sources.push(url.to_string());
+                    }
+                }
+            }
+        }
+        Self {
+            agent: agent.to_string(),
+            answer,
+            steps,
+            actions,
+            sources,
+        }
+    }
+}
+
+/// Exposes an agent as a tool. The default parameters are `task`, `context` and `expected_output`; use
+/// [`AgentTool::with_parameters`] to ask the calling agent for different fields. Every string argument other than
+/// `task` is added to the task as a labelled section.
+#[derive(Clone)]
+pub struct AgentTool {
+    agent: Arc<Mutex<Box<dyn Agent>>>,
+    name: &'static str,
+    description: &'static str,
+    parameters: Value,
+}
+
+impl AgentTool {
+    pub fn new(agent: Box<dyn Agent>) -> Self {
+        let name = agent.name();
+        let description = agent.description();
+        Self {
+            agent: Arc::new(Mutex::new(agent)),
+            name,
+            description,
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "task": {
+                        "type": "string",
+                        "description": "The task to perform. Be specific and complete, the team member does not see your conversation."
+                    },
+                    "context": {
+                        "type": "string",
+                        "description": "What is already known that can help with the task"
+                    },
+                    "expected_output": {
+                        "type": "string",
+                        "description": "What the answer should contain and how it should be formatted"
+                    }
+                },
+                "required": ["task"]
+            }),
+        }
+    }
+
+    /// Replaces the JSON schema of the tool arguments. The schema must have a string `task` property.
+    pub fn with_parameters(mut self, parameters: Value) -> Self {
+        self.parameters = parameters;
+        self
+    }
+}
+
+/// Writes the arguments of a call as the task for the sub-agent.
+fn task_from_arguments(arguments: &Value) -> Result<String, ToolError> {
+    let mut task = arguments["task"]
+        .as_str()
+        .ok_or_else(|| ToolError::InvalidArguments("The `task` argument is required".to_string()))?
+        .to_string();
+    if let Some(arguments) = arguments.as_object() {
+        for (name, value) in arguments.iter().filter(|(name, _)| *name != "task") {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                Value::Null => continue,
+                value => value.to_string(),
+            };
+            let mut label = name.replace('_', " ");
+            if let Some(first) = label.get_mut(0..1) {
+                first.make_ascii_uppercase();
+            }
+            task.push_str(&format!("\n\n{}:\n{}", label, value));
+        }
+    }
+    Ok(task)
+}
+
+impl AnyTool for AgentTool {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn tool_info(&self) -> ToolInfo {
+        ToolInfo {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo {
+                name: self.name.to_string(),
+                description: self.description.to_string(),
+                parameters: self.parameters.clone(),
+            },
+        }
+    }
+}
+
+impl AgentTool {
+    /// Runs the sub-agent on the task of the call. In a run, the sub-agent gets the [`RunContext`] of the run, so it
+    /// reads and writes the same values as the agent that called it and stops when that run is cancelled. It also
+    /// runs one level deeper than that agent, like a managed agent: its steps count towards the
+    /// [`DelegationLimits`](crate::agent::DelegationLimits) of the calling agent and its run is a child of the run
+    /// of the calling agent.
+    async fn run_agent(
+        &self,
+        json_args: Value,
+        context: Option<&RunContext>,
+    ) -> Result<String, AgentError> {
+        let task = task_from_arguments(&json_args)?;
+        let mut agent = self.agent.lock().await;
+        if let Some(context) = context {
+            if let Some(delegation) = context.caller_delegation() {
+                agent.set_delegation(delegation.child(agent.name())?);
+                agent.set_parent_run_id(context.caller_run_id().map(str::to_string));
+            }
+            agent.set_context(context.clone());
+        }
+        let answer = agent.run(&task, true).await?;
+        let report = AgentReport::from_logs(self.name, answer, agent.get_logs_mut());
+        serde_json::to_string_pretty(&report).map_err(|e| AgentError::Execution(e.to_string()))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AsyncTool for AgentTool {
+    async fn forward_json(&self, json_args: Value) -> Result<String, AgentError> {
+        self.run_agent(json_args, None).await
+    }
+
+    async fn call(&self, json_args: Value, context: &RunContext) -> Result<String, AgentError> {
+        self.run_agent(json_args, Some(context)).await
+    }
+
+    fn clone_box(&self) -> Box<dyn AsyncTool> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        agent::{AgentStep, Delegation, DelegationLimits},
+        models::openai::{FunctionCall, ToolCall},
+    };
+
+    #[test]
+    fn test_task_from_arguments() {
+        let task = task_from_arguments(&json!({
+            "task": "Find the population of Eindhoven",
+            "expected_output": "A number",
+            "context": null
+        }))
+        .unwrap();
+        assert_eq!(
+            task,
+            "Find the population of Eindhoven\n\nExpected output:\nA number"
+        );
+        assert!(task_from_arguments(&json!({"context": "x"})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_agent_tool_shares_context() {
+        let agent = crate::agent::FunctionCallingAgentBuilder::new(
+            crate::models::testing::ScriptedModel::new().with_final_answer("done"),
+        )
+        .build()
+        .unwrap();
+        let tool = AgentTool::new(Box::new(agent));
+        let context = RunContext::new();
+        let report = AsyncTool::call(&tool, json!({"task": "Do the task"}), &context)
+            .await
+            .unwrap();
+        assert!(report.contains("\"answer\": \"done\""));
+
+        tool.agent
+            .lock()
+            .await
+            .get_context()
+            .insert("written_by", json!("researcher"));
+        assert_eq!(context.get_value("written_by"), Some(json!("researcher")));
+    }
+
+    #[tokio::test]
+    async fn test_agent_tool_delegation() {
+        let limits = DelegationLimits::new().with_max_depth(1);
+        let agent = crate::agent::FunctionCallingAgentBuilder::new(
+            crate::models::testing::ScriptedModel::new().with_final_answer("done"),
+        )
+        .build()
+        .unwrap();
+        let tool = AgentTool::new(Box::new(agent));
+        let root = Delegation::new(limits);
+        let context = RunContext::new().with_caller(Some("run-1".to_string()), &root);
+        AsyncTool::call(&tool, json!({"task": "Do the task"}), &context)
+            .await
+            .unwrap();
+        {
+            let agent = tool.agent.lock().await;
+            assert_eq!(agent.get_delegation().depth, 1);
+            assert_eq!(agent.get_parent_run_id(), Some("run-1".to_string()));
+        }
+        assert_eq!(root.sub_agent_steps(), 1);
+
+        let nested = root.child("manager").unwrap();
+        let context = RunContext::new().with_caller(Some("run-2".to_string()), &nested);
+        let result = AsyncTool::call(&tool, json!({"task": "Do the task"}), &context).await;
+        assert!(matches!(result, Err(AgentError::DelegationLimit(_))));
+    }
+
+    #[test]
+    fn test_report_from_logs() {
+        let logs = vec![
+            Step::TaskStep("task".to_string()),
+            Step::ActionStep(AgentStep {
+                tool_call: Some(vec![ToolCall {
+                    id: None,
+                    call_type: Some("function".to_string()),
+                    function: FunctionCall {
+                        name: "visit_website".to_string(),
+                        arguments: json!({"url": "https://example.com/a"}),
+                    },
+                }]),
+                observations: Some(vec![
+                    "See https://example.com/b. And https://example.com/a".to_string()
+                ]),
+                step: 1,
+                ..Default::default()
+            }),
+            Step::ActionStep(AgentStep {
+                step: 2,
+                final_answer: Some("done".into()),
+                ..Default::default()
+            }),
+        ];
+        let report = AgentReport::from_logs("researcher", "done".to_string(), &logs);
+        assert_eq!(report.steps, 2);
+        assert_eq!(
+            report.actions,
+            vec![r#"visit_website({"url":"https://example.com/a"})"#]
+        );
+        assert_eq!(
+            report.sources,
+            vec!["https://example.com/a", "https://example.com/b"]
+        );
+    }
+}
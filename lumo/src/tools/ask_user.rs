@@ -0,0 +1,191 @@
+//! This module contains the ask user tool. The model uses this tool to ask the user a question in the middle of a
+//! run, and the reply is its observation.
+//!
+//! The question goes to the [`AgentIo`] of the host application, and the run waits until it replies. [`StdinIo`]
+//! asks on the terminal. [`ChannelIo`] sends every question with a reply channel to a receiver, for applications
+//! such as servers that answer from another task.
+//!
+//! ```rust,ignore
+//! let (io, mut questions) = ChannelIo::new();
+//! tokio::spawn(async move {
+//!     while let Some(question) = questions.recv().await {
+//!         let reply = frontend.ask(&question.question).await;
+//!         question.reply(reply);
+//!     }
+//! });
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_io(Arc::new(io))
+//!     .build()?;
+//! ```
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+
+use super::base::BaseTool;
+use super::tool_traits::Tool;
+
+/// The way an agent talks to its user while it runs.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait AgentIo: Send + Sync {
+    /// Asks the user `question` and waits for the reply.
+    async fn ask(&self, question: &str) -> Result<String>;
+}
+
+/// Asks on standard output and reads the reply from standard input.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone, Default)]
+pub struct StdinIo;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AgentIo for StdinIo {
+    async fn ask(&self, question: &str) -> Result<String> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let mut stdout = tokio::io::stdout();
+        stdout
+            .write_all(format!("{}\n> ", question).as_bytes())
+            .await?;
+        stdout.flush().await?;
+        let mut reply = String::new();
+        BufReader::new(tokio::io::stdin())
+            .read_line(&mut reply)
+            .await?;
+        Ok(reply.trim().to_string())
+    }
+}
+
+/// A question of the agent, sent by a [`ChannelIo`].
+#[derive(Debug)]
+pub struct UserQuestion {
+    pub question: String,
+    reply: oneshot::Sender<String>,
+}
+
+impl UserQuestion {
+    /// Resumes the run with `reply` as the answer to the question.
+    pub fn reply(self, reply: impl Into<String>) {
+        let _ = self.reply.send(reply.into());
+    }
+}
+
+/// Sends the questions of the agent to a receiver, which replies to them with [`UserQuestion::reply`].
+#[derive(Debug, Clone)]
+pub struct ChannelIo {
+    questions: mpsc::UnboundedSender<UserQuestion>,
+}
+
+impl ChannelIo {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<UserQuestion>) {
+        let (questions, receiver) = mpsc::unbounded_channel();
+        (Self { questions }, receiver)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AgentIo for ChannelIo {
+    async fn ask(&self, question: &str) -> Result<String> {
+        let (reply, receiver) = oneshot::channel();
+        self.questions
+            .send(UserQuestion {
+                question: question.to_string(),
+                reply,
+            })
+            .ok()
+            .context("Nobody is listening for questions to the user")?;
+        receiver
+            .await
+            .context("The question to the user was dropped without a reply")
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "AskUserToolParams")]
+pub struct AskUserToolParams {
+    #[schemars(description = "The question to ask the user")]
+    question: String,
+}
+
+#[derive(Clone)]
+pub struct AskUserTool {
+    pub tool: BaseTool,
+    pub io: Arc<dyn AgentIo>,
+}
+
+impl AskUserTool {
+    pub fn new(io: Arc<dyn AgentIo>) -> Self {
+        AskUserTool {
+            tool: BaseTool {
+                name: "ask_user",
+                description: "Asks the user a question and returns the reply. Use it when the task is ambiguous or misses information that only the user has, not for things you can find out yourself.",
+            },
+            io,
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Tool for AskUserTool {
+    type Params = AskUserToolParams;
+    fn name(&self) -> &'static str {
+        self.tool.name
+    }
+    fn description(&self) -> &'static str {
+        self.tool.description
+    }
+    async fn forward(&self, arguments: AskUserToolParams) -> Result<String> {
+        let reply = self.io.ask(&arguments.question).await?;
+        if reply.trim().is_empty() {
+            return Ok("The user did not reply.".to_string());
+        }
+        Ok(format!("The user replied: {}", reply))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ask_user_tool() {
+        let (io, mut questions) = ChannelIo::new();
+        tokio::spawn(async move {
+            while let Some(question) = questions.recv().await {
+                let reply = if question.question.contains("city") {
+                    "Eindhoven"
+                } else {
+                    ""
+                };
+                question.reply(reply);
+            }
+        });
+        let tool = AskUserTool::new(Arc::new(io));
+        let reply = tool
+            .forward(AskUserToolParams {
+                question: "Which city do you mean?".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply, "The user replied: Eindhoven");
+        let reply = tool
+            .forward(AskUserToolParams {
+                question: "Anything else?".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply, "The user did not reply.");
+
+        let (io, questions) = ChannelIo::new();
+        drop(questions);
+        assert!(io.ask("Still there?").await.is_err());
+    }
+}
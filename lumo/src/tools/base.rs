@@ -20,7 +20,8 @@ pub struct BaseTool {
     pub description: &'static str,
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Tool for BaseTool {
     type Params = serde_json::Value;
     fn name(&self) -> &'static str {
@@ -86,7 +86,8 @@ impl DuckDuckGoSearchTool {
     }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Tool for DuckDuckGoSearchTool {
     type Params = DuckDuckGoSearchToolParams;
     fn name(&self) -> &'static str {
@@ -92,7 +92,8 @@ impl ExaSearchTool {
     }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Tool for ExaSearchTool {
     type Params = ExaSearchToolParams;
     fn name(&self) -> &'static str {
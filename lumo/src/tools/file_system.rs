@@ -0,0 +1,679 @@
+//! This module contains the file system tools. The model uses these tools to read, write, list and patch files
+//! inside a configured root directory. Paths that resolve outside of the root are rejected.
+
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::base::BaseTool;
+use super::tool_traits::Tool;
+
+/// Default maximum number of bytes a file system tool will read or write.
+pub const DEFAULT_MAX_FILE_BYTES: usize = 1024 * 1024;
+
+/// Default maximum number of entries returned by the list directory tool.
+pub const DEFAULT_MAX_DIR_ENTRIES: usize = 500;
+
+/// A root directory that all file system tools are confined to.
+#[derive(Debug, Serialize, Clone)]
+pub struct FileSystemRoot {
+    pub root: PathBuf,
+    pub max_bytes: usize,
+}
+
+impl FileSystemRoot {
+    pub fn new(root: impl Into<PathBuf>, max_bytes: Option<usize>) -> Self {
+        Self {
+            root: root.into(),
+            max_bytes: max_bytes.unwrap_or(DEFAULT_MAX_FILE_BYTES),
+        }
+    }
+
+    /// Resolves a path given by the model to a path inside the root directory.
+    ///
+    /// The path is normalized lexically first, so `..` components can never climb above the root. The deepest
+    /// existing ancestor is then canonicalized to make sure symlinks do not point outside of the root either.
+    /// Symlinks whose target does not exist are rejected, since writing through them could create a file anywhere.
+    pub fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let root = fs::canonicalize(&self.root)
+            .map_err(|e| anyhow!("Root directory {:?} is not accessible: {}", self.root, e))?;
+
+        let requested = Path::new(path);
+        let relative = if requested.is_absolute() {
+            requested
+                .strip_prefix(&root)
+                .or_else(|_| requested.strip_prefix(&self.root))
+                .map_err(|_| anyhow!("Path '{}' is outside of the allowed root directory", path))?
+                .to_path_buf()
+        } else {
+            requested.to_path_buf()
+        };
+
+        let mut normalized = PathBuf::new();
+        for component in relative.components() {
+            match component {
+                Component::CurDir => {}
+                Component::Normal(part) => normalized.push(part),
+                Component::ParentDir => {
+                    if !normalized.pop() {
+                        return Err(anyhow!(
+                            "Path '{}' is outside of the allowed root directory",
+                            path
+                        ));
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => {
+                    return Err(anyhow!(
+                        "Path '{}' is outside of the allowed root directory",
+                        path
+                    ));
+                }
+            }
+        }
+
+        let resolved = root.join(&normalized);
+
+        // Walk up to the deepest ancestor that exists and make sure it does not escape through a symlink. A
+        // symlink counts as existing even when its target does not, so a dangling link is never written through.
+        let mut existing = resolved.as_path();
+        while fs::symlink_metadata(existing).is_err() {
+            existing = match existing.parent() {
+                Some(parent) => parent,
+                None => break,
+            };
+        }
+        let canonical = fs::canonicalize(existing).map_err(|e| {
+            if existing.is_symlink() {
+                anyhow!("Path '{}' goes through a symlink whose target does not exist", path)
+            } else {
+                anyhow!("Failed to resolve path '{}': {}", path, e)
+            }
+        })?;
+        if !canonical.starts_with(&root) {
+            return Err(anyhow!(
+                "Path '{}' is outside of the allowed root directory",
+                path
+            ));
+        }
+
+        Ok(resolved)
+    }
+
+    fn display(&self, path: &Path) -> String {
+        let root = fs::canonicalize(&self.root).unwrap_or_else(|_| self.root.clone());
+        path.strip_prefix(&root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "FileReadToolParams")]
+pub struct FileReadToolParams {
+    #[schemars(description = "The path of the file to read, relative to the root directory")]
+    path: String,
+    #[schemars(description = "The line to start reading from, starting at 1. Defaults to 1")]
+    offset: Option<usize>,
+    #[schemars(description = "The maximum number of lines to read. Defaults to the whole file")]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FileReadTool {
+    pub tool: BaseTool,
+    pub root: FileSystemRoot,
+}
+
+impl FileReadTool {
+    pub fn new(root: impl Into<PathBuf>, max_bytes: Option<usize>) -> Self {
+        FileReadTool {
+            tool: BaseTool {
+                name: "read_file",
+                description: "Reads the content of a text file. Optionally reads only a range of lines.",
+            },
+            root: FileSystemRoot::new(root, max_bytes),
+        }
+    }
+
+    pub fn forward(&self, path: &str, offset: Option<usize>, limit: Option<usize>) -> Result<String> {
+        let resolved = self.root.resolve(path)?;
+        if !resolved.is_file() {
+            return Err(anyhow!("File '{}' does not exist", path));
+        }
+        let size = fs::metadata(&resolved)?.len() as usize;
+        if size > self.root.max_bytes && offset.is_none() && limit.is_none() {
+            return Err(anyhow!(
+                "File '{}' is {} bytes which exceeds the {} byte limit. Read it in parts using offset and limit.",
+                path,
+                size,
+                self.root.max_bytes
+            ));
+        }
+        let file = fs::File::open(&resolved)
+            .map_err(|e| anyhow!("Failed to read file '{}': {}", path, e))?;
+
+        // Lines are read one at a time and the reading stops at the byte limit, so a large `limit` never reads
+        // more of the file than the limit allows.
+        let start = offset.unwrap_or(1).max(1) - 1;
+        let mut selected = String::new();
+        let mut truncated = false;
+        for line in BufReader::new(file)
+            .lines()
+            .skip(start)
+            .take(limit.unwrap_or(usize::MAX))
+        {
+            let line = line.map_err(|e| anyhow!("Failed to read file '{}': {}", path, e))?;
+            if !selected.is_empty() {
+                selected.push('\n');
+            }
+            selected.push_str(&line);
+            if selected.len() > self.root.max_bytes {
+                truncated = true;
+                break;
+            }
+        }
+        if truncated {
+            let mut end = self.root.max_bytes;
+            while !selected.is_char_boundary(end) {
+                end -= 1;
+            }
+            selected.truncate(end);
+            return Ok(format!(
+                "{}\n....This content has been truncated due to the {} byte limit.....",
+                selected, self.root.max_bytes
+            ));
+        }
+        Ok(selected)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Tool for FileReadTool {
+    type Params = FileReadToolParams;
+    fn name(&self) -> &'static str {
+        self.tool.name
+    }
+    fn description(&self) -> &'static str {
+        self.tool.description
+    }
+    async fn forward(&self, arguments: FileReadToolParams) -> Result<String> {
+        self.forward(&arguments.path, arguments.offset, arguments.limit)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "FileWriteToolParams")]
+pub struct FileWriteToolParams {
+    #[schemars(description = "The path of the file to write, relative to the root directory")]
+    path: String,
+    #[schemars(description = "The content to write to the file")]
+    content: String,
+    #[schemars(description = "Append to the file instead of overwriting it. Defaults to false")]
+    append: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FileWriteTool {
+    pub tool: BaseTool,
+    pub root: FileSystemRoot,
+}
+
+impl FileWriteTool {
+    pub fn new(root: impl Into<PathBuf>, max_bytes: Option<usize>) -> Self {
+        FileWriteTool {
+            tool: BaseTool {
+                name: "write_file",
+                description: "Writes content to a text file, creating the file and its parent directories if needed.",
+            },
+            root: FileSystemRoot::new(root, max_bytes),
+        }
+    }
+
+    pub fn forward(&self, path: &str, content: &str, append: bool) -> Result<String> {
+        if content.len() > self.root.max_bytes {
+            return Err(anyhow!(
+                "Content is {} bytes which exceeds the {} byte limit",
+                content.len(),
+                self.root.max_bytes
+            ));
+        }
+        let resolved = self.root.resolve(path)?;
+        if resolved.is_dir() {
+            return Err(anyhow!("'{}' is a directory", path));
+        }
+        if let Some(parent) = resolved.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if append {
+            let existing = fs::metadata(&resolved).map(|m| m.len() as usize).unwrap_or(0);
+            if existing + content.len() > self.root.max_bytes {
+                return Err(anyhow!(
+                    "Appending would grow '{}' beyond the {} byte limit",
+                    path,
+                    self.root.max_bytes
+                ));
+            }
+            use std::io::Write;
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&resolved)?;
+            file.write_all(content.as_bytes())?;
+        } else {
+            fs::write(&resolved, content)?;
+        }
+        Ok(format!(
+            "Wrote {} bytes to {}",
+            content.len(),
+            self.root.display(&resolved)
+        ))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Tool for FileWriteTool {
+    type Params = FileWriteToolParams;
+    fn name(&self) -> &'static str {
+        self.tool.name
+    }
+    fn description(&self) -> &'static str {
+        self.tool.description
+    }
+    async fn forward(&self, arguments: FileWriteToolParams) -> Result<String> {
+        self.forward(
+            &arguments.path,
+            &arguments.content,
+            arguments.append.unwrap_or(false),
+        )
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "ListDirToolParams")]
+pub struct ListDirToolParams {
+    #[schemars(description = "The directory to list, relative to the root directory. Defaults to the root")]
+    path: Option<String>,
+    #[schemars(description = "Whether to list subdirectories recursively. Defaults to false")]
+    recursive: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ListDirTool {
+    pub tool: BaseTool,
+    pub root: FileSystemRoot,
+    pub max_entries: usize,
+}
+
+impl ListDirTool {
+    pub fn new(root: impl Into<PathBuf>, max_entries: Option<usize>) -> Self {
+        ListDirTool {
+            tool: BaseTool {
+                name: "list_dir",
+                description: "Lists the files and directories in a directory. Directories end with a '/'.",
+            },
+            root: FileSystemRoot::new(root, None),
+            max_entries: max_entries.unwrap_or(DEFAULT_MAX_DIR_ENTRIES),
+        }
+    }
+
+    pub fn forward(&self, path: &str, recursive: bool) -> Result<String> {
+        let resolved = self.root.resolve(path)?;
+        if !resolved.is_dir() {
+            return Err(anyhow!("Directory '{}' does not exist", path));
+        }
+        let mut entries = Vec::new();
+        let mut truncated = false;
+        let mut stack = vec![resolved];
+        while let Some(dir) = stack.pop() {
+            let mut children = fs::read_dir(&dir)?
+                .filter_map(|entry| entry.ok())
+                .collect::<Vec<_>>();
+            children.sort_by_key(|entry| entry.file_name());
+            for child in children {
+                if entries.len() >= self.max_entries {
+                    truncated = true;
+                    break;
+                }
+                let child_path = child.path();
+                let file_type = child.file_type()?;
+                let display = self.root.display(&child_path);
+                if file_type.is_dir() {
+                    entries.push(format!("{}/", display));
+                    if recursive {
+                        stack.push(child_path);
+                    }
+                } else {
+                    let size = child.metadata().map(|m| m.len()).unwrap_or(0);
+                    entries.push(format!("{} ({} bytes)", display, size));
+                }
+            }
+            if truncated {
+                break;
+            }
+        }
+        if entries.is_empty() {
+            return Ok(format!("Directory '{}' is empty", path));
+        }
+        let mut listing = entries.join("\n");
+        if truncated {
+            listing.push_str(&format!(
+                "\n....Listing truncated after {} entries.....",
+                self.max_entries
+            ));
+        }
+        Ok(listing)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Tool for ListDirTool {
+    type Params = ListDirToolParams;
+    fn name(&self) -> &'static str {
+        self.tool.name
+    }
+    fn description(&self) -> &'static str {
+        self.tool.description
+    }
+    async fn forward(&self, arguments: ListDirToolParams) -> Result<String> {
+        self.forward(
+            arguments.path.as_deref().unwrap_or("."),
+            arguments.recursive.unwrap_or(false),
+        )
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "ApplyPatchToolParams")]
+pub struct ApplyPatchToolParams {
+    #[schemars(description = "The path of the file to patch, relative to the root directory")]
+    path: String,
+    #[schemars(
+        description = "A unified diff to apply to the file. Each hunk starts with a '@@ -start,count +start,count @@' header
+        followed by lines prefixed with ' ' (context), '-' (removed) or '+' (added)"
+    )]
+    patch: String,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ApplyPatchTool {
+    pub tool: BaseTool,
+    pub root: FileSystemRoot,
+}
+
+impl ApplyPatchTool {
+    pub fn new(root: impl Into<PathBuf>, max_bytes: Option<usize>) -> Self {
+        ApplyPatchTool {
+            tool: BaseTool {
+                name: "apply_patch",
+                description: "Applies a unified diff to a text file. Use this to make targeted edits instead of rewriting whole files.",
+            },
+            root: FileSystemRoot::new(root, max_bytes),
+        }
+    }
+
+    pub fn forward(&self, path: &str, patch: &str) -> Result<String> {
+        if patch.len() > self.root.max_bytes {
+            return Err(anyhow!(
+                "Patch is {} bytes which exceeds the {} byte limit",
+                patch.len(),
+                self.root.max_bytes
+            ));
+        }
+        let resolved = self.root.resolve(path)?;
+        let original = if resolved.is_file() {
+            fs::read_to_string(&resolved)?
+        } else {
+            String::new()
+        };
+        let (patched, hunks) = apply_unified_patch(&original, patch)?;
+        if patched.len() > self.root.max_bytes {
+            return Err(anyhow!(
+                "Patched file would exceed the {} byte limit",
+                self.root.max_bytes
+            ));
+        }
+        if let Some(parent) = resolved.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&resolved, patched)?;
+        Ok(format!(
+            "Applied {} hunk(s) to {}",
+            hunks,
+            self.root.display(&resolved)
+        ))
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Tool for ApplyPatchTool {
+    type Params = ApplyPatchToolParams;
+    fn name(&self) -> &'static str {
+        self.tool.name
+    }
+    fn description(&self) -> &'static str {
+        self.tool.description
+    }
+    async fn forward(&self, arguments: ApplyPatchToolParams) -> Result<String> {
+        self.forward(&arguments.path, &arguments.patch)
+    }
+}
+
+struct Hunk {
+    old_start: usize,
+    lines: Vec<(char, String)>,
+}
+
+fn parse_hunk_start(header: &str) -> Result<usize> {
+    // "@@ -12,5 +12,7 @@ optional section"
+    let old = header
+        .trim_start_matches('@')
+        .split_whitespace()
+        .find(|part| part.starts_with('-'))
+        .ok_or_else(|| anyhow!("Invalid hunk header: {}", header))?;
+    let start = old[1..].split(',').next().unwrap_or("0");
+    start
+        .parse::<usize>()
+        .map_err(|_| anyhow!("Invalid hunk header: {}", header))
+}
+
+fn parse_hunks(patch: &str) -> Result<Vec<Hunk>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for line in patch.lines() {
+        if line.starts_with("@@") {
+            hunks.push(Hunk {
+                old_start: parse_hunk_start(line)?,
+                lines: Vec::new(),
+            });
+            continue;
+        }
+        let Some(hunk) = hunks.last_mut() else {
+            // Skip file headers such as "--- a/file" and "+++ b/file" before the first hunk.
+            continue;
+        };
+        if line.starts_with('\\') {
+            // "\ No newline at end of file"
+            continue;
+        }
+        match line.chars().next() {
+            Some(kind @ (' ' | '-' | '+')) => hunk.lines.push((kind, line[1..].to_string())),
+            // Some models drop the leading space of empty context lines.
+            None => hunk.lines.push((' ', String::new())),
+            Some(_) => return Err(anyhow!("Invalid patch line: '{}'", line)),
+        }
+    }
+    if hunks.is_empty() {
+        return Err(anyhow!("The patch does not contain any hunks"));
+    }
+    Ok(hunks)
+}
+
+fn matches_at(lines: &[String], expected: &[&str], at: usize) -> bool {
+    at + expected.len() <= lines.len()
+        && expected
+            .iter()
+            .enumerate()
+            .all(|(i, line)| lines[at + i].trim_end() == line.trim_end())
+}
+
+/// Applies a unified diff to `original` and returns the patched content together with the number of applied hunks.
+///
+/// Hunks are applied at the line given in their header when the context matches, otherwise the closest position
+/// where the context matches is used. A hunk whose context cannot be found makes the whole patch fail.
+pub fn apply_unified_patch(original: &str, patch: &str) -> Result<(String, usize)> {
+    let hunks = parse_hunks(patch)?;
+    let mut lines: Vec<String> = original.lines().map(|l| l.to_string()).collect();
+    let trailing_newline = original.is_empty() || original.ends_with('\n');
+
+    // Offset between line numbers in the original file and the current state of `lines`.
+    let mut delta: isize = 0;
+    for (index, hunk) in hunks.iter().enumerate() {
+        let expected: Vec<&str> = hunk
+            .lines
+            .iter()
+            .filter(|(kind, _)| *kind != '+')
+            .map(|(_, line)| line.as_str())
+            .collect();
+        let replacement: Vec<String> = hunk
+            .lines
+            .iter()
+            .filter(|(kind, _)| *kind != '-')
+            .map(|(_, line)| line.clone())
+            .collect();
+
+        let hint = (hunk.old_start.max(1) as isize - 1 + delta).max(0) as usize;
+        let position = if expected.is_empty() {
+            Some(hint.min(lines.len()))
+        } else if matches_at(&lines, &expected, hint) {
+            Some(hint)
+        } else {
+            (0..=lines.len())
+                .filter(|at| matches_at(&lines, &expected, *at))
+                .min_by_key(|at| at.abs_diff(hint))
+        };
+        let position = position.ok_or_else(|| {
+            anyhow!(
+                "Hunk {} does not match the file content. Read the file again and regenerate the patch.",
+                index + 1
+            )
+        })?;
+
+        lines.splice(position..position + expected.len(), replacement.iter().cloned());
+        delta += replacement.len() as isize - expected.len() as isize;
+    }
+
+    let mut patched = lines.join("\n");
+    if trailing_newline && !patched.is_empty() {
+        patched.push('\n');
+    }
+    Ok((patched, hunks.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("lumo_fs_{}", nanoid::nanoid!(8)));
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn test_resolve_rejects_traversal() {
+        let root = temp_root();
+        let fs_root = FileSystemRoot::new(&root, None);
+        assert!(fs_root.resolve("../etc/passwd").is_err());
+        assert!(fs_root.resolve("a/../../b").is_err());
+        assert!(fs_root.resolve("/etc/passwd").is_err());
+        assert!(fs_root.resolve("a/../b.txt").is_ok());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_rejects_dangling_symlink() {
+        let root = temp_root();
+        let outside = temp_root();
+        std::os::unix::fs::symlink(outside.join("escaped.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(outside.join("missing"), root.join("dir")).unwrap();
+        let fs_root = FileSystemRoot::new(&root, None);
+        assert!(fs_root.resolve("link.txt").is_err());
+        assert!(fs_root.resolve("dir/escaped.txt").is_err());
+        assert!(FileWriteTool::new(&root, None)
+            .forward("link.txt", "escaped", false)
+            .is_err());
+        assert!(!outside.join("escaped.txt").exists());
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_read_and_list() {
+        let root = temp_root();
+        let write = FileWriteTool::new(&root, None);
+        let read = FileReadTool::new(&root, None);
+        let list = ListDirTool::new(&root, None);
+
+        write.forward("src/main.txt", "one\ntwo\nthree\n", false).unwrap();
+        assert_eq!(read.forward("src/main.txt", Some(2), Some(1)).unwrap(), "two");
+        let listing = list.forward(".", true).unwrap();
+        assert!(listing.contains("src/"));
+        assert!(listing.contains("main.txt"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_write_respects_size_limit() {
+        let root = temp_root();
+        let write = FileWriteTool::new(&root, Some(4));
+        assert!(write.forward("big.txt", "too large", false).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_read_respects_size_limit_with_offset_and_limit() {
+        let root = temp_root();
+        fs::write(root.join("big.txt"), "0123456789\n".repeat(10)).unwrap();
+        let read = FileReadTool::new(&root, Some(15));
+        assert!(read.forward("big.txt", None, None).is_err());
+        for (offset, limit) in [(Some(1), None), (None, Some(1000)), (Some(3), Some(1000))] {
+            let content = read.forward("big.txt", offset, limit).unwrap();
+            assert!(content.starts_with("0123456789\n01234\n"));
+            assert!(content.contains("truncated due to the 15 byte limit"));
+        }
+        assert_eq!(
+            read.forward("big.txt", Some(2), Some(1)).unwrap(),
+            "0123456789"
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_apply_unified_patch() {
+        let original = "fn main() {\n    println!(\"hello\");\n}\n";
+        let patch = "--- a/main.rs\n+++ b/main.rs\n@@ -1,3 +1,4 @@\n fn main() {\n-    println!(\"hello\");\n+    println!(\"hello\");\n+    println!(\"world\");\n }\n";
+        let (patched, hunks) = apply_unified_patch(original, patch).unwrap();
+        assert_eq!(hunks, 1);
+        assert_eq!(
+            patched,
+            "fn main() {\n    println!(\"hello\");\n    println!(\"world\");\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_unified_patch_rejects_mismatch() {
+        let original = "a\nb\nc\n";
+        let patch = "@@ -1,2 +1,2 @@\n x\n-y\n+z\n";
+        assert!(apply_unified_patch(original, patch).is_err());
+    }
+}
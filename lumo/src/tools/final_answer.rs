@@ -31,7 +31,8 @@ impl FinalAnswerTool {
     }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Tool for FinalAnswerTool {
     type Params = FinalAnswerToolParams;
     fn name(&self) -> &'static str {
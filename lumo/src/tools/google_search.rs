@@ -125,7 +125,8 @@ impl GoogleSearchTool {
     }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Tool for GoogleSearchTool {
     type Params = GoogleSearchToolParams;
     fn name(&self) -> &'static str {
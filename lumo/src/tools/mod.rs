@@ -1,11 +1,22 @@
 //! This module contains the tools that can be used in an agent. These are the default tools that are available.
 //! You can also implement your own tools by implementing the `Tool` trait.
 
+pub mod agent_tool;
+pub mod ask_user;
 pub mod base;
 pub mod ddg_search;
+pub mod file_system;
 pub mod final_answer;
 pub mod google_search;
+pub mod openapi;
+pub mod postprocess;
+pub mod read_artifact;
+pub mod registry;
+pub mod retriever;
+pub mod search_tools;
+pub mod tool_formats;
 pub mod tool_traits;
+pub mod validation;
 pub mod visit_website;
 pub mod exa_search;
 pub mod tavily_search;
@@ -13,11 +24,21 @@ pub mod tavily_search;
 #[cfg(feature = "code-agent")]
 pub mod python_interpreter;
 
+pub use agent_tool::*;
+pub use ask_user::*;
 pub use base::*;
 pub use ddg_search::*;
+pub use file_system::*;
 pub use final_answer::*;
 pub use google_search::*;
+pub use openapi::*;
+pub use postprocess::*;
+pub use read_artifact::*;
+pub use registry::*;
+pub use retriever::*;
+pub use search_tools::*;
 pub use tool_traits::*;
+pub use validation::*;
 pub use visit_website::*;
 pub use tavily_search::*;
 
@@ -0,0 +1,645 @@
+//! This module turns the operations of an OpenAPI 3 spec into tools, so that an agent can call a REST API without a
+//! hand-written wrapper for every endpoint.
+//!
+//! Every operation becomes one tool, named after its `operationId`. The path, query and header parameters of the
+//! operation and its JSON request body become the arguments of the tool, and the response is returned as the
+//! observation, truncated to a maximum size.
+//!
+//! ```rust,ignore
+//! let toolset = OpenApiToolset::load("petstore.yaml")?
+//!     .with_bearer_token(&std::env::var("PETSTORE_TOKEN")?)
+//!     .with_operations(&["listPets", "showPetById"]);
+//! let agent = FunctionCallingAgentBuilder::new(model)
+//!     .with_tools(toolset.tools())
+//!     .build()?;
+//! ```
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Map, Value};
+
+use super::tool_traits::{AnyTool, AsyncTool, ToolFunctionInfo, ToolInfo, ToolType};
+use crate::{
+    artifacts::truncate_observation,
+    context::RunContext,
+    errors::{AgentError, ToolError},
+};
+
+const METHODS: [&str; 7] = ["get", "put", "post", "delete", "options", "head", "patch"];
+
+/// How deep `$ref`s are followed, so that a recursive schema does not expand forever.
+const MAX_REF_DEPTH: usize = 8;
+
+/// The maximum length of a tool name accepted by the chat APIs.
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterLocation {
+    Path,
+    Query,
+    Header,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenApiParameter {
+    pub name: String,
+    pub location: ParameterLocation,
+}
+
+/// The settings shared by the tools of a toolset.
+#[derive(Debug)]
+struct ApiSettings {
+    client: reqwest::Client,
+    base_url: String,
+    headers: Vec<(String, String)>,
+    max_response_size: usize,
+}
+
+/// The operations of an OpenAPI 3 spec, to be turned into tools with [`OpenApiToolset::tools`].
+#[derive(Debug, Clone)]
+pub struct OpenApiToolset {
+    spec: Value,
+    base_url: Option<String>,
+    headers: Vec<(String, String)>,
+    operations: Option<Vec<String>>,
+    max_response_size: usize,
+    timeout: Duration,
+}
+
+impl OpenApiToolset {
+    /// Reads a spec that was already parsed from JSON or YAML.
+    pub fn from_spec(spec: Value) -> Result<Self> {
+        match spec.get("openapi").and_then(Value::as_str) {
+            Some(version) if version.starts_with('3') => {}
+            Some(version) => bail!(
+                "Unsupported OpenAPI version {}, only OpenAPI 3 is supported",
+                version
+            ),
+            None => bail!("The spec has no `openapi` version, only OpenAPI 3 specs are supported"),
+        }
+        if !spec.get("paths").is_some_and(Value::is_object) {
+            bail!("The spec has no `paths`");
+        }
+        Ok(Self {
+            spec,
+            base_url: None,
+            headers: Vec::new(),
+            operations: None,
+            max_response_size: 10000,
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    pub fn from_json(spec: &str) -> Result<Self> {
+        Self::from_spec(serde_json::from_str(spec).context("The spec is not valid JSON")?)
+    }
+
+    pub fn from_yaml(spec: &str) -> Result<Self> {
+        Self::from_spec(serde_yaml::from_str(spec).context("The spec is not valid YAML")?)
+    }
+
+    /// Reads a spec from a `.json`, `.yaml` or `.yml` file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let spec = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the spec {}", path.display()))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&spec),
+            _ => Self::from_yaml(&spec),
+        }
+    }
+
+    /// Overrides the first server of the spec.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    /// Sends `name: value` with every request, e.g. for an API key. The value can refer to the values of the run as
+    /// `{{context.<key>}}`.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_bearer_token(self, token: &str) -> Self {
+        self.with_header("Authorization", &format!("Bearer {}", token))
+    }
+
+    /// Only turns the operations with these `operationId`s into tools.
+    pub fn with_operations(mut self, operation_ids: &[&str]) -> Self {
+        self.operations = Some(operation_ids.iter().map(|id| id.to_string()).collect());
+        self
+    }
+
+    /// The maximum number of characters of a response. Defaults to 10000.
+    pub fn with_max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+
+    /// The timeout of a request. Defaults to 30 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn base_url(&self) -> String {
+        self.base_url
+            .clone()
+            .or_else(|| self.spec["servers"][0]["url"].as_str().map(str::to_string))
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    /// One tool per operation of the spec, in the order of the spec.
+    pub fn operation_tools(&self) -> Vec<OpenApiTool> {
+        let settings = Arc::new(ApiSettings {
+            client: reqwest::Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .unwrap_or_else(|_| reqwest::Client::new()),
+            base_url: self.base_url(),
+            headers: self.headers.clone(),
+            max_response_size: self.max_response_size,
+        });
+        let mut tools = Vec::new();
+        let Some(paths) = self.spec["paths"].as_object() else {
+            return tools;
+        };
+        for (path, path_item) in paths {
+            let path_item = self.resolve(path_item, 0);
+            for method in METHODS {
+                let Some(operation) = path_item.get(method) else {
+                    continue;
+                };
+                let operation_id = operation["operationId"].as_str();
+                if let Some(operations) = &self.operations {
+                    if !operation_id
+                        .is_some_and(|id| operations.iter().any(|allowed| allowed == id))
+                    {
+                        continue;
+                    }
+                }
+                let name = tool_name(operation_id.unwrap_or(&format!("{}_{}", method, path)));
+                tools.push(
+                    self.operation_tool(name, method, path, &path_item, operation, &settings),
+                );
+            }
+        }
+        tools
+    }
+
+    /// The tools of [`OpenApiToolset::operation_tools`], to pass to an agent builder.
+    pub fn tools(&self) -> Vec<Box<dyn AsyncTool>> {
+        self.operation_tools()
+            .into_iter()
+            .map(|tool| Box::new(tool) as Box<dyn AsyncTool>)
+            .collect()
+    }
+
+    fn operation_tool(
+        &self,
+        name: String,
+        method: &str,
+        path: &str,
+        path_item: &Value,
+        operation: &Value,
+        settings: &Arc<ApiSettings>,
+    ) -> OpenApiTool {
+        let mut properties = Map::new();
+        let mut required = Vec::new();
+        let mut parameters = Vec::new();
+        // Parameters of the operation override the parameters of the path with the same name and location.
+        let declared = path_item["parameters"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .chain(operation["parameters"].as_array().into_iter().flatten());
+        for parameter in declared {
+            let parameter = self.resolve(parameter, 0);
+            let location = match parameter["in"].as_str() {
+                Some("path") => ParameterLocation::Path,
+                Some("query") => ParameterLocation::Query,
+                Some("header") => ParameterLocation::Header,
+                _ => continue,
+            };
+            let Some(parameter_name) = parameter["name"].as_str() else {
+                continue;
+            };
+            let mut schema = self.resolve(&parameter["schema"], 0);
+            if !schema.is_object() {
+                schema = json!({"type": "string"});
+            }
+            if let Some(description) = parameter["description"].as_str() {
+                schema["description"] = json!(description);
+            }
+            properties.insert(parameter_name.to_string(), schema);
+            let is_required = location == ParameterLocation::Path
+                || parameter["required"].as_bool().unwrap_or(false);
+            parameters.retain(|existing: &OpenApiParameter| existing.name != parameter_name);
+            required.retain(|existing: &String| existing != parameter_name);
+            if is_required {
+                required.push(parameter_name.to_string());
+            }
+            parameters.push(OpenApiParameter {
+                name: parameter_name.to_string(),
+                location,
+            });
+        }
+
+        let request_body = self.resolve(&operation["requestBody"], 0);
+        let body_schema = request_body["content"]["application/json"]["schema"].clone();
+        let has_body = !body_schema.is_null();
+        if has_body {
+            let mut schema = self.resolve(&body_schema, 0);
+            if let Some(description) = request_body["description"].as_str() {
+                schema["description"] = json!(description);
+            }
+            properties.insert("body".to_string(), schema);
+            if request_body["required"].as_bool().unwrap_or(false) {
+                required.push("body".to_string());
+            }
+        }
+
+        let description = [&operation["summary"], &operation["description"]]
+            .iter()
+            .filter_map(|text| text.as_str())
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let description = if description.is_empty() {
+            format!("Calls {} {}", method.to_uppercase(), path)
+        } else {
+            description
+        };
+
+        OpenApiTool {
+            name: Box::leak(name.into_boxed_str()),
+            description: Box::leak(description.into_boxed_str()),
+            method: method.to_uppercase(),
+            path: path.to_string(),
+            parameters,
+            has_body,
+            schema: json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            }),
+            settings: settings.clone(),
+        }
+    }
+
+    /// Replaces the local `$ref`s of `value` with what they point to.
+    fn resolve(&self, value: &Value, depth: usize) -> Value {
+        match value {
+            Value::Object(map) => {
+                if let Some(reference) = map.get("$ref").and_then(Value::as_str) {
+                    let target = reference
+                        .strip_prefix('#')
+                        .and_then(|pointer| self.spec.pointer(pointer));
+                    return match target {
+                        Some(target) if depth < MAX_REF_DEPTH => self.resolve(target, depth + 1),
+                        _ => json!({}),
+                    };
+                }
+                Value::Object(
+                    map.iter()
+                        .map(|(key, value)| (key.clone(), self.resolve(value, depth)))
+                        .collect(),
+                )
+            }
+            Value::Array(values) => Value::Array(
+                values
+                    .iter()
+                    .map(|value| self.resolve(value, depth))
+                    .collect(),
+            ),
+            value => value.clone(),
+        }
+    }
+}
+
+/// Keeps the characters that tool names allow.
+fn tool_name(name: &str) -> String {
+    let mut name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    while name.contains("__") {
+        name = name.replace("__", "_");
+    }
+    name.trim_matches('_')
+        .chars()
+        .take(MAX_NAME_LENGTH)
+        .collect()
+}
+
+/// Percent-encodes a path parameter.
+fn encode_path_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn argument_to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// One operation of an OpenAPI spec.
+#[derive(Debug, Clone)]
+pub struct OpenApiTool {
+    name: &'static str,
+    description: &'static str,
+    method: String,
+    path: String,
+    parameters: Vec<OpenApiParameter>,
+    has_body: bool,
+    schema: Value,
+    settings: Arc<ApiSettings>,
+}
+
+impl OpenApiTool {
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn parameters(&self) -> &[OpenApiParameter] {
+        &self.parameters
+    }
+
+    /// The url of a call, with the path parameters filled in.
+    fn url(&self, arguments: &Value) -> Result<String, ToolError> {
+        let mut path = self.path.clone();
+        for parameter in &self.parameters {
+            if parameter.location != ParameterLocation::Path {
+                continue;
+            }
+            let value = arguments
+                .get(&parameter.name)
+                .filter(|value| !value.is_null());
+            let Some(value) = value else {
+                return Err(ToolError::InvalidArguments(format!(
+                    "The path parameter `{}` is required",
+                    parameter.name
+                )));
+            };
+            path = path.replace(
+                &format!("{{{}}}", parameter.name),
+                &encode_path_segment(&argument_to_string(value)),
+            );
+        }
+        Ok(format!("{}{}", self.settings.base_url, path))
+    }
+
+    /// The header parameters of a call and the headers of the toolset, rendered with `context`.
+    fn headers(&self, arguments: &Value, context: &RunContext) -> Vec<(String, String)> {
+        let mut headers = self.arguments_in(arguments, ParameterLocation::Header);
+        headers.extend(
+            self.settings
+                .headers
+                .iter()
+                .map(|(name, value)| (name.clone(), context.render(value))),
+        );
+        headers
+    }
+
+    /// The values of the parameters in `location` that the call has.
+    fn arguments_in(
+        &self,
+        arguments: &Value,
+        location: ParameterLocation,
+    ) -> Vec<(String, String)> {
+        self.parameters
+            .iter()
+            .filter(|parameter| parameter.location == location)
+            .filter_map(|parameter| {
+                let value = arguments
+                    .get(&parameter.name)
+                    .filter(|value| !value.is_null())?;
+                let value = match value {
+                    Value::Array(values) => values
+                        .iter()
+                        .map(argument_to_string)
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    value => argument_to_string(value),
+                };
+                Some((parameter.name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+impl AnyTool for OpenApiTool {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self) -> &'static str {
+        self.description
+    }
+
+    fn tool_info(&self) -> ToolInfo {
+        ToolInfo {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo {
+                name: self.name.to_string(),
+                description: self.description.to_string(),
+                parameters: self.schema.clone(),
+            },
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AsyncTool for OpenApiTool {
+    async fn forward_json(&self, json_args: Value) -> Result<String, AgentError> {
+        self.call(json_args, &RunContext::new()).await
+    }
+
+    /// Makes the request. The headers of the toolset can refer to the values of the run as `{{context.<key>}}`,
+    /// so that a value such as the token of the user does not go through the model.
+    async fn call(&self, json_args: Value, context: &RunContext) -> Result<String, AgentError> {
+        let url = self.url(&json_args)?;
+        let method = reqwest::Method::from_bytes(self.method.as_bytes())
+            .map_err(|e| AgentError::Execution(e.to_string()))?;
+        let mut request = self
+            .settings
+            .client
+            .request(method, &url)
+            .query(&self.arguments_in(&json_args, ParameterLocation::Query));
+        for (name, value) in self.headers(&json_args, context) {
+            request = request.header(name, value);
+        }
+        if self.has_body {
+            if let Some(body) = json_args.get("body").filter(|body| !body.is_null()) {
+                request = request.json(body);
+            }
+        }
+
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                ToolError::Timeout(format!("{} {} timed out", self.method, url))
+            } else {
+                ToolError::Recoverable(format!("{} {} failed: {}", self.method, url, e))
+            }
+        })?;
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let text = response
+            .text()
+            .await
+            .map_err(|e| ToolError::Recoverable(format!("Failed to read the response: {}", e)))?;
+        let text = truncate_observation(&text, self.settings.max_response_size, None);
+
+        if status.is_success() {
+            return Ok(if text.is_empty() {
+                format!("{} {} returned {}", self.method, self.path, status)
+            } else {
+                text
+            });
+        }
+        let message = format!(
+            "{} {} returned {}: {}",
+            self.method, self.path, status, text
+        );
+        Err(match status.as_u16() {
+            429 => ToolError::RateLimited {
+                message,
+                retry_after,
+            },
+            401 | 403 => ToolError::Fatal(message),
+            408 | 504 => ToolError::Timeout(message),
+            _ => ToolError::Recoverable(message),
+        }
+        .into())
+    }
+
+    fn clone_box(&self) -> Box<dyn AsyncTool> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r#"
+openapi: 3.0.0
+servers:
+  - url: https://petstore.example.com/v1/
+paths:
+  /pets/{petId}:
+    parameters:
+      - name: petId
+        in: path
+        description: The id of the pet
+        schema:
+          type: string
+    get:
+      operationId: showPetById
+      summary: Info for a specific pet
+      parameters:
+        - name: fields
+          in: query
+          schema:
+            type: array
+            items:
+              type: string
+    put:
+      summary: Update a pet
+      requestBody:
+        required: true
+        content:
+          application/json:
+            schema:
+              $ref: '#/components/schemas/Pet'
+components:
+  schemas:
+    Pet:
+      type: object
+      required: [name]
+      properties:
+        name:
+          type: string
+"#;
+
+    #[test]
+    fn test_openapi_tools() {
+        let toolset = OpenApiToolset::from_yaml(SPEC).unwrap();
+        let tools = toolset.operation_tools();
+        assert_eq!(tools.len(), 2);
+
+        let show = &tools[0];
+        assert_eq!(show.name(), "showPetById");
+        assert_eq!(show.description(), "Info for a specific pet");
+        let schema = show.tool_info().function.parameters;
+        assert_eq!(schema["required"], json!(["petId"]));
+        assert_eq!(
+            schema["properties"]["petId"]["description"],
+            "The id of the pet"
+        );
+        let arguments = json!({"petId": "a b", "fields": ["name", "tag"]});
+        assert_eq!(
+            show.url(&arguments).unwrap(),
+            "https://petstore.example.com/v1/pets/a%20b"
+        );
+        assert_eq!(
+            show.arguments_in(&arguments, ParameterLocation::Query),
+            vec![("fields".to_string(), "name,tag".to_string())]
+        );
+        assert!(show.url(&json!({})).is_err());
+
+        let update = &tools[1];
+        assert_eq!(update.name(), "put_pets_petId");
+        let schema = update.tool_info().function.parameters;
+        assert_eq!(schema["required"], json!(["petId", "body"]));
+        assert_eq!(schema["properties"]["body"]["required"], json!(["name"]));
+
+        let context = RunContext::new().with_value("user_token", json!("t-42"));
+        let tools = toolset
+            .clone()
+            .with_header("X-User-Token", "{{context.user_token}}")
+            .operation_tools();
+        assert_eq!(
+            tools[0].headers(&arguments, &context),
+            vec![("X-User-Token".to_string(), "t-42".to_string())]
+        );
+
+        let filtered = toolset.with_operations(&["showPetById"]).operation_tools();
+        assert_eq!(filtered.len(), 1);
+        assert!(OpenApiToolset::from_json(r#"{"swagger": "2.0", "paths": {}}"#).is_err());
+    }
+}
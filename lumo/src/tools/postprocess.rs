@@ -0,0 +1,366 @@
+//! This module contains the post-processors that shorten the output of a tool before the model sees it, such as
+//! turning HTML into markdown or keeping only some fields of a JSON response. Any tool can have a chain of them,
+//! which are applied in the order they were added.
+//!
+//! ```rust,ignore
+//! let tool = FetchTool::new()
+//!     .with_postprocessor(HtmlToMarkdown::new())
+//!     .with_postprocessor(Summarize::new(model, 300));
+//! let tool = OrdersApi::new()
+//!     .with_postprocessor(JsonFields::new(&["id", "items.*.sku", "total"]));
+//! ```
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use super::tool_traits::{AnyTool, AsyncTool, ToolInfo};
+use crate::{
+    context::RunContext,
+    errors::AgentError,
+    models::{
+        model_traits::Model,
+        types::{GenerationConfig, Message},
+    },
+};
+
+/// Transforms the output of a tool before it becomes an observation.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait PostProcessor: Send + Sync {
+    async fn process(&self, output: String) -> Result<String, AgentError>;
+}
+
+/// A tool whose output goes through a chain of post-processors.
+#[derive(Clone)]
+pub struct ProcessedTool {
+    tool: Arc<dyn AsyncTool>,
+    processors: Vec<Arc<dyn PostProcessor>>,
+}
+
+impl ProcessedTool {
+    pub fn new(tool: Box<dyn AsyncTool>) -> Self {
+        Self {
+            tool: Arc::from(tool),
+            processors: Vec::new(),
+        }
+    }
+
+    /// Adds a post-processor after the ones the tool already has.
+    pub fn with_postprocessor(mut self, processor: impl PostProcessor + 'static) -> Self {
+        self.processors.push(Arc::new(processor));
+        self
+    }
+}
+
+/// Adds [`with_postprocessor`](WithPostProcessor::with_postprocessor) to every tool.
+pub trait WithPostProcessor {
+    fn with_postprocessor(self, processor: impl PostProcessor + 'static) -> ProcessedTool;
+}
+
+impl<T: AsyncTool + 'static> WithPostProcessor for T {
+    fn with_postprocessor(self, processor: impl PostProcessor + 'static) -> ProcessedTool {
+        ProcessedTool::new(Box::new(self)).with_postprocessor(processor)
+    }
+}
+
+impl AnyTool for ProcessedTool {
+    fn name(&self) -> &'static str {
+        self.tool.name()
+    }
+
+    fn description(&self) -> &'static str {
+        self.tool.description()
+    }
+
+    fn tool_info(&self) -> ToolInfo {
+        self.tool.tool_info()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl AsyncTool for ProcessedTool {
+    async fn forward_json(&self, json_args: Value) -> Result<String, AgentError> {
+        self.call(json_args, &RunContext::new()).await
+    }
+
+    async fn call(&self, json_args: Value, context: &RunContext) -> Result<String, AgentError> {
+        let mut output = self.tool.call(json_args, context).await?;
+        for processor in &self.processors {
+            output = processor.process(output).await?;
+        }
+        Ok(output)
+    }
+
+    fn clone_box(&self) -> Box<dyn AsyncTool> {
+        Box::new(self.clone())
+    }
+}
+
+/// Converts HTML to markdown, without scripts, styles and navigation. Output that is not HTML is left as it is.
+pub struct HtmlToMarkdown {
+    skipped_tags: Vec<String>,
+}
+
+impl HtmlToMarkdown {
+    pub fn new() -> Self {
+        Self::with_skipped_tags(&["script", "style", "header", "nav", "footer"])
+    }
+
+    /// Drops the elements with these tags and their content.
+    pub fn with_skipped_tags(tags: &[&str]) -> Self {
+        Self {
+            skipped_tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+}
+
+impl Default for HtmlToMarkdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl PostProcessor for HtmlToMarkdown {
+    async fn process(&self, output: String) -> Result<String, AgentError> {
+        if !output.trim_start().starts_with('<') {
+            return Ok(output);
+        }
+        let converter = htmd::HtmlToMarkdown::builder()
+            .skip_tags(self.skipped_tags.iter().map(String::as_str).collect())
+            .build();
+        Ok(converter.convert(&output).unwrap_or(output))
+    }
+}
+
+/// Asks a model to summarize the output in at most `max_tokens` tokens. Output that is already that short, at about
+/// four characters per token, is left as it is.
+pub struct Summarize {
+    model: Arc<dyn Model>,
+    max_tokens: usize,
+    instructions: Option<String>,
+}
+
+impl Summarize {
+    pub fn new(model: impl Model, max_tokens: usize) -> Self {
+        Self::from_arc(Arc::new(model), max_tokens)
+    }
+
+    /// Summarizes with a model that is also used elsewhere, e.g. by the agent.
+    pub fn from_arc(model: Arc<dyn Model>, max_tokens: usize) -> Self {
+        Self {
+            model,
+            max_tokens,
+            instructions: None,
+        }
+    }
+
+    /// What the summary should keep, e.g. "Keep every date and amount".
+    pub fn with_instructions(mut self, instructions: &str) -> Self {
+        self.instructions = Some(instructions.to_string());
+        self
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl PostProcessor for Summarize {
+    async fn process(&self, output: String) -> Result<String, AgentError> {
+        if output.chars().count() <= self.max_tokens * 4 {
+            return Ok(output);
+        }
+        let mut prompt = format!(
+            "Summarize the following tool output in at most {} tokens. Keep the facts, names and numbers that \
+             matter, and do not add anything that is not in the output.",
+            self.max_tokens
+        );
+        if let Some(instructions) = &self.instructions {
+            prompt = format!("{} {}", prompt, instructions);
+        }
+        let response = self
+            .model
+            .run(
+                vec![
+                    Message::system(&prompt),
+                    Message::user(&format!("Tool output:\n{}", output)),
+                ],
+                None,
+                vec![],
+                GenerationConfig::new().with_max_tokens(self.max_tokens),
+            )
+            .await?;
+        response.get_response()
+    }
+}
+
+/// Keeps only the matches of a regex, one per line. With a capture group, only the first group of each match is
+/// kept.
+pub struct RegexExtract {
+    regex: Regex,
+}
+
+impl RegexExtract {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+        })
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl PostProcessor for RegexExtract {
+    async fn process(&self, output: String) -> Result<String, AgentError> {
+        let matches = self
+            .regex
+            .captures_iter(&output)
+            .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+            .map(|found| found.as_str())
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            return Ok(format!(
+                "The output of the tool has nothing that matches `{}`.",
+                self.regex.as_str()
+            ));
+        }
+        Ok(matches.join("\n"))
+    }
+}
+
+/// Keeps only some fields of a JSON output, by their path: `user.name` for a nested field, `items.0` for an item of
+/// an array and `items.*.id` for a field of every item. The fields are returned as an object keyed by path.
+/// Output that is not JSON is left as it is.
+pub struct JsonFields {
+    paths: Vec<String>,
+}
+
+impl JsonFields {
+    pub fn new(paths: &[&str]) -> Self {
+        Self {
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+        }
+    }
+}
+
+fn project(value: &Value, path: &[&str]) -> Option<Value> {
+    let Some((segment, rest)) = path.split_first() else {
+        return Some(value.clone());
+    };
+    match (value, *segment) {
+        (Value::Array(items), "*") => Some(Value::Array(
+            items
+                .iter()
+                .filter_map(|item| project(item, rest))
+                .collect(),
+        )),
+        (Value::Array(items), index) => project(items.get(index.parse::<usize>().ok()?)?, rest),
+        (Value::Object(fields), name) => project(fields.get(name)?, rest),
+        _ => None,
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl PostProcessor for JsonFields {
+    async fn process(&self, output: String) -> Result<String, AgentError> {
+        let Ok(value) = serde_json::from_str::<Value>(&output) else {
+            return Ok(output);
+        };
+        let mut fields = Map::new();
+        for path in &self.paths {
+            let segments = path.split('.').collect::<Vec<_>>();
+            if let Some(field) = project(&value, &segments) {
+                fields.insert(path.clone(), field);
+            }
+        }
+        Ok(Value::Object(fields).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{base::BaseTool, tool_traits::Tool};
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize, JsonSchema)]
+    struct EchoToolParams {
+        text: String,
+    }
+
+    #[derive(Clone)]
+    struct EchoTool {
+        tool: BaseTool,
+    }
+
+    #[async_trait]
+    impl Tool for EchoTool {
+        type Params = EchoToolParams;
+        fn name(&self) -> &'static str {
+            self.tool.name
+        }
+        fn description(&self) -> &'static str {
+            self.tool.description
+        }
+        async fn forward(&self, arguments: EchoToolParams) -> Result<String> {
+            Ok(arguments.text)
+        }
+    }
+
+    fn echo() -> EchoTool {
+        EchoTool {
+            tool: BaseTool {
+                name: "echo",
+                description: "Returns its input",
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_postprocessors() {
+        let tool = echo()
+            .with_postprocessor(JsonFields::new(&["user.name", "items.*.id", "missing"]))
+            .with_postprocessor(RegexExtract::new(r#""items.\*.id":\[([^\]]*)\]"#).unwrap());
+        assert_eq!(tool.name(), "echo");
+        let output = json!({
+            "user": {"name": "Ada", "email": "ada@example.com"},
+            "items": [{"id": 1, "price": 3}, {"id": 2, "price": 5}]
+        });
+        let projected = JsonFields::new(&["user.name", "items.*.id", "items.1.price", "missing"])
+            .process(output.to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&projected).unwrap(),
+            json!({"user.name": "Ada", "items.*.id": [1, 2], "items.1.price": 5})
+        );
+        assert_eq!(
+            tool.forward_json(json!({"text": output.to_string()}))
+                .await
+                .unwrap(),
+            "1,2"
+        );
+
+        let markdown = HtmlToMarkdown::new()
+            .process("<html><nav>Menu</nav><h1>Title</h1><p>Text</p></html>".to_string())
+            .await
+            .unwrap();
+        assert!(markdown.contains("Title") && markdown.contains("Text"));
+        assert!(!markdown.contains("Menu") && !markdown.contains('<'));
+        assert_eq!(
+            HtmlToMarkdown::new()
+                .process("plain text".to_string())
+                .await
+                .unwrap(),
+            "plain text"
+        );
+    }
+}
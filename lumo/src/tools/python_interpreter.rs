@@ -8,6 +8,7 @@ use std::sync::{Arc, RwLock};
 use super::base::BaseTool;
 use super::tool_traits::Tool;
 use crate::local_python_interpreter::LocalPythonInterpreter;
+use crate::sandbox::{interpreter_result, SandboxBackend};
 use anyhow::Result;
 
 #[derive(Deserialize, JsonSchema)]
@@ -25,6 +26,8 @@ pub struct PythonInterpreterToolParams {
 pub struct PythonInterpreterTool {
     pub tool: BaseTool,
     pub interpreter: Arc<RwLock<ManuallyDrop<LocalPythonInterpreter>>>,
+    /// Runs the code here instead of the local interpreter when it is set.
+    pub sandbox: Option<Arc<dyn SandboxBackend>>,
 }
 
 impl PythonInterpreterTool {
@@ -35,11 +38,21 @@ impl PythonInterpreterTool {
                 description:  "This is a tool that evaluates python code. It can be used to perform calculations. Make sure to print the result using print()."
             },
             interpreter: Arc::new(RwLock::new(ManuallyDrop::new(LocalPythonInterpreter::new(None, None)))),
+            sandbox: None,
         }
     }
+
+    /// Evaluates the code in a sandbox, such as a [`DockerSandbox`](crate::sandbox::DockerSandbox), which can import
+    /// any library installed in it. The sandbox can be shared, e.g. with a code agent, so that both see the same
+    /// files.
+    pub fn with_sandbox(mut self, sandbox: Arc<dyn SandboxBackend>) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Tool for PythonInterpreterTool {
     type Params = PythonInterpreterToolParams;
     fn name(&self) -> &'static str {
@@ -49,7 +62,15 @@ impl Tool for PythonInterpreterTool {
         self.tool.description
     }
     async fn forward(&self, arguments: PythonInterpreterToolParams) -> Result<String> {
-        let result = self.interpreter.write().unwrap().forward(&arguments.code);
+        let result = match &self.sandbox {
+            Some(sandbox) => interpreter_result(
+                sandbox
+                    .execute(&arguments.code)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Error evaluating code: {}", e))?,
+            ),
+            None => self.interpreter.write().unwrap().forward(&arguments.code),
+        };
         match result {
             Ok(result) => {
                 if result.1.is_empty() {
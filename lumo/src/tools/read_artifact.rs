@@ -0,0 +1,84 @@
+//! This module contains the read artifact tool. The model uses this tool to read the parts of a large tool output
+//! that were left out of its observation.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::base::BaseTool;
+use super::tool_traits::Tool;
+use crate::artifacts::ArtifactStore;
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "ReadArtifactToolParams")]
+pub struct ReadArtifactToolParams {
+    #[schemars(description = "The id of the artifact to read")]
+    id: String,
+    #[schemars(description = "The character to start reading from. Defaults to 0")]
+    offset: Option<usize>,
+    #[schemars(description = "The number of characters to read")]
+    length: Option<usize>,
+}
+
+#[derive(Clone)]
+pub struct ReadArtifactTool {
+    pub tool: BaseTool,
+    pub store: Arc<dyn ArtifactStore>,
+    /// The number of characters returned when the model does not ask for a length.
+    pub default_length: usize,
+}
+
+impl ReadArtifactTool {
+    pub fn new(store: Arc<dyn ArtifactStore>, default_length: Option<usize>) -> Self {
+        ReadArtifactTool {
+            tool: BaseTool {
+                name: "read_artifact",
+                description: "Reads part of a tool output that was too large to show in full. Give the artifact id from the truncated output and the character offset to read from.",
+            },
+            store,
+            default_length: default_length.unwrap_or(10000),
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Tool for ReadArtifactTool {
+    type Params = ReadArtifactToolParams;
+    fn name(&self) -> &'static str {
+        self.tool.name
+    }
+    fn description(&self) -> &'static str {
+        self.tool.description
+    }
+    async fn forward(&self, arguments: ReadArtifactToolParams) -> Result<String> {
+        let content = self
+            .store
+            .get(&arguments.id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No artifact found with id: {}", arguments.id))?;
+        let total = content.chars().count();
+        let offset = arguments.offset.unwrap_or(0);
+        if offset >= total {
+            return Err(anyhow::anyhow!(
+                "Offset {} is past the end of the artifact, which has {} characters",
+                offset,
+                total
+            ));
+        }
+        let length = arguments.length.unwrap_or(self.default_length);
+        let part = content
+            .chars()
+            .skip(offset)
+            .take(length)
+            .collect::<String>();
+        let end = offset + part.chars().count();
+        Ok(format!(
+            "Characters {} to {} of {}:\n{}",
+            offset, end, total, part
+        ))
+    }
+}
@@ -0,0 +1,296 @@
+//! A registry of tools that can be looked up by name or tag, so that agents can be assembled from configuration and
+//! crates can ship packs of tools.
+//!
+//! ```rust,ignore
+//! ToolRegistry::global()
+//!     .write()
+//!     .unwrap()
+//!     .register(WeatherTool::new(), &["weather"]);
+//!
+//! let tools = ToolRegistry::global().read().unwrap().select(&ToolSelection {
+//!     names: vec!["visit_website".to_string()],
+//!     tags: vec!["weather".to_string()],
+//! })?;
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    exa_search::ExaSearchTool, AsyncTool, DuckDuckGoSearchTool, GoogleSearchTool, TavilySearchTool,
+    VisitWebsiteTool,
+};
+
+/// Creates a new instance of a tool. Tools that need an API key are created lazily, so that a missing key only fails
+/// the agents that use them.
+pub type ToolFactory = Arc<dyn Fn() -> Result<Box<dyn AsyncTool>> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct ToolEntry {
+    pub name: String,
+    pub description: String,
+    pub tags: Vec<String>,
+    factory: ToolFactory,
+}
+
+impl ToolEntry {
+    pub fn create(&self) -> Result<Box<dyn AsyncTool>> {
+        (self.factory)().with_context(|| format!("Failed to create tool {}", self.name))
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+impl std::fmt::Debug for ToolEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ToolEntry")
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .field("tags", &self.tags)
+            .finish()
+    }
+}
+
+/// The tools to pick from a registry for an agent: every tool named in `names`, in that order, followed by every
+/// tool that has one of the `tags`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ToolSelection {
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl ToolSelection {
+    pub fn names(names: &[&str]) -> Self {
+        Self {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            tags: vec![],
+        }
+    }
+
+    pub fn tags(tags: &[&str]) -> Self {
+        Self {
+            names: vec![],
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ToolRegistry {
+    entries: BTreeMap<String, ToolEntry>,
+}
+
+impl ToolRegistry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with the built-in tools that need no configuration besides an API key in the environment.
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry
+            .register(DuckDuckGoSearchTool::new(), &["search", "web"])
+            .register(VisitWebsiteTool::new(), &["web"])
+            .register_factory(
+                "google_search",
+                "Performs a google web search for your query then returns a string of the top search results.",
+                &["search", "web"],
+                || {
+                    let api_key = required_env("SERPAPI_API_KEY")?;
+                    Ok(Box::new(GoogleSearchTool::new(Some(api_key))))
+                },
+            )
+            .register_factory(
+                "tavily_search",
+                "Performs a Tavily web search for your query then returns a string of the top search results with LLMs.",
+                &["search", "web"],
+                || {
+                    let api_key = required_env("TAVILY_API_KEY")?;
+                    Ok(Box::new(TavilySearchTool::new(Some(api_key))))
+                },
+            )
+            .register_factory(
+                "exa_search",
+                "Performs a exa web search for your query then returns a string of the top search results.",
+                &["search", "web"],
+                || {
+                    let api_key = required_env("EXA_API_KEY")?;
+                    Ok(Box::new(ExaSearchTool::new(3, Some(api_key))))
+                },
+            );
+        #[cfg(feature = "code-agent")]
+        registry.register(super::PythonInterpreterTool::new(), &["code"]);
+        registry
+    }
+
+    /// The registry shared by the whole process, which starts with the built-in tools. Crates that ship tools
+    /// register them here, and [`AgentConfig`](crate::config::AgentConfig) looks tools up here.
+    pub fn global() -> &'static RwLock<ToolRegistry> {
+        static GLOBAL: OnceLock<RwLock<ToolRegistry>> = OnceLock::new();
+        GLOBAL.get_or_init(|| RwLock::new(ToolRegistry::with_builtins()))
+    }
+
+    /// Registers a tool under its own name. Every agent that selects it gets a clone. A tool registered earlier
+    /// under the same name is replaced.
+    pub fn register(&mut self, tool: impl AsyncTool + 'static, tags: &[&str]) -> &mut Self {
+        let name = tool.name().to_string();
+        let description = tool.description().to_string();
+        let tool: Arc<dyn AsyncTool> = Arc::new(tool);
+        self.insert(
+            name,
+            description,
+            tags,
+            Arc::new(move || Ok(tool.clone_box())),
+        )
+    }
+
+    /// Registers a tool that is created by `factory` every time it is selected.
+    pub fn register_factory<F>(
+        &mut self,
+        name: &str,
+        description: &str,
+        tags: &[&str],
+        factory: F,
+    ) -> &mut Self
+    where
+        F: Fn() -> Result<Box<dyn AsyncTool>> + Send + Sync + 'static,
+    {
+        self.insert(
+            name.to_string(),
+            description.to_string(),
+            tags,
+            Arc::new(factory),
+        )
+    }
+
+    fn insert(
+        &mut self,
+        name: String,
+        description: String,
+        tags: &[&str],
+        factory: ToolFactory,
+    ) -> &mut Self {
+        let entry = ToolEntry {
+            name: name.clone(),
+            description,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            factory,
+        };
+        self.entries.insert(name, entry);
+        self
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Option<ToolEntry> {
+        self.entries.remove(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ToolEntry> {
+        self.entries.get(name)
+    }
+
+    /// Creates the tool registered under `name`.
+    pub fn create(&self, name: &str) -> Result<Box<dyn AsyncTool>> {
+        self.get(name)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unknown tool: {}. The registered tools are: {}",
+                    name,
+                    self.names().join(", ")
+                )
+            })?
+            .create()
+    }
+
+    /// The registered tools, sorted by name.
+    pub fn list(&self) -> impl Iterator<Item = &ToolEntry> {
+        self.entries.values()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.entries.keys().map(|name| name.as_str()).collect()
+    }
+
+    pub fn with_tag<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a ToolEntry> {
+        self.list().filter(move |entry| entry.has_tag(tag))
+    }
+
+    /// Creates the tools of `selection`. Fails if a name is not registered, while a tag without tools selects
+    /// nothing.
+    pub fn select(&self, selection: &ToolSelection) -> Result<Vec<Box<dyn AsyncTool>>> {
+        let mut names: Vec<&str> = vec![];
+        for name in &selection.names {
+            if !self.contains(name) {
+                return Err(anyhow!(
+                    "Unknown tool: {}. The registered tools are: {}",
+                    name,
+                    self.names().join(", ")
+                ));
+            }
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        for tag in &selection.tags {
+            for entry in self.with_tag(tag) {
+                if !names.contains(&entry.name.as_str()) {
+                    names.push(&entry.name);
+                }
+            }
+        }
+        names.into_iter().map(|name| self.create(name)).collect()
+    }
+}
+
+fn required_env(name: &str) -> Result<String> {
+    std::env::var(name).with_context(|| format!("{} must be set", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_and_select() {
+        let mut registry = ToolRegistry::with_builtins();
+        assert!(registry.contains("duckduckgo_search"));
+        registry.register_factory(
+            "fails",
+            "A tool that cannot be created",
+            &["broken"],
+            || Err(anyhow!("no key")),
+        );
+        registry.register(VisitWebsiteTool::new(), &["browse"]);
+        assert_eq!(registry.get("visit_website").unwrap().tags, vec!["browse"]);
+
+        let tools = registry
+            .select(&ToolSelection {
+                names: vec!["duckduckgo_search".to_string()],
+                tags: vec!["browse".to_string(), "missing".to_string()],
+            })
+            .unwrap();
+        let names: Vec<_> = tools.iter().map(|tool| tool.name()).collect();
+        assert_eq!(names, vec!["duckduckgo_search", "visit_website"]);
+
+        assert!(registry
+            .select(&ToolSelection::names(&["unknown"]))
+            .is_err());
+        assert!(registry.select(&ToolSelection::tags(&["broken"])).is_err());
+        assert!(registry.unregister("fails").is_some());
+        assert!(registry
+            .select(&ToolSelection::tags(&["broken"]))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+//! This module contains the retriever tool. The model uses this tool to run a semantic search over documents that
+//! were indexed in a vector store.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::base::BaseTool;
+use super::tool_traits::Tool;
+use crate::models::embeddings::EmbeddingModel;
+use crate::retrieval::{Document, ScoredDocument, VectorStore};
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "RetrieverToolParams")]
+pub struct RetrieverToolParams {
+    #[schemars(
+        description = "The query to search for. Phrase it like the text you expect to find rather than as a question"
+    )]
+    query: String,
+    #[schemars(description = "The number of documents to return")]
+    top_k: Option<usize>,
+}
+
+#[derive(Clone)]
+pub struct RetrieverTool {
+    pub tool: BaseTool,
+    pub store: Arc<dyn VectorStore>,
+    pub embedding_model: Arc<dyn EmbeddingModel>,
+    pub top_k: usize,
+}
+
+impl RetrieverTool {
+    pub fn new(
+        store: Arc<dyn VectorStore>,
+        embedding_model: Arc<dyn EmbeddingModel>,
+        top_k: Option<usize>,
+    ) -> Self {
+        RetrieverTool {
+            tool: BaseTool {
+                name: "retriever",
+                description: "Retrieves the documents from the knowledge base that are most semantically similar to your query.",
+            },
+            store,
+            embedding_model,
+            top_k: top_k.unwrap_or(5),
+        }
+    }
+
+    /// Embeds and indexes documents so that they can be retrieved by the tool.
+    pub async fn add_documents(&self, documents: Vec<Document>) -> Result<()> {
+        let texts = documents.iter().map(|d| d.text.clone()).collect::<Vec<_>>();
+        let embeddings = self.embedding_model.embed(texts).await?;
+        self.store.add(documents, embeddings).await
+    }
+
+    pub async fn forward(&self, query: &str, top_k: Option<usize>) -> Result<Vec<ScoredDocument>> {
+        let embedding = self.embedding_model.embed_query(query).await?;
+        self.store
+            .search(&embedding, top_k.unwrap_or(self.top_k))
+            .await
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Tool for RetrieverTool {
+    type Params = RetrieverToolParams;
+    fn name(&self) -> &'static str {
+        self.tool.name
+    }
+    fn description(&self) -> &'static str {
+        self.tool.description
+    }
+    async fn forward(&self, arguments: RetrieverToolParams) -> Result<String> {
+        let results = self.forward(&arguments.query, arguments.top_k).await?;
+        if results.is_empty() {
+            return Err(anyhow::anyhow!(
+                "No documents found for query: {}",
+                arguments.query
+            ));
+        }
+        Ok(results
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                format!(
+                    "===== Document {} (id: {}, score: {:.3}) =====\n{}",
+                    i + 1,
+                    r.document.id,
+                    r.score,
+                    r.document.text
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AgentError;
+    use crate::retrieval::InMemoryVectorStore;
+
+    /// Embeds texts by counting a few keywords so that the test does not need an embedding API.
+    struct KeywordEmbeddingModel;
+
+    #[async_trait]
+    impl EmbeddingModel for KeywordEmbeddingModel {
+        async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AgentError> {
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    ["rust", "python", "coffee"]
+                        .iter()
+                        .map(|word| text.to_lowercase().matches(word).count() as f32)
+                        .collect()
+                })
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retriever_tool() {
+        let tool = RetrieverTool::new(
+            Arc::new(InMemoryVectorStore::new()),
+            Arc::new(KeywordEmbeddingModel),
+            Some(1),
+        );
+        tool.add_documents(vec![
+            Document::new("1", "Rust has a borrow checker"),
+            Document::new("2", "Coffee is brewed from beans"),
+        ])
+        .await
+        .unwrap();
+        let result = Tool::forward(
+            &tool,
+            RetrieverToolParams {
+                query: "how do I brew coffee".to_string(),
+                top_k: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert!(result.contains("Coffee is brewed"));
+        assert!(!result.contains("borrow checker"));
+    }
+}
@@ -0,0 +1,263 @@
+//! This module contains the tool selection for providers that cap the number of tools per request. When an agent
+//! has more tools than the cap, only the tools most relevant to the task are sent to the model, and the model can find
+//! the others with the `search_tools` tool. The tools it finds are sent with the requests that follow.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use super::base::BaseTool;
+use super::tool_traits::{Tool, ToolInfo};
+
+pub const SEARCH_TOOLS_NAME: &str = "search_tools";
+
+/// The number of tools listed by one search.
+const SEARCH_RESULTS: usize = 5;
+
+/// Words that match the description of almost every tool.
+const STOP_WORDS: [&str; 16] = [
+    "the", "and", "for", "with", "that", "this", "from", "you", "your", "are", "use", "tool",
+    "can", "will", "into", "what",
+];
+
+#[derive(Debug, Default)]
+struct SelectorState {
+    /// Every tool of the last request, before the selection.
+    catalog: Vec<ToolInfo>,
+    /// The tools found with `search_tools`, the most recent first.
+    found: Vec<String>,
+}
+
+/// Picks the tools sent with each request when there are more than `max_tools`. Clones share the tools found with
+/// the `search_tools` tool.
+#[derive(Debug, Clone)]
+pub struct ToolSelector {
+    pub max_tools: usize,
+    state: Arc<RwLock<SelectorState>>,
+}
+
+impl ToolSelector {
+    pub fn new(max_tools: usize) -> Self {
+        Self {
+            max_tools: max_tools.max(1),
+            state: Arc::default(),
+        }
+    }
+
+    /// The `search_tools` tool that searches the tools of this selector.
+    pub fn search_tool(&self) -> SearchToolsTool {
+        SearchToolsTool::new(self.clone())
+    }
+
+    /// The tools to send with a request about `query`, in the order of `tools`. `final_answer`, `search_tools` and
+    /// the tools found with `search_tools` are always kept; the other places go to the tools that match the most
+    /// words of the query. When all the tools fit, they are all sent but `search_tools`, which has nothing to find.
+    pub fn select(&self, query: &str, tools: Vec<ToolInfo>) -> Vec<ToolInfo> {
+        let mut state = self.state.write().unwrap();
+        state.catalog = tools.clone();
+        let tool_count = tools
+            .iter()
+            .filter(|tool| tool.function.name != SEARCH_TOOLS_NAME)
+            .count();
+        if !self.defers(tool_count) {
+            return tools
+                .into_iter()
+                .filter(|tool| tool.function.name != SEARCH_TOOLS_NAME)
+                .collect();
+        }
+
+        let mut kept = BTreeSet::new();
+        for name in ["final_answer", SEARCH_TOOLS_NAME]
+            .into_iter()
+            .chain(state.found.iter().map(String::as_str))
+        {
+            if kept.len() < self.max_tools && tools.iter().any(|tool| tool.function.name == name) {
+                kept.insert(name.to_string());
+            }
+        }
+        for index in rank(query, &tools) {
+            if kept.len() >= self.max_tools {
+                break;
+            }
+            kept.insert(tools[index].function.name.clone());
+        }
+        tools
+            .into_iter()
+            .filter(|tool| kept.contains(&tool.function.name))
+            .collect()
+    }
+
+    /// Whether some of `tool_count` tools, not counting `search_tools`, are left out of the requests.
+    pub fn defers(&self, tool_count: usize) -> bool {
+        tool_count > self.max_tools
+    }
+
+    /// The tools of the last request that best match `query`, which are then kept in the requests that follow.
+    fn search(&self, query: &str) -> Vec<ToolInfo> {
+        let mut state = self.state.write().unwrap();
+        let results = rank(query, &state.catalog)
+            .into_iter()
+            .filter(|index| {
+                let name = state.catalog[*index].function.name.as_str();
+                name != SEARCH_TOOLS_NAME && name != "final_answer"
+            })
+            .take(SEARCH_RESULTS)
+            .map(|index| state.catalog[index].clone())
+            .collect::<Vec<_>>();
+        for tool in results.iter().rev() {
+            state.found.retain(|name| *name != tool.function.name);
+            state.found.insert(0, tool.function.name.clone());
+        }
+        state.found.truncate(self.max_tools);
+        results
+    }
+}
+
+fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .map(str::to_lowercase)
+        .filter(|word| word.len() > 2 && !STOP_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// The indices of the tools that share words with `query`, the best match first. Ties keep the order of the tools.
+fn rank(query: &str, tools: &[ToolInfo]) -> Vec<usize> {
+    let query = words(query);
+    let mut scores = tools
+        .iter()
+        .enumerate()
+        .map(|(index, tool)| {
+            let tool_words = words(&format!(
+                "{} {}",
+                tool.function.name, tool.function.description
+            ));
+            (query.intersection(&tool_words).count(), index)
+        })
+        .filter(|(score, _)| *score > 0)
+        .collect::<Vec<_>>();
+    scores.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scores.into_iter().map(|(_, index)| index).collect()
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(title = "SearchToolsToolParams")]
+pub struct SearchToolsToolParams {
+    #[schemars(description = "Keywords describing what the tool you need does")]
+    query: String,
+}
+
+#[derive(Clone)]
+pub struct SearchToolsTool {
+    pub tool: BaseTool,
+    pub selector: ToolSelector,
+}
+
+impl SearchToolsTool {
+    pub fn new(selector: ToolSelector) -> Self {
+        SearchToolsTool {
+            tool: BaseTool {
+                name: SEARCH_TOOLS_NAME,
+                description: "Not all of your tools are listed. Searches the other tools by keywords and makes the ones found available in the next steps.",
+            },
+            selector,
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Tool for SearchToolsTool {
+    type Params = SearchToolsToolParams;
+    fn name(&self) -> &'static str {
+        self.tool.name
+    }
+    fn description(&self) -> &'static str {
+        self.tool.description
+    }
+    async fn forward(&self, arguments: SearchToolsToolParams) -> Result<String> {
+        let results = self.selector.search(&arguments.query);
+        if results.is_empty() {
+            return Ok(format!(
+                "No tool matches \"{}\". Try other keywords.",
+                arguments.query
+            ));
+        }
+        let results = results
+            .iter()
+            .map(|tool| {
+                format!(
+                    "- {}: {}\n  Parameters: {}",
+                    tool.function.name, tool.function.description, tool.function.parameters
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(format!("These tools are now available:\n{}", results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{ToolFunctionInfo, ToolType};
+    use serde_json::json;
+
+    fn info(name: &str, description: &str) -> ToolInfo {
+        ToolInfo {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo {
+                name: name.to_string(),
+                description: description.to_string(),
+                parameters: json!({"type": "object", "properties": {}}),
+            },
+        }
+    }
+
+    fn names(tools: &[ToolInfo]) -> Vec<&str> {
+        tools
+            .iter()
+            .map(|tool| tool.function.name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_tool_selector() {
+        let selector = ToolSelector::new(3);
+        let tools = vec![
+            info("get_weather", "Gets the weather forecast of a city"),
+            info("send_email", "Sends an email to a recipient"),
+            info("search_web", "Searches the web for a query"),
+            info(SEARCH_TOOLS_NAME, "Searches the other tools"),
+            info("final_answer", "Gives the final answer"),
+        ];
+        let selected = selector.select("What is the weather in Paris?", tools.clone());
+        assert_eq!(
+            names(&selected),
+            vec!["get_weather", SEARCH_TOOLS_NAME, "final_answer"]
+        );
+
+        let observation = selector
+            .search_tool()
+            .forward(SearchToolsToolParams {
+                query: "email".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(observation.contains("send_email"));
+        let selected = selector.select("What is the weather in Paris?", tools.clone());
+        assert_eq!(
+            names(&selected),
+            vec!["send_email", SEARCH_TOOLS_NAME, "final_answer"]
+        );
+
+        let all = ToolSelector::new(10).select("anything", tools.clone());
+        assert_eq!(
+            names(&all),
+            vec!["get_weather", "send_email", "search_web", "final_answer"]
+        );
+    }
+}
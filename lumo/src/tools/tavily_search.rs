@@ -118,7 +118,8 @@ impl TavilySearchTool {
     }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Tool for TavilySearchTool {
     type Params = TavilySearchToolParams;
     fn name(&self) -> &'static str {
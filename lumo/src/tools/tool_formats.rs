@@ -0,0 +1,138 @@
+//! Conversions of tool definitions to and from the formats of the model APIs, used by the model providers and to
+//! export a catalog of tools to other systems.
+//!
+//! ```rust,ignore
+//! let catalog = tools.iter().map(|tool| tool.tool_info().to_anthropic()).collect::<Vec<_>>();
+//! let tool = ToolInfo::from_openai(&json!({
+//!     "type": "function",
+//!     "function": {"name": "search", "description": "Searches the web", "parameters": {"type": "object"}}
+//! }))?;
+//! ```
+
+use serde_json::{json, Value};
+
+use super::tool_traits::{ToolFunctionInfo, ToolInfo, ToolType};
+
+/// The keys of a JSON schema that Gemini rejects at the top level of the parameters of a function.
+const GEMINI_UNSUPPORTED_KEYS: [&str; 3] = ["$schema", "title", "additionalProperties"];
+
+impl ToolInfo {
+    /// The tool as an item of the `tools` of an OpenAI chat completions request.
+    pub fn to_openai(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": self.function.to_openai(),
+        })
+    }
+
+    /// The tool as an item of the `tools` of an Anthropic messages request.
+    pub fn to_anthropic(&self) -> Value {
+        let mut input_schema = self.function.parameters.clone();
+        if let Value::Object(ref mut schema) = input_schema {
+            schema.remove("$schema");
+        }
+        json!({
+            "name": self.function.name,
+            "description": self.function.description,
+            "input_schema": input_schema,
+        })
+    }
+
+    /// The tool as an item of the `function_declarations` of a Gemini request.
+    pub fn to_gemini(&self) -> Value {
+        let mut parameters = self.function.parameters.clone();
+        if let Value::Object(ref mut schema) = parameters {
+            for key in GEMINI_UNSUPPORTED_KEYS {
+                schema.remove(key);
+            }
+        }
+        json!({
+            "name": self.function.name,
+            "description": self.function.description,
+            "parameters": parameters,
+        })
+    }
+
+    /// Reads an OpenAI tool definition, either a whole item of `tools` or only its `function`. A definition without
+    /// `parameters` gets a schema without properties.
+    pub fn from_openai(value: &Value) -> Result<Self, serde_json::Error> {
+        let function = value.get("function").unwrap_or(value);
+        Ok(Self {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo::from_openai(function)?,
+        })
+    }
+}
+
+impl ToolFunctionInfo {
+    /// The `function` of an OpenAI tool definition.
+    pub fn to_openai(&self) -> Value {
+        json!({
+            "name": self.name,
+            "description": self.description,
+            "parameters": self.parameters,
+        })
+    }
+
+    pub fn from_openai(value: &Value) -> Result<Self, serde_json::Error> {
+        let mut function = serde_json::from_value::<Self>(value.clone())?;
+        if function.parameters.is_null() {
+            function.parameters = json!({"type": "object", "properties": {}});
+        }
+        Ok(function)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search() -> ToolInfo {
+        ToolInfo {
+            tool_type: ToolType::Function,
+            function: ToolFunctionInfo {
+                name: "search".to_string(),
+                description: "Searches the web".to_string(),
+                parameters: json!({
+                    "$schema": "http://json-schema.org/draft-07/schema#",
+                    "title": "SearchParams",
+                    "type": "object",
+                    "properties": {"query": {"type": "string"}},
+                    "required": ["query"],
+                    "additionalProperties": false
+                }),
+            },
+        }
+    }
+
+    #[test]
+    fn test_tool_formats() {
+        let tool = search();
+        let openai = tool.to_openai();
+        assert_eq!(openai, json!(tool));
+        assert_eq!(openai["function"]["parameters"]["required"][0], "query");
+
+        let anthropic = tool.to_anthropic();
+        assert_eq!(anthropic["name"], "search");
+        assert_eq!(
+            anthropic["input_schema"]["properties"]["query"]["type"],
+            "string"
+        );
+        assert!(anthropic["input_schema"].get("$schema").is_none());
+
+        let gemini = tool.to_gemini();
+        assert_eq!(gemini["description"], "Searches the web");
+        for key in GEMINI_UNSUPPORTED_KEYS {
+            assert!(gemini["parameters"].get(key).is_none());
+        }
+        assert_eq!(gemini["parameters"]["type"], "object");
+
+        let parsed = ToolInfo::from_openai(&openai).unwrap();
+        assert_eq!(parsed.function.name, "search");
+        assert_eq!(parsed.function.parameters, tool.function.parameters);
+        let parsed =
+            ToolInfo::from_openai(&json!({"name": "now", "description": "The time"})).unwrap();
+        assert_eq!(parsed.function.parameters["type"], "object");
+        assert!(ToolInfo::from_openai(&json!({"description": "No name"})).is_err());
+    }
+}
@@ -5,18 +5,23 @@ use async_trait::async_trait;
 use schemars::gen::SchemaSettings;
 use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::fmt::Debug;
+use std::time::Duration;
 
-use crate::errors::{AgentError, AgentExecutionError};
+use crate::context::RunContext;
+use crate::errors::{AgentError, AgentExecutionError, ToolError};
 use crate::models::openai::FunctionCall;
 
+use super::validation::validate_arguments;
+
 /// A trait for parameters that can be used in a tool. This defines the arguments that can be passed to the tool.
 pub trait Parameters: DeserializeOwned + JsonSchema {}
 
 /// A trait for tools that can be used in an agent.
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 pub trait Tool: Send + Sync {
     type Params: Parameters;
     /// The name of the tool.
@@ -25,26 +30,37 @@ pub trait Tool: Send + Sync {
     fn description(&self) -> &'static str;
     /// The function to call when the tool is used.
     async fn forward(&self, arguments: Self::Params) -> Result<String>;
+    /// Called instead of [`forward`](Tool::forward) when the tool is used in a run, with the [`RunContext`] of the
+    /// run. Tools that read or write the context implement this.
+    async fn forward_with_context(
+        &self,
+        arguments: Self::Params,
+        _context: &RunContext,
+    ) -> Result<String> {
+        self.forward(arguments).await
+    }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub enum ToolType {
     #[serde(rename = "function")]
     Function,
 }
 
 /// A struct that contains information about a tool. This is used to serialize the tool for the API.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ToolInfo {
     #[serde(rename = "type")]
     pub tool_type: ToolType,
     pub function: ToolFunctionInfo,
 }
 /// This struct contains information about the function to call when the tool is used.
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ToolFunctionInfo {
     pub name: String,
+    #[serde(default)]
     pub description: String,
+    #[serde(default)]
     pub parameters: Value,
 }
 
@@ -78,10 +94,112 @@ pub fn get_json_schema(tool: &ToolInfo) -> serde_json::Value {
     json!(tool)
 }
 
-#[async_trait]
+/// How tool calls that fail with a timeout or a rate limit are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolRetryPolicy {
+    pub max_retries: usize,
+    /// The wait before the first retry. It doubles with every retry, unless a rate limited tool says how long to
+    /// wait.
+    pub initial_backoff: Duration,
+}
+
+impl Default for ToolRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            initial_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ToolRetryPolicy {
+    pub fn new(max_retries: usize, initial_backoff: Duration) -> Self {
+        Self {
+            max_retries,
+            initial_backoff,
+        }
+    }
+
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self::new(0, Duration::ZERO)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 pub trait ToolGroup {
     async fn call(&self, arguments: &FunctionCall) -> Result<String, AgentExecutionError>;
     fn tool_info(&self) -> Vec<ToolInfo>;
+
+    /// Calls the tool with the [`RunContext`] of the run.
+    async fn call_with_context(
+        &self,
+        arguments: &FunctionCall,
+        _context: &RunContext,
+    ) -> Result<String, AgentExecutionError>
+    where
+        Self: Sync,
+    {
+        self.call(arguments).await
+    }
+
+    /// Calls the tool and retries it according to `policy` when it fails with a retryable [`ToolError`].
+    async fn call_with_retry(
+        &self,
+        arguments: &FunctionCall,
+        policy: &ToolRetryPolicy,
+        context: &RunContext,
+    ) -> Result<String, AgentExecutionError>
+    where
+        Self: Sync,
+    {
+        retry_tool_call(&arguments.name, policy, || {
+            self.call_with_context(arguments, context)
+        })
+        .await
+    }
+}
+
+/// Makes a tool call with `call` and makes it again according to `policy` while it fails with a retryable
+/// [`ToolError`].
+pub(crate) async fn retry_tool_call<F, Fut>(
+    name: &str,
+    policy: &ToolRetryPolicy,
+    mut call: F,
+) -> Result<String, AgentError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<String, AgentError>>,
+{
+    let mut backoff = policy.initial_backoff;
+    let mut retries = 0;
+    loop {
+        match call().await {
+            Err(AgentError::Tool(error))
+                if error.is_retryable() && retries < policy.max_retries =>
+            {
+                let wait = match &error {
+                    ToolError::RateLimited {
+                        retry_after: Some(retry_after),
+                        ..
+                    } => *retry_after,
+                    _ => backoff,
+                };
+                retries += 1;
+                tracing::warn!(
+                    tool = %name,
+                    error = %error,
+                    retry = retries,
+                    "Retrying tool call in {:?}",
+                    wait
+                );
+                crate::runtime::sleep(wait).await;
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
 }
 
 pub trait AnyTool: Send + Sync {
@@ -90,25 +208,48 @@ pub trait AnyTool: Send + Sync {
     fn tool_info(&self) -> ToolInfo;
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 pub trait AsyncTool: AnyTool {
     async fn forward_json(&self, json_args: serde_json::Value) -> Result<String, AgentError>;
+    /// Calls the tool with the [`RunContext`] of the run.
+    async fn call(
+        &self,
+        json_args: serde_json::Value,
+        _context: &RunContext,
+    ) -> Result<String, AgentError> {
+        self.forward_json(json_args).await
+    }
     fn clone_box(&self) -> Box<dyn AsyncTool>;
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl<T: Tool + Clone + 'static> AsyncTool for T {
     async fn forward_json(&self, json_args: serde_json::Value) -> Result<String, AgentError> {
+        AsyncTool::call(self, json_args, &RunContext::new()).await
+    }
+
+    async fn call(
+        &self,
+        json_args: serde_json::Value,
+        context: &RunContext,
+    ) -> Result<String, AgentError> {
         let params = serde_json::from_value::<T::Params>(json_args.clone()).map_err(|e| {
-            AgentError::Parsing(format!(
+            AgentError::Tool(ToolError::InvalidArguments(format!(
                 "Error when executing tool with arguments: {:?}: {}. As a reminder, this tool's description is: {} and takes inputs: {}",
                 json_args,
                 e,
                 self.description(),
                 json!(&self.tool_info().function.parameters)["properties"]
-            ))
+            )))
         })?;
-        Tool::forward(self, params).await.map_err(|e| AgentError::Execution(e.to_string()))
+        Tool::forward_with_context(self, params, context)
+            .await
+            .map_err(|e| match e.downcast::<ToolError>() {
+                Ok(error) => AgentError::Tool(error),
+                Err(e) => AgentError::Execution(e.to_string()),
+            })
     }
 
     fn clone_box(&self) -> Box<dyn AsyncTool> {
@@ -130,13 +271,32 @@ impl<T: Tool + Clone + 'static> AnyTool for T {
     }
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl ToolGroup for Vec<Box<dyn AsyncTool>> {
     async fn call(&self, arguments: &FunctionCall) -> Result<String, AgentError> {
+        self.call_with_context(arguments, &RunContext::new()).await
+    }
+
+    async fn call_with_context(
+        &self,
+        arguments: &FunctionCall,
+        context: &RunContext,
+    ) -> Result<String, AgentError> {
         let tool = self.iter().find(|tool| tool.name() == arguments.name);
         if let Some(tool) = tool {
+            let parameters = tool.tool_info().function.parameters;
+            let errors = validate_arguments(&parameters, &arguments.arguments);
+            if !errors.is_empty() {
+                return Err(AgentError::Tool(ToolError::InvalidArguments(format!(
+                    "Invalid arguments for tool `{}`: {}. As a reminder, this tool takes inputs: {}",
+                    arguments.name,
+                    errors.join("; "),
+                    parameters["properties"]
+                ))));
+            }
             let p = arguments.arguments.clone();
-            return tool.forward_json(p).await;
+            return tool.call(p, context).await;
         }
         Err(AgentError::Execution("Tool not found".to_string()))
     }
@@ -144,3 +304,73 @@ impl ToolGroup for Vec<Box<dyn AsyncTool>> {
         self.iter().map(|tool| tool.tool_info()).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(serde::Deserialize, JsonSchema)]
+    struct FlakyToolParams {}
+
+    /// Times out on the first call and reports a fatal error once it has been called `fatal_after` times.
+    #[derive(Clone)]
+    struct FlakyTool {
+        calls: Arc<AtomicUsize>,
+        fatal_after: usize,
+    }
+
+    #[async_trait]
+    impl Tool for FlakyTool {
+        type Params = FlakyToolParams;
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+        fn description(&self) -> &'static str {
+            "A tool that fails"
+        }
+        async fn forward(&self, _arguments: FlakyToolParams) -> Result<String> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if calls >= self.fatal_after {
+                Err(ToolError::Fatal("credentials revoked".to_string()).into())
+            } else if calls == 1 {
+                Err(ToolError::Timeout("no response after 10s".to_string()).into())
+            } else {
+                Ok("done".to_string())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_call_with_retry() {
+        let call = FunctionCall {
+            name: "flaky".to_string(),
+            arguments: json!({}),
+        };
+        let policy = ToolRetryPolicy::new(2, Duration::ZERO);
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let tools: Vec<Box<dyn AsyncTool>> = vec![Box::new(FlakyTool {
+            calls: calls.clone(),
+            fatal_after: 10,
+        })];
+        let context = RunContext::new();
+        assert_eq!(
+            tools.call_with_retry(&call, &policy, &context).await.unwrap(),
+            "done"
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let tools: Vec<Box<dyn AsyncTool>> = vec![Box::new(FlakyTool {
+            calls: Arc::new(AtomicUsize::new(0)),
+            fatal_after: 2,
+        })];
+        match tools.call_with_retry(&call, &policy, &context).await {
+            Err(AgentError::Tool(error)) => assert!(error.is_fatal()),
+            result => panic!("expected a fatal tool error, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! This module validates the arguments of a tool call against the JSON schema of the tool before the tool is run.
+//!
+//! Only the parts of JSON schema that the tool parameters use are checked: `type`, `required`, `enum`,
+//! `properties`, `items`, `anyOf` and `oneOf`. Anything else in the schema is accepted as is.
+
+use serde_json::{Map, Value};
+
+/// Checks `arguments` against `schema` and returns a description of every mismatch. An empty list means the
+/// arguments are valid.
+pub fn validate_arguments(schema: &Value, arguments: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    // Models often send `null` for tools that take no arguments.
+    let empty = Value::Object(Map::new());
+    let arguments = if arguments.is_null() { &empty } else { arguments };
+    validate(schema, arguments, "", &mut errors);
+    errors
+}
+
+fn validate(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+
+    for key in ["anyOf", "oneOf"] {
+        if let Some(variants) = schema.get(key).and_then(Value::as_array) {
+            let matches = variants.iter().any(|variant| {
+                let mut variant_errors = Vec::new();
+                validate(variant, value, path, &mut variant_errors);
+                variant_errors.is_empty()
+            });
+            if !matches {
+                errors.push(format!(
+                    "{} does not match any of the allowed schemas",
+                    describe(path)
+                ));
+                return;
+            }
+        }
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let types = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{} must be of type {}, got {}",
+                describe(path),
+                types.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{} must be one of {}, got {}",
+                describe(path),
+                Value::Array(allowed.clone()),
+                value
+            ));
+        }
+    }
+
+    if let Value::Object(object) = value {
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for field in required.iter().filter_map(Value::as_str) {
+                if object.get(field).is_none_or(Value::is_null) {
+                    errors.push(format!(
+                        "missing required field `{}`",
+                        join(path, field)
+                    ));
+                }
+            }
+        }
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            for (field, field_value) in object {
+                if let Some(field_schema) = properties.get(field) {
+                    validate(field_schema, field_value, &join(path, field), errors);
+                }
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (i, item) in items.iter().enumerate() {
+            validate(item_schema, item, &format!("{}[{}]", path, i), errors);
+        }
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_string()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+fn describe(path: &str) -> String {
+    if path.is_empty() {
+        "the arguments".to_string()
+    } else {
+        format!("`{}`", path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_arguments() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string" },
+                "top_k": { "type": ["integer", "null"] },
+                "mode": { "type": "string", "enum": ["fast", "exact"] },
+                "tags": { "type": "array", "items": { "type": "string" } }
+            },
+            "required": ["query"]
+        });
+
+        assert!(validate_arguments(&schema, &json!({ "query": "rust", "top_k": null })).is_empty());
+
+        let mut errors = validate_arguments(
+            &schema,
+            &json!({ "top_k": "5", "mode": "slow", "tags": ["a", 1] }),
+        );
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                "`mode` must be one of [\"fast\",\"exact\"], got \"slow\"",
+                "`tags[1]` must be of type string, got integer",
+                "`top_k` must be of type integer or null, got string",
+                "missing required field `query`",
+            ]
+        );
+
+        assert_eq!(
+            validate_arguments(&schema, &Value::Null),
+            vec!["missing required field `query`"]
+        );
+    }
+}
@@ -77,7 +77,8 @@ pub struct VisitWebsiteToolParams {
     url: String,
 }
 
-#[async_trait]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
 impl Tool for VisitWebsiteTool {
     type Params = VisitWebsiteToolParams;
     fn name(&self) -> &'static str {
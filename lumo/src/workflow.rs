@@ -0,0 +1,388 @@
+//! Workflows, which wire agents and plain async functions into a graph and run them over a shared state.
+//!
+//! A managed agent is called by its parent when the parent's model decides to, so a fixed pipeline such as
+//! researcher → writer → reviewer, where the reviewer sends the draft back until it is good enough, cannot be written
+//! with managed agents alone. A [`Workflow`] names its nodes and the edges between them, and an [`Orchestrator`]
+//! runs it:
+//!
+//! - Every node gets the [`RunContext`] of the workflow, and its output is saved in it under the name of the node.
+//!   The task of an [`AgentNode`] is a template that refers to these values as `{{context.<key>}}`, and the task of
+//!   the run is saved as `input`.
+//! - A conditional edge picks the next node from the state, which makes loops possible. [`END`] stops the branch.
+//! - Nodes run in rounds. The nodes that the last round points to run in the next one, at the same time and each
+//!   once. A node with edges to several nodes fans out to all of them, and a node that several branches of equal
+//!   length lead to runs once, after all of them.
+//!
+//! ```rust,ignore
+//! let workflow = Workflow::new("researcher")
+//!     .add_agent("researcher", Box::new(researcher), "Find sources on {{context.input}}")
+//!     .add_agent("writer", Box::new(writer), "Write an article from these notes: {{context.researcher}}\n{{context.reviewer}}")
+//!     .add_agent("reviewer", Box::new(reviewer), "Review this article, and answer APPROVED if it is good: {{context.writer}}")
+//!     .add_edge("researcher", "writer")
+//!     .add_edge("writer", "reviewer")
+//!     .add_conditional_edge("reviewer", |state| {
+//!         let review: String = state.get("reviewer").ok().flatten().unwrap_or_default();
+//!         if review.contains("APPROVED") { END } else { "writer" }.to_string()
+//!     });
+//! let mut orchestrator = Orchestrator::new(workflow)?.with_max_rounds(10);
+//! let run = orchestrator.run("The history of Eindhoven").await?;
+//! println!("{}", run.state["writer"]);
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use futures::future::join_all;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{agent::Agent, context::RunContext, errors::AgentError, runtime::Instant};
+
+/// The target of an edge that ends the branch.
+pub const END: &str = "__end__";
+
+/// A step of a workflow. Its output is saved in the state under the name of the node.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait Node: Send {
+    async fn run(&mut self, state: &RunContext) -> Result<Value, AgentError>;
+}
+
+/// A node that runs an agent on a task rendered from the state. The answer is its output.
+pub struct AgentNode {
+    agent: Box<dyn Agent>,
+    task: String,
+}
+
+impl AgentNode {
+    pub fn new(agent: Box<dyn Agent>, task: &str) -> Self {
+        Self {
+            agent,
+            task: task.to_string(),
+        }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl Node for AgentNode {
+    async fn run(&mut self, state: &RunContext) -> Result<Value, AgentError> {
+        let task = state.render(&self.task);
+        let answer = self.agent.run(&task, true).await?;
+        Ok(Value::String(answer))
+    }
+}
+
+/// A node that runs an async function of the state.
+pub struct FnNode<F>(F);
+
+impl<F, Fut> FnNode<F>
+where
+    F: FnMut(RunContext) -> Fut + Send,
+    Fut: Future<Output = Result<Value, AgentError>> + Send,
+{
+    pub fn new(function: F) -> Self {
+        Self(function)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl<F, Fut> Node for FnNode<F>
+where
+    F: FnMut(RunContext) -> Fut + Send,
+    Fut: Future<Output = Result<Value, AgentError>> + Send,
+{
+    async fn run(&mut self, state: &RunContext) -> Result<Value, AgentError> {
+        (self.0)(state.clone()).await
+    }
+}
+
+type Router = Box<dyn Fn(&RunContext) -> String + Send + Sync>;
+
+enum Edge {
+    To(String),
+    /// Goes to the node that the router returns, or ends the branch on [`END`].
+    When(Router),
+}
+
+/// The nodes and edges of a workflow, which an [`Orchestrator`] runs.
+pub struct Workflow {
+    start: String,
+    nodes: BTreeMap<String, Box<dyn Node>>,
+    edges: BTreeMap<String, Vec<Edge>>,
+}
+
+impl Workflow {
+    /// A workflow that starts at the node `start`.
+    pub fn new(start: &str) -> Self {
+        Self {
+            start: start.to_string(),
+            nodes: BTreeMap::new(),
+            edges: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a node, or replaces the node with the same name.
+    pub fn add_node(mut self, name: &str, node: impl Node + 'static) -> Self {
+        self.nodes.insert(name.to_string(), Box::new(node));
+        self
+    }
+
+    pub fn add_agent(self, name: &str, agent: Box<dyn Agent>, task: &str) -> Self {
+        self.add_node(name, AgentNode::new(agent, task))
+    }
+
+    pub fn add_fn<F, Fut>(self, name: &str, function: F) -> Self
+    where
+        F: FnMut(RunContext) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Value, AgentError>> + Send + 'static,
+    {
+        self.add_node(name, FnNode::new(function))
+    }
+
+    /// Runs `to` after `from`. Several edges from one node fan out.
+    pub fn add_edge(mut self, from: &str, to: &str) -> Self {
+        self.edges
+            .entry(from.to_string())
+            .or_default()
+            .push(Edge::To(to.to_string()));
+        self
+    }
+
+    /// Runs the node that `router` returns after `from`, or ends the branch when it returns [`END`].
+    pub fn add_conditional_edge(
+        mut self,
+        from: &str,
+        router: impl Fn(&RunContext) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.edges
+            .entry(from.to_string())
+            .or_default()
+            .push(Edge::When(Box::new(router)));
+        self
+    }
+}
+
+/// A node run of a workflow.
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowStep {
+    pub node: String,
+    /// The round the node ran in, which starts at 1.
+    pub round: usize,
+    pub output: Value,
+    pub latency: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkflowRun {
+    /// The output of the last node that ran.
+    pub output: Value,
+    pub steps: Vec<WorkflowStep>,
+    /// The state at the end of the run.
+    pub state: BTreeMap<String, Value>,
+    pub rounds: usize,
+}
+
+impl WorkflowRun {
+    /// The names of the nodes in the order they ran.
+    pub fn path(&self) -> Vec<&str> {
+        self.steps.iter().map(|step| step.node.as_str()).collect()
+    }
+}
+
+/// Runs a [`Workflow`].
+pub struct Orchestrator {
+    workflow: Workflow,
+    state: RunContext,
+    max_rounds: usize,
+}
+
+impl Orchestrator {
+    /// Fails if the start or an edge refers to a node that the workflow does not have.
+    pub fn new(workflow: Workflow) -> Result<Self> {
+        if !workflow.nodes.contains_key(&workflow.start) {
+            bail!(
+                "The workflow starts at `{}`, which is not a node",
+                workflow.start
+            );
+        }
+        for (from, edges) in &workflow.edges {
+            if !workflow.nodes.contains_key(from) {
+                bail!(
+                    "The workflow has an edge from `{}`, which is not a node",
+                    from
+                );
+            }
+            for edge in edges {
+                if let Edge::To(to) = edge {
+                    if to != END && !workflow.nodes.contains_key(to) {
+                        bail!("The workflow has an edge to `{}`, which is not a node", to);
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            workflow,
+            state: RunContext::new(),
+            max_rounds: 25,
+        })
+    }
+
+    /// The state that the nodes share. Agents built with a clone of it as their context can read and write it from
+    /// their tools and prompts.
+    pub fn with_state(mut self, state: RunContext) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// The number of rounds after which a run fails, which stops loops that never end.
+    pub fn with_max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    pub fn state(&self) -> RunContext {
+        self.state.clone()
+    }
+
+    /// Runs the workflow on `input` until every branch has ended. The state keeps the values of earlier runs.
+    pub async fn run(&mut self, input: &str) -> Result<WorkflowRun, AgentError> {
+        self.state.insert("input", Value::String(input.to_string()));
+        let mut active = BTreeSet::from([self.workflow.start.clone()]);
+        let mut steps = Vec::new();
+        let mut round = 0;
+        while !active.is_empty() {
+            round += 1;
+            if round > self.max_rounds {
+                return Err(AgentError::Execution(format!(
+                    "The workflow did not end within {} rounds",
+                    self.max_rounds
+                )));
+            }
+            let state = &self.state;
+            let runs = self
+                .workflow
+                .nodes
+                .iter_mut()
+                .filter(|(name, _)| active.contains(*name))
+                .map(|(name, node)| async move {
+                    let started = Instant::now();
+                    let output = node.run(state).await;
+                    (name.clone(), output, started.elapsed())
+                });
+            let mut next = BTreeSet::new();
+            for (name, output, latency) in join_all(runs).await {
+                let output = output?;
+                tracing::debug!(node = %name, round, "Workflow node finished");
+                self.state.insert(&name, output.clone());
+                steps.push(WorkflowStep {
+                    node: name.clone(),
+                    round,
+                    output,
+                    latency,
+                });
+            }
+            for name in &active {
+                for edge in self.workflow.edges.get(name).into_iter().flatten() {
+                    let to = match edge {
+                        Edge::To(to) => to.clone(),
+                        Edge::When(router) => router(&self.state),
+                    };
+                    if to == END {
+                        continue;
+                    }
+                    if !self.workflow.nodes.contains_key(&to) {
+                        return Err(AgentError::Execution(format!(
+                            "The edge from `{}` goes to `{}`, which is not a node",
+                            name, to
+                        )));
+                    }
+                    next.insert(to);
+                }
+            }
+            active = next;
+        }
+        Ok(WorkflowRun {
+            output: steps
+                .last()
+                .map(|step| step.output.clone())
+                .unwrap_or_default(),
+            steps,
+            state: self.state.snapshot(),
+            rounds: round,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::FunctionCallingAgentBuilder, models::testing::ScriptedModel};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_workflow() {
+        let writer = ScriptedModel::new()
+            .with_final_answer("Eindhoven is a city.")
+            .with_final_answer("Eindhoven is a city in the Netherlands.");
+        let handle = writer.clone();
+        let writer = FunctionCallingAgentBuilder::new(writer).build().unwrap();
+        let workflow = Workflow::new("plan")
+            .add_fn("plan", |_| async { Ok(json!("two sources")) })
+            .add_fn("search", |state: RunContext| async move {
+                Ok(json!(format!(
+                    "Found {}",
+                    state.render("{{context.input}}")
+                )))
+            })
+            .add_fn("wiki", |_| async { Ok(json!("Wiki page")) })
+            .add_agent(
+                "writer",
+                Box::new(writer),
+                "Write about {{context.input}} from: {{context.search}}; {{context.wiki}}",
+            )
+            .add_fn("reviewer", |state: RunContext| async move {
+                let draft: String = state.get("writer").unwrap().unwrap();
+                Ok(json!(draft.contains("Netherlands")))
+            })
+            .add_edge("plan", "search")
+            .add_edge("plan", "wiki")
+            .add_edge("search", "writer")
+            .add_edge("wiki", "writer")
+            .add_edge("writer", "reviewer")
+            .add_conditional_edge("reviewer", |state| {
+                match state.get_value("reviewer") {
+                    Some(Value::Bool(true)) => END,
+                    _ => "writer",
+                }
+                .to_string()
+            });
+        let mut orchestrator = Orchestrator::new(workflow).unwrap();
+        let run = orchestrator.run("Eindhoven").await.unwrap();
+        assert_eq!(
+            run.path(),
+            vec!["plan", "search", "wiki", "writer", "reviewer", "writer", "reviewer"]
+        );
+        assert_eq!(run.rounds, 6);
+        assert_eq!(run.output, json!(true));
+        assert_eq!(
+            run.state["writer"],
+            json!("Eindhoven is a city in the Netherlands.")
+        );
+        assert!(handle.calls()[0]
+            .last_message()
+            .unwrap()
+            .ends_with("Write about Eindhoven from: Found Eindhoven; Wiki page"));
+
+        let workflow = Workflow::new("loop")
+            .add_fn("loop", |_| async { Ok(Value::Null) })
+            .add_edge("loop", "loop");
+        let mut orchestrator = Orchestrator::new(workflow).unwrap().with_max_rounds(3);
+        assert!(orchestrator.run("").await.is_err());
+        assert!(Orchestrator::new(Workflow::new("missing")).is_err());
+    }
+}